@@ -0,0 +1,178 @@
+use crate::homewizard_client::HomewizardDevice;
+use std::error::Error;
+use std::net::Ipv4Addr;
+use tracing::debug;
+
+/// An ipv4 range like `192.168.1.0/24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: u32,
+    mask: u32,
+}
+
+impl Cidr {
+    pub fn parse(cidr: &str) -> Result<Self, Box<dyn Error>> {
+        let invalid = || format!("{} isn't an ipv4 range like 192.168.1.0/24", cidr);
+        let (address, prefix_length) = cidr.trim().split_once('/').ok_or_else(invalid)?;
+        let address: Ipv4Addr = address.parse().map_err(|_| invalid())?;
+        let prefix_length: u32 = prefix_length.parse().map_err(|_| invalid())?;
+        if prefix_length > 32 {
+            return Err(invalid().into());
+        }
+        let mask = u32::MAX.checked_shl(32 - prefix_length).unwrap_or(0);
+
+        Ok(Self {
+            network: u32::from(address) & mask,
+            mask,
+        })
+    }
+
+    pub fn contains(&self, address: &Ipv4Addr) -> bool {
+        u32::from(*address) & self.mask == self.network
+    }
+}
+
+/// Leaves only the addresses within any of `cidrs` on the discovered devices, dropping devices
+/// left without any. No ranges allows every address.
+pub fn filter(cidrs: &[Cidr], devices: Vec<HomewizardDevice>) -> Vec<HomewizardDevice> {
+    if cidrs.is_empty() {
+        return devices;
+    }
+
+    devices
+        .into_iter()
+        .filter_map(|mut device| {
+            let (allowed, filtered) = device
+                .ip_addresses
+                .drain()
+                .partition(|address| cidrs.iter().any(|cidr| cidr.contains(address)));
+            device.ip_addresses = allowed;
+            let filtered: Vec<Ipv4Addr> = filtered.into_iter().collect();
+
+            if device.ip_addresses.is_empty() {
+                debug!(
+                    "Dropping discovered device {}, none of its addresses {:?} is in allowedCidrs",
+                    device.fullname, filtered
+                );
+                return None;
+            }
+            if !filtered.is_empty() {
+                debug!(
+                    "Dropping addresses {:?} of discovered device {} outside allowedCidrs",
+                    filtered, device.fullname
+                );
+            }
+
+            Some(device)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::capture_logs;
+
+    fn device(fullname: &str, addresses: &[&str]) -> HomewizardDevice {
+        HomewizardDevice {
+            fullname: fullname.into(),
+            ip_addresses: addresses
+                .iter()
+                .map(|address| address.parse().unwrap())
+                .collect(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        }
+    }
+
+    fn cidrs() -> Vec<Cidr> {
+        vec![
+            Cidr::parse("192.168.1.0/24").unwrap(),
+            Cidr::parse("10.0.0.7/32").unwrap(),
+        ]
+    }
+
+    #[test]
+    fn parse_rejects_invalid_ranges() {
+        // act
+        let results = vec![
+            Cidr::parse("192.168.1.0"),
+            Cidr::parse("192.168.1.0/33"),
+            Cidr::parse("192.168.1/24"),
+            Cidr::parse("fe80::/64"),
+        ];
+
+        assert!(results.iter().all(|result| result.is_err()));
+    }
+
+    #[test]
+    fn contains_matches_addresses_within_prefix() {
+        let cidr = Cidr::parse("192.168.1.17/28").unwrap();
+        let everything = Cidr::parse("0.0.0.0/0").unwrap();
+
+        // act
+        let contained: Vec<bool> = vec!["192.168.1.16", "192.168.1.31", "192.168.1.32"]
+            .into_iter()
+            .map(|address| cidr.contains(&address.parse().unwrap()))
+            .collect();
+
+        assert_eq!(contained, vec![true, true, false]);
+        assert!(everything.contains(&"172.16.0.1".parse().unwrap()));
+    }
+
+    #[test]
+    fn filter_keeps_in_range_and_drops_out_of_range_devices() {
+        let devices = vec![
+            device("energysocket-1._hwenergy._tcp.local.", &["192.168.1.31"]),
+            device("p1meter-guest._hwenergy._tcp.local.", &["192.168.50.12"]),
+            device("watermeter-1._hwenergy._tcp.local.", &["10.0.0.7"]),
+        ];
+
+        // act
+        let filtered = filter(&cidrs(), devices);
+
+        let names: Vec<&str> = filtered.iter().map(|d| d.fullname.as_str()).collect();
+        assert_eq!(
+            names,
+            vec![
+                "energysocket-1._hwenergy._tcp.local.",
+                "watermeter-1._hwenergy._tcp.local."
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_keeps_in_range_address_of_mixed_device() {
+        let devices = vec![device(
+            "energysocket-1._hwenergy._tcp.local.",
+            &["192.168.50.31", "192.168.1.31"],
+        )];
+
+        // act
+        let logs = capture_logs("debug", || {
+            let filtered = filter(&cidrs(), devices);
+
+            assert_eq!(filtered.len(), 1);
+            assert_eq!(
+                filtered[0].ip_addresses,
+                vec!["192.168.1.31".parse().unwrap()].into_iter().collect()
+            );
+        });
+
+        assert!(logs.contains("Dropping addresses [192.168.50.31]"));
+    }
+
+    #[test]
+    fn filter_allows_everything_without_ranges() {
+        let devices = vec![device(
+            "p1meter-guest._hwenergy._tcp.local.",
+            &["192.168.50.12"],
+        )];
+
+        // act
+        let filtered = filter(&[], devices);
+
+        assert_eq!(filtered.len(), 1);
+    }
+}
@@ -0,0 +1,284 @@
+use crate::exporter::ConfigObserver;
+use crate::model::Config;
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::net::{Ipv4Addr, UdpSocket};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+pub const SERVICE_TYPE: &str = "_jarvis-exporter._tcp.local.";
+
+pub struct AnnounceConfig {
+    pub instance_name: String,
+    pub address: Ipv4Addr,
+    pub metrics_port: Option<u16>,
+}
+
+impl AnnounceConfig {
+    pub fn new(
+        instance_name: String,
+        address: Ipv4Addr,
+        metrics_port: Option<u16>,
+    ) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "AnnounceConfig::new(instance_name: {}, address: {}, metrics_port: {:?})",
+            instance_name, address, metrics_port
+        );
+        if instance_name.trim().is_empty() {
+            return Err("The instance name to announce can't be empty".into());
+        }
+
+        Ok(Self {
+            instance_name,
+            address,
+            metrics_port,
+        })
+    }
+
+    /// Announcing is opt-in with `MDNS_ANNOUNCE=true`; returns no config otherwise. The instance
+    /// is named after `HOSTNAME` and announced at `MDNS_ANNOUNCE_ADDRESS`, or else the address
+    /// multicast traffic leaves from.
+    pub fn from_env(metrics_port: Option<u16>) -> Result<Option<Self>, Box<dyn Error>> {
+        if env::var("MDNS_ANNOUNCE").map_or(true, |announce| announce != "true") {
+            return Ok(None);
+        }
+        let instance_name =
+            env::var("HOSTNAME").unwrap_or_else(|_| "jarvis-homewizard-exporter".to_string());
+        let address = match env::var("MDNS_ANNOUNCE_ADDRESS") {
+            Ok(address) => address.parse()?,
+            Err(_) => multicast_source_address()?,
+        };
+
+        Ok(Some(Self::new(instance_name, address, metrics_port)?))
+    }
+}
+
+/// The local address multicast traffic leaves from; connecting a udp socket sends nothing.
+fn multicast_source_address() -> Result<Ipv4Addr, Box<dyn Error>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((Ipv4Addr::new(224, 0, 0, 251), 5353))?;
+    match socket.local_addr()?.ip() {
+        std::net::IpAddr::V4(address) if !address.is_unspecified() => Ok(address),
+        other => Err(format!(
+            "Can't announce at address {}, set MDNS_ANNOUNCE_ADDRESS",
+            other
+        )
+        .into()),
+    }
+}
+
+/// Registers and unregisters services, like the mdns daemon does.
+pub trait ServiceRegistry {
+    fn register(&self, service: ServiceInfo) -> Result<(), Box<dyn Error>>;
+    fn unregister(&self, fullname: &str) -> Result<(), Box<dyn Error>>;
+}
+
+impl ServiceRegistry for ServiceDaemon {
+    fn register(&self, service: ServiceInfo) -> Result<(), Box<dyn Error>> {
+        ServiceDaemon::register(self, service)
+            .map_err(|e| format!("Failed registering mdns service: {}", e).into())
+    }
+
+    fn unregister(&self, fullname: &str) -> Result<(), Box<dyn Error>> {
+        let receiver = ServiceDaemon::unregister(self, fullname)
+            .map_err(|e| format!("Failed unregistering mdns service: {}", e))?;
+        // waiting for the goodbye to go out, before the process exits
+        let _ = receiver.recv_timeout(Duration::from_secs(1));
+
+        Ok(())
+    }
+}
+
+/// Builds the service announcing the exporter, with its version, location and - when metrics
+/// get served - metrics port in TXT records.
+pub fn service_info(
+    config: &AnnounceConfig,
+    location: &str,
+) -> Result<ServiceInfo, Box<dyn Error>> {
+    let mut properties: HashMap<String, String> = vec![
+        ("version", env!("CARGO_PKG_VERSION")),
+        ("location", location),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect();
+    if let Some(metrics_port) = config.metrics_port {
+        properties.insert("metrics_port".to_string(), metrics_port.to_string());
+    }
+
+    ServiceInfo::new(
+        SERVICE_TYPE,
+        &config.instance_name,
+        &format!("{}.local.", config.instance_name),
+        config.address.to_string().as_str(),
+        config.metrics_port.unwrap_or(0),
+        Some(properties),
+    )
+    .map_err(|e| format!("Failed building mdns service: {}", e).into())
+}
+
+/// Announces the exporter over mdns, again whenever its location changes, until it's
+/// unregistered.
+pub struct Announcer {
+    registry: Box<dyn ServiceRegistry>,
+    config: AnnounceConfig,
+    announced: RefCell<Option<(String, String)>>,
+}
+
+impl Announcer {
+    pub fn new(registry: Box<dyn ServiceRegistry>, config: AnnounceConfig) -> Self {
+        Self {
+            registry,
+            config,
+            announced: RefCell::new(None),
+        }
+    }
+
+    /// Announces through a daemon of its own, next to the one browsing for devices.
+    pub fn start(config: AnnounceConfig) -> Result<Self, Box<dyn Error>> {
+        let daemon =
+            ServiceDaemon::new().map_err(|e| format!("Failed to create mdns daemon: {}", e))?;
+
+        Ok(Self::new(Box::new(daemon), config))
+    }
+
+    /// Registers the service for `location`, unless it's registered for it already; registering
+    /// it again replaces its TXT records.
+    pub fn announce(&self, location: &str) -> Result<(), Box<dyn Error>> {
+        let mut announced = self.announced.borrow_mut();
+        if matches!(&*announced, Some((_, announced_location)) if announced_location == location) {
+            return Ok(());
+        }
+
+        let service = service_info(&self.config, location)?;
+        let fullname = service.get_fullname().to_string();
+        self.registry.register(service)?;
+        info!("Announced {} over mdns for location {}", fullname, location);
+        *announced = Some((fullname, location.to_string()));
+
+        Ok(())
+    }
+
+    /// Unregisters the service, when it was announced. Failing to only gets logged.
+    pub fn unregister(&self) {
+        if let Some((fullname, _)) = self.announced.borrow_mut().take() {
+            match self.registry.unregister(&fullname) {
+                Ok(()) => info!("Unregistered {} from mdns", fullname),
+                Err(e) => warn!("{}", e),
+            }
+        }
+    }
+}
+
+impl ConfigObserver for Announcer {
+    fn config_changed(&self, config: &Config) {
+        if let Err(e) = self.announce(&config.location) {
+            warn!("Failed announcing the exporter over mdns: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Call {
+        Register(String, HashMap<String, String>, u16),
+        Unregister(String),
+    }
+
+    #[derive(Clone, Default)]
+    struct MockRegistry {
+        calls: Rc<RefCell<Vec<Call>>>,
+    }
+
+    impl ServiceRegistry for MockRegistry {
+        fn register(&self, service: ServiceInfo) -> Result<(), Box<dyn Error>> {
+            self.calls.borrow_mut().push(Call::Register(
+                service.get_fullname().to_string(),
+                service.get_properties().clone(),
+                service.get_port(),
+            ));
+            Ok(())
+        }
+
+        fn unregister(&self, fullname: &str) -> Result<(), Box<dyn Error>> {
+            self.calls
+                .borrow_mut()
+                .push(Call::Unregister(fullname.to_string()));
+            Ok(())
+        }
+    }
+
+    fn announce_config(metrics_port: Option<u16>) -> AnnounceConfig {
+        AnnounceConfig::new(
+            "exporter-1".into(),
+            Ipv4Addr::new(192, 168, 1, 10),
+            metrics_port,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn service_info_has_version_location_and_metrics_port() {
+        // act
+        let service = service_info(&announce_config(Some(9101)), "My Home").unwrap();
+
+        assert_eq!(
+            service.get_fullname(),
+            "exporter-1._jarvis-exporter._tcp.local."
+        );
+        assert_eq!(service.get_port(), 9101);
+        let properties = service.get_properties();
+        assert_eq!(properties["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(properties["location"], "My Home");
+        assert_eq!(properties["metrics_port"], "9101");
+    }
+
+    #[test]
+    fn service_info_leaves_out_metrics_port_without_metrics_server() {
+        // act
+        let service = service_info(&announce_config(None), "My Home").unwrap();
+
+        assert!(!service.get_properties().contains_key("metrics_port"));
+    }
+
+    #[test]
+    fn announcer_registers_again_only_when_location_changes_and_unregisters() {
+        let registry = MockRegistry::default();
+        let announcer = Announcer::new(Box::new(registry.clone()), announce_config(None));
+        let config = |location: &str| Config {
+            location: location.into(),
+            ..Default::default()
+        };
+
+        // act
+        announcer.announce("My Home").unwrap();
+        announcer.config_changed(&config("My Home"));
+        announcer.config_changed(&config("Holiday Home"));
+        announcer.unregister();
+        announcer.unregister();
+
+        let calls = registry.calls.borrow();
+        let registered_locations: Vec<&str> = calls
+            .iter()
+            .filter_map(|call| match call {
+                Call::Register(_, properties, _) => Some(properties["location"].as_str()),
+                Call::Unregister(_) => None,
+            })
+            .collect();
+        assert_eq!(registered_locations, vec!["My Home", "Holiday Home"]);
+        assert_eq!(
+            calls.last(),
+            Some(&Call::Unregister(
+                "exporter-1._jarvis-exporter._tcp.local.".into()
+            ))
+        );
+        assert_eq!(calls.len(), 3);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::homewizard_client::TriplePhaseKwhMeterDataResponse;
+use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+
+const APPARENT_POWER_SUFFIX: &str = " apparent power";
+
+/// Gauges of the apparent power of a SDM630 kWh meter in VA, in total and per phase, named like
+/// `kWh meter 3-phase l1 apparent power`; only for the values its firmware reports.
+pub fn samples(
+    product_type: &str,
+    friendly_name: &str,
+    data: &TriplePhaseKwhMeterDataResponse,
+) -> Vec<Sample> {
+    [
+        (None, data.apparent_power_va),
+        (Some("l1"), data.apparent_power_l1_va),
+        (Some("l2"), data.apparent_power_l2_va),
+        (Some("l3"), data.apparent_power_l3_va),
+    ]
+    .iter()
+    .filter_map(|(phase, value)| {
+        let sample_name = match phase {
+            Some(phase) => format!("{} {}{}", friendly_name, phase, APPARENT_POWER_SUFFIX),
+            None => format!("{}{}", friendly_name, APPARENT_POWER_SUFFIX),
+        };
+
+        value.map(|value| Sample {
+            entity_type: EntityType::Device,
+            entity_name: product_type.to_string(),
+            sample_type: SampleType::ElectricityConsumption,
+            sample_name,
+            metric_type: MetricType::Gauge,
+            value,
+        })
+    })
+    .collect()
+}
+
+pub fn is_apparent_power(sample: &Sample) -> bool {
+    matches!(sample.entity_type, EntityType::Device)
+        && matches!(sample.sample_type, SampleType::ElectricityConsumption)
+        && matches!(sample.metric_type, MetricType::Gauge)
+        && sample.sample_name.ends_with(APPARENT_POWER_SUFFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_name_phase_and_quantity() {
+        let data = TriplePhaseKwhMeterDataResponse {
+            apparent_power_va: Some(1100.0),
+            apparent_power_l1_va: Some(1080.5),
+            apparent_power_l2_va: Some(19.5),
+            apparent_power_l3_va: Some(0.0),
+            ..Default::default()
+        };
+
+        // act
+        let samples = samples("SDM630-wifi", "kWh meter 3-phase", &data);
+
+        let names: Vec<&str> = samples
+            .iter()
+            .map(|sample| sample.sample_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                "kWh meter 3-phase apparent power",
+                "kWh meter 3-phase l1 apparent power",
+                "kWh meter 3-phase l2 apparent power",
+                "kWh meter 3-phase l3 apparent power",
+            ]
+        );
+        assert_eq!(samples[1].value, 1080.5);
+        assert!(samples.iter().all(is_apparent_power));
+    }
+
+    #[test]
+    fn samples_skip_values_missing_on_older_firmware() {
+        let data = TriplePhaseKwhMeterDataResponse {
+            apparent_power_l1_va: Some(1080.5),
+            ..Default::default()
+        };
+
+        // act
+        let samples = samples("SDM630-wifi", "kWh meter 3-phase", &data);
+
+        assert_eq!(samples.len(), 1);
+        assert_eq!(
+            samples[0].sample_name,
+            "kWh meter 3-phase l1 apparent power"
+        );
+    }
+}
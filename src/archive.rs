@@ -0,0 +1,215 @@
+use crate::model::ArchiveConfig;
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use jarvis_lib::model::Measurement;
+use std::error::Error;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+
+const FILE_PREFIX: &str = "measurements-";
+const FILE_EXTENSION: &str = ".jsonl";
+
+/// Appends every published measurement as a json line to a file per day, pruning files older
+/// than the retention on the first write and whenever the day changes.
+#[derive(Default)]
+pub struct MeasurementArchive {
+    last_pruned: Mutex<Option<NaiveDate>>,
+}
+
+impl MeasurementArchive {
+    pub fn archive(
+        &self,
+        config: &ArchiveConfig,
+        measurements: &[Measurement],
+        now: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        let directory = Path::new(&config.directory);
+        fs::create_dir_all(directory)?;
+
+        let today = now.date_naive();
+        let mut last_pruned = self.last_pruned.lock().unwrap();
+        if *last_pruned != Some(today) {
+            if let Some(retention_days) = config.retention_days {
+                prune(directory, today - Duration::days(retention_days.into()))?;
+            }
+            *last_pruned = Some(today);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(file_path(directory, today))?;
+        for measurement in measurements {
+            let mut line = serde_json::to_vec(measurement)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn file_path(directory: &Path, date: NaiveDate) -> PathBuf {
+    directory.join(format!(
+        "{}{}{}",
+        FILE_PREFIX,
+        date.format("%Y-%m-%d"),
+        FILE_EXTENSION
+    ))
+}
+
+/// Removes archive files for days before `oldest_kept`; other files are left alone.
+fn prune(directory: &Path, oldest_kept: NaiveDate) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let date = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(FILE_PREFIX))
+            .and_then(|name| name.strip_suffix(FILE_EXTENSION))
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+
+        if let Some(date) = date {
+            if date < oldest_kept {
+                info!("Pruning archived measurements {}", path.display());
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+    use std::env;
+    use uuid::Uuid;
+
+    fn archive_config(retention_days: Option<u32>) -> ArchiveConfig {
+        ArchiveConfig {
+            directory: env::temp_dir()
+                .join(format!("archive-{}", Uuid::new_v4()))
+                .to_string_lossy()
+                .to_string(),
+            retention_days,
+        }
+    }
+
+    fn measurement() -> Measurement {
+        Measurement {
+            id: Uuid::new_v4().to_string(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![Sample {
+                entity_type: EntityType::Device,
+                entity_name: "HWE-SKT".into(),
+                sample_type: SampleType::ElectricityConsumption,
+                sample_name: "Bonenmaler".into(),
+                metric_type: MetricType::Gauge,
+                value: 1450.0,
+            }],
+            measured_at_time: Utc::now(),
+        }
+    }
+
+    fn read_lines(path: PathBuf) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn archive_appends_measurements_as_json_lines() {
+        let config = archive_config(None);
+        let archive = MeasurementArchive::default();
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let (first, second) = (measurement(), measurement());
+
+        // act
+        archive.archive(&config, &[first.clone()], now).unwrap();
+        archive.archive(&config, &[second.clone()], now).unwrap();
+
+        let lines = read_lines(Path::new(&config.directory).join("measurements-2023-06-01.jsonl"));
+        assert_eq!(lines.len(), 2);
+        let archived: Measurement = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(archived.id, second.id);
+        fs::remove_dir_all(&config.directory).unwrap();
+    }
+
+    #[test]
+    fn archive_rotates_files_when_the_day_changes() {
+        let config = archive_config(None);
+        let archive = MeasurementArchive::default();
+
+        // act
+        archive
+            .archive(
+                &config,
+                &[measurement()],
+                Utc.with_ymd_and_hms(2023, 6, 1, 23, 59, 0).unwrap(),
+            )
+            .unwrap();
+        archive
+            .archive(
+                &config,
+                &[measurement()],
+                Utc.with_ymd_and_hms(2023, 6, 2, 0, 1, 0).unwrap(),
+            )
+            .unwrap();
+
+        let directory = Path::new(&config.directory);
+        assert_eq!(
+            read_lines(directory.join("measurements-2023-06-01.jsonl")).len(),
+            1
+        );
+        assert_eq!(
+            read_lines(directory.join("measurements-2023-06-02.jsonl")).len(),
+            1
+        );
+        fs::remove_dir_all(&config.directory).unwrap();
+    }
+
+    #[test]
+    fn archive_prunes_files_older_than_retention() {
+        let config = archive_config(Some(7));
+        let directory = Path::new(&config.directory);
+        fs::create_dir_all(directory).unwrap();
+        fs::write(directory.join("measurements-2023-05-01.jsonl"), "").unwrap();
+        fs::write(directory.join("measurements-2023-05-25.jsonl"), "").unwrap();
+        fs::write(directory.join("notes.txt"), "").unwrap();
+        let archive = MeasurementArchive::default();
+
+        // act
+        archive
+            .archive(
+                &config,
+                &[measurement()],
+                Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+            )
+            .unwrap();
+
+        assert!(!directory.join("measurements-2023-05-01.jsonl").exists());
+        assert!(directory.join("measurements-2023-05-25.jsonl").exists());
+        assert!(directory.join("measurements-2023-06-01.jsonl").exists());
+        assert!(directory.join("notes.txt").exists());
+
+        // act
+        archive
+            .archive(
+                &config,
+                &[measurement()],
+                Utc.with_ymd_and_hms(2023, 6, 2, 12, 0, 0).unwrap(),
+            )
+            .unwrap();
+
+        assert!(!directory.join("measurements-2023-05-25.jsonl").exists());
+        fs::remove_dir_all(&config.directory).unwrap();
+    }
+}
@@ -0,0 +1,390 @@
+use crate::error::DeviceError;
+use crate::homewizard_client::{HomewizardClient, HomewizardDevice, HomewizardDeviceType};
+use crate::model::Config;
+use serde::Serialize;
+use std::error::Error;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+pub struct BenchConfig {
+    pub iterations: usize,
+    pub delay: Duration,
+}
+
+impl BenchConfig {
+    pub fn new(iterations: usize, delay: Duration) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "BenchConfig::new(iterations: {}, delay: {:?})",
+            iterations, delay
+        );
+        if iterations == 0 {
+            return Err("--iterations has to be at least 1".into());
+        }
+
+        Ok(Self { iterations, delay })
+    }
+}
+
+/// Latencies of the data requests of a device that got answered, in milliseconds, and how many
+/// requests failed.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceBench {
+    pub name: String,
+    pub product_type: Option<String>,
+    pub serial: Option<String>,
+    pub requests: usize,
+    pub errors: usize,
+    pub error_rate: f64,
+    pub min_ms: Option<f64>,
+    pub median_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    /// Why the device couldn't be benched at all, when its `/api` endpoint failed.
+    pub error: Option<String>,
+}
+
+/// Discovers the devices - or takes the device given by address - and requests the data
+/// endpoint of each `iterations` times, `delay` apart, one device after the other, with the
+/// token `config` has for its serial. Nothing gets published or stored.
+pub fn bench(
+    homewizard_client: &HomewizardClient,
+    config: &Config,
+    bench_config: &BenchConfig,
+) -> Result<Vec<DeviceBench>, Box<dyn Error>> {
+    let mut devices = homewizard_client
+        .discover_devices()
+        .map_err(DeviceError::new)?;
+    devices.sort_by(|a, b| a.fullname.cmp(&b.fullname));
+
+    Ok(devices
+        .iter()
+        .map(|device| bench_device(homewizard_client, config, bench_config, device))
+        .collect())
+}
+
+fn bench_device(
+    homewizard_client: &HomewizardClient,
+    config: &Config,
+    bench_config: &BenchConfig,
+    device: &HomewizardDevice,
+) -> DeviceBench {
+    let device_info = match homewizard_client.get_device_info(device) {
+        Ok(device_info) => device_info,
+        Err(e) => {
+            warn!(
+                "Skipping device {}, requesting its info failed: {}",
+                device.fullname, e
+            );
+            let mut device_bench = summarize(&device.fullname, &[], 0);
+            device_bench.error = Some(e.to_string());
+            return device_bench;
+        }
+    };
+
+    if matches!(
+        HomewizardDeviceType::from_str(&device_info.product_type),
+        Ok(HomewizardDeviceType::WaterMeter)
+    ) {
+        warn!(
+            "Benching water meter {}: on battery it only wakes up its wifi now and then, and {} requests drain the battery",
+            device.fullname, bench_config.iterations
+        );
+    }
+    info!(
+        "Benching device {} with {} requests {:?} apart...",
+        device.fullname, bench_config.iterations, bench_config.delay
+    );
+
+    let mut latencies = vec![];
+    let mut errors = 0;
+    for iteration in 0..bench_config.iterations {
+        // battery powered devices don't cope with requests in quick succession
+        if iteration > 0 {
+            thread::sleep(bench_config.delay);
+        }
+        let start = Instant::now();
+        match homewizard_client.fetch_data(config, device, &device_info) {
+            Ok(()) => latencies.push(start.elapsed()),
+            Err(e) => {
+                debug!(
+                    "Request {} of device {} failed: {}",
+                    iteration, device.fullname, e
+                );
+                errors += 1;
+            }
+        }
+    }
+
+    let mut device_bench = summarize(&device.fullname, &latencies, errors);
+    device_bench.product_type = Some(device_info.product_type);
+    device_bench.serial = Some(device_info.serial);
+    device_bench
+}
+
+/// Min, median, p95 and max of `latencies` by nearest rank, none without any latency.
+fn summarize(name: &str, latencies: &[Duration], errors: usize) -> DeviceBench {
+    let mut milliseconds: Vec<f64> = latencies
+        .iter()
+        .map(|latency| latency.as_secs_f64() * 1000.0)
+        .collect();
+    milliseconds.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |percentile: f64| {
+        let rank = (percentile * milliseconds.len() as f64).ceil() as usize;
+        milliseconds.get(rank.max(1) - 1).copied()
+    };
+    let requests = latencies.len() + errors;
+
+    DeviceBench {
+        name: name.to_string(),
+        product_type: None,
+        serial: None,
+        requests,
+        errors,
+        error_rate: if requests == 0 {
+            0.0
+        } else {
+            errors as f64 / requests as f64
+        },
+        min_ms: milliseconds.first().copied(),
+        median_ms: percentile(0.5),
+        p95_ms: percentile(0.95),
+        max_ms: milliseconds.last().copied(),
+        error: None,
+    }
+}
+
+/// Renders the benches as a table, one line per device with its latencies in milliseconds.
+pub fn render_table(benches: &[DeviceBench]) -> String {
+    let milliseconds = |value: Option<f64>| {
+        value
+            .map(|value| format!("{:.1}", value))
+            .unwrap_or_else(|| "-".to_string())
+    };
+
+    let mut lines = vec![format!(
+        "{:<45} {:<8} {:<14} {:>8} {:>7} {:>8} {:>8} {:>8} {:>8}",
+        "DEVICE", "TYPE", "SERIAL", "REQUESTS", "ERRORS", "MIN", "MEDIAN", "P95", "MAX"
+    )];
+    for bench in benches {
+        lines.push(format!(
+            "{:<45} {:<8} {:<14} {:>8} {:>6.0}% {:>8} {:>8} {:>8} {:>8}",
+            bench.name,
+            bench.product_type.as_deref().unwrap_or("-"),
+            bench.serial.as_deref().unwrap_or("-"),
+            bench.requests,
+            bench.error_rate * 100.0,
+            milliseconds(bench.min_ms),
+            milliseconds(bench.median_ms),
+            milliseconds(bench.p95_ms),
+            milliseconds(bench.max_ms),
+        ));
+    }
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::StaticDiscoverer;
+    use crate::homewizard_client::HomewizardClientConfig;
+    use crate::model::{DeviceTokenConfig, StaticDevice};
+    use crate::test_support::{FakeDeviceServer, FakeResponse};
+    use std::net::{Ipv4Addr, SocketAddrV4};
+
+    const SOCKET_INFO: &str = r#"{"product_type":"HWE-SKT","product_name":"Energy Socket","serial":"3c39e72e33ce","firmware_version":"3.02","api_version":"v1"}"#;
+    const SOCKET_DATA: &str = r#"{"wifi_ssid":"My Wi-Fi","wifi_strength":94,"total_power_import_t1_kwh":30.511,"total_power_export_t1_kwh":85.951,"active_power_w":543,"active_power_l1_w":543}"#;
+
+    fn device(server: &FakeDeviceServer, fullname: &str) -> HomewizardDevice {
+        HomewizardDevice {
+            fullname: fullname.into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        }
+    }
+
+    fn millis(milliseconds: &[u64]) -> Vec<Duration> {
+        milliseconds
+            .iter()
+            .map(|milliseconds| Duration::from_millis(*milliseconds))
+            .collect()
+    }
+
+    #[test]
+    fn summarize_takes_percentiles_by_nearest_rank() {
+        let latencies = millis(&[50, 10, 40, 20, 30, 60, 70, 80, 90, 1000]);
+
+        // act
+        let bench = summarize("energysocket-1._hwenergy._tcp.local.", &latencies, 2);
+
+        assert_eq!(bench.requests, 12);
+        assert_eq!(bench.errors, 2);
+        assert_eq!(bench.error_rate, 2.0 / 12.0);
+        assert_eq!(bench.min_ms, Some(10.0));
+        assert_eq!(bench.median_ms, Some(50.0));
+        assert_eq!(bench.p95_ms, Some(1000.0));
+        assert_eq!(bench.max_ms, Some(1000.0));
+    }
+
+    #[test]
+    fn summarize_has_no_latencies_when_every_request_failed() {
+        // act
+        let bench = summarize("energysocket-1._hwenergy._tcp.local.", &[], 3);
+
+        assert_eq!(bench.error_rate, 1.0);
+        assert_eq!(bench.min_ms, None);
+        assert_eq!(bench.median_ms, None);
+        assert_eq!(bench.p95_ms, None);
+    }
+
+    #[test]
+    fn bench_measures_latencies_and_errors_of_every_device() {
+        let socket = FakeDeviceServer::start();
+        socket.respond("/api", FakeResponse::json(SOCKET_INFO));
+        for delay in [40, 80, 120, 160] {
+            socket.respond(
+                "/api/v1/data",
+                FakeResponse::json(SOCKET_DATA).delayed(Duration::from_millis(delay)),
+            );
+        }
+        let failing = FakeDeviceServer::start();
+        failing
+            .respond("/api", FakeResponse::json(SOCKET_INFO))
+            .respond("/api/v1/data", FakeResponse::json(SOCKET_DATA))
+            .respond("/api/v1/data", FakeResponse::status(503));
+        let unreachable = FakeDeviceServer::start();
+        unreachable.respond("/api", FakeResponse::status(404));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![
+                device(&socket, "energysocket-1._hwenergy._tcp.local."),
+                device(&failing, "energysocket-2._hwenergy._tcp.local."),
+                device(&unreachable, "energysocket-3._hwenergy._tcp.local."),
+            ])));
+
+        // act
+        let benches = bench(
+            &homewizard_client,
+            &Config::default(),
+            &BenchConfig::new(4, Duration::from_millis(5)).unwrap(),
+        )
+        .unwrap();
+
+        let ms = |value: Option<f64>| value.unwrap();
+        assert_eq!(benches[0].requests, 4);
+        assert_eq!(benches[0].errors, 0);
+        assert_eq!(benches[0].serial.as_deref(), Some("3c39e72e33ce"));
+        assert!((40.0..80.0).contains(&ms(benches[0].min_ms)));
+        assert!((80.0..120.0).contains(&ms(benches[0].median_ms)));
+        assert!(ms(benches[0].p95_ms) >= 160.0);
+        assert_eq!(benches[0].p95_ms, benches[0].max_ms);
+        assert_eq!(benches[1].errors, 3);
+        assert_eq!(benches[1].error_rate, 0.75);
+        assert_eq!(benches[2].requests, 0);
+        assert!(benches[2].error.is_some());
+        assert_eq!(socket.request_count("/api/v1/data"), 4);
+        assert_eq!(unreachable.request_count("/api/v1/data"), 0);
+    }
+
+    #[test]
+    fn bench_counts_error_statuses_as_failures_even_with_a_json_body() {
+        let socket = FakeDeviceServer::start();
+        socket
+            .respond("/api", FakeResponse::json(SOCKET_INFO))
+            .respond("/api/v1/data", FakeResponse::json(SOCKET_DATA))
+            .respond(
+                "/api/v1/data",
+                FakeResponse {
+                    status: 502,
+                    ..FakeResponse::json(r#"{"error":"bad gateway"}"#)
+                },
+            )
+            .respond(
+                "/api/v1/data",
+                FakeResponse {
+                    status: 401,
+                    ..FakeResponse::json(r#"{"error":"user:unauthorized"}"#)
+                },
+            );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![device(
+                &socket,
+                "energysocket-1._hwenergy._tcp.local.",
+            )])));
+
+        // act
+        let benches = bench(
+            &homewizard_client,
+            &Config::default(),
+            &BenchConfig::new(3, Duration::from_millis(1)).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(benches[0].requests, 3);
+        assert_eq!(benches[0].errors, 2);
+        assert_eq!(benches[0].min_ms, benches[0].max_ms);
+    }
+
+    #[test]
+    fn bench_sends_token_of_pinned_device_to_v2_data_endpoint() {
+        let socket = FakeDeviceServer::start();
+        socket
+            .respond(
+                "/api",
+                FakeResponse::json(&SOCKET_INFO.replace(r#""v1""#, r#""v2""#)),
+            )
+            .respond("/api/v2/data", FakeResponse::json(SOCKET_DATA))
+            .require_token("/api/v2/data", "2A4F0C9B");
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![device(
+                &socket,
+                "energysocket-1._hwenergy._tcp.local.",
+            )])));
+        let config = Config {
+            location: "My Home".into(),
+            devices: vec![StaticDevice::Pinned {
+                address: SocketAddrV4::new(Ipv4Addr::LOCALHOST, socket.address().port()),
+                serial: "3c39e72e33ce".into(),
+                auth: DeviceTokenConfig {
+                    token: Some("2A4F0C9B".into()),
+                    ..Default::default()
+                },
+            }],
+            ..Default::default()
+        };
+        let bench_config = BenchConfig::new(3, Duration::from_millis(1)).unwrap();
+
+        // act
+        let with_token = bench(&homewizard_client, &config, &bench_config).unwrap();
+        let without_token = bench(&homewizard_client, &Config::default(), &bench_config).unwrap();
+
+        assert_eq!(with_token[0].requests, 3);
+        assert_eq!(with_token[0].errors, 0);
+        assert_eq!(without_token[0].errors, 3);
+    }
+
+    #[test]
+    fn render_table_prints_a_line_per_device() {
+        let mut bench = summarize(
+            "energysocket-1._hwenergy._tcp.local.",
+            &millis(&[12, 18]),
+            0,
+        );
+        bench.product_type = Some("HWE-SKT".into());
+        bench.serial = Some("3c39e72e33ce".into());
+
+        // act
+        let table = render_table(&[bench]);
+
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("DEVICE"));
+        assert!(lines[1].contains("HWE-SKT"));
+        assert!(lines[1].ends_with("12.0     12.0     18.0     18.0"));
+        assert!(lines[1].contains(" 0% "));
+    }
+}
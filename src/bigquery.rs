@@ -0,0 +1,484 @@
+use crate::exporter::MeasurementPublisher;
+use async_trait::async_trait;
+use jarvis_lib::model::{Measurement, Sample};
+use serde::Serialize;
+use serde_json::json;
+use std::cell::Cell;
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+use tracing::{debug, info};
+
+const BIGQUERY_URL: &str = "https://bigquery.googleapis.com";
+const BIGQUERY_SCOPE: &str = "https://www.googleapis.com/auth/bigquery";
+
+pub struct BigQueryPublisherConfig {
+    base_url: String,
+    project_id: String,
+    dataset: String,
+    table: String,
+    timeout: Duration,
+}
+
+impl BigQueryPublisherConfig {
+    pub fn new(
+        base_url: String,
+        project_id: String,
+        dataset: String,
+        table: String,
+        timeout: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "BigQueryPublisherConfig::new(base_url: {}, project_id: {}, dataset: {}, table: {}, timeout: {:?})",
+            base_url, project_id, dataset, table, timeout
+        );
+
+        Ok(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            project_id,
+            dataset,
+            table,
+            timeout,
+        })
+    }
+
+    /// Returns no config when `BIGQUERY_PROJECT_ID` isn't set, since streaming to BigQuery is
+    /// optional.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let project_id = match env::var("BIGQUERY_PROJECT_ID") {
+            Ok(project_id) => project_id,
+            Err(_) => return Ok(None),
+        };
+        let dataset = env::var("BIGQUERY_DATASET")
+            .map_err(|_| "BIGQUERY_DATASET is required with BIGQUERY_PROJECT_ID")?;
+        let table = env::var("BIGQUERY_TABLE").unwrap_or_else(|_| "measurements".to_string());
+        let timeout_seconds: u64 = env::var("BIGQUERY_TIMEOUT_SECONDS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()?;
+
+        Ok(Some(Self::new(
+            BIGQUERY_URL.to_string(),
+            project_id,
+            dataset,
+            table,
+            Duration::from_secs(timeout_seconds),
+        )?))
+    }
+
+    fn tables_url(&self) -> String {
+        format!(
+            "{}/bigquery/v2/projects/{}/datasets/{}/tables",
+            self.base_url, self.project_id, self.dataset
+        )
+    }
+}
+
+/// Hands out OAuth access tokens for the BigQuery api.
+#[async_trait(?Send)]
+pub trait TokenProvider {
+    async fn token(&self) -> Result<String, Box<dyn Error>>;
+}
+
+/// Takes the service account from `GOOGLE_APPLICATION_CREDENTIALS`, or the metadata server when
+/// running in Google Cloud.
+#[async_trait(?Send)]
+impl TokenProvider for gcp_auth::AuthenticationManager {
+    async fn token(&self) -> Result<String, Box<dyn Error>> {
+        Ok(self
+            .get_token(&[BIGQUERY_SCOPE])
+            .await?
+            .as_str()
+            .to_string())
+    }
+}
+
+/// Streams the samples of every measurement as rows into a BigQuery table, which gets created
+/// with the schema of the rows on the first publish if it doesn't exist yet.
+pub struct BigQueryPublisher<T: TokenProvider> {
+    config: BigQueryPublisherConfig,
+    token_provider: T,
+    client: reqwest::Client,
+    table_exists: Cell<bool>,
+}
+
+impl BigQueryPublisher<gcp_auth::AuthenticationManager> {
+    pub async fn from_service_account(
+        config: BigQueryPublisherConfig,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::new(config, gcp_auth::AuthenticationManager::new().await?)
+    }
+}
+
+impl<T: TokenProvider> BigQueryPublisher<T> {
+    pub fn new(config: BigQueryPublisherConfig, token_provider: T) -> Result<Self, Box<dyn Error>> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .connect_timeout(config.timeout)
+            .build()?;
+
+        Ok(Self {
+            config,
+            token_provider,
+            client,
+            table_exists: Cell::new(false),
+        })
+    }
+
+    async fn ensure_table(&self, token: &str) -> Result<(), Box<dyn Error>> {
+        if self.table_exists.get() {
+            return Ok(());
+        }
+
+        let table_url = format!("{}/{}", self.config.tables_url(), self.config.table);
+        let response = self
+            .client
+            .get(&table_url)
+            .bearer_auth(token)
+            .send()
+            .await?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            info!(
+                "Creating BigQuery table {}.{}.{}",
+                self.config.project_id, self.config.dataset, self.config.table
+            );
+            self.client
+                .post(self.config.tables_url())
+                .bearer_auth(token)
+                .json(&table_resource(&self.config))
+                .send()
+                .await?
+                .error_for_status()?;
+        } else {
+            response.error_for_status()?;
+        }
+
+        self.table_exists.set(true);
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl<T: TokenProvider> MeasurementPublisher for BigQueryPublisher<T> {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        if measurement.samples.is_empty() {
+            return Ok(());
+        }
+
+        let token = self.token_provider.token().await?;
+        self.ensure_table(&token).await?;
+
+        let response: serde_json::Value = self
+            .client
+            .post(format!(
+                "{}/{}/insertAll",
+                self.config.tables_url(),
+                self.config.table
+            ))
+            .bearer_auth(&token)
+            .json(&insert_all_request(measurement))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // rejected rows don't fail the request, they're listed in the response instead
+        match response.get("insertErrors") {
+            Some(errors) => Err(format!("BigQuery rejected rows: {}", errors).into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    measurement_id: &'a str,
+    location: &'a str,
+    source: &'a str,
+    entity_type: String,
+    entity_name: &'a str,
+    sample_type: String,
+    sample_name: &'a str,
+    metric_type: String,
+    value: f64,
+    measured_at: String,
+}
+
+fn row<'a>(measurement: &'a Measurement, sample: &'a Sample) -> Row<'a> {
+    Row {
+        measurement_id: &measurement.id,
+        location: &measurement.location,
+        source: &measurement.source,
+        entity_type: format!("{:?}", sample.entity_type),
+        entity_name: &sample.entity_name,
+        sample_type: format!("{:?}", sample.sample_type),
+        sample_name: &sample.sample_name,
+        metric_type: format!("{:?}", sample.metric_type),
+        value: sample.value,
+        measured_at: measurement.measured_at_time.to_rfc3339(),
+    }
+}
+
+/// Body of a streaming insert with a row per sample; the insert ids let BigQuery drop rows that
+/// get inserted twice when a request is retried.
+fn insert_all_request(measurement: &Measurement) -> serde_json::Value {
+    let rows: Vec<serde_json::Value> = measurement
+        .samples
+        .iter()
+        .enumerate()
+        .map(|(i, sample)| {
+            json!({
+                "insertId": format!("{}-{}", measurement.id, i),
+                "json": row(measurement, sample),
+            })
+        })
+        .collect();
+
+    json!({ "rows": rows })
+}
+
+/// The columns of [`Row`], with the table partitioned by day of measurement.
+fn table_schema() -> serde_json::Value {
+    let field = |name: &str, field_type: &str| {
+        json!({
+            "name": name,
+            "type": field_type,
+            "mode": "REQUIRED",
+        })
+    };
+
+    json!({
+        "fields": [
+            field("measurement_id", "STRING"),
+            field("location", "STRING"),
+            field("source", "STRING"),
+            field("entity_type", "STRING"),
+            field("entity_name", "STRING"),
+            field("sample_type", "STRING"),
+            field("sample_name", "STRING"),
+            field("metric_type", "STRING"),
+            field("value", "FLOAT64"),
+            field("measured_at", "TIMESTAMP"),
+        ]
+    })
+}
+
+fn table_resource(config: &BigQueryPublisherConfig) -> serde_json::Value {
+    json!({
+        "tableReference": {
+            "projectId": config.project_id,
+            "datasetId": config.dataset,
+            "tableId": config.table,
+        },
+        "schema": table_schema(),
+        "timePartitioning": { "type": "DAY", "field": "measured_at" },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeDeviceServer, FakeResponse};
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, MetricType, SampleType};
+
+    const TABLE_PATH: &str = "/bigquery/v2/projects/jarvis/datasets/home/tables/measurements";
+    const TABLES_PATH: &str = "/bigquery/v2/projects/jarvis/datasets/home/tables";
+    const INSERT_ALL_PATH: &str =
+        "/bigquery/v2/projects/jarvis/datasets/home/tables/measurements/insertAll";
+
+    struct StaticToken;
+
+    #[async_trait(?Send)]
+    impl TokenProvider for StaticToken {
+        async fn token(&self) -> Result<String, Box<dyn Error>> {
+            Ok("secret".to_string())
+        }
+    }
+
+    fn measurement() -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![
+                Sample {
+                    entity_type: EntityType::Tariff,
+                    entity_name: "HWE-P1".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "t1 import".into(),
+                    metric_type: MetricType::Counter,
+                    value: 38989839600.0,
+                },
+                Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-WTR".into(),
+                    sample_type: SampleType::WaterConsumption,
+                    sample_name: "Watermeter".into(),
+                    metric_type: MetricType::Gauge,
+                    value: 0.45,
+                },
+            ],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn publisher(server: &FakeDeviceServer) -> BigQueryPublisher<StaticToken> {
+        publisher_with_timeout(server, Duration::from_secs(5))
+    }
+
+    fn publisher_with_timeout(
+        server: &FakeDeviceServer,
+        timeout: Duration,
+    ) -> BigQueryPublisher<StaticToken> {
+        BigQueryPublisher::new(
+            BigQueryPublisherConfig::new(
+                server.base_url(),
+                "jarvis".into(),
+                "home".into(),
+                "measurements".into(),
+                timeout,
+            )
+            .unwrap(),
+            StaticToken,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn insert_all_request_has_a_row_per_sample() {
+        // act
+        let request = insert_all_request(&measurement());
+
+        assert_eq!(
+            request,
+            json!({
+                "rows": [
+                    {
+                        "insertId": "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10-0",
+                        "json": {
+                            "measurement_id": "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10",
+                            "location": "My Home",
+                            "source": "jarvis-homewizard-exporter",
+                            "entity_type": "Tariff",
+                            "entity_name": "HWE-P1",
+                            "sample_type": "ElectricityConsumption",
+                            "sample_name": "t1 import",
+                            "metric_type": "Counter",
+                            "value": 38989839600.0,
+                            "measured_at": "2023-06-01T12:00:00+00:00",
+                        },
+                    },
+                    {
+                        "insertId": "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10-1",
+                        "json": {
+                            "measurement_id": "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10",
+                            "location": "My Home",
+                            "source": "jarvis-homewizard-exporter",
+                            "entity_type": "Device",
+                            "entity_name": "HWE-WTR",
+                            "sample_type": "WaterConsumption",
+                            "sample_name": "Watermeter",
+                            "metric_type": "Gauge",
+                            "value": 0.45,
+                            "measured_at": "2023-06-01T12:00:00+00:00",
+                        },
+                    },
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn table_schema_has_a_column_for_every_row_field() {
+        let row = serde_json::to_value(row(&measurement(), &measurement().samples[0])).unwrap();
+
+        // act
+        let schema = table_schema();
+
+        let columns: Vec<&str> = schema["fields"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|field| field["name"].as_str().unwrap())
+            .collect();
+        let row_fields: Vec<&str> = row
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(|k| k.as_str())
+            .collect();
+        let mut sorted_columns = columns.clone();
+        sorted_columns.sort_unstable();
+        assert_eq!(sorted_columns, row_fields);
+        assert_eq!(schema["fields"][8]["type"], "FLOAT64");
+        assert_eq!(schema["fields"][9]["type"], "TIMESTAMP");
+    }
+
+    #[tokio::test]
+    async fn publish_creates_missing_table_once_and_inserts_rows() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(TABLES_PATH, FakeResponse::json("{}"))
+            .respond(
+                INSERT_ALL_PATH,
+                FakeResponse::json(r#"{"kind":"bigquery#tableDataInsertAllResponse"}"#),
+            );
+        let publisher = publisher(&server);
+
+        // act
+        publisher.publish(&measurement()).await.unwrap();
+        publisher.publish(&measurement()).await.unwrap();
+
+        assert_eq!(server.request_count(TABLE_PATH), 1);
+        assert_eq!(server.request_count(TABLES_PATH), 1);
+        assert_eq!(server.request_count(INSERT_ALL_PATH), 2);
+        let create = server
+            .requests()
+            .into_iter()
+            .find(|request| request.path == TABLES_PATH)
+            .unwrap();
+        let table: serde_json::Value = serde_json::from_str(&create.body).unwrap();
+        assert_eq!(table["schema"], table_schema());
+        assert_eq!(table["tableReference"]["tableId"], "measurements");
+        assert_eq!(create.header("Authorization"), Some("Bearer secret"));
+    }
+
+    #[tokio::test]
+    async fn publish_fails_on_rejected_rows() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(TABLE_PATH, FakeResponse::json("{}"))
+            .respond(
+                INSERT_ALL_PATH,
+                FakeResponse::json(
+                    r#"{"insertErrors":[{"index":0,"errors":[{"reason":"invalid"}]}]}"#,
+                ),
+            );
+
+        // act
+        let result = publisher(&server).publish(&measurement()).await;
+
+        assert!(result.is_err());
+        assert_eq!(server.request_count(TABLES_PATH), 0);
+    }
+
+    #[tokio::test]
+    async fn publish_fails_when_bigquery_doesnt_respond_within_timeout() {
+        let server = FakeDeviceServer::start();
+        server.respond(
+            TABLE_PATH,
+            FakeResponse::json("{}").delayed(Duration::from_secs(2)),
+        );
+        let started = std::time::Instant::now();
+
+        // act
+        let result = publisher_with_timeout(&server, Duration::from_millis(200))
+            .publish(&measurement())
+            .await;
+
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(server.request_count(INSERT_ALL_PATH), 0);
+    }
+}
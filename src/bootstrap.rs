@@ -0,0 +1,227 @@
+use crate::error::{ConfigError, DeviceError};
+use crate::homewizard_client::DeviceInfoResponse;
+use crate::self_test::DeviceProbe;
+use serde_yaml::{Mapping, Value};
+use std::error::Error;
+use tracing::{info, warn};
+
+/// Location of a generated config without an existing one to take it from.
+const LOCATION_PLACEHOLDER: &str = "My Home";
+
+/// Optional settings appended as comments when the config doesn't have them yet, by key.
+const OPTIONAL_SETTINGS: [(&str, &str); 5] = [
+    ("onEmpty", "# onEmpty: skip # or error (default) or heartbeat\n"),
+    ("strict", "# strict: true # fail the cycle when a configured device fails\n"),
+    (
+        "allowedCidrs",
+        "# allowedCidrs: # only measure devices in these ranges\n# - 192.168.1.0/24\n",
+    ),
+    (
+        "deviceEvents",
+        "# deviceEvents: # publish an event when a device goes offline\n#   offlineThreshold: 3\n",
+    ),
+    (
+        "missingDevices",
+        "# missingDevices: # publish an event when a named device isn't found\n#   missingCycles: 3\n",
+    ),
+];
+
+/// Discovers the devices, requests the `/api` of each and renders a config naming every device
+/// by its product name and the end of its serial, for a new site to start from. The names,
+/// location and other settings of `existing` config contents are kept as they are, only devices
+/// without a name get one. Fails as a config error when `existing` can't be parsed.
+pub fn bootstrap(
+    devices: &dyn DeviceProbe,
+    existing: Option<&str>,
+) -> Result<String, Box<dyn Error>> {
+    let existing = match existing {
+        Some(existing) => parse_existing(existing).map_err(ConfigError::new)?,
+        None => Mapping::new(),
+    };
+
+    let mut infos = vec![];
+    for device in devices.discover_devices().map_err(DeviceError::new)? {
+        match devices.get_device_info(&device) {
+            Ok(device_info) => infos.push(device_info),
+            Err(e) => warn!(
+                "Leaving device {} out of the config, requesting its info failed: {}",
+                device.fullname, e
+            ),
+        }
+    }
+    infos.sort_by(|a, b| a.serial.cmp(&b.serial));
+    info!("Generating config for {} devices", infos.len());
+
+    render(&infos, existing)
+}
+
+fn parse_existing(existing: &str) -> Result<Mapping, Box<dyn Error>> {
+    match serde_yaml::from_str(existing)? {
+        Value::Mapping(existing) => Ok(existing),
+        Value::Null => Ok(Mapping::new()),
+        _ => Err("The existing config has to be a mapping".into()),
+    }
+}
+
+fn render(infos: &[DeviceInfoResponse], mut existing: Mapping) -> Result<String, Box<dyn Error>> {
+    let key = |key: &str| Value::String(key.to_string());
+
+    let mut names = match existing.remove(&key("names")) {
+        Some(Value::Mapping(names)) => names,
+        Some(Value::Null) | None => Mapping::new(),
+        Some(_) => {
+            return Err(
+                ConfigError::new("names of the existing config has to be a mapping".into()).into(),
+            )
+        }
+    };
+    for device_info in infos {
+        let serial = key(&device_info.serial);
+        if !names.contains_key(&serial) {
+            names.insert(serial, key(&default_name(device_info)));
+        }
+    }
+
+    // location and names go first, where they're easiest to edit
+    let mut config = Mapping::new();
+    config.insert(
+        key("location"),
+        existing
+            .remove(&key("location"))
+            .unwrap_or_else(|| key(LOCATION_PLACEHOLDER)),
+    );
+    config.insert(key("names"), Value::Mapping(names));
+    config.extend(existing);
+
+    let mut rendered = format!(
+        "# Generated by jarvis-homewizard-exporter bootstrap from {} devices, edit before use\n",
+        infos.len()
+    );
+    rendered.push_str(&serde_yaml::to_string(&config)?);
+    for (setting, commented) in OPTIONAL_SETTINGS.iter() {
+        if !config.contains_key(&key(setting)) {
+            rendered.push_str(commented);
+        }
+    }
+
+    Ok(rendered)
+}
+
+/// Product name followed by the last four characters of the serial, which tells devices of the
+/// same type apart until they get a proper name.
+fn default_name(device_info: &DeviceInfoResponse) -> String {
+    let suffix_start = device_info.serial.len().saturating_sub(4);
+
+    format!(
+        "{} {}",
+        device_info.product_name,
+        device_info.serial.get(suffix_start..).unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard_client::HomewizardDevice;
+    use crate::model::Config;
+    use std::collections::HashSet;
+
+    struct MockDevices {
+        infos: Vec<DeviceInfoResponse>,
+    }
+
+    impl DeviceProbe for MockDevices {
+        fn discover_devices(&self) -> Result<Vec<HomewizardDevice>, Box<dyn Error + Send + Sync>> {
+            Ok((0..=self.infos.len())
+                .map(|n| HomewizardDevice {
+                    fullname: n.to_string(),
+                    ip_addresses: HashSet::new(),
+                    port: 80,
+                    hostname: None,
+                    api_path: None,
+                })
+                .collect())
+        }
+
+        fn get_device_info(
+            &self,
+            device: &HomewizardDevice,
+        ) -> Result<DeviceInfoResponse, Box<dyn Error + Send + Sync>> {
+            // the last discovered device never answers
+            let n: usize = device.fullname.parse()?;
+            self.infos
+                .get(n)
+                .cloned()
+                .ok_or_else(|| "connection timed out".into())
+        }
+    }
+
+    fn device_info(product_type: &str, product_name: &str, serial: &str) -> DeviceInfoResponse {
+        DeviceInfoResponse {
+            product_type: product_type.into(),
+            product_name: product_name.into(),
+            serial: serial.into(),
+            firmware_version: "3.02".into(),
+            api_version: "v1".into(),
+        }
+    }
+
+    fn devices() -> MockDevices {
+        MockDevices {
+            infos: vec![
+                device_info("HWE-WTR", "Watermeter", "3c39e72d7a68"),
+                device_info("HWE-SKT", "Energy Socket", "3c39e72e33ce"),
+                device_info("HWE-P1", "P1 meter", "5c2faf0a8b3e"),
+            ],
+        }
+    }
+
+    #[test]
+    fn bootstrap_names_every_answering_device() {
+        // act
+        let rendered = bootstrap(&devices(), None).unwrap();
+
+        let config: Config = serde_yaml::from_str(&rendered).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.location, "My Home");
+        assert_eq!(config.names.len(), 3);
+        assert_eq!(config.names["3c39e72e33ce"], "Energy Socket 33ce");
+        assert_eq!(config.names["5c2faf0a8b3e"], "P1 meter 8b3e");
+        assert!(rendered
+            .starts_with("# Generated by jarvis-homewizard-exporter bootstrap from 3 devices"));
+        assert!(rendered.contains("# strict: true"));
+    }
+
+    #[test]
+    fn bootstrap_keeps_existing_names_and_settings() {
+        let existing = "location: Holiday Home\nnames:\n  3c39e72e33ce: Bonenmaler\nstrict: true\n";
+
+        // act
+        let rendered = bootstrap(&devices(), Some(existing)).unwrap();
+
+        let config: Config = serde_yaml::from_str(&rendered).unwrap();
+        assert_eq!(config.location, "Holiday Home");
+        assert_eq!(config.names["3c39e72e33ce"], "Bonenmaler");
+        assert_eq!(config.names["3c39e72d7a68"], "Watermeter 7a68");
+        assert!(config.strict);
+        assert!(!rendered.contains("# strict: true"));
+    }
+
+    #[test]
+    fn bootstrap_fails_as_config_error_for_invalid_existing_config() {
+        // act
+        let result = bootstrap(&devices(), Some("- location: My Home\n"));
+
+        assert!(ConfigError::is(result.unwrap_err().as_ref()));
+    }
+
+    #[test]
+    fn bootstrap_round_trips_its_own_output() {
+        let rendered = bootstrap(&devices(), None).unwrap();
+
+        // act
+        let rerendered = bootstrap(&devices(), Some(&rendered)).unwrap();
+
+        assert_eq!(rerendered, rendered);
+    }
+}
@@ -0,0 +1,47 @@
+use jarvis_lib::model::{MetricType, Sample};
+
+/// Combines the samples of several quick reads of a device into one set: gauges take the mean of
+/// the reads and counters the last read. With `min_max` the device's own gauge also gets a `min`
+/// and `max` gauge, appended after the other samples.
+pub fn aggregate(reads: &[Vec<Sample>], friendly_name: &str, min_max: bool) -> Vec<Sample> {
+    let last = match reads.last() {
+        Some(last) => last,
+        None => return vec![],
+    };
+    // a read of another shape, like a P1 meter whose gas reading came or went, can't be lined up
+    let reads: Vec<&Vec<Sample>> = reads
+        .iter()
+        .filter(|read| read.len() == last.len())
+        .collect();
+
+    let mut samples = Vec::with_capacity(last.len());
+    let mut extremes = vec![];
+    for (i, sample) in last.iter().enumerate() {
+        if !matches!(sample.metric_type, MetricType::Gauge) {
+            samples.push(sample.clone());
+            continue;
+        }
+
+        let values: Vec<f64> = reads.iter().map(|read| read[i].value).collect();
+        samples.push(Sample {
+            value: values.iter().sum::<f64>() / values.len() as f64,
+            ..sample.clone()
+        });
+
+        if min_max && sample.sample_name == friendly_name {
+            extremes.push(Sample {
+                sample_name: format!("{} min", friendly_name),
+                value: values.iter().cloned().fold(f64::INFINITY, f64::min),
+                ..sample.clone()
+            });
+            extremes.push(Sample {
+                sample_name: format!("{} max", friendly_name),
+                value: values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+                ..sample.clone()
+            });
+        }
+    }
+    samples.extend(extremes);
+
+    samples
+}
@@ -0,0 +1,121 @@
+use jarvis_lib::model::{MetricType, Sample};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+struct CarriedCounters {
+    samples: Vec<Sample>,
+    /// Consecutive cycles the counters got carried forward since the device last succeeded.
+    cycles: u32,
+}
+
+/// Keeps the counters of every device from its last successful cycle, to carry them forward
+/// through the cycles the device fails. Gauges are never kept: a power reading of minutes ago
+/// says nothing about now, while a counter can't have gone down. The counters only live as long
+/// as the process.
+#[derive(Default)]
+pub struct CarryForward {
+    devices: Mutex<HashMap<String, CarriedCounters>>,
+}
+
+impl CarryForward {
+    /// Keeps the counters among the samples of a device that succeeded.
+    pub fn remember(&self, serial: &str, samples: &[Sample]) {
+        let samples = samples
+            .iter()
+            .filter(|sample| matches!(sample.metric_type, MetricType::Counter))
+            .cloned()
+            .collect();
+        self.devices
+            .lock()
+            .unwrap()
+            .insert(serial.to_string(), CarriedCounters { samples, cycles: 0 });
+    }
+
+    /// The kept counters of a device that failed, with the number of consecutive cycles they've
+    /// been carried forward this one included; nothing once they've been carried forward for
+    /// `max_cycles` cycles.
+    pub fn carry(&self, serial: &str, max_cycles: u32) -> Option<(Vec<Sample>, u32)> {
+        let mut devices = self.devices.lock().unwrap();
+        let carried = devices.get_mut(serial)?;
+        if carried.cycles >= max_cycles || carried.samples.is_empty() {
+            return None;
+        }
+
+        carried.cycles += 1;
+        Some((carried.samples.clone(), carried.cycles))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jarvis_lib::model::{EntityType, SampleType};
+
+    fn samples() -> Vec<Sample> {
+        vec![
+            Sample {
+                entity_type: EntityType::Device,
+                entity_name: "HWE-WTR".into(),
+                sample_type: SampleType::WaterConsumption,
+                sample_name: "Watermeter".into(),
+                metric_type: MetricType::Counter,
+                value: 123.456,
+            },
+            Sample {
+                entity_type: EntityType::Device,
+                entity_name: "HWE-WTR".into(),
+                sample_type: SampleType::WaterConsumption,
+                sample_name: "Watermeter".into(),
+                metric_type: MetricType::Gauge,
+                value: 7.5,
+            },
+        ]
+    }
+
+    #[test]
+    fn carry_returns_counters_within_window() {
+        let carry_forward = CarryForward::default();
+        carry_forward.remember("3c39e72d7a68", &samples());
+
+        // act
+        let first = carry_forward.carry("3c39e72d7a68", 2);
+        let second = carry_forward.carry("3c39e72d7a68", 2);
+
+        let (first_samples, first_cycles) = first.unwrap();
+        assert_eq!(first_samples.len(), 1);
+        assert_eq!(first_samples[0].value, 123.456);
+        assert_eq!(first_cycles, 1);
+        assert_eq!(second.unwrap().1, 2);
+    }
+
+    #[test]
+    fn carry_stops_after_window_until_device_succeeds_again() {
+        let carry_forward = CarryForward::default();
+        carry_forward.remember("3c39e72d7a68", &samples());
+        carry_forward.carry("3c39e72d7a68", 1);
+
+        // act
+        let expired = carry_forward.carry("3c39e72d7a68", 1);
+        carry_forward.remember("3c39e72d7a68", &samples());
+        let renewed = carry_forward.carry("3c39e72d7a68", 1);
+
+        assert!(expired.is_none());
+        assert_eq!(renewed.unwrap().1, 1);
+        assert!(carry_forward.carry("aabbccddeeff", 1).is_none());
+    }
+
+    #[test]
+    fn carry_never_returns_gauges() {
+        let carry_forward = CarryForward::default();
+        let gauges: Vec<Sample> = samples()
+            .into_iter()
+            .filter(|sample| matches!(sample.metric_type, MetricType::Gauge))
+            .collect();
+        carry_forward.remember("3c39e72d7a68", &gauges);
+
+        // act
+        let carried = carry_forward.carry("3c39e72d7a68", 3);
+
+        assert!(carried.is_none());
+    }
+}
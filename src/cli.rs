@@ -0,0 +1,294 @@
+use clap::{Parser, Subcommand};
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::path::PathBuf;
+
+const ENVIRONMENT_HELP: &str = "\
+Environment variables:
+  CONFIG_PATH                       Path of the yaml config file, or comma separated files merged in order [default: /configs/config.yaml]
+  CONFIG_LOCATION                   Location of the measurements, enough to run without a config file
+  CONFIG_NAMES                      Device names as serial=name pairs, added to the ones from the config file
+  CONFIG_ON_EMPTY                   Behavior of a cycle without samples, overriding onEmpty of the config file
+  TIMEOUT_SECONDS                   Duration of mdns discovery [default: 10]
+  CYCLE_DEADLINE_SECONDS            Finish a cycle with the samples so far after this many seconds
+  INTERVAL_SECONDS                  Keep running and measure on this interval instead of once
+  STARTUP_JITTER_SECONDS            Delay the first cycle by a random duration up to this many seconds [default: 0]
+  CYCLE_JITTER_SECONDS              Add a random duration up to this many seconds to every interval [default: 0]
+  RESTART_BACKOFF_SECONDS           Initial backoff before restarting a failed exporter [default: 1]
+  RESTART_MAX_BACKOFF_SECONDS       Maximum backoff before restarting a failed exporter [default: 300]
+  DRY_RUN                           Print the measurement instead of publishing it [default: false]
+  OUTPUT                            Publish measurements to nats, as json lines to stdout or to mqtt [default: nats]
+  NATS_HOST                         NATS server for measurements and events [default: jarvis-nats]
+  NATS_SUBJECT                      NATS subject for measurements [default: jarvis-measurements]
+  NATS_SUBJECT_TEMPLATE             NATS subject per location or device, with {location}, {source}, {serial} and {name}
+  MQTT_BROKER_URL                   Also publish every sample to this mqtt:// broker
+  MQTT_USERNAME, MQTT_PASSWORD      Credentials for the MQTT broker
+  MQTT_BASE_TOPIC                   First level of the MQTT topics [default: homewizard]
+  MQTT_QOS                          Quality of service of MQTT messages [default: 1]
+  MQTT_HOME_ASSISTANT_PREFIX        Publish Home Assistant discovery config under this prefix
+  REMOTE_WRITE_URL                  Also push the sample metrics to this Prometheus remote write endpoint
+  REMOTE_WRITE_USERNAME, REMOTE_WRITE_PASSWORD
+                                    Basic auth credentials for the remote write endpoint
+  TEXTFILE_DIRECTORY                Also write the samples of the last cycle to homewizard.prom in this directory
+  WEBHOOK_URL                       Also post every measurement as json to this url
+  WEBHOOK_BEARER_TOKEN              Bearer token sent to the webhook
+  WEBHOOK_HEADERS                   Extra headers sent to the webhook, as name=value pairs separated by commas
+  WEBHOOK_TIMEOUT_SECONDS           Timeout of a webhook request [default: 10]
+  WEBHOOK_MAX_ATTEMPTS              Attempts to post a measurement when the webhook fails [default: 3]
+  WEBHOOK_INSECURE_SKIP_VERIFY      Accept any tls certificate of the webhook [default: false]
+  INFLUXDB_URL                      Also write every measurement to this InfluxDB v2 server
+  INFLUXDB_ORG, INFLUXDB_BUCKET     Organization and bucket to write to, required with INFLUXDB_URL
+  INFLUXDB_TOKEN                    API token with write access to the bucket, required with INFLUXDB_URL
+  INFLUXDB_MAX_ATTEMPTS             Attempts to write a measurement when InfluxDB fails [default: 3]
+  BIGQUERY_PROJECT_ID               Also stream every sample into BigQuery in this project
+  BIGQUERY_DATASET                  Dataset of the BigQuery table, required with BIGQUERY_PROJECT_ID
+  BIGQUERY_TABLE                    BigQuery table, created when missing [default: measurements]
+  BIGQUERY_TIMEOUT_SECONDS          Timeout of a BigQuery request [default: 10]
+  GOOGLE_APPLICATION_CREDENTIALS    Service account key file used for BigQuery
+  METRICS_PORT                      Serve the samples of the last cycle as Prometheus metrics on this port
+  SELF_METRICS                      Also serve the number of devices discovered and read when true
+  MDNS_ANNOUNCE                     Announce the exporter as _jarvis-exporter._tcp.local. over mdns when true
+  MDNS_ANNOUNCE_ADDRESS             Ipv4 address to announce the exporter at [default: the multicast interface]
+  MEASUREMENT_FILE_CONFIG_MAP_NAME  Config map storing the last measurements
+  EXPORTER_STATE_FILE_PATH          File persisting device health between runs
+  SKIP_SELF_TEST                    Start without checking devices, NATS and state first when true
+  SPOOL_DIRECTORY                   Directory keeping measurements that failed to publish for a later attempt
+  SPOOL_MAX_ENTRIES                 Number of spooled measurements kept at most [default: 1000]
+  SPOOL_MAX_AGE_HOURS               Drop spooled measurements older than this number of hours
+  DEVICE_URL_TEMPLATE               Base url of device endpoints, with {ip}, {port} and {name} filled in
+  DEVICE_CA_FILE                    Pem file of a CA to trust for https device urls
+  DEVICE_TLS_INSECURE               Skip verifying certificates of https device urls when true
+  DEVICE_PROXY                      Request devices through the HTTP_PROXY/HTTPS_PROXY proxy when true
+  REPLAY_DIRECTORY                  Measure the devices in this directory of fixture responses
+  OTEL_EXPORTER_OTLP_ENDPOINT       Export traces and sample metrics over OTLP to this endpoint
+  RUST_LOG                          Log filter, for example info or debug";
+
+/// Discovers HomeWizard devices through mdns and publishes their readings as jarvis measurements.
+#[derive(Parser, Debug)]
+#[command(version, after_help = ENVIRONMENT_HELP)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Measure once, or every INTERVAL_SECONDS, and publish to NATS (default)
+    Run,
+    /// Discover devices on the network, print them and exit
+    Discover,
+    /// Perform exactly one measurement and exit, ignoring INTERVAL_SECONDS
+    Measure {
+        /// Print the measurement as json instead of publishing it, like DRY_RUN=true
+        #[arg(long)]
+        dry_run: bool,
+        /// Skip discovery and measure only the device at this ip address (optionally with port)
+        #[arg(long, value_parser = parse_device_address)]
+        ip: Option<SocketAddrV4>,
+        /// Abort unless the device at --ip has this serial
+        #[arg(long, requires = "ip")]
+        serial: Option<String>,
+    },
+    /// Discover devices and print a config naming each of them, to edit for a new site
+    Bootstrap {
+        /// Existing config file to add the devices without a name to
+        #[arg(long)]
+        config: Option<PathBuf>,
+    },
+    /// Request the data endpoint of every device a number of times and print its latencies, without publishing
+    Bench {
+        /// Number of requests per device
+        #[arg(long, default_value_t = 20)]
+        iterations: usize,
+        /// Delay between the requests to a device, to spare battery powered devices
+        #[arg(long, default_value_t = 1000)]
+        delay_ms: u64,
+        /// Skip discovery and bench only the device at this ip address (optionally with port)
+        #[arg(long, value_parser = parse_device_address)]
+        ip: Option<SocketAddrV4>,
+        /// Print the latencies as json instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Read and validate the config file, then exit
+    ValidateConfig,
+    /// Print the JSON schema of the config file and exit
+    Schema,
+    /// Dump the info and data responses of one device, without publishing anything
+    Diagnose {
+        /// Ip address (optionally with port) or serial of the device
+        target: String,
+    },
+    /// Blink the led of the v2 device at an ip address, with the token of its entry in devices
+    Identify {
+        /// Ip address with port of the device, like 192.168.1.31:443
+        #[arg(value_parser = parse_device_address)]
+        address: SocketAddrV4,
+    },
+    /// Print the last measurements and exporter state kept between cycles as json, secrets redacted
+    InspectState,
+    /// Show the devices and their latest values in the terminal, measuring every INTERVAL_SECONDS without publishing
+    Tui,
+    /// Exit 0 when the last successful measurement is recent enough, for a container HEALTHCHECK
+    Healthcheck {
+        /// Maximum age of the last successful measurement
+        #[arg(long, default_value_t = 600)]
+        max_age_seconds: u64,
+    },
+}
+
+impl Cli {
+    /// The subcommand to execute; running without one keeps the behaviour from before there was
+    /// a command line.
+    pub fn subcommand(&self) -> Command {
+        self.command.clone().unwrap_or(Command::Run)
+    }
+}
+
+fn parse_device_address(value: &str) -> Result<SocketAddrV4, String> {
+    if let Ok(address) = value.parse::<SocketAddrV4>() {
+        return Ok(address);
+    }
+
+    value
+        .parse::<Ipv4Addr>()
+        .map(|ip_address| SocketAddrV4::new(ip_address, 80))
+        .map_err(|_| format!("{} is not an ipv4 address", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn bare_invocation_runs() {
+        let cli = Cli::try_parse_from(["jarvis-homewizard-exporter"]).unwrap();
+
+        assert_eq!(cli.subcommand(), Command::Run);
+    }
+
+    #[test]
+    fn parses_subcommands() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(
+                std::iter::once("jarvis-homewizard-exporter").chain(args.iter().copied()),
+            )
+            .unwrap()
+            .subcommand()
+        };
+
+        assert_eq!(parse(&["run"]), Command::Run);
+        assert_eq!(parse(&["discover"]), Command::Discover);
+        assert_eq!(
+            parse(&["measure"]),
+            Command::Measure {
+                dry_run: false,
+                ip: None,
+                serial: None
+            }
+        );
+        assert_eq!(
+            parse(&["measure", "--dry-run"]),
+            Command::Measure {
+                dry_run: true,
+                ip: None,
+                serial: None
+            }
+        );
+        assert_eq!(
+            parse(&[
+                "measure",
+                "--ip",
+                "192.168.1.20",
+                "--serial",
+                "3c39e72d7a68"
+            ]),
+            Command::Measure {
+                dry_run: false,
+                ip: Some("192.168.1.20:80".parse().unwrap()),
+                serial: Some("3c39e72d7a68".into())
+            }
+        );
+        assert_eq!(
+            parse(&["measure", "--ip", "127.0.0.1:8080"]),
+            Command::Measure {
+                dry_run: false,
+                ip: Some("127.0.0.1:8080".parse().unwrap()),
+                serial: None
+            }
+        );
+        assert_eq!(parse(&["bootstrap"]), Command::Bootstrap { config: None });
+        assert_eq!(
+            parse(&["bootstrap", "--config", "config.yaml"]),
+            Command::Bootstrap {
+                config: Some(PathBuf::from("config.yaml"))
+            }
+        );
+        assert_eq!(
+            parse(&[
+                "bench",
+                "--iterations",
+                "5",
+                "--ip",
+                "192.168.1.31",
+                "--json"
+            ]),
+            Command::Bench {
+                iterations: 5,
+                delay_ms: 1000,
+                ip: Some("192.168.1.31:80".parse().unwrap()),
+                json: true,
+            }
+        );
+        assert_eq!(parse(&["validate-config"]), Command::ValidateConfig);
+        assert_eq!(parse(&["schema"]), Command::Schema);
+        assert_eq!(parse(&["inspect-state"]), Command::InspectState);
+        assert_eq!(parse(&["tui"]), Command::Tui);
+        assert_eq!(
+            parse(&["healthcheck"]),
+            Command::Healthcheck {
+                max_age_seconds: 600
+            }
+        );
+        assert_eq!(
+            parse(&["healthcheck", "--max-age-seconds", "120"]),
+            Command::Healthcheck {
+                max_age_seconds: 120
+            }
+        );
+        assert_eq!(
+            parse(&["diagnose", "192.168.1.31"]),
+            Command::Diagnose {
+                target: "192.168.1.31".into()
+            }
+        );
+        assert_eq!(
+            parse(&["identify", "192.168.1.31:443"]),
+            Command::Identify {
+                address: "192.168.1.31:443".parse().unwrap()
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_subcommand() {
+        let result = Cli::try_parse_from(["jarvis-homewizard-exporter", "publish"]);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn help_describes_environment_variables() {
+        let help = Cli::command().render_long_help().to_string();
+
+        assert!(help.contains("validate-config"));
+        assert!(help.contains("TIMEOUT_SECONDS"));
+        assert!(help.contains("INTERVAL_SECONDS"));
+        assert!(help.contains("DRY_RUN"));
+    }
+
+    #[test]
+    fn cli_definition_is_consistent() {
+        Cli::command().debug_assert();
+    }
+}
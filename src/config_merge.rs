@@ -0,0 +1,273 @@
+use crate::model::Config;
+use jarvis_lib::config_client::SetDefaults;
+use serde_yaml::{Mapping, Value};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Fields identifying an item in a list of mappings, tried in this order; items of a later file
+/// with the same value for it are merged into the earlier item rather than appended.
+const ITEM_KEYS: [&str; 3] = ["serial", "location", "name"];
+
+/// The paths in a comma separated list of config files.
+pub fn config_paths(config_path: &str) -> Vec<PathBuf> {
+    config_path
+        .split(',')
+        .map(str::trim)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Reads the config files in `paths` and merges them in order into one config. The first file
+/// failing to be read returns its io error as is, so a missing config file can be told apart;
+/// any later file failing is an error of its own.
+pub fn read_merged(paths: &[PathBuf]) -> Result<Config, Box<dyn Error>> {
+    let mut merged = Value::Null;
+    for (index, path) in paths.iter().enumerate() {
+        let value = match read(path) {
+            Ok(value) => value,
+            Err(e) if index == 0 => return Err(e),
+            Err(e) => {
+                return Err(format!(
+                    "Failed reading config override file {}: {}",
+                    path.display(),
+                    e
+                )
+                .into())
+            }
+        };
+        merged = merge(merged, value);
+    }
+    info!(
+        "Merged config files {}",
+        paths
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    );
+
+    let mut config: Config = serde_yaml::from_value(merged)?;
+    config.set_defaults();
+
+    Ok(config)
+}
+
+fn read(path: &Path) -> Result<Value, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+
+    // a key twice within one file fails parsing, like it does for a single config file
+    Ok(serde_yaml::from_str(&contents)?)
+}
+
+/// Merges `other` into `base`: mappings key by key, lists item by item, and anything else -
+/// scalars, or values of different kinds - replaced by `other`.
+fn merge(base: Value, other: Value) -> Value {
+    match (base, other) {
+        (Value::Mapping(mut base), Value::Mapping(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => {
+                        let earlier = std::mem::replace(existing, Value::Null);
+                        *existing = merge(earlier, value);
+                    }
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+            Value::Mapping(base)
+        }
+        (Value::Sequence(mut base), Value::Sequence(other)) => {
+            for item in other {
+                match base.iter().position(|existing| same_item(existing, &item)) {
+                    Some(position) => {
+                        let existing = std::mem::replace(&mut base[position], Value::Null);
+                        base[position] = merge(existing, item);
+                    }
+                    None => base.push(item),
+                }
+            }
+            Value::Sequence(base)
+        }
+        (_, other) => other,
+    }
+}
+
+/// Whether two list items are the same: equal scalars, or mappings with the same value for the
+/// first of [`ITEM_KEYS`] either has.
+fn same_item(existing: &Value, item: &Value) -> bool {
+    match (existing, item) {
+        (Value::Mapping(existing), Value::Mapping(item)) => {
+            match ITEM_KEYS.iter().find(|key| item_key(item, key).is_some()) {
+                Some(key) => item_key(existing, key) == item_key(item, key),
+                None => false,
+            }
+        }
+        (Value::Mapping(_), _) | (_, Value::Mapping(_)) => false,
+        (existing, item) => existing == item,
+    }
+}
+
+fn item_key<'a>(mapping: &'a Mapping, key: &str) -> Option<&'a Value> {
+    mapping.get(&Value::String(key.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use uuid::Uuid;
+
+    /// Writes `contents` to a temporary config file, removed when dropped.
+    struct TempConfig(PathBuf);
+
+    impl TempConfig {
+        fn new(contents: &str) -> Self {
+            let path = env::temp_dir().join(format!("config-{}.yaml", Uuid::new_v4()));
+            fs::write(&path, contents).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempConfig {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn read_merged_files(files: &[&TempConfig]) -> Result<Config, Box<dyn Error>> {
+        read_merged(
+            &files
+                .iter()
+                .map(|file| file.0.clone())
+                .collect::<Vec<PathBuf>>(),
+        )
+    }
+
+    #[test]
+    fn config_paths_splits_comma_separated_list() {
+        // act
+        let paths = config_paths("/configs/base.yaml, /configs/site.yaml,");
+
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from("/configs/base.yaml"),
+                PathBuf::from("/configs/site.yaml")
+            ]
+        );
+    }
+
+    #[test]
+    fn read_merged_overrides_scalars_with_later_file() {
+        let base = TempConfig::new(
+            "location: My Home\nonEmpty: skip\ndeviceEvents:\n  offlineThreshold: 2\n",
+        );
+        let site =
+            TempConfig::new("location: Holiday Home\ndeviceEvents:\n  offlineThreshold: 5\n");
+
+        // act
+        let config = read_merged_files(&[&base, &site]).unwrap();
+
+        assert_eq!(config.location, "Holiday Home");
+        assert_eq!(config.on_empty, crate::model::OnEmpty::Skip);
+        assert_eq!(config.device_events.unwrap().offline_threshold, 5);
+    }
+
+    #[test]
+    fn read_merged_merges_names_by_serial() {
+        let base = TempConfig::new(
+            "location: My Home\nnames:\n  3c39e72e33ce: Bonenmaler\n  5c2faf0a8b3e: P1 meter\n",
+        );
+        let site = TempConfig::new("names:\n  3c39e72e33ce: Koffie\n  3c39e72d7a68: Watermeter\n");
+
+        // act
+        let config = read_merged_files(&[&base, &site]).unwrap();
+
+        assert_eq!(config.names.len(), 3);
+        assert_eq!(config.names["3c39e72e33ce"], "Koffie");
+        assert_eq!(config.names["5c2faf0a8b3e"], "P1 meter");
+        assert_eq!(config.names["3c39e72d7a68"], "Watermeter");
+    }
+
+    #[test]
+    fn read_merged_merges_lists_by_key() {
+        let base = TempConfig::new(
+            "location: My Home\nlocations:\n- location: Garage\n  serials: [3c39e72e33ce]\n- location: Shed\n  serials: [5c2faf0a8b3e]\n",
+        );
+        let site = TempConfig::new(
+            "locations:\n- location: Garage\n  serials: [3c39e72e33ce, 3c39e72d7a68]\n- location: Attic\n  serials: [aabbccddeeff]\n",
+        );
+
+        // act
+        let config = read_merged_files(&[&base, &site]).unwrap();
+
+        let locations: Vec<(&str, Vec<&str>)> = config
+            .locations
+            .iter()
+            .map(|location| {
+                (
+                    location.location.as_str(),
+                    location.serials.iter().map(String::as_str).collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            locations,
+            vec![
+                ("Garage", vec!["3c39e72e33ce", "3c39e72d7a68"]),
+                ("Shed", vec!["5c2faf0a8b3e"]),
+                ("Attic", vec!["aabbccddeeff"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_matches_list_items_by_serial_first() {
+        let base: Value = serde_yaml::from_str(
+            "devices:\n- serial: 3c39e72e33ce\n  name: Bonenmaler\n- serial: 5c2faf0a8b3e\n  name: P1 meter\n",
+        )
+        .unwrap();
+        let site: Value =
+            serde_yaml::from_str("devices:\n- serial: 3c39e72e33ce\n  name: Koffie\n").unwrap();
+
+        // act
+        let merged = merge(base, site);
+
+        let expected: Value = serde_yaml::from_str(
+            "devices:\n- serial: 3c39e72e33ce\n  name: Koffie\n- serial: 5c2faf0a8b3e\n  name: P1 meter\n",
+        )
+        .unwrap();
+        assert_eq!(merged, expected);
+    }
+
+    #[test]
+    fn read_merged_fails_on_missing_override_file() {
+        let base = TempConfig::new("location: My Home\n");
+        let missing = env::temp_dir().join(format!("config-{}.yaml", Uuid::new_v4()));
+
+        // act
+        let result = read_merged(&[base.0.clone(), missing.clone()]);
+
+        let error = result.unwrap_err();
+        assert!(error.to_string().starts_with(&format!(
+            "Failed reading config override file {}",
+            missing.display()
+        )));
+        assert!(error.downcast_ref::<std::io::Error>().is_none());
+    }
+
+    #[test]
+    fn read_merged_keeps_duplicate_keys_within_a_file_an_error() {
+        let base = TempConfig::new("location: My Home\n");
+        let site = TempConfig::new("names:\n  3c39e72e33ce: Bonenmaler\n  3c39e72e33ce: Koffie\n");
+
+        // act
+        let result = read_merged_files(&[&base, &site]);
+
+        assert!(result.is_err());
+    }
+}
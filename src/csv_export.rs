@@ -0,0 +1,354 @@
+use crate::apparent_power::is_apparent_power;
+use crate::daily_consumption::is_daily_consumption;
+use crate::derived::{
+    is_cost, is_device_reachable, is_energy_delta, is_fetch_failures, is_water_liters,
+};
+use crate::model::CsvConfig;
+use crate::sample_cap::is_dropped_samples;
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use jarvis_lib::model::{EntityType, Measurement, MetricType, Sample, SampleType};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+
+const DATE_PLACEHOLDER: &str = "{date}";
+
+const HEADER: &str =
+    "timestamp,location,device,entity_type,sample_type,metric_type,sample_name,value,unit";
+
+/// Appends a row per sample of every published measurement to a csv file, a file per day when
+/// its path has a `{date}` placeholder, pruning files older than the retention on the first
+/// write and whenever the day changes.
+#[derive(Default)]
+pub struct CsvExport {
+    last_pruned: Mutex<Option<NaiveDate>>,
+}
+
+impl CsvExport {
+    pub fn export(
+        &self,
+        config: &CsvConfig,
+        measurements: &[Measurement],
+        now: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        let today = now.date_naive();
+        let path = file_path(&config.path, today);
+        if let Some(directory) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(directory)?;
+        }
+
+        let mut last_pruned = self.last_pruned.lock().unwrap();
+        if *last_pruned != Some(today) {
+            if let Some(retention_days) = config.retention_days {
+                prune(&config.path, today - Duration::days(retention_days.into()))?;
+            }
+            *last_pruned = Some(today);
+        }
+
+        let mut file = OpenOptions::new()
+            .read(true)
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let mut rows = String::new();
+        if file.metadata()?.len() == 0 {
+            rows.push_str(HEADER);
+            rows.push('\n');
+        } else if !ends_with_newline(&mut file)? {
+            // a row cut off by a crash mustn't swallow the first row of this cycle
+            rows.push('\n');
+        }
+        for measurement in measurements {
+            for sample in measurement.samples.iter() {
+                rows.push_str(&row(measurement, sample));
+                rows.push('\n');
+            }
+        }
+
+        // a single write, so a failing cycle leaves at most one partial row behind
+        file.write_all(rows.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+fn file_path(template: &str, date: NaiveDate) -> PathBuf {
+    PathBuf::from(template.replace(DATE_PLACEHOLDER, &date.format("%Y-%m-%d").to_string()))
+}
+
+fn ends_with_newline(file: &mut File) -> Result<bool, Box<dyn Error>> {
+    let mut last = [0u8; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+
+    Ok(last[0] == b'\n')
+}
+
+fn row(measurement: &Measurement, sample: &Sample) -> String {
+    [
+        measurement
+            .measured_at_time
+            .to_rfc3339_opts(SecondsFormat::Secs, true),
+        measurement.location.clone(),
+        sample.entity_name.clone(),
+        format!("{:?}", sample.entity_type),
+        format!("{:?}", sample.sample_type),
+        format!("{:?}", sample.metric_type),
+        sample.sample_name.clone(),
+        sample.value.to_string(),
+        unit(sample).to_string(),
+    ]
+    .iter()
+    .map(|field| escape(field))
+    .collect::<Vec<String>>()
+    .join(",")
+}
+
+/// Quotes a field holding a comma, quote or line break, doubling its quotes.
+fn escape(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Unit of the value of the sample, empty for counts, costs and anything else without one.
+fn unit(sample: &Sample) -> &'static str {
+    if is_cost(sample)
+        || is_fetch_failures(sample)
+        || is_dropped_samples(sample)
+        || is_device_reachable(sample)
+    {
+        return "";
+    }
+    if is_apparent_power(sample) {
+        return "VA";
+    }
+    if is_water_liters(sample) {
+        return "l";
+    }
+
+    match (&sample.sample_type, &sample.metric_type) {
+        // energy counters and what's derived from them are in joules
+        (
+            SampleType::ElectricityConsumption | SampleType::ElectricityProduction,
+            MetricType::Counter,
+        ) => "J",
+        (
+            SampleType::ElectricityConsumption | SampleType::ElectricityProduction,
+            MetricType::Gauge,
+        ) if is_energy_delta(sample) || is_daily_consumption(sample) => "J",
+        (
+            SampleType::ElectricityConsumption | SampleType::ElectricityProduction,
+            MetricType::Gauge,
+        ) => "W",
+        // the energy equivalent of the gas counter
+        (SampleType::GasConsumption, MetricType::Counter)
+            if matches!(sample.entity_type, EntityType::Tariff) =>
+        {
+            "J"
+        }
+        (SampleType::GasConsumption | SampleType::WaterConsumption, MetricType::Counter) => "m3",
+        (SampleType::GasConsumption | SampleType::WaterConsumption, MetricType::Gauge)
+            if is_daily_consumption(sample) =>
+        {
+            "m3"
+        }
+        (SampleType::GasConsumption | SampleType::WaterConsumption, MetricType::Gauge) => "m3/h",
+        _ => "",
+    }
+}
+
+/// Removes the files of the `template` for days before `oldest_kept`; other files are left
+/// alone. Only a template with the date in its file name has files per day.
+fn prune(template: &str, oldest_kept: NaiveDate) -> Result<(), Box<dyn Error>> {
+    let template = Path::new(template);
+    let (prefix, suffix) = match template
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split_once(DATE_PLACEHOLDER))
+    {
+        Some(affixes) => affixes,
+        None => return Ok(()),
+    };
+    let directory = match template.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        let date = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|name| name.strip_suffix(suffix))
+            .and_then(|date| NaiveDate::parse_from_str(date, "%Y-%m-%d").ok());
+
+        if let Some(date) = date {
+            if date < oldest_kept {
+                info!("Pruning exported csv {}", path.display());
+                fs::remove_file(&path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::env;
+    use uuid::Uuid;
+
+    fn csv_config(file_name: &str, retention_days: Option<u32>) -> (CsvConfig, PathBuf) {
+        let directory = env::temp_dir().join(format!("csv-{}", Uuid::new_v4()));
+        let config = CsvConfig {
+            path: directory.join(file_name).to_string_lossy().to_string(),
+            retention_days,
+        };
+
+        (config, directory)
+    }
+
+    fn measurement(sample_name: &str, value: f64) -> Measurement {
+        Measurement {
+            id: Uuid::new_v4().to_string(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![Sample {
+                entity_type: EntityType::Device,
+                entity_name: "HWE-SKT".into(),
+                sample_type: SampleType::ElectricityConsumption,
+                sample_name: sample_name.into(),
+                metric_type: MetricType::Gauge,
+                value,
+            }],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn read_lines(path: PathBuf) -> Vec<String> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn export_writes_header_once_and_a_row_per_sample() {
+        let (config, directory) = csv_config("consumption-{date}.csv", None);
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+
+        // act
+        CsvExport::default()
+            .export(&config, &[measurement("Bonenmaler", 1450.0)], now)
+            .unwrap();
+        // a restart starts with a fresh export appending to the same file
+        CsvExport::default()
+            .export(&config, &[measurement("Bonenmaler", 98.5)], now)
+            .unwrap();
+
+        assert_eq!(
+            read_lines(directory.join("consumption-2023-06-01.csv")),
+            vec![
+                HEADER,
+                "2023-06-01T12:00:00Z,My Home,HWE-SKT,Device,ElectricityConsumption,Gauge,Bonenmaler,1450,W",
+                "2023-06-01T12:00:00Z,My Home,HWE-SKT,Device,ElectricityConsumption,Gauge,Bonenmaler,98.5,W",
+            ]
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn export_quotes_names_with_commas_and_quotes() {
+        let (config, directory) = csv_config("consumption.csv", None);
+
+        // act
+        CsvExport::default()
+            .export(
+                &config,
+                &[measurement("Keuken, \"Koffie\"", 1450.0)],
+                Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+            )
+            .unwrap();
+
+        let lines = read_lines(directory.join("consumption.csv"));
+        assert!(lines[1].ends_with(",Gauge,\"Keuken, \"\"Koffie\"\"\",1450,W"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn export_continues_on_a_new_line_after_a_cut_off_row() {
+        let (config, directory) = csv_config("consumption.csv", None);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(
+            directory.join("consumption.csv"),
+            format!("{}\n2023-06-01T11:59:00Z,My Ho", HEADER),
+        )
+        .unwrap();
+
+        // act
+        CsvExport::default()
+            .export(
+                &config,
+                &[measurement("Bonenmaler", 1450.0)],
+                Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+            )
+            .unwrap();
+
+        let lines = read_lines(directory.join("consumption.csv"));
+        assert_eq!(lines.len(), 3);
+        assert!(lines[2].starts_with("2023-06-01T12:00:00Z,My Home,"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn export_rotates_files_by_day_and_prunes_older_than_retention() {
+        let (config, directory) = csv_config("consumption-{date}.csv", Some(7));
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("consumption-2023-05-01.csv"), "").unwrap();
+        fs::write(directory.join("consumption-2023-05-25.csv"), "").unwrap();
+        fs::write(directory.join("notes.csv"), "").unwrap();
+        let export = CsvExport::default();
+
+        // act
+        export
+            .export(
+                &config,
+                &[measurement("Bonenmaler", 1450.0)],
+                Utc.with_ymd_and_hms(2023, 6, 1, 23, 59, 0).unwrap(),
+            )
+            .unwrap();
+        export
+            .export(
+                &config,
+                &[measurement("Bonenmaler", 1450.0)],
+                Utc.with_ymd_and_hms(2023, 6, 2, 0, 1, 0).unwrap(),
+            )
+            .unwrap();
+
+        assert!(!directory.join("consumption-2023-05-01.csv").exists());
+        assert!(!directory.join("consumption-2023-05-25.csv").exists());
+        assert!(directory.join("notes.csv").exists());
+        assert_eq!(
+            read_lines(directory.join("consumption-2023-06-01.csv")).len(),
+            2
+        );
+        assert_eq!(
+            read_lines(directory.join("consumption-2023-06-02.csv")).len(),
+            2
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use crate::derived::{is_cost, is_water_liters};
+use chrono::NaiveDate;
+use jarvis_lib::model::{MetricType, Sample, SampleType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const DAILY_CONSUMPTION_SUFFIX: &str = " today";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct DailySnapshot {
+    date: NaiveDate,
+    /// Value of the counter when it was first read that day, or when it last got reset.
+    start: f64,
+    last: f64,
+    /// Consumption of the day up to the last reset of the counter.
+    before_reset: f64,
+}
+
+/// Remembers the value of every counter at the start of the day, so the consumption so far that
+/// day can be derived from its current value.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyConsumptionTracker {
+    snapshots: HashMap<String, DailySnapshot>,
+}
+
+impl DailyConsumptionTracker {
+    /// Records the value of a counter read on local `date` and returns its consumption so far that
+    /// day. The first reading of a day takes the snapshot, so a counter first read mid-day counts
+    /// from then; a counter that went down got reset, and counts on from its new value.
+    pub fn observe(&mut self, series: &str, value: f64, date: NaiveDate) -> f64 {
+        let snapshot = self
+            .snapshots
+            .entry(series.to_string())
+            .or_insert(DailySnapshot {
+                date,
+                start: value,
+                last: value,
+                before_reset: 0.0,
+            });
+        if snapshot.date != date {
+            *snapshot = DailySnapshot {
+                date,
+                start: value,
+                last: value,
+                before_reset: 0.0,
+            };
+        }
+        if value < snapshot.last {
+            snapshot.before_reset += snapshot.last - snapshot.start;
+            snapshot.start = value;
+        }
+        snapshot.last = value;
+
+        snapshot.before_reset + (value - snapshot.start)
+    }
+}
+
+/// The consumption so far on local `date` of every consumption counter among `samples`, as a
+/// gauge named after the counter followed by `today`.
+pub fn samples(
+    tracker: &mut DailyConsumptionTracker,
+    samples: &[Sample],
+    date: NaiveDate,
+) -> Vec<Sample> {
+    samples
+        .iter()
+        .filter(|sample| is_consumption_counter(sample))
+        .map(|sample| {
+            let series = format!(
+                "{}/{:?}/{}",
+                sample.entity_name, sample.sample_type, sample.sample_name
+            );
+            Sample {
+                sample_name: format!("{}{}", sample.sample_name, DAILY_CONSUMPTION_SUFFIX),
+                metric_type: MetricType::Gauge,
+                value: tracker.observe(&series, sample.value, date),
+                ..sample.clone()
+            }
+        })
+        .collect()
+}
+
+/// Counters of energy, gas and water read from devices; costs and the water total in liters are
+/// derived from those already.
+fn is_consumption_counter(sample: &Sample) -> bool {
+    matches!(sample.metric_type, MetricType::Counter)
+        && matches!(
+            sample.sample_type,
+            SampleType::ElectricityConsumption
+                | SampleType::ElectricityProduction
+                | SampleType::GasConsumption
+                | SampleType::WaterConsumption
+        )
+        && !is_cost(sample)
+        && !is_water_liters(sample)
+}
+
+/// Whether the sample is the consumption of a counter so far today rather than a gauge read from
+/// a device.
+pub fn is_daily_consumption(sample: &Sample) -> bool {
+    matches!(sample.metric_type, MetricType::Gauge)
+        && matches!(
+            sample.sample_type,
+            SampleType::ElectricityConsumption
+                | SampleType::ElectricityProduction
+                | SampleType::GasConsumption
+                | SampleType::WaterConsumption
+        )
+        && sample.sample_name.ends_with(DAILY_CONSUMPTION_SUFFIX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jarvis_lib::model::EntityType;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2023, 6, day).unwrap()
+    }
+
+    #[test]
+    fn observe_counts_from_first_reading_of_the_day() {
+        let mut tracker = DailyConsumptionTracker::default();
+
+        // act
+        let first = tracker.observe("water", 123.0, date(1));
+        let later = tracker.observe("water", 123.5, date(1));
+
+        assert_eq!(first, 0.0);
+        assert_eq!(later, 0.5);
+    }
+
+    #[test]
+    fn observe_starts_over_on_the_next_day() {
+        let mut tracker = DailyConsumptionTracker::default();
+        tracker.observe("water", 123.0, date(1));
+        tracker.observe("water", 124.0, date(1));
+
+        // act
+        let next_day = tracker.observe("water", 124.25, date(2));
+        let later = tracker.observe("water", 124.75, date(2));
+
+        assert_eq!(next_day, 0.0);
+        assert_eq!(later, 0.5);
+    }
+
+    #[test]
+    fn observe_keeps_consumption_before_a_reset() {
+        let mut tracker = DailyConsumptionTracker::default();
+        tracker.observe("water", 123.0, date(1));
+        tracker.observe("water", 124.0, date(1));
+
+        // act
+        let reset = tracker.observe("water", 0.0, date(1));
+        let after_reset = tracker.observe("water", 0.5, date(1));
+
+        assert_eq!(reset, 1.0);
+        assert_eq!(after_reset, 1.5);
+    }
+
+    #[test]
+    fn samples_derive_gauges_of_consumption_counters_only() {
+        let mut tracker = DailyConsumptionTracker::default();
+        let sample = |sample_name: &str, metric_type: MetricType, value: f64| Sample {
+            entity_type: EntityType::Device,
+            entity_name: "HWE-WTR".into(),
+            sample_type: SampleType::WaterConsumption,
+            sample_name: sample_name.into(),
+            metric_type,
+            value,
+        };
+        samples(
+            &mut tracker,
+            &[sample("Watermeter", MetricType::Counter, 123.0)],
+            date(1),
+        );
+
+        // act
+        let daily = samples(
+            &mut tracker,
+            &[
+                sample("Watermeter", MetricType::Counter, 123.5),
+                sample("Watermeter", MetricType::Gauge, 7.5),
+            ],
+            date(1),
+        );
+
+        assert_eq!(daily.len(), 1);
+        assert_eq!(daily[0].sample_name, "Watermeter today");
+        assert_eq!(daily[0].metric_type, MetricType::Gauge);
+        assert_eq!(daily[0].value, 0.5);
+        assert!(is_daily_consumption(&daily[0]));
+    }
+}
@@ -0,0 +1,255 @@
+use crate::model::{DeadbandConfig, DeadbandThreshold};
+use crate::mqtt::topic_level;
+use jarvis_lib::model::{Measurement, MetricType, Sample};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Keeps the last published value of every series and counts cycles, so samples that barely
+/// changed can be left out while every series still gets published in full periodically. The
+/// values only live as long as the process; after a restart series are compared with the last
+/// published measurement.
+#[derive(Default)]
+pub struct Deadband {
+    published: Mutex<HashMap<String, f64>>,
+    /// Cycles counted so far, and whether the current one publishes every sample.
+    cycles: Mutex<(u32, bool)>,
+}
+
+impl Deadband {
+    /// Counts a cycle and tells whether it publishes every sample, as every
+    /// `fullPublishEveryCycles` cycle does.
+    pub fn next_cycle(&self, config: &DeadbandConfig) -> bool {
+        let mut cycles = self.cycles.lock().unwrap();
+        let count = cycles.0.wrapping_add(1);
+        let full = config
+            .full_publish_every_cycles
+            .map_or(false, |every_cycles| count % every_cycles.max(1) == 0);
+        *cycles = (count, full);
+
+        full
+    }
+
+    /// Leaves out the samples of a device that changed less than their threshold since they were
+    /// last published, unless the current cycle publishes in full. A sample without an earlier
+    /// value always stays.
+    pub fn filter(
+        &self,
+        config: &DeadbandConfig,
+        samples: &mut Vec<Sample>,
+        previous: Option<&Measurement>,
+    ) {
+        let full = self.cycles.lock().unwrap().1;
+        let mut published = self.published.lock().unwrap();
+
+        samples.retain(|sample| {
+            let key = series_key(sample);
+            let last = published.get(&key).copied().or_else(|| {
+                previous?
+                    .samples
+                    .iter()
+                    .find(|previous| series_key(previous) == key)
+                    .map(|previous| previous.value)
+            });
+            let unchanged = last.map_or(false, |last| {
+                within(threshold(config, sample), sample, last)
+            });
+            if full || !unchanged {
+                published.insert(key, sample.value);
+            }
+
+            full || !unchanged
+        });
+    }
+}
+
+fn series_key(sample: &Sample) -> String {
+    format!(
+        "{:?}/{}/{:?}/{}/{:?}",
+        sample.entity_type,
+        sample.entity_name,
+        sample.sample_type,
+        sample.sample_name,
+        sample.metric_type
+    )
+}
+
+/// The threshold of the sample type of `sample`, or else the global one.
+fn threshold<'a>(config: &'a DeadbandConfig, sample: &Sample) -> &'a DeadbandThreshold {
+    config
+        .sample_types
+        .get(&topic_level(&format!("{:?}", sample.sample_type)))
+        .unwrap_or(&config.threshold)
+}
+
+/// Whether `sample` changed less than any threshold set since `last`; percentages don't apply to
+/// counters, which only grow.
+fn within(threshold: &DeadbandThreshold, sample: &Sample, last: f64) -> bool {
+    let change = (sample.value - last).abs();
+    let absolute = threshold
+        .absolute
+        .map_or(false, |absolute| change < absolute);
+    let percentage = match sample.metric_type {
+        MetricType::Counter => false,
+        _ => threshold
+            .percentage
+            .map_or(false, |percentage| change < last.abs() * percentage / 100.0),
+    };
+
+    absolute || percentage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jarvis_lib::model::{EntityType, SampleType};
+
+    fn sample(metric_type: MetricType, value: f64) -> Sample {
+        Sample {
+            entity_type: EntityType::Device,
+            entity_name: "HWE-SKT".into(),
+            sample_type: SampleType::ElectricityConsumption,
+            sample_name: "Bonenmaler".into(),
+            metric_type,
+            value,
+        }
+    }
+
+    fn config(absolute: Option<f64>, percentage: Option<f64>) -> DeadbandConfig {
+        DeadbandConfig {
+            threshold: DeadbandThreshold {
+                absolute,
+                percentage,
+            },
+            sample_types: HashMap::new(),
+            full_publish_every_cycles: None,
+        }
+    }
+
+    fn values(samples: &[Sample]) -> Vec<f64> {
+        samples.iter().map(|sample| sample.value).collect()
+    }
+
+    #[test]
+    fn filter_keeps_every_sample_of_first_cycle() {
+        let deadband = Deadband::default();
+        let mut samples = vec![sample(MetricType::Gauge, 100.0)];
+
+        // act
+        deadband.filter(&config(Some(5.0), None), &mut samples, None);
+
+        assert_eq!(values(&samples), vec![100.0]);
+    }
+
+    #[test]
+    fn filter_leaves_out_gauge_below_threshold_and_keeps_it_above() {
+        let deadband = Deadband::default();
+        let config = config(Some(5.0), None);
+        deadband.filter(&config, &mut vec![sample(MetricType::Gauge, 100.0)], None);
+
+        // act
+        let mut below = vec![sample(MetricType::Gauge, 104.0)];
+        deadband.filter(&config, &mut below, None);
+        let mut above = vec![sample(MetricType::Gauge, 106.0)];
+        deadband.filter(&config, &mut above, None);
+
+        assert!(below.is_empty());
+        assert_eq!(values(&above), vec![106.0]);
+    }
+
+    #[test]
+    fn filter_compares_with_last_published_value() {
+        let deadband = Deadband::default();
+        let config = config(Some(5.0), None);
+        deadband.filter(&config, &mut vec![sample(MetricType::Gauge, 100.0)], None);
+        deadband.filter(&config, &mut vec![sample(MetricType::Gauge, 103.0)], None);
+
+        // act
+        let mut drifted = vec![sample(MetricType::Gauge, 106.0)];
+        deadband.filter(&config, &mut drifted, None);
+
+        assert_eq!(values(&drifted), vec![106.0]);
+    }
+
+    #[test]
+    fn filter_applies_percentage_to_gauges_only() {
+        let deadband = Deadband::default();
+        let config = config(None, Some(10.0));
+        deadband.filter(
+            &config,
+            &mut vec![
+                sample(MetricType::Gauge, 100.0),
+                sample(MetricType::Counter, 100.0),
+            ],
+            None,
+        );
+
+        // act
+        let mut samples = vec![
+            sample(MetricType::Gauge, 105.0),
+            sample(MetricType::Counter, 105.0),
+        ];
+        deadband.filter(&config, &mut samples, None);
+
+        assert_eq!(samples.len(), 1);
+        assert_eq!(samples[0].metric_type, MetricType::Counter);
+    }
+
+    #[test]
+    fn filter_takes_threshold_of_sample_type() {
+        let deadband = Deadband::default();
+        let mut config = config(Some(5.0), None);
+        config.sample_types.insert(
+            "electricity-consumption".into(),
+            DeadbandThreshold {
+                absolute: Some(1.0),
+                percentage: None,
+            },
+        );
+        deadband.filter(&config, &mut vec![sample(MetricType::Gauge, 100.0)], None);
+
+        // act
+        let mut samples = vec![sample(MetricType::Gauge, 102.0)];
+        deadband.filter(&config, &mut samples, None);
+
+        assert_eq!(values(&samples), vec![102.0]);
+    }
+
+    #[test]
+    fn filter_compares_with_previous_measurement_after_restart() {
+        let deadband = Deadband::default();
+        let previous = Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: "jarvis-homewizard-exporter".into(),
+            location: "My Home".into(),
+            samples: vec![sample(MetricType::Gauge, 100.0)],
+            measured_at_time: chrono::Utc::now(),
+        };
+        let mut samples = vec![sample(MetricType::Gauge, 101.0)];
+
+        // act
+        deadband.filter(&config(Some(5.0), None), &mut samples, Some(&previous));
+
+        assert!(samples.is_empty());
+    }
+
+    #[test]
+    fn next_cycle_publishes_in_full_every_interval() {
+        let deadband = Deadband::default();
+        let config = DeadbandConfig {
+            full_publish_every_cycles: Some(3),
+            ..config(Some(5.0), None)
+        };
+
+        // act
+        let published: Vec<usize> = (0..6)
+            .map(|_| {
+                deadband.next_cycle(&config);
+                let mut samples = vec![sample(MetricType::Gauge, 100.0)];
+                deadband.filter(&config, &mut samples, None);
+                samples.len()
+            })
+            .collect();
+
+        assert_eq!(published, vec![1, 0, 1, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,479 @@
+use crate::fetch_failures::FetchFailureCounter;
+use crate::homewizard_client::{
+    CycleSummary, HomewizardDeviceType, MeasuredDevice, P1MeterDataResponse,
+};
+use crate::model::{
+    CostConfig, DeviceReachableConfig, GasEnergyConfig, GasFlowConfig, NetPowerConfig,
+    PhaseImbalanceConfig, SocketTotalConfig, UntrackedPowerConfig,
+};
+use crate::units::{cubic_meters_to_liters, gas_cubic_meters_to_kwh, kwh_to_joules};
+use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use tracing::debug;
+
+const ENERGY_DELTA_SUFFIX: &str = " delta";
+const WATER_LITERS_SUFFIX: &str = " liters";
+const REACHABLE_SUFFIX: &str = " reachable";
+const FETCH_FAILURES_SUFFIX: &str = " fetch failures";
+
+/// Net grid power of a P1 meter, which is its signed active power: positive while the house
+/// imports from the grid, negative while it exports.
+pub fn net_power(
+    config: &NetPowerConfig,
+    product_type: &str,
+    friendly_name: &str,
+    active_power_w: f64,
+) -> Sample {
+    Sample {
+        entity_type: EntityType::Device,
+        entity_name: product_type.to_string(),
+        sample_type: SampleType::ElectricityConsumption,
+        sample_name: format!("{} {}", friendly_name, config.name),
+        metric_type: MetricType::Gauge,
+        value: active_power_w,
+    }
+}
+
+/// Largest deviation in watts of the power of a phase from the mean of the three phases, or
+/// nothing unless all three phases have a finite reading.
+pub fn phase_imbalance(
+    config: &PhaseImbalanceConfig,
+    product_type: &str,
+    friendly_name: &str,
+    phases: [Option<f64>; 3],
+) -> Option<Sample> {
+    let mut powers = [0.0; 3];
+    for (power, phase) in powers.iter_mut().zip(phases.iter()) {
+        *power = phase.filter(|phase| phase.is_finite())?;
+    }
+    let mean = powers.iter().sum::<f64>() / 3.0;
+    let imbalance = powers
+        .iter()
+        .map(|power| (power - mean).abs())
+        .fold(0.0, f64::max);
+
+    Some(Sample {
+        entity_type: EntityType::Device,
+        entity_name: product_type.to_string(),
+        sample_type: SampleType::ElectricityConsumption,
+        sample_name: format!("{} {}", friendly_name, config.name),
+        metric_type: MetricType::Gauge,
+        value: imbalance,
+    })
+}
+
+/// Energy equivalent of the gas reading of a P1 meter, in joules like the electricity counters,
+/// so gas and electricity consumption can be compared.
+pub fn gas_energy(config: &GasEnergyConfig, product_type: &str, total_gas_m3: f64) -> Sample {
+    Sample {
+        entity_type: EntityType::Tariff,
+        entity_name: product_type.to_string(),
+        sample_type: SampleType::GasConsumption,
+        sample_name: "gas energy equivalent".into(),
+        metric_type: MetricType::Counter,
+        value: kwh_to_joules(gas_cubic_meters_to_kwh(
+            total_gas_m3,
+            config.calorific_value,
+        )),
+    }
+}
+
+/// Gas flow of a P1 meter in m3/h, named after the meter like its power gauge.
+pub fn gas_flow(
+    config: &GasFlowConfig,
+    product_type: &str,
+    friendly_name: &str,
+    cubic_meters_per_hour: f64,
+) -> Sample {
+    Sample {
+        entity_type: EntityType::Device,
+        entity_name: product_type.to_string(),
+        sample_type: SampleType::GasConsumption,
+        sample_name: format!("{} {}", friendly_name, config.name),
+        metric_type: MetricType::Gauge,
+        value: cubic_meters_per_hour,
+    }
+}
+
+/// Total of a water meter in liters, for consumers working in whole liters.
+pub fn water_liters(product_type: &str, friendly_name: &str, total_m3: f64) -> Sample {
+    Sample {
+        entity_type: EntityType::Device,
+        entity_name: product_type.to_string(),
+        sample_type: SampleType::WaterConsumption,
+        sample_name: format!("{}{}", friendly_name, WATER_LITERS_SUFFIX),
+        metric_type: MetricType::Counter,
+        value: cubic_meters_to_liters(total_m3),
+    }
+}
+
+/// Whether the sample is a water total in liters rather than in m3.
+pub fn is_water_liters(sample: &Sample) -> bool {
+    matches!(sample.entity_type, EntityType::Device)
+        && matches!(sample.sample_type, SampleType::WaterConsumption)
+        && matches!(sample.metric_type, MetricType::Counter)
+        && sample.sample_name.ends_with(WATER_LITERS_SUFFIX)
+}
+
+/// Running cost counters of the tariff registers and gas reading of a P1 meter, one for every
+/// configured price.
+pub fn p1_costs(
+    config: &CostConfig,
+    product_type: &str,
+    data: &P1MeterDataResponse,
+) -> Vec<Sample> {
+    let registers = [
+        (
+            SampleType::ElectricityConsumption,
+            "t1 import cost",
+            config.electricity_t1,
+            Some(data.total_power_import_t1_kwh),
+        ),
+        (
+            SampleType::ElectricityConsumption,
+            "t2 import cost",
+            config.electricity_t2,
+            Some(data.total_power_import_t2_kwh),
+        ),
+        (
+            SampleType::GasConsumption,
+            "gas cost",
+            config.gas,
+            data.total_gas_m3,
+        ),
+    ];
+
+    registers
+        .iter()
+        .filter_map(|(sample_type, sample_name, price, total)| {
+            Some(cost(
+                product_type,
+                sample_type.clone(),
+                sample_name.to_string(),
+                (*price)? * (*total)?,
+            ))
+        })
+        .collect()
+}
+
+/// Running cost counter of the total of a water meter, when a water price is configured.
+pub fn water_cost(
+    config: &CostConfig,
+    product_type: &str,
+    friendly_name: &str,
+    total_m3: f64,
+) -> Option<Sample> {
+    Some(cost(
+        product_type,
+        SampleType::WaterConsumption,
+        format!("{} cost", friendly_name),
+        config.water? * total_m3,
+    ))
+}
+
+/// Cost samples are tariff counters named after what they're the cost of, telling them apart
+/// from the consumption counters they share a sample type with.
+fn cost(product_type: &str, sample_type: SampleType, sample_name: String, value: f64) -> Sample {
+    Sample {
+        entity_type: EntityType::Tariff,
+        entity_name: product_type.to_string(),
+        sample_type,
+        sample_name,
+        metric_type: MetricType::Counter,
+        value,
+    }
+}
+
+/// Whether the sample is a cost counter rather than a reading in the unit of its sample type.
+pub fn is_cost(sample: &Sample) -> bool {
+    matches!(sample.entity_type, EntityType::Tariff) && sample.sample_name.ends_with(" cost")
+}
+
+/// Gauges of the energy every electricity counter among the samples of a device added since the
+/// previous measurement, in joules like the counters. A counter that's missing from the previous
+/// measurement or went down since, because the device got reset or replaced, gets no gauge.
+pub fn energy_deltas(samples: &[Sample], previous_samples: &[Sample]) -> Vec<Sample> {
+    samples
+        .iter()
+        .filter(|sample| {
+            matches!(sample.metric_type, MetricType::Counter)
+                && matches!(
+                    sample.sample_type,
+                    SampleType::ElectricityConsumption | SampleType::ElectricityProduction
+                )
+                && !is_cost(sample)
+        })
+        .filter_map(|sample| {
+            let previous = previous_samples.iter().find(|previous| {
+                previous.metric_type == sample.metric_type
+                    && previous.entity_type == sample.entity_type
+                    && previous.entity_name == sample.entity_name
+                    && previous.sample_type == sample.sample_type
+                    && previous.sample_name == sample.sample_name
+            })?;
+            if sample.value < previous.value {
+                debug!(
+                    "Skipping energy delta of {} {} after its counter went down from {} to {}",
+                    sample.entity_name, sample.sample_name, previous.value, sample.value
+                );
+                return None;
+            }
+
+            Some(Sample {
+                entity_type: sample.entity_type.clone(),
+                entity_name: sample.entity_name.clone(),
+                sample_type: sample.sample_type.clone(),
+                sample_name: format!("{}{}", sample.sample_name, ENERGY_DELTA_SUFFIX),
+                metric_type: MetricType::Gauge,
+                value: sample.value - previous.value,
+            })
+        })
+        .collect()
+}
+
+/// Whether the sample is an energy delta rather than a power gauge.
+pub fn is_energy_delta(sample: &Sample) -> bool {
+    matches!(sample.metric_type, MetricType::Gauge)
+        && matches!(
+            sample.sample_type,
+            SampleType::ElectricityConsumption | SampleType::ElectricityProduction
+        )
+        && sample.sample_name.ends_with(ENERGY_DELTA_SUFFIX)
+}
+
+/// Gauges that are 1 for a device read successfully this cycle and 0 otherwise, for every device
+/// in `names` - also when it wasn't discovered, unless `include_undiscovered` is false because
+/// the cycle only targeted a single device - and with `include_unconfigured` for every other
+/// device of the cycle as well. An unconfigured device whose info couldn't be fetched is only
+/// known by its mdns name.
+pub fn device_reachable(
+    config: &DeviceReachableConfig,
+    names: &HashMap<String, String>,
+    summary: &CycleSummary,
+    include_undiscovered: bool,
+) -> Vec<Sample> {
+    let succeeded = |serial: &str| {
+        summary
+            .succeeded_devices
+            .iter()
+            .any(|device| device.serial == serial)
+    };
+    let failed = |serial: &str| summary.failed_devices.iter().any(|failed| failed == serial);
+
+    // sorted, so the samples keep their order from cycle to cycle
+    let configured: BTreeMap<&String, &String> = names.iter().collect();
+    let mut samples: Vec<Sample> = configured
+        .into_iter()
+        .filter(|(serial, _)| include_undiscovered || succeeded(serial) || failed(serial))
+        .map(|(serial, friendly_name)| reachable(serial, friendly_name, succeeded(serial)))
+        .collect();
+
+    if config.include_unconfigured {
+        let unconfigured = |serial: &String| !names.contains_key(serial);
+        samples.extend(
+            summary
+                .succeeded_devices
+                .iter()
+                .filter(|device| unconfigured(&device.serial))
+                .map(|device| reachable(&device.serial, &device.friendly_name, true)),
+        );
+        samples.extend(
+            summary
+                .failed_devices
+                .iter()
+                .filter(|serial_or_name| unconfigured(serial_or_name))
+                .map(|serial_or_name| reachable(serial_or_name, serial_or_name, false)),
+        );
+    }
+
+    samples
+}
+
+/// Jarvis samples have no type for the state of a device, so reachability is a gauge of the
+/// electricity consumption type with the serial as entity name, told apart by its name.
+fn reachable(serial_or_name: &str, friendly_name: &str, reachable: bool) -> Sample {
+    Sample {
+        entity_type: EntityType::Device,
+        entity_name: serial_or_name.to_string(),
+        sample_type: SampleType::ElectricityConsumption,
+        sample_name: format!("{}{}", friendly_name, REACHABLE_SUFFIX),
+        metric_type: MetricType::Gauge,
+        value: if reachable { 1.0 } else { 0.0 },
+    }
+}
+
+/// Whether the sample tells if a device was reachable rather than its active power.
+pub fn is_device_reachable(sample: &Sample) -> bool {
+    matches!(sample.entity_type, EntityType::Device)
+        && matches!(sample.sample_type, SampleType::ElectricityConsumption)
+        && matches!(sample.metric_type, MetricType::Gauge)
+        && sample.sample_name.ends_with(REACHABLE_SUFFIX)
+}
+
+/// Counters of the failed fetches so far of every device in `names`, with the serial as entity
+/// name like the reachability gauges; emitted every cycle, also when the count didn't change.
+/// Without `include_undiscovered` only for the devices of the cycle.
+pub fn fetch_failures(
+    names: &HashMap<String, String>,
+    counter: &FetchFailureCounter,
+    summary: &CycleSummary,
+    include_undiscovered: bool,
+) -> Vec<Sample> {
+    let in_cycle = |serial: &str| {
+        summary
+            .succeeded_devices
+            .iter()
+            .any(|device| device.serial == serial)
+            || summary.failed_devices.iter().any(|failed| failed == serial)
+    };
+
+    // sorted, so the samples keep their order from cycle to cycle
+    let configured: BTreeMap<&String, &String> = names.iter().collect();
+    configured
+        .into_iter()
+        .filter(|(serial, _)| include_undiscovered || in_cycle(serial))
+        .map(|(serial, friendly_name)| Sample {
+            entity_type: EntityType::Device,
+            entity_name: serial.to_string(),
+            sample_type: SampleType::ElectricityConsumption,
+            sample_name: format!("{}{}", friendly_name, FETCH_FAILURES_SUFFIX),
+            metric_type: MetricType::Counter,
+            value: counter.count(serial) as f64,
+        })
+        .collect()
+}
+
+/// Whether the sample counts failed fetches of a device rather than imported energy.
+pub fn is_fetch_failures(sample: &Sample) -> bool {
+    matches!(sample.entity_type, EntityType::Device)
+        && matches!(sample.sample_type, SampleType::ElectricityConsumption)
+        && matches!(sample.metric_type, MetricType::Counter)
+        && sample.sample_name.ends_with(FETCH_FAILURES_SUFFIX)
+}
+
+/// The active power gauge among the samples of a measured device, the first one for a P1 meter
+/// that also emits its net power.
+fn active_power(samples: &[Sample], device: &MeasuredDevice) -> Option<f64> {
+    samples
+        .get(device.samples.clone())?
+        .iter()
+        .find(|sample| {
+            matches!(
+                (&sample.sample_type, &sample.metric_type),
+                (SampleType::ElectricityConsumption, MetricType::Gauge)
+            )
+        })
+        .map(|sample| sample.value)
+}
+
+/// Summed active power of the measured devices of a type, or nothing when none was measured.
+fn total_active_power(
+    samples: &[Sample],
+    devices: &[MeasuredDevice],
+    counts: impl Fn(&str) -> bool,
+) -> Option<f64> {
+    let powers: Vec<f64> = devices
+        .iter()
+        .filter(|device| counts(&device.info.product_type))
+        .filter_map(|device| active_power(samples, device))
+        .collect();
+    if powers.is_empty() {
+        return None;
+    }
+
+    Some(powers.iter().sum())
+}
+
+/// Whether the active power of a device of this product type adds to the socket total.
+pub fn counts_towards_socket_total(config: &SocketTotalConfig, product_type: &str) -> bool {
+    match HomewizardDeviceType::from_str(product_type) {
+        Ok(HomewizardDeviceType::EnergySocket) => true,
+        Ok(HomewizardDeviceType::SinglePhaseKwhMeter)
+        | Ok(HomewizardDeviceType::TriplePhaseKwhMeter) => config.include_kwh_meters,
+        _ => false,
+    }
+}
+
+/// Sum of the active power gauges of the measured devices that count towards the socket total,
+/// or nothing when none of them was measured this cycle.
+pub fn socket_total(
+    config: &SocketTotalConfig,
+    samples: &[Sample],
+    devices: &[MeasuredDevice],
+) -> Option<Sample> {
+    let total = total_active_power(samples, devices, |product_type| {
+        counts_towards_socket_total(config, product_type)
+    })?;
+
+    Some(Sample {
+        entity_type: EntityType::Device,
+        entity_name: config.entity_name.clone(),
+        sample_type: SampleType::ElectricityConsumption,
+        sample_name: config.name.clone(),
+        metric_type: MetricType::Gauge,
+        value: total,
+    })
+}
+
+/// Active power of the P1 meters minus that of the energy sockets, the consumption behind no
+/// socket. Needs both a P1 meter and a socket measured this cycle, since without the P1 meter
+/// there's nothing to subtract from and without sockets it equals the P1 meter's own gauge.
+pub fn untracked_power(
+    config: &UntrackedPowerConfig,
+    samples: &[Sample],
+    devices: &[MeasuredDevice],
+) -> Option<Sample> {
+    let p1_power = total_active_power(samples, devices, |product_type| {
+        HomewizardDeviceType::from_str(product_type) == Ok(HomewizardDeviceType::P1Meter)
+    })?;
+    let socket_power = total_active_power(samples, devices, |product_type| {
+        HomewizardDeviceType::from_str(product_type) == Ok(HomewizardDeviceType::EnergySocket)
+    })?;
+
+    let untracked = p1_power - socket_power;
+
+    Some(Sample {
+        entity_type: EntityType::Device,
+        entity_name: config.entity_name.clone(),
+        sample_type: SampleType::ElectricityConsumption,
+        sample_name: config.name.clone(),
+        metric_type: MetricType::Gauge,
+        value: if config.clamp_at_zero {
+            untracked.max(0.0)
+        } else {
+            untracked
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phase_imbalance_needs_three_finite_phases() {
+        let config = PhaseImbalanceConfig {
+            name: "phase imbalance".into(),
+        };
+
+        // act
+        let without_phase = phase_imbalance(&config, "HWE-P1", "P1", [Some(1.0), None, Some(2.0)]);
+        let with_nan = phase_imbalance(
+            &config,
+            "HWE-P1",
+            "P1",
+            [Some(1.0), Some(f64::NAN), Some(2.0)],
+        );
+        let with_infinity = phase_imbalance(
+            &config,
+            "HWE-P1",
+            "P1",
+            [Some(f64::INFINITY), Some(1.0), Some(2.0)],
+        );
+
+        assert!(without_phase.is_none());
+        assert!(with_nan.is_none());
+        assert!(with_infinity.is_none());
+    }
+}
@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceHealth {
+    pub friendly_name: String,
+    pub last_seen: DateTime<Utc>,
+    pub consecutive_failures: u32,
+    pub offline: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum DeviceEventType {
+    Offline,
+    Recovered,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceEvent {
+    pub event_type: DeviceEventType,
+    pub serial: String,
+    pub friendly_name: String,
+    pub last_seen: DateTime<Utc>,
+    pub consecutive_failures: u32,
+}
+
+/// A device that was read successfully during a cycle.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SucceededDevice {
+    pub serial: String,
+    pub friendly_name: String,
+}
+
+/// Tracks every device seen so far by serial, so devices that stop responding - or stop being
+/// discovered at all - can be reported once when they go offline and once when they recover.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct DeviceHealthTracker {
+    devices: BTreeMap<String, DeviceHealth>,
+}
+
+impl DeviceHealthTracker {
+    pub fn get(&self, serial: &str) -> Option<&DeviceHealth> {
+        self.devices.get(serial)
+    }
+
+    /// Updates the tracker with the outcome of a cycle and returns the resulting state changes.
+    pub fn observe(
+        &mut self,
+        measured_at: DateTime<Utc>,
+        succeeded: &[SucceededDevice],
+        offline_threshold: u32,
+    ) -> Vec<DeviceEvent> {
+        let mut events = vec![];
+
+        for device in succeeded {
+            let health = self
+                .devices
+                .entry(device.serial.clone())
+                .or_insert_with(|| DeviceHealth {
+                    friendly_name: device.friendly_name.clone(),
+                    last_seen: measured_at,
+                    consecutive_failures: 0,
+                    offline: false,
+                });
+
+            if health.offline {
+                events.push(DeviceEvent {
+                    event_type: DeviceEventType::Recovered,
+                    serial: device.serial.clone(),
+                    friendly_name: device.friendly_name.clone(),
+                    last_seen: health.last_seen,
+                    consecutive_failures: health.consecutive_failures,
+                });
+            }
+
+            health.friendly_name = device.friendly_name.clone();
+            health.last_seen = measured_at;
+            health.consecutive_failures = 0;
+            health.offline = false;
+        }
+
+        let succeeded_serials: HashSet<&str> = succeeded
+            .iter()
+            .map(|device| device.serial.as_str())
+            .collect();
+
+        for (serial, health) in self
+            .devices
+            .iter_mut()
+            .filter(|(serial, _)| !succeeded_serials.contains(serial.as_str()))
+        {
+            health.consecutive_failures += 1;
+
+            if !health.offline && health.consecutive_failures >= offline_threshold {
+                health.offline = true;
+                events.push(DeviceEvent {
+                    event_type: DeviceEventType::Offline,
+                    serial: serial.clone(),
+                    friendly_name: health.friendly_name.clone(),
+                    last_seen: health.last_seen,
+                    consecutive_failures: health.consecutive_failures,
+                });
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn water_meter() -> SucceededDevice {
+        SucceededDevice {
+            serial: "3c39e72d7a68".into(),
+            friendly_name: "Watermeter".into(),
+        }
+    }
+
+    #[test]
+    fn observe_reports_offline_once_after_threshold_and_recovery_once() {
+        let mut tracker = DeviceHealthTracker::default();
+        let start = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let cycle = |n: i64| start + Duration::minutes(5 * n);
+
+        // act
+        let seen = tracker.observe(cycle(0), &[water_meter()], 3);
+        let first_failure = tracker.observe(cycle(1), &[], 3);
+        let second_failure = tracker.observe(cycle(2), &[], 3);
+        let offline = tracker.observe(cycle(3), &[], 3);
+        let still_offline = tracker.observe(cycle(4), &[], 3);
+        let recovered = tracker.observe(cycle(5), &[water_meter()], 3);
+        let still_online = tracker.observe(cycle(6), &[water_meter()], 3);
+
+        assert!(seen.is_empty());
+        assert!(first_failure.is_empty());
+        assert!(second_failure.is_empty());
+        assert_eq!(
+            offline,
+            vec![DeviceEvent {
+                event_type: DeviceEventType::Offline,
+                serial: "3c39e72d7a68".into(),
+                friendly_name: "Watermeter".into(),
+                last_seen: cycle(0),
+                consecutive_failures: 3,
+            }]
+        );
+        assert!(still_offline.is_empty());
+        assert_eq!(
+            recovered,
+            vec![DeviceEvent {
+                event_type: DeviceEventType::Recovered,
+                serial: "3c39e72d7a68".into(),
+                friendly_name: "Watermeter".into(),
+                last_seen: cycle(0),
+                consecutive_failures: 4,
+            }]
+        );
+        assert!(still_online.is_empty());
+        assert_eq!(tracker.get("3c39e72d7a68").unwrap().last_seen, cycle(5));
+    }
+
+    #[test]
+    fn observe_resets_failure_count_on_success_below_threshold() {
+        let mut tracker = DeviceHealthTracker::default();
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+
+        // act
+        tracker.observe(now, &[water_meter()], 2);
+        tracker.observe(now, &[], 2);
+        tracker.observe(now, &[water_meter()], 2);
+        let events = tracker.observe(now, &[], 2);
+
+        assert!(events.is_empty());
+        assert_eq!(tracker.get("3c39e72d7a68").unwrap().consecutive_failures, 1);
+    }
+
+    #[test]
+    fn device_event_serializes_with_camel_case_fields() {
+        let event = DeviceEvent {
+            event_type: DeviceEventType::Offline,
+            serial: "3c39e72d7a68".into(),
+            friendly_name: "Watermeter".into(),
+            last_seen: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+            consecutive_failures: 3,
+        };
+
+        // act
+        let json = serde_json::to_value(&event).unwrap();
+
+        assert_eq!(json["eventType"], "offline");
+        assert_eq!(json["friendlyName"], "Watermeter");
+        assert_eq!(json["lastSeen"], "2023-06-01T12:00:00Z");
+        assert_eq!(json["consecutiveFailures"], 3);
+    }
+}
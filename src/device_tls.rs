@@ -0,0 +1,209 @@
+use openssl::nid::Nid;
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509StoreContext, X509};
+use rustls::client::{ServerCertVerified, ServerCertVerifier};
+use rustls::{Certificate, ClientConfig, ServerName};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// The HomeWizard CA that signs the certificates of v2 devices, which isn't in any system trust
+/// store.
+const HOMEWIZARD_CA: &[u8] = include_bytes!("certs/homewizard-ca.pem");
+
+/// The certificates of the bundled HomeWizard CA.
+pub fn bundled_roots() -> Result<Vec<X509>, Box<dyn Error + Send + Sync>> {
+    X509::stack_from_pem(HOMEWIZARD_CA)
+        .map_err(|e| format!("Failed parsing the bundled HomeWizard CA: {}", e).into())
+}
+
+/// The serial in the common name `appliance/<product type>/<serial>` HomeWizard issues device
+/// certificates with.
+pub fn appliance_serial(common_name: &str) -> Option<&str> {
+    match common_name.split('/').collect::<Vec<&str>>()[..] {
+        ["appliance", product_type, serial] if !product_type.is_empty() && !serial.is_empty() => {
+            Some(serial)
+        }
+        _ => None,
+    }
+}
+
+/// Tls config for requests to v2 devices, accepting only certificates that chain to `roots` and
+/// are issued to the device with `expected_serial`, or to any device when it isn't known yet.
+pub fn client_config(roots: Vec<X509>, expected_serial: Option<&str>) -> ClientConfig {
+    ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(ApplianceCertVerifier {
+            roots,
+            expected_serial: expected_serial.map(String::from),
+        }))
+        .with_no_client_auth()
+}
+
+/// Device certificates name the device instead of a host or ip address, so the name is checked
+/// against the serial rather than the address the device is requested at.
+struct ApplianceCertVerifier {
+    roots: Vec<X509>,
+    expected_serial: Option<String>,
+}
+
+impl ApplianceCertVerifier {
+    fn verify(&self, end_entity: &[u8], intermediates: &[Certificate]) -> Result<(), String> {
+        let certificate =
+            X509::from_der(end_entity).map_err(|e| format!("Invalid certificate: {}", e))?;
+        self.verify_chain(&certificate, intermediates)
+            .map_err(|e| format!("Certificate isn't issued by a trusted CA: {}", e))?;
+
+        let common_name = certificate
+            .subject_name()
+            .entries_by_nid(Nid::COMMONNAME)
+            .next()
+            .and_then(|entry| entry.data().as_utf8().ok())
+            .map(|common_name| common_name.to_string())
+            .ok_or("Certificate has no common name")?;
+        let serial = appliance_serial(&common_name)
+            .ok_or_else(|| format!("Certificate {} isn't issued to a device", common_name))?;
+        match &self.expected_serial {
+            Some(expected) if !serial.eq_ignore_ascii_case(expected) => Err(format!(
+                "Certificate {} is issued to serial {} instead of {}",
+                common_name, serial, expected
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn verify_chain(
+        &self,
+        certificate: &X509,
+        intermediates: &[Certificate],
+    ) -> Result<(), Box<dyn Error>> {
+        let mut store = X509StoreBuilder::new()?;
+        for root in self.roots.iter() {
+            store.add_cert(root.clone())?;
+        }
+        let store = store.build();
+        let mut chain = Stack::new()?;
+        for intermediate in intermediates {
+            chain.push(X509::from_der(&intermediate.0)?)?;
+        }
+
+        let mut context = X509StoreContext::new()?;
+        let result = context.init(&store, certificate, &chain, |context| {
+            Ok(match context.verify_cert()? {
+                true => Ok(()),
+                false => Err(context.error().error_string().to_string()),
+            })
+        })?;
+
+        Ok(result?)
+    }
+}
+
+impl ServerCertVerifier for ApplianceCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        self.verify(&end_entity.0, intermediates)
+            .map(|()| ServerCertVerified::assertion())
+            .map_err(rustls::Error::General)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn fixture(name: &str) -> Vec<u8> {
+        fs::read(format!("tests/fixtures/tls/{}", name)).unwrap()
+    }
+
+    fn verifier(expected_serial: Option<&str>) -> ApplianceCertVerifier {
+        ApplianceCertVerifier {
+            roots: X509::stack_from_pem(&fixture("test-ca.pem")).unwrap(),
+            expected_serial: expected_serial.map(String::from),
+        }
+    }
+
+    fn device_certificate() -> Vec<u8> {
+        X509::from_pem(&fixture("device-5c2faf0a8b3e.pem"))
+            .unwrap()
+            .to_der()
+            .unwrap()
+    }
+
+    #[test]
+    fn appliance_serial_takes_serial_from_common_name() {
+        assert_eq!(
+            appliance_serial("appliance/p1dongle/5c2faf0a8b3e"),
+            Some("5c2faf0a8b3e")
+        );
+        assert_eq!(appliance_serial("appliance/p1dongle/"), None);
+        assert_eq!(appliance_serial("appliance/5c2faf0a8b3e"), None);
+        assert_eq!(appliance_serial("192.168.1.31"), None);
+    }
+
+    #[test]
+    fn verify_accepts_device_certificate_of_trusted_ca_with_expected_serial() {
+        // act
+        let pinned = verifier(Some("5C2FAF0A8B3E")).verify(&device_certificate(), &[]);
+        let unpinned = verifier(None).verify(&device_certificate(), &[]);
+
+        assert_eq!(pinned, Ok(()));
+        assert_eq!(unpinned, Ok(()));
+    }
+
+    #[test]
+    fn verify_rejects_device_certificate_with_other_serial() {
+        // act
+        let result = verifier(Some("3c39e72e33ce")).verify(&device_certificate(), &[]);
+
+        assert_eq!(
+            result,
+            Err("Certificate appliance/p1dongle/5c2faf0a8b3e is issued to serial 5c2faf0a8b3e instead of 3c39e72e33ce".to_string())
+        );
+    }
+
+    #[test]
+    fn verify_rejects_certificate_of_untrusted_ca() {
+        let verifier = ApplianceCertVerifier {
+            roots: vec![],
+            expected_serial: None,
+        };
+
+        // act
+        let result = verifier.verify(&device_certificate(), &[]);
+
+        assert!(result
+            .unwrap_err()
+            .starts_with("Certificate isn't issued by a trusted CA"));
+    }
+
+    #[test]
+    fn verify_rejects_certificate_that_isnt_issued_to_a_device() {
+        let ca = X509::from_pem(&fixture("test-ca.pem")).unwrap();
+
+        // act
+        let result = verifier(None).verify(&ca.to_der().unwrap(), &[]);
+
+        assert_eq!(
+            result,
+            Err("Certificate Jarvis test CA isn't issued to a device".to_string())
+        );
+    }
+
+    #[test]
+    fn bundled_roots_parse() {
+        // act
+        let roots = bundled_roots();
+
+        assert!(roots.is_ok());
+    }
+}
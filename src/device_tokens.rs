@@ -0,0 +1,110 @@
+use crate::model::{Config, StaticDevice};
+use std::error::Error;
+use std::fs;
+
+/// Reads the token of every device with a `tokenFile`, failing on a file that can't be read or
+/// holds nothing but whitespace. The tokens themselves never get logged.
+pub fn read_token_files(config: &mut Config) -> Result<(), Box<dyn Error>> {
+    for device in config.static_devices_mut() {
+        let (serial, auth) = match device {
+            StaticDevice::Pinned { serial, auth, .. } => (serial, auth),
+            StaticDevice::Address(_) => continue,
+        };
+        let path = match &auth.token_file {
+            Some(path) => path,
+            None => continue,
+        };
+
+        let contents = fs::read_to_string(path).map_err(|e| {
+            format!(
+                "tokenFile {} of device {} can't be read: {}",
+                path, serial, e
+            )
+        })?;
+        // secrets mounted from a file often end with a line break
+        let file_token = contents.trim();
+        if file_token.is_empty() {
+            return Err(format!(
+                "tokenFile {} of device {} holds an empty token",
+                path, serial
+            )
+            .into());
+        }
+        auth.file_token = Some(file_token.to_string());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{DeviceTokenConfig, LocationConfig};
+    use std::env;
+    use uuid::Uuid;
+
+    fn config(token_file: &str) -> Config {
+        Config {
+            location: "My Home".into(),
+            locations: vec![LocationConfig {
+                location: "Parents".into(),
+                serials: vec![],
+                devices: vec![StaticDevice::Pinned {
+                    address: "10.1.0.31:80".parse().unwrap(),
+                    serial: "3c39e72e33ce".into(),
+                    auth: DeviceTokenConfig {
+                        token_file: Some(token_file.into()),
+                        ..Default::default()
+                    },
+                }],
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn read_token_files_reads_trimmed_token() {
+        let path = env::temp_dir().join(format!("token-{}", Uuid::new_v4()));
+        fs::write(&path, "2A4F0C9B\n").unwrap();
+        let mut config = config(&path.to_string_lossy());
+
+        // act
+        read_token_files(&mut config).unwrap();
+
+        assert_eq!(config.device_token("3c39e72e33ce"), Some("2A4F0C9B"));
+        assert!(config.validate().is_ok());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_token_files_fails_on_missing_file_naming_the_device() {
+        let mut config = config("/nonexistent/token");
+
+        // act
+        let result = read_token_files(&mut config);
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("tokenFile /nonexistent/token of device 3c39e72e33ce can't be read"));
+    }
+
+    #[test]
+    fn read_token_files_fails_on_empty_token_naming_the_device() {
+        let path = env::temp_dir().join(format!("token-{}", Uuid::new_v4()));
+        fs::write(&path, " \n").unwrap();
+        let mut config = config(&path.to_string_lossy());
+
+        // act
+        let result = read_token_files(&mut config);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!(
+                "tokenFile {} of device 3c39e72e33ce holds an empty token",
+                path.display()
+            )
+        );
+        fs::remove_file(path).unwrap();
+    }
+}
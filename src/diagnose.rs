@@ -0,0 +1,297 @@
+use crate::error::DeviceError;
+use crate::homewizard_client::{
+    DeviceInfoResponse, EnergySocketDataResponse, HomewizardClient, HomewizardDeviceType,
+    P1MeterDataResponse, SinglePhaseKwhMeterDataResponse, TriplePhaseKwhMeterDataResponse,
+    WaterMeterDataResponse,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt::{self, Debug};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// A device to diagnose, given either by address or by serial.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnoseTarget {
+    Address(String),
+    Serial(String),
+}
+
+impl DiagnoseTarget {
+    pub fn parse(target: &str) -> Self {
+        if target.parse::<IpAddr>().is_ok() || target.parse::<SocketAddr>().is_ok() {
+            DiagnoseTarget::Address(target.to_string())
+        } else {
+            DiagnoseTarget::Serial(target.to_lowercase())
+        }
+    }
+}
+
+/// Raw and parsed form of a single endpoint response.
+pub struct EndpointDiagnosis {
+    pub url: String,
+    pub duration: Duration,
+    pub raw: Value,
+    pub parsed: Result<String, String>,
+    pub unknown_fields: Vec<String>,
+}
+
+pub struct Diagnosis {
+    pub info: EndpointDiagnosis,
+    pub data: Option<EndpointDiagnosis>,
+}
+
+impl fmt::Display for EndpointDiagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "GET {} ({} ms)", self.url, self.duration.as_millis())?;
+        writeln!(
+            f,
+            "{}",
+            serde_json::to_string_pretty(&self.raw).map_err(|_| fmt::Error)?
+        )?;
+        match &self.parsed {
+            Ok(parsed) => writeln!(f, "Parsed as:\n{}", parsed)?,
+            Err(e) => writeln!(f, "Failed parsing: {}", e)?,
+        }
+        if self.unknown_fields.is_empty() {
+            writeln!(f, "Unknown fields: none")
+        } else {
+            writeln!(f, "Unknown fields: {}", self.unknown_fields.join(", "))
+        }
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.info)?;
+        match &self.data {
+            Some(data) => write!(f, "\n{}", data),
+            None => writeln!(f, "\nNo data endpoint known for this product type"),
+        }
+    }
+}
+
+/// Resolves the base url of the target device, discovering devices to find it by serial.
+pub fn resolve_base_url(
+    target: &DiagnoseTarget,
+    homewizard_client: &HomewizardClient,
+) -> Result<String, Box<dyn Error>> {
+    let serial = match target {
+        DiagnoseTarget::Address(address) => return Ok(format!("http://{}", address)),
+        DiagnoseTarget::Serial(serial) => serial,
+    };
+
+    let client = reqwest::blocking::Client::new();
+    for device in homewizard_client
+        .discover_devices()
+        .map_err(DeviceError::new)?
+    {
+        let base_url = match homewizard_client.base_url(&device) {
+            Ok(base_url) => base_url,
+            Err(_) => continue,
+        };
+        let device_info = client
+            .get(format!("{}/api", base_url))
+            .send()
+            .and_then(|response| response.json::<DeviceInfoResponse>());
+
+        if let Ok(device_info) = device_info {
+            if device_info.serial.to_lowercase() == *serial {
+                return Ok(base_url);
+            }
+        }
+    }
+
+    Err(format!("No device with serial {} discovered", serial).into())
+}
+
+/// Fetches the info and data endpoints of the device at `base_url` and compares the responses
+/// with the structs they get parsed into. Never publishes anything.
+pub fn diagnose(base_url: &str, timeout: Duration) -> Result<Diagnosis, Box<dyn Error>> {
+    // like for measuring, a proxy from the environment can't reach devices on the local network
+    let client = reqwest::blocking::Client::builder()
+        .timeout(timeout)
+        .no_proxy()
+        .build()?;
+
+    let info = fetch::<DeviceInfoResponse>(&client, format!("{}/api", base_url))?;
+    let device_info: DeviceInfoResponse = serde_json::from_value(info.raw.clone())?;
+    info!(
+        "Diagnosing {} {} with serial {}",
+        device_info.product_type, device_info.product_name, device_info.serial
+    );
+
+    let data_url = format!("{}/api/{}/data", base_url, device_info.api_version);
+    let data = match HomewizardDeviceType::from_str(&device_info.product_type) {
+        Ok(HomewizardDeviceType::P1Meter) => Some(fetch::<P1MeterDataResponse>(&client, data_url)?),
+        Ok(HomewizardDeviceType::EnergySocket) => {
+            Some(fetch::<EnergySocketDataResponse>(&client, data_url)?)
+        }
+        Ok(HomewizardDeviceType::SinglePhaseKwhMeter) => {
+            Some(fetch::<SinglePhaseKwhMeterDataResponse>(&client, data_url)?)
+        }
+        Ok(HomewizardDeviceType::TriplePhaseKwhMeter) => {
+            Some(fetch::<TriplePhaseKwhMeterDataResponse>(&client, data_url)?)
+        }
+        Ok(HomewizardDeviceType::WaterMeter) => {
+            Some(fetch::<WaterMeterDataResponse>(&client, data_url)?)
+        }
+        Err(_) => None,
+    };
+
+    Ok(Diagnosis { info, data })
+}
+
+fn fetch<T: DeserializeOwned + Serialize + Debug>(
+    client: &reqwest::blocking::Client,
+    url: String,
+) -> Result<EndpointDiagnosis, Box<dyn Error>> {
+    let start = Instant::now();
+    let raw: Value = client.get(&url).send()?.error_for_status()?.json()?;
+    let duration = start.elapsed();
+
+    let (parsed, unknown_fields) = match serde_json::from_value::<T>(raw.clone()) {
+        Ok(parsed) => {
+            let known = serde_json::to_value(&parsed)?;
+            (Ok(format!("{:#?}", parsed)), unknown_fields(&raw, &known))
+        }
+        Err(e) => (Err(e.to_string()), vec![]),
+    };
+
+    Ok(EndpointDiagnosis {
+        url,
+        duration,
+        raw,
+        parsed,
+        unknown_fields,
+    })
+}
+
+/// Returns the top-level fields present in the raw response but not in its parsed struct.
+fn unknown_fields(raw: &Value, known: &Value) -> Vec<String> {
+    match (raw.as_object(), known.as_object()) {
+        (Some(raw), Some(known)) => {
+            let mut unknown: Vec<String> = raw
+                .keys()
+                .filter(|key| !known.contains_key(*key))
+                .cloned()
+                .collect();
+            unknown.sort();
+            unknown
+        }
+        _ => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeDeviceServer, FakeResponse};
+
+    const SOCKET_INFO: &str = r#"{"product_type":"HWE-SKT","product_name":"Energy Socket","serial":"3c39e72e33ce","firmware_version":"3.02","api_version":"v1"}"#;
+
+    #[test]
+    fn parse_target_distinguishes_addresses_from_serials() {
+        assert_eq!(
+            DiagnoseTarget::parse("192.168.1.31"),
+            DiagnoseTarget::Address("192.168.1.31".into())
+        );
+        assert_eq!(
+            DiagnoseTarget::parse("127.0.0.1:8080"),
+            DiagnoseTarget::Address("127.0.0.1:8080".into())
+        );
+        assert_eq!(
+            DiagnoseTarget::parse("3C39E72E33CE"),
+            DiagnoseTarget::Serial("3c39e72e33ce".into())
+        );
+    }
+
+    #[test]
+    fn diagnose_reports_raw_and_parsed_responses() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api", FakeResponse::json(SOCKET_INFO))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(
+                    r#"{"wifi_ssid":"My Wi-Fi","wifi_strength":100,"total_power_import_t1_kwh":30.511,"total_power_export_t1_kwh":0.0,"active_power_w":543.0,"active_power_l1_w":543.0}"#,
+                ),
+            );
+
+        // act
+        let diagnosis = diagnose(&server.base_url(), Duration::from_secs(5)).unwrap();
+
+        assert!(diagnosis.info.unknown_fields.is_empty());
+        assert!(diagnosis.info.parsed.is_ok());
+        let data = diagnosis.data.as_ref().unwrap();
+        assert_eq!(data.url, format!("{}/api/v1/data", server.base_url()));
+        assert_eq!(data.raw["active_power_w"], 543.0);
+        assert!(data
+            .parsed
+            .as_ref()
+            .unwrap()
+            .contains("EnergySocketDataResponse"));
+        assert!(data.unknown_fields.is_empty());
+        assert!(diagnosis.to_string().contains("Unknown fields: none"));
+    }
+
+    #[test]
+    fn diagnose_highlights_unknown_fields() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(
+                "/api",
+                FakeResponse::json(
+                    r#"{"product_type":"HWE-SKT","product_name":"Energy Socket","serial":"3c39e72e33ce","firmware_version":"4.00","api_version":"v1","cloud_enabled":true}"#,
+                ),
+            )
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(
+                    r#"{"wifi_ssid":"My Wi-Fi","wifi_strength":100,"total_power_import_t1_kwh":30.511,"total_power_export_t1_kwh":0.0,"active_power_w":543.0,"active_power_l1_w":543.0,"active_voltage_v":231.2,"active_current_a":2.35}"#,
+                ),
+            );
+
+        // act
+        let diagnosis = diagnose(&server.base_url(), Duration::from_secs(5)).unwrap();
+
+        assert_eq!(diagnosis.info.unknown_fields, vec!["cloud_enabled"]);
+        assert_eq!(
+            diagnosis.data.as_ref().unwrap().unknown_fields,
+            vec!["active_current_a", "active_voltage_v"]
+        );
+        assert!(diagnosis
+            .to_string()
+            .contains("Unknown fields: active_current_a, active_voltage_v"));
+    }
+
+    #[test]
+    fn diagnose_reports_unparseable_data() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api", FakeResponse::json(SOCKET_INFO))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(r#"{"wifi_ssid":"My Wi-Fi"}"#),
+            );
+
+        // act
+        let diagnosis = diagnose(&server.base_url(), Duration::from_secs(5)).unwrap();
+
+        assert!(diagnosis.data.unwrap().parsed.is_err());
+    }
+
+    #[test]
+    fn diagnose_fails_when_device_is_unreachable() {
+        let server = FakeDeviceServer::start();
+
+        // act
+        let result = diagnose(&server.base_url(), Duration::from_secs(5));
+
+        assert!(result.is_err());
+    }
+}
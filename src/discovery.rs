@@ -0,0 +1,344 @@
+use crate::homewizard_client::{HomewizardDevice, DEFAULT_PORT};
+use crate::model::Config;
+#[cfg(feature = "mdns")]
+use mdns_sd::{ServiceDaemon, ServiceEvent};
+#[cfg(feature = "mdns")]
+use std::collections::HashMap;
+use std::error::Error;
+use std::time::Duration;
+#[cfg(feature = "mdns")]
+use std::time::Instant;
+#[cfg(feature = "mdns")]
+use tracing::{debug, info, warn};
+
+/// Finds the devices to measure.
+pub trait DeviceDiscoverer {
+    /// Returns the devices found within `timeout`.
+    fn discover(
+        &self,
+        timeout: Duration,
+    ) -> Result<Vec<HomewizardDevice>, Box<dyn Error + Send + Sync>>;
+}
+
+/// The discoverer a [`crate::HomewizardClient`] starts with: mdns.
+#[cfg(feature = "mdns")]
+pub fn default_discoverer() -> Box<dyn DeviceDiscoverer + Send + Sync> {
+    Box::new(MdnsDiscoverer {})
+}
+
+/// The discoverer a [`crate::HomewizardClient`] starts with: without the `mdns` feature one that
+/// finds nothing, leaving only the devices configured by address to measure.
+#[cfg(not(feature = "mdns"))]
+pub fn default_discoverer() -> Box<dyn DeviceDiscoverer + Send + Sync> {
+    Box::new(StaticDiscoverer::new(vec![]))
+}
+
+/// Fails when the exporter has no way to find devices: built without the `mdns` feature, with no
+/// devices configured by address in `devices` or under `locations`.
+pub fn check_source(config: &Config) -> Result<(), Box<dyn Error>> {
+    let configured = config.static_devices().next().is_some();
+    if cfg!(feature = "mdns") || configured {
+        return Ok(());
+    }
+
+    Err("Built without the mdns feature, so devices can't be discovered; configure devices by address in devices or under locations, or measure one with measure --ip".into())
+}
+
+/// Browses mdns for the `_hwenergy._tcp` services HomeWizard devices advertise, for the whole
+/// timeout.
+#[cfg(feature = "mdns")]
+pub struct MdnsDiscoverer {}
+
+#[cfg(feature = "mdns")]
+impl DeviceDiscoverer for MdnsDiscoverer {
+    fn discover(
+        &self,
+        timeout: Duration,
+    ) -> Result<Vec<HomewizardDevice>, Box<dyn Error + Send + Sync>> {
+        let mut devices: HashMap<String, HomewizardDevice> = HashMap::new();
+
+        // Create a daemon
+        let mdns =
+            ServiceDaemon::new().map_err(|e| format!("Failed to create mdns daemon: {}", e))?;
+
+        // Browse for a service type.
+        let service_type = "_hwenergy._tcp.local.";
+        let receiver = mdns
+            .browse(service_type)
+            .map_err(|e| format!("Failed to browse {}: {}", service_type, e))?;
+
+        let start = Instant::now();
+
+        // waiting with a timeout, so discovery ends on time even when no events come in at all
+        while let Ok(event) = receiver.recv_timeout(timeout.saturating_sub(start.elapsed())) {
+            match event {
+                ServiceEvent::ServiceResolved(info) => {
+                    let fullname = info.get_fullname().to_string();
+                    let ip_addresses = info.get_addresses().clone();
+                    let port = advertised_port(info.get_port());
+                    let hostname = Some(info.get_hostname().to_string())
+                        .filter(|hostname| !hostname.is_empty());
+                    info!(
+                        "At {:?}: Resolved a new service: {} host: {} port: {} IP: {:?}",
+                        start.elapsed(),
+                        fullname,
+                        hostname.as_deref().unwrap_or("-"),
+                        port,
+                        ip_addresses
+                    );
+
+                    let api_path = info.get_properties().get("path").and_then(|path| {
+                        let api_path = parse_api_path(path);
+                        if api_path.is_none() {
+                            warn!(
+                                "Ignoring path {:?} advertised by {}, falling back to the api version",
+                                path, fullname
+                            );
+                        }
+                        api_path
+                    });
+
+                    devices.insert(
+                        fullname.clone(),
+                        HomewizardDevice {
+                            fullname,
+                            ip_addresses,
+                            port,
+                            hostname,
+                            api_path,
+                        },
+                    );
+                }
+                other_event => {
+                    debug!(
+                        "At {:?} : Received other event: {:?}",
+                        start.elapsed(),
+                        &other_event
+                    );
+                }
+            }
+        }
+
+        if let Err(e) = mdns.shutdown() {
+            warn!("Failed shutting down mdns daemon: {}", e);
+        }
+
+        Ok(devices.into_values().collect())
+    }
+}
+
+/// The port a device advertised, or [`DEFAULT_PORT`] when it advertised none.
+#[cfg_attr(not(feature = "mdns"), allow(dead_code))]
+pub(crate) fn advertised_port(port: u16) -> u16 {
+    match port {
+        0 => DEFAULT_PORT,
+        port => port,
+    }
+}
+
+/// Normalizes the `path` TXT record of a device to an absolute path without trailing slash;
+/// `None` for anything that isn't a plain absolute path below the root.
+#[cfg_attr(not(feature = "mdns"), allow(dead_code))]
+pub(crate) fn parse_api_path(path: &str) -> Option<String> {
+    let path = path.trim().trim_end_matches('/');
+    let valid = path.starts_with('/')
+        && path.split('/').skip(1).all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-._~".contains(c))
+        });
+
+    valid.then(|| path.to_string())
+}
+
+/// Always returns the same devices, without touching the network.
+pub struct StaticDiscoverer {
+    devices: Vec<HomewizardDevice>,
+}
+
+impl StaticDiscoverer {
+    pub fn new(devices: Vec<HomewizardDevice>) -> Self {
+        Self { devices }
+    }
+}
+
+impl DeviceDiscoverer for StaticDiscoverer {
+    fn discover(
+        &self,
+        _timeout: Duration,
+    ) -> Result<Vec<HomewizardDevice>, Box<dyn Error + Send + Sync>> {
+        Ok(self.devices.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::LocationConfig;
+    #[cfg(feature = "mdns")]
+    use crate::test_support::FakeMdnsResponder;
+    use std::net::Ipv4Addr;
+    #[cfg(feature = "mdns")]
+    use uuid::Uuid;
+
+    #[cfg(feature = "mdns")]
+    fn instance_name(prefix: &str) -> String {
+        format!(
+            "{}-{}",
+            prefix,
+            &Uuid::new_v4().to_simple().to_string()[..12]
+        )
+    }
+
+    #[test]
+    fn static_discoverer_returns_its_devices() {
+        let device = HomewizardDevice {
+            fullname: "watermeter-2D7A68._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::new(192, 168, 1, 20)].into_iter().collect(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        };
+        let discoverer = StaticDiscoverer::new(vec![device.clone()]);
+
+        // act
+        let devices = discoverer.discover(Duration::from_secs(10)).unwrap();
+
+        assert_eq!(devices, vec![device]);
+    }
+
+    #[test]
+    fn advertised_port_defaults_to_80() {
+        // act
+        let absent = advertised_port(0);
+        let advertised = advertised_port(443);
+
+        assert_eq!(absent, 80);
+        assert_eq!(advertised, 443);
+    }
+
+    #[test]
+    fn parse_api_path_normalizes_advertised_path() {
+        // act
+        let v1 = parse_api_path("/api/v1");
+        let trailing_slash = parse_api_path("/api/v2/");
+        let relative = parse_api_path("api/v1");
+        let root = parse_api_path("/");
+        let parent = parse_api_path("/api/../admin");
+        let query = parse_api_path("/api/v1?token=secret");
+
+        assert_eq!(v1, Some("/api/v1".into()));
+        assert_eq!(trailing_slash, Some("/api/v2".into()));
+        assert_eq!(relative, None);
+        assert_eq!(root, None);
+        assert_eq!(parent, None);
+        assert_eq!(query, None);
+    }
+
+    fn config_with_devices(devices: Vec<std::net::SocketAddrV4>) -> Config {
+        Config {
+            location: "My Home".into(),
+            locations: vec![LocationConfig {
+                location: "Parents".into(),
+                serials: vec![],
+                devices: devices.into_iter().map(Into::into).collect(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_source_accepts_devices_configured_by_address() {
+        let config = config_with_devices(vec!["10.1.0.31:80".parse().unwrap()]);
+
+        // act
+        let result = check_source(&config);
+
+        assert!(result.is_ok());
+    }
+
+    #[cfg(feature = "mdns")]
+    #[test]
+    fn check_source_accepts_mdns_without_configured_devices() {
+        // act
+        let result = check_source(&config_with_devices(vec![]));
+
+        assert!(result.is_ok());
+    }
+
+    #[cfg(not(feature = "mdns"))]
+    #[test]
+    fn check_source_fails_without_mdns_and_configured_devices() {
+        // act
+        let result = check_source(&config_with_devices(vec![]));
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .starts_with("Built without the mdns feature"));
+    }
+
+    #[cfg(not(feature = "mdns"))]
+    #[test]
+    fn default_discoverer_finds_nothing_without_mdns() {
+        // act
+        let devices = default_discoverer()
+            .discover(Duration::from_secs(10))
+            .unwrap();
+
+        assert!(devices.is_empty());
+    }
+
+    #[cfg(feature = "mdns")]
+    #[test]
+    #[ignore = "needs multicast loopback, run with cargo test -- --ignored"]
+    fn mdns_discoverer_resolves_announced_devices_once() {
+        let responder = FakeMdnsResponder::start();
+        let p1_meter =
+            responder.announce(&instance_name("p1meter"), "HWE-P1", "5c2faf0a8b3e", 8080);
+        let socket = responder.announce(
+            &instance_name("energysocket"),
+            "HWE-SKT",
+            "3c39e72e33ce",
+            8081,
+        );
+
+        // act
+        let devices = MdnsDiscoverer {}.discover(Duration::from_secs(3)).unwrap();
+
+        // devices on the network running the test are discovered too
+        let announced: Vec<&HomewizardDevice> = devices
+            .iter()
+            .filter(|device| device.fullname == p1_meter || device.fullname == socket)
+            .collect();
+        assert_eq!(announced.len(), 2);
+        let p1_device = announced
+            .iter()
+            .find(|device| device.fullname == p1_meter)
+            .unwrap();
+        assert_eq!(p1_device.port, 8080);
+        assert!(p1_device
+            .hostname
+            .as_deref()
+            .map_or(false, |hostname| hostname.ends_with(".local.")));
+        assert!(p1_device.ip_addresses.contains(&Ipv4Addr::LOCALHOST));
+        assert_eq!(p1_device.api_path.as_deref(), Some("/api/v1"));
+    }
+
+    #[cfg(feature = "mdns")]
+    #[test]
+    #[ignore = "needs multicast loopback, run with cargo test -- --ignored"]
+    fn mdns_discoverer_stops_at_timeout_without_events() {
+        let start = Instant::now();
+
+        // act
+        MdnsDiscoverer {}
+            .discover(Duration::from_millis(500))
+            .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,310 @@
+use crate::config_merge;
+use crate::device_tokens;
+use crate::model::{Config, OnEmpty};
+use crate::name_sanitization;
+use jarvis_lib::config_client::{ConfigClient, SetDefaults};
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::io;
+use std::path::PathBuf;
+use tracing::{debug, info};
+
+/// Config options set through `CONFIG_*` environment variables, layered over the config file,
+/// or making up the whole config when there is no config file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EnvConfig {
+    location: Option<String>,
+    names: Option<HashMap<String, String>>,
+    on_empty: Option<OnEmpty>,
+    /// Config files merged in order instead of reading the single one of the config client.
+    config_paths: Vec<PathBuf>,
+}
+
+impl EnvConfig {
+    pub fn new(
+        location: Option<String>,
+        names: Option<HashMap<String, String>>,
+        on_empty: Option<OnEmpty>,
+    ) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "EnvConfig::new(location: {:?}, names: {:?}, on_empty: {:?})",
+            location, names, on_empty
+        );
+        Ok(Self {
+            location,
+            names,
+            on_empty,
+            config_paths: vec![],
+        })
+    }
+
+    /// Merges the config files in `config_paths` in order, when there is more than one.
+    pub fn with_config_paths(mut self, config_paths: Vec<PathBuf>) -> Self {
+        debug!(
+            "EnvConfig::with_config_paths(config_paths: {:?})",
+            config_paths
+        );
+        self.config_paths = config_paths;
+        self
+    }
+
+    /// Reads `CONFIG_LOCATION`, `CONFIG_NAMES` and `CONFIG_ON_EMPTY`, and `CONFIG_PATH` when it
+    /// lists several comma separated config files.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let location = env::var("CONFIG_LOCATION").ok();
+        let names = env::var("CONFIG_NAMES")
+            .ok()
+            .map(|names| parse_names(&names))
+            .transpose()?;
+        let on_empty = env::var("CONFIG_ON_EMPTY")
+            .ok()
+            .map(|on_empty| parse_on_empty(&on_empty))
+            .transpose()?;
+
+        let config_paths = env::var("CONFIG_PATH")
+            .ok()
+            .map(|config_path| config_merge::config_paths(&config_path))
+            .unwrap_or_default();
+
+        Ok(Self::new(location, names, on_empty)?.with_config_paths(config_paths))
+    }
+
+    /// Overrides the options of `config` that are set; names get added to the ones of the
+    /// config file, replacing the name of a serial in both.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(location) = &self.location {
+            config.location = location.clone();
+        }
+        if let Some(names) = &self.names {
+            config.names.extend(names.clone());
+        }
+        if let Some(on_empty) = self.on_empty {
+            config.on_empty = on_empty;
+        }
+    }
+
+    /// The config made up of these options alone, with every other option at its default,
+    /// validated since no config file vouches for it.
+    pub fn to_config(&self) -> Result<Config, Box<dyn Error>> {
+        if self.location.is_none() {
+            return Err("CONFIG_LOCATION is required without a config file".into());
+        }
+
+        let mut config = Config::default();
+        config.set_defaults();
+        self.apply(&mut config);
+        config.validate()?;
+
+        Ok(config)
+    }
+}
+
+/// Reads the config file - or merges the config files - with the `CONFIG_*` environment
+/// variables layered over it. Without a config file `CONFIG_LOCATION` turns the environment
+/// variables into the whole config. Either way the names get sanitized and the token files
+/// read.
+pub fn read_config(
+    config_client: &ConfigClient,
+    env_config: &EnvConfig,
+) -> Result<Config, Box<dyn Error>> {
+    let result = if env_config.config_paths.len() > 1 {
+        config_merge::read_merged(&env_config.config_paths)
+    } else {
+        config_client.read_config_from_file()
+    };
+    let mut config: Config = match result {
+        Ok(mut config) => {
+            env_config.apply(&mut config);
+            config
+        }
+        Err(e) if env_config.location.is_some() && is_not_found(e.as_ref()) => {
+            info!("No config file found, taking the config from CONFIG_* environment variables");
+            env_config.to_config()?
+        }
+        Err(e) => return Err(e),
+    };
+    name_sanitization::sanitize_names(&mut config);
+    device_tokens::read_token_files(&mut config)?;
+
+    Ok(config)
+}
+
+fn is_not_found(error: &(dyn Error + 'static)) -> bool {
+    let mut current = Some(error);
+    while let Some(error) = current {
+        if let Some(io_error) = error.downcast_ref::<io::Error>() {
+            return io_error.kind() == io::ErrorKind::NotFound;
+        }
+        current = error.source();
+    }
+
+    false
+}
+
+/// Parses comma separated `serial=name` pairs.
+fn parse_names(names: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let mut parsed = HashMap::new();
+
+    for pair in names.split(',').filter(|pair| !pair.trim().is_empty()) {
+        let (serial, name) = match pair.split_once('=') {
+            Some((serial, name)) if !serial.trim().is_empty() && !name.trim().is_empty() => {
+                (serial.trim().to_string(), name.trim().to_string())
+            }
+            _ => {
+                return Err(format!(
+                    "CONFIG_NAMES has to be a list of serial=name pairs instead of {}",
+                    pair
+                )
+                .into())
+            }
+        };
+        if parsed.insert(serial.clone(), name).is_some() {
+            return Err(format!("CONFIG_NAMES has serial {} more than once", serial).into());
+        }
+    }
+
+    Ok(parsed)
+}
+
+fn parse_on_empty(on_empty: &str) -> Result<OnEmpty, Box<dyn Error>> {
+    serde_json::from_value(serde_json::Value::String(on_empty.to_string())).map_err(|_| {
+        format!(
+            "CONFIG_ON_EMPTY has to be error, skip or heartbeat instead of {}",
+            on_empty
+        )
+        .into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jarvis_lib::config_client::ConfigClientConfig;
+
+    #[test]
+    fn parse_names_reads_serial_name_pairs() {
+        // act
+        let names = parse_names("3c39e72e33ce=Bonenmaler, 5c2faf0a8b3e = P1 meter,").unwrap();
+
+        assert_eq!(names.len(), 2);
+        assert_eq!(names["3c39e72e33ce"], "Bonenmaler");
+        assert_eq!(names["5c2faf0a8b3e"], "P1 meter");
+    }
+
+    #[test]
+    fn parse_names_rejects_malformed_pairs() {
+        // act
+        let without_name = parse_names("3c39e72e33ce=Bonenmaler,5c2faf0a8b3e");
+        let empty_serial = parse_names("=Bonenmaler");
+        let empty_name = parse_names("3c39e72e33ce= ");
+        let duplicate = parse_names("3c39e72e33ce=Bonenmaler,3c39e72e33ce=Koffie");
+
+        assert_eq!(
+            without_name.unwrap_err().to_string(),
+            "CONFIG_NAMES has to be a list of serial=name pairs instead of 5c2faf0a8b3e"
+        );
+        assert!(empty_serial.is_err());
+        assert!(empty_name.is_err());
+        assert_eq!(
+            duplicate.unwrap_err().to_string(),
+            "CONFIG_NAMES has serial 3c39e72e33ce more than once"
+        );
+    }
+
+    #[test]
+    fn parse_on_empty_accepts_lowercase_variants_only() {
+        // act
+        let skip = parse_on_empty("skip");
+        let invalid = parse_on_empty("Skip");
+
+        assert_eq!(skip.unwrap(), OnEmpty::Skip);
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn read_config_builds_config_from_env_without_config_file() {
+        let config_client =
+            ConfigClient::new(ConfigClientConfig::new("missing-config.yaml".to_string()).unwrap());
+        let env_config = EnvConfig::new(
+            Some("My Home".into()),
+            Some(parse_names("3c39e72e33ce=Bonenmaler").unwrap()),
+            Some(OnEmpty::Heartbeat),
+        )
+        .unwrap();
+
+        // act
+        let config = read_config(&config_client, &env_config).unwrap();
+
+        assert_eq!(config.location, "My Home");
+        assert_eq!(config.names["3c39e72e33ce"], "Bonenmaler");
+        assert_eq!(config.on_empty, OnEmpty::Heartbeat);
+        assert_eq!(config.device_events, None);
+    }
+
+    #[test]
+    fn read_config_requires_a_location_without_config_file() {
+        let config_client =
+            ConfigClient::new(ConfigClientConfig::new("missing-config.yaml".to_string()).unwrap());
+        let env_config = EnvConfig::new(None, Some(HashMap::new()), None).unwrap();
+
+        // act
+        let result = read_config(&config_client, &env_config);
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn to_config_validates_the_config() {
+        let env_config = EnvConfig::new(Some(" ".into()), None, None).unwrap();
+
+        // act
+        let result = env_config.to_config();
+
+        assert_eq!(result.unwrap_err().to_string(), "location can't be empty");
+    }
+
+    #[test]
+    fn read_config_merges_config_files_before_layering_env() {
+        let config_client =
+            ConfigClient::new(ConfigClientConfig::new("missing-config.yaml".to_string()).unwrap());
+        let env_config = EnvConfig::new(
+            None,
+            Some(parse_names("5c2faf0a8b3e=P1 meter").unwrap()),
+            None,
+        )
+        .unwrap()
+        .with_config_paths(config_merge::config_paths(
+            "test-config.yaml,tests/fixtures/config/site-override.yaml",
+        ));
+
+        // act
+        let config = read_config(&config_client, &env_config).unwrap();
+
+        assert_eq!(config.location, "Holiday Home");
+        assert_eq!(config.names["3c39e72e33ce"], "Koffie");
+        assert_eq!(config.names["5c2faf0a8b3e"], "P1 meter");
+        assert_eq!(config.device_events.unwrap().offline_threshold, 2);
+    }
+
+    #[test]
+    fn read_config_layers_env_over_config_file() {
+        let config_client =
+            ConfigClient::new(ConfigClientConfig::new("test-config.yaml".to_string()).unwrap());
+        let env_config = EnvConfig::new(
+            Some("Holiday Home".into()),
+            Some(parse_names("5c2faf0a8b3e=P1 meter").unwrap()),
+            None,
+        )
+        .unwrap();
+
+        // act
+        let config = read_config(&config_client, &env_config).unwrap();
+
+        assert_eq!(config.location, "Holiday Home");
+        assert_eq!(config.names["3c39e72e33ce"], "Bonenmaler");
+        assert_eq!(config.names["5c2faf0a8b3e"], "P1 meter");
+        assert_eq!(config.on_empty, OnEmpty::Error);
+        assert!(config.device_events.is_some());
+    }
+}
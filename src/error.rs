@@ -0,0 +1,189 @@
+use std::error::Error;
+use std::fmt;
+
+/// Exit code for config errors: an unreadable or invalid config file or environment variable.
+pub const EXIT_CONFIG: i32 = 78;
+/// Exit code for device errors: discovery failing or the targeted device not responding.
+pub const EXIT_DEVICE: i32 = 69;
+/// Exit code for failures publishing measurements to NATS or reading and storing state.
+pub const EXIT_PUBLISH: i32 = 74;
+/// Exit code for panics.
+pub const EXIT_PANIC: i32 = 70;
+/// Exit code for any other error.
+pub const EXIT_OTHER: i32 = 1;
+
+/// The config can't be read or is invalid; retrying won't help until it gets fixed.
+#[derive(Debug)]
+pub struct ConfigError {
+    source: Box<dyn Error>,
+}
+
+impl ConfigError {
+    pub fn new(source: Box<dyn Error>) -> Self {
+        Self { source }
+    }
+
+    /// Whether `error` or any error in its source chain is a config error, which no amount of
+    /// retrying will resolve.
+    pub fn is(error: &(dyn Error + 'static)) -> bool {
+        exit_code(error) == EXIT_CONFIG
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid config: {}", self.source)
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// Devices couldn't be discovered or the device that was asked for couldn't be read. It can be
+/// sent between threads, like the errors of the device requests it wraps.
+#[derive(Debug)]
+pub struct DeviceError {
+    source: Box<dyn Error + Send + Sync>,
+}
+
+impl DeviceError {
+    pub fn new(source: Box<dyn Error + Send + Sync>) -> Self {
+        Self { source }
+    }
+}
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Device failure: {}", self.source)
+    }
+}
+
+impl Error for DeviceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// Measurements couldn't be published, or the last measurements couldn't be read or stored.
+#[derive(Debug)]
+pub struct PublishError {
+    source: Box<dyn Error>,
+}
+
+impl PublishError {
+    pub fn new(source: Box<dyn Error>) -> Self {
+        Self { source }
+    }
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Publish failure: {}", self.source)
+    }
+}
+
+impl Error for PublishError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+/// Maps an error to the exit code of the process, using the first typed error in its chain.
+pub fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    let mut current = Some(error);
+
+    while let Some(error) = current {
+        if error.is::<ConfigError>() {
+            return EXIT_CONFIG;
+        }
+        if error.is::<DeviceError>() {
+            return EXIT_DEVICE;
+        }
+        if error.is::<PublishError>() {
+            return EXIT_PUBLISH;
+        }
+        current = error.source();
+    }
+
+    EXIT_OTHER
+}
+
+/// Makes a panic anywhere in the process exit with [`EXIT_PANIC`], after the default hook has
+/// reported it.
+pub fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        std::process::exit(EXIT_PANIC);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug)]
+    struct WrappingError(Box<dyn Error>);
+
+    impl fmt::Display for WrappingError {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Cycle failed: {}", self.0)
+        }
+    }
+
+    impl Error for WrappingError {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(self.0.as_ref())
+        }
+    }
+
+    #[test]
+    fn exit_code_maps_typed_errors() {
+        let config_error: Box<dyn Error> =
+            ConfigError::new("missing field `location`".into()).into();
+        let device_error: Box<dyn Error> = DeviceError::new("Failed to browse".into()).into();
+        let publish_error: Box<dyn Error> =
+            PublishError::new("nats: connection refused".into()).into();
+        let other_error: Box<dyn Error> = "something else".into();
+
+        assert_eq!(exit_code(config_error.as_ref()), EXIT_CONFIG);
+        assert_eq!(exit_code(device_error.as_ref()), EXIT_DEVICE);
+        assert_eq!(exit_code(publish_error.as_ref()), EXIT_PUBLISH);
+        assert_eq!(exit_code(other_error.as_ref()), EXIT_OTHER);
+    }
+
+    #[test]
+    fn exit_code_finds_typed_errors_in_the_source_chain() {
+        let error = WrappingError(PublishError::new("nats: connection refused".into()).into());
+
+        assert_eq!(exit_code(&error), EXIT_PUBLISH);
+    }
+
+    #[test]
+    fn config_error_is_found_in_the_source_chain() {
+        let wrapped = WrappingError(ConfigError::new("missing field `location`".into()).into());
+        let publish_error =
+            WrappingError(PublishError::new("nats: connection refused".into()).into());
+
+        assert!(ConfigError::is(&wrapped));
+        assert!(!ConfigError::is(&publish_error));
+    }
+
+    #[test]
+    fn exit_codes_are_distinct() {
+        let mut codes = vec![
+            EXIT_CONFIG,
+            EXIT_DEVICE,
+            EXIT_PUBLISH,
+            EXIT_PANIC,
+            EXIT_OTHER,
+        ];
+        codes.sort();
+        codes.dedup();
+
+        assert_eq!(codes.len(), 5);
+    }
+}
@@ -0,0 +1,107 @@
+use serde::Serialize;
+use std::env;
+use std::error::Error;
+use tracing::{debug, warn};
+
+/// Publishes exporter events (as opposed to measurements) as json on a NATS subject.
+pub trait EventPublisher {
+    /// Publishes the serialized event on `subject`.
+    fn publish(&self, subject: &str, payload: &[u8]) -> Result<(), Box<dyn Error>>;
+}
+
+/// Serializes and publishes each event, logging rather than returning failures so events never
+/// break a measurement cycle.
+pub fn publish_events<T: Serialize>(publisher: &dyn EventPublisher, subject: &str, events: &[T]) {
+    for event in events {
+        let result = match serde_json::to_vec(event) {
+            Ok(payload) => publisher.publish(subject, &payload),
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(e) = result {
+            warn!("Failed publishing event to subject {}: {}", subject, e);
+        }
+    }
+}
+
+/// Settings of a [`NatsEventPublisher`].
+pub struct NatsEventPublisherConfig {
+    host: String,
+}
+
+impl NatsEventPublisherConfig {
+    pub fn new(host: String) -> Result<Self, Box<dyn Error>> {
+        debug!("NatsEventPublisherConfig::new(host: {})", host);
+        Ok(Self { host })
+    }
+
+    /// Reads `NATS_HOST`.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let host = env::var("NATS_HOST").unwrap_or_else(|_| "jarvis-nats".to_string());
+
+        Self::new(host)
+    }
+}
+
+/// Connects to the same NATS server as the measurement publisher, but sends to a subject of
+/// choice per event. The connection is made once and reconnects by itself when the server goes
+/// away, so events don't cost a handshake each.
+pub struct NatsEventPublisher {
+    connection: nats::Connection,
+}
+
+impl NatsEventPublisher {
+    pub fn connect(config: NatsEventPublisherConfig) -> Result<Self, Box<dyn Error>> {
+        let connection = nats::connect(&config.host)
+            .map_err(|e| format!("Failed connecting to NATS at {}: {}", config.host, e))?;
+
+        Ok(Self { connection })
+    }
+}
+
+impl EventPublisher for NatsEventPublisher {
+    fn publish(&self, subject: &str, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.connection.publish(subject, payload)?;
+        self.connection.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::FakeNatsServer;
+    use serde_json::json;
+
+    #[test]
+    fn nats_event_publisher_publishes_every_event_over_one_connection() {
+        let server = FakeNatsServer::start();
+        let publisher = NatsEventPublisher::connect(
+            NatsEventPublisherConfig::new(server.address().to_string()).unwrap(),
+        )
+        .unwrap();
+
+        // act
+        publish_events(
+            &publisher,
+            "jarvis.events.devices",
+            &[json!({"event": "offline"}), json!({"event": "online"})],
+        );
+
+        assert_eq!(server.connections(), 1);
+        assert_eq!(
+            server.published(),
+            vec![
+                (
+                    "jarvis.events.devices".to_string(),
+                    r#"{"event":"offline"}"#.to_string()
+                ),
+                (
+                    "jarvis.events.devices".to_string(),
+                    r#"{"event":"online"}"#.to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::model::ExpectedSamples;
+use crate::mqtt::topic_level;
+use jarvis_lib::model::Sample;
+
+/// How the samples of a device fall short of what is expected of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shortfall {
+    pub expected: usize,
+    pub actual: usize,
+    /// The expected samples that are missing, only known when they're listed by name.
+    pub missing: Vec<String>,
+}
+
+/// Names a sample the way the part of its mqtt topic after the device does, for instance
+/// `t1-import/electricity-consumption/counter`.
+pub fn sample_key(sample: &Sample) -> String {
+    format!(
+        "{}/{}/{}",
+        topic_level(&sample.sample_name),
+        topic_level(&format!("{:?}", sample.sample_type)),
+        topic_level(&format!("{:?}", sample.metric_type))
+    )
+}
+
+/// Compares the samples a device produced with what is expected of it.
+pub fn shortfall(expected: &ExpectedSamples, samples: &[Sample]) -> Option<Shortfall> {
+    match expected {
+        ExpectedSamples::Count(count) => (samples.len() < *count).then(|| Shortfall {
+            expected: *count,
+            actual: samples.len(),
+            missing: vec![],
+        }),
+        ExpectedSamples::Names(names) => {
+            let keys: Vec<String> = samples.iter().map(sample_key).collect();
+            let missing: Vec<String> = names
+                .iter()
+                .filter(|name| !keys.contains(name))
+                .cloned()
+                .collect();
+
+            (!missing.is_empty()).then(|| Shortfall {
+                expected: names.len(),
+                actual: names.len() - missing.len(),
+                missing,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jarvis_lib::model::{EntityType, MetricType, SampleType};
+
+    fn samples() -> Vec<Sample> {
+        vec![
+            Sample {
+                entity_type: EntityType::Device,
+                entity_name: "HWE-P1".into(),
+                sample_type: SampleType::ElectricityConsumption,
+                sample_name: "t1 import".into(),
+                metric_type: MetricType::Counter,
+                value: 1.0,
+            },
+            Sample {
+                entity_type: EntityType::Device,
+                entity_name: "HWE-P1".into(),
+                sample_type: SampleType::ElectricityConsumption,
+                sample_name: "P1 meter".into(),
+                metric_type: MetricType::Gauge,
+                value: 2.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn shortfall_accepts_exact_match() {
+        // act
+        let count = shortfall(&ExpectedSamples::Count(2), &samples());
+        let names = shortfall(
+            &ExpectedSamples::Names(vec![
+                "t1-import/electricity-consumption/counter".into(),
+                "p1-meter/electricity-consumption/gauge".into(),
+            ]),
+            &samples(),
+        );
+
+        assert_eq!(count, None);
+        assert_eq!(names, None);
+    }
+
+    #[test]
+    fn shortfall_counts_missing_samples() {
+        // act
+        let result = shortfall(&ExpectedSamples::Count(5), &samples());
+
+        assert_eq!(
+            result,
+            Some(Shortfall {
+                expected: 5,
+                actual: 2,
+                missing: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn shortfall_names_missing_samples() {
+        // act
+        let result = shortfall(
+            &ExpectedSamples::Names(vec![
+                "t1-import/electricity-consumption/counter".into(),
+                "gas/gas-consumption/counter".into(),
+            ]),
+            &samples(),
+        );
+
+        assert_eq!(
+            result,
+            Some(Shortfall {
+                expected: 2,
+                actual: 1,
+                missing: vec!["gas/gas-consumption/counter".into()],
+            })
+        );
+    }
+}
@@ -0,0 +1,1629 @@
+use crate::archive::MeasurementArchive;
+use crate::csv_export::CsvExport;
+use crate::env_config::{self, EnvConfig};
+use crate::error::{ConfigError, PublishError};
+use crate::homewizard_client::{CycleSummary, HomewizardDevice};
+use crate::jitter::{self, JitterConfig};
+use crate::manual_trigger::TriggerSignal;
+use crate::model::Config;
+use crate::reload::ReloadSignal;
+use crate::shutdown::ShutdownSignal;
+use crate::spool::MeasurementSpool;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use jarvis_lib::config_client::ConfigClient;
+use jarvis_lib::measurement_client::MeasurementClient;
+use jarvis_lib::model::Measurement;
+use jarvis_lib::nats_client::NatsClient;
+use jarvis_lib::state_client::StateClient;
+use serde::{Deserialize, Serialize};
+use std::cell::{Cell, RefCell};
+use std::env;
+use std::error::Error;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+
+pub struct ExporterConfig {
+    pub dry_run: bool,
+    pub interval: Option<Duration>,
+    pub output: Output,
+    /// Cycles in a row that may fail on an interval before the exporter fails as a whole.
+    pub max_failed_cycles: u32,
+}
+
+impl ExporterConfig {
+    pub fn new(
+        dry_run: bool,
+        interval: Option<Duration>,
+        output: Output,
+        max_failed_cycles: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "ExporterConfig::new(dry_run: {}, interval: {:?}, output: {:?}, max_failed_cycles: {})",
+            dry_run, interval, output, max_failed_cycles
+        );
+        if max_failed_cycles == 0 {
+            return Err("MAX_FAILED_CYCLES has to be at least 1".into());
+        }
+        Ok(Self {
+            dry_run,
+            interval,
+            output,
+            max_failed_cycles,
+        })
+    }
+
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let dry_run: bool = env::var("DRY_RUN")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()?;
+        let interval = match env::var("INTERVAL_SECONDS") {
+            Ok(interval_seconds) => Some(Duration::from_secs(interval_seconds.parse()?)),
+            Err(_) => None,
+        };
+        let output: Output = env::var("OUTPUT")
+            .unwrap_or_else(|_| "nats".to_string())
+            .parse()?;
+        let max_failed_cycles: u32 = env::var("MAX_FAILED_CYCLES")
+            .unwrap_or_else(|_| "5".to_string())
+            .parse()?;
+
+        Self::new(dry_run, interval, output, max_failed_cycles)
+    }
+}
+
+/// Where published measurements go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Nats,
+    /// One json line per measurement on stdout, without any NATS infrastructure.
+    Stdout,
+    /// A retained message per sample on an MQTT broker, instead of NATS.
+    Mqtt,
+}
+
+impl FromStr for Output {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Output, Self::Err> {
+        match input {
+            "nats" => Ok(Output::Nats),
+            "stdout" => Ok(Output::Stdout),
+            "mqtt" => Ok(Output::Mqtt),
+            _ => Err(format!(
+                "OUTPUT has to be nats, stdout or mqtt instead of {}",
+                input
+            )),
+        }
+    }
+}
+
+/// Destination for the measurements of a cycle.
+#[async_trait(?Send)]
+pub trait MeasurementPublisher {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>>;
+}
+
+#[async_trait(?Send)]
+impl MeasurementPublisher for NatsClient {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        NatsClient::publish(self, measurement).await
+    }
+}
+
+/// Told about the config each cycle runs with, such as after it got reloaded.
+pub trait ConfigObserver {
+    fn config_changed(&self, config: &Config);
+}
+
+/// Keeps the last published measurements, which get handed to the next cycle.
+#[async_trait(?Send)]
+pub trait MeasurementStore {
+    fn read(&self) -> Result<Option<Vec<Measurement>>, Box<dyn Error>>;
+    async fn store(&self, measurements: &[Measurement]) -> Result<(), Box<dyn Error>>;
+}
+
+#[async_trait(?Send)]
+impl MeasurementStore for StateClient {
+    fn read(&self) -> Result<Option<Vec<Measurement>>, Box<dyn Error>> {
+        self.read_state()
+    }
+
+    async fn store(&self, measurements: &[Measurement]) -> Result<(), Box<dyn Error>> {
+        self.store_state(measurements).await
+    }
+}
+
+/// Keeps the last measurements in memory only, for outputs that don't need the state client.
+#[derive(Default)]
+pub struct InMemoryStore {
+    measurements: RefCell<Option<Vec<Measurement>>>,
+}
+
+#[async_trait(?Send)]
+impl MeasurementStore for InMemoryStore {
+    fn read(&self) -> Result<Option<Vec<Measurement>>, Box<dyn Error>> {
+        Ok(self.measurements.borrow().clone())
+    }
+
+    async fn store(&self, measurements: &[Measurement]) -> Result<(), Box<dyn Error>> {
+        *self.measurements.borrow_mut() = Some(measurements.to_vec());
+        Ok(())
+    }
+}
+
+/// The latest measurement that got published and stored, telling when the exporter last worked.
+/// It's taken from the last measurements in the store, which only get stored once published.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LastSuccess {
+    pub measured_at: DateTime<Utc>,
+    pub measurement_id: String,
+    pub samples: usize,
+}
+
+impl LastSuccess {
+    pub fn from_measurements(measurements: &[Measurement]) -> Option<Self> {
+        measurements
+            .iter()
+            .max_by_key(|measurement| measurement.measured_at_time)
+            .map(|measurement| Self {
+                measured_at: measurement.measured_at_time,
+                measurement_id: measurement.id.clone(),
+                samples: measurement.samples.len(),
+            })
+    }
+
+    /// Time since the measurement, or zero for one dated in the future.
+    pub fn age(&self, now: DateTime<Utc>) -> Duration {
+        (now - self.measured_at).to_std().unwrap_or_default()
+    }
+}
+
+/// Drives measurement cycles the same way jarvis-lib's `ExporterService` does - read config and
+/// last measurements, measure, publish, store - either once or on a fixed interval.
+pub struct Exporter {
+    config_client: ConfigClient,
+    env_config: EnvConfig,
+    measurement_client: Box<dyn MeasurementClient<Config>>,
+    publisher: Box<dyn MeasurementPublisher>,
+    secondary_publishers: Vec<Box<dyn MeasurementPublisher>>,
+    store: Box<dyn MeasurementStore>,
+    cycle_summary: Option<Arc<Mutex<Option<CycleSummary>>>>,
+    archive: MeasurementArchive,
+    csv_export: CsvExport,
+    spool: Option<MeasurementSpool>,
+    last_success: Arc<Mutex<Option<LastSuccess>>>,
+    startup_delay: Cell<Option<Duration>>,
+    cycle_jitter_max: Duration,
+    reload_signal: Option<ReloadSignal>,
+    trigger_signal: Option<TriggerSignal>,
+    config: RefCell<Option<Config>>,
+    config_observers: Vec<Rc<dyn ConfigObserver>>,
+    max_failed_cycles: Option<u32>,
+    failed_cycles: Cell<u32>,
+}
+
+impl Exporter {
+    pub fn new(
+        config_client: ConfigClient,
+        measurement_client: Box<dyn MeasurementClient<Config>>,
+        publisher: Box<dyn MeasurementPublisher>,
+        store: Box<dyn MeasurementStore>,
+    ) -> Self {
+        Self {
+            config_client,
+            env_config: EnvConfig::default(),
+            measurement_client,
+            publisher,
+            secondary_publishers: vec![],
+            store,
+            cycle_summary: None,
+            archive: MeasurementArchive::default(),
+            csv_export: CsvExport::default(),
+            spool: None,
+            last_success: Arc::new(Mutex::new(None)),
+            startup_delay: Cell::new(None),
+            cycle_jitter_max: Duration::ZERO,
+            reload_signal: None,
+            trigger_signal: None,
+            config: RefCell::new(None),
+            config_observers: vec![],
+            max_failed_cycles: None,
+            failed_cycles: Cell::new(0),
+        }
+    }
+
+    /// Returns a handle to the last successful measurement, for reporting it elsewhere.
+    pub fn last_success_handle(&self) -> Arc<Mutex<Option<LastSuccess>>> {
+        self.last_success.clone()
+    }
+
+    /// Reads the last successful measurement back from the store at startup and logs how long
+    /// the exporter was dark. Failing to read it only gets logged, since the first cycle reads
+    /// the store again anyway.
+    pub fn restore_last_success(&self) {
+        let last_success = match self.store.read() {
+            Ok(last_measurements) => {
+                LastSuccess::from_measurements(&last_measurements.unwrap_or_default())
+            }
+            Err(e) => {
+                warn!("Failed reading last successful measurement: {}", e);
+                return;
+            }
+        };
+
+        match &last_success {
+            Some(last_success) => info!(
+                measured_at = %last_success.measured_at,
+                measurement_id = %last_success.measurement_id,
+                samples = last_success.samples,
+                dark_seconds = last_success.age(Utc::now()).as_secs(),
+                "Exporter last published a measurement {:?} ago",
+                last_success.age(Utc::now())
+            ),
+            None => info!("No successful measurement recorded yet"),
+        }
+        *self.last_success.lock().unwrap() = last_success;
+    }
+
+    /// Layers the `CONFIG_*` environment variables over the config file every cycle.
+    pub fn with_env_config(mut self, env_config: EnvConfig) -> Self {
+        self.env_config = env_config;
+        self
+    }
+
+    /// Delays the first cycle by a random duration drawn once up to `startup_max`, and every
+    /// sleep between cycles by one up to `cycle_max`.
+    pub fn with_jitter(mut self, jitter_config: &JitterConfig) -> Self {
+        self.startup_delay = Cell::new(Some(jitter::draw(jitter_config.startup_max)));
+        self.cycle_jitter_max = jitter_config.cycle_max;
+        self
+    }
+
+    /// Reloads the config right away whenever `reload_signal` requests it, besides reading it
+    /// every cycle. Once a config got read, one that fails to read or validate keeps the
+    /// previous one in use instead of failing the cycle.
+    pub fn with_reload(mut self, reload_signal: ReloadSignal) -> Self {
+        self.reload_signal = Some(reload_signal);
+        self
+    }
+
+    /// Runs an extra cycle between the scheduled ones whenever `trigger_signal` requests one,
+    /// without moving the scheduled ones.
+    pub fn with_manual_trigger(mut self, trigger_signal: TriggerSignal) -> Self {
+        self.trigger_signal = Some(trigger_signal);
+        self
+    }
+
+    /// Tells `config_observer` about the config of every cycle, and about a reloaded config right
+    /// away.
+    pub fn with_config_observer(mut self, config_observer: Rc<dyn ConfigObserver>) -> Self {
+        self.config_observers.push(config_observer);
+        self
+    }
+
+    /// Makes a run on an interval fail once `max_failed_cycles` cycles in a row failed, like
+    /// when the publisher stays unreachable, for the supervisor to restart it.
+    pub fn with_max_failed_cycles(mut self, max_failed_cycles: u32) -> Self {
+        self.max_failed_cycles = Some(max_failed_cycles);
+        self
+    }
+
+    /// Keeps measurements that fail to publish in `spool`, publishing them before the
+    /// measurements of later cycles.
+    pub fn with_spool(mut self, spool: MeasurementSpool) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Publishes the measurements left in the spool by earlier runs. Failing to only gets
+    /// logged, since every cycle tries again before publishing.
+    pub async fn replay_spool(&self) {
+        if let Some(spool) = &self.spool {
+            if let Err(e) = spool.replay(self.publisher.as_ref(), Utc::now()).await {
+                warn!("Failed publishing spooled measurements: {}", e);
+            }
+        }
+    }
+
+    /// Completes and logs the summary the measurement client leaves behind after every cycle.
+    pub fn with_cycle_summary(mut self, cycle_summary: Arc<Mutex<Option<CycleSummary>>>) -> Self {
+        self.cycle_summary = Some(cycle_summary);
+        self
+    }
+
+    /// Also publishes every measurement through `secondary_publisher`, after the main publisher.
+    /// Its failures are only logged, so they never fail a cycle.
+    pub fn with_secondary_publisher(
+        mut self,
+        secondary_publisher: Box<dyn MeasurementPublisher>,
+    ) -> Self {
+        self.secondary_publishers.push(secondary_publisher);
+        self
+    }
+
+    /// Runs a single cycle when no interval is set, otherwise keeps running cycles until a
+    /// shutdown is requested, a cycle fails on the config or too many cycles failed in a row.
+    /// An in-flight cycle always gets to finish. The startup delay only precedes the first run,
+    /// not the ones after the supervisor restarted the exporter.
+    pub async fn run(
+        &self,
+        interval: Option<Duration>,
+        mut shutdown: ShutdownSignal,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(startup_delay) = self.startup_delay.take().filter(|delay| !delay.is_zero()) {
+            info!(
+                startup_delay_ms = startup_delay.as_millis() as u64,
+                "Delaying the first cycle by {:?} of startup jitter", startup_delay
+            );
+            tokio::select! {
+                _ = tokio::time::sleep(startup_delay) => {},
+                _ = shutdown.requested() => {
+                    info!("Stopping after shutdown request");
+                    return Ok(());
+                },
+            }
+        }
+
+        let interval = match interval {
+            Some(interval) => interval,
+            None => return self.run_once().await,
+        };
+        self.failed_cycles.set(0);
+
+        loop {
+            self.run_logged_cycle(false).await?;
+
+            if shutdown.is_requested() {
+                info!("Stopping after shutdown request");
+                return Ok(());
+            }
+
+            let cycle_jitter = jitter::draw(self.cycle_jitter_max);
+            info!(
+                cycle_jitter_ms = cycle_jitter.as_millis() as u64,
+                "Sleeping {:?} until the next cycle...",
+                interval + cycle_jitter
+            );
+            let next_cycle = tokio::time::Instant::now() + interval + cycle_jitter;
+            loop {
+                tokio::select! {
+                    _ = tokio::time::sleep_until(next_cycle) => break,
+                    _ = shutdown.requested() => {
+                        info!("Stopping after shutdown request");
+                        return Ok(());
+                    },
+                    _ = reload_requested(self.reload_signal.as_ref()) => {
+                        self.reload_config();
+                    },
+                    _ = trigger_requested(self.trigger_signal.as_ref()) => {
+                        self.run_logged_cycle(true).await?;
+                        if shutdown.is_requested() {
+                            info!("Stopping after shutdown request");
+                            return Ok(());
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    /// Runs a cycle of an interval in a span of its own, logging its failure. A config failure
+    /// is returned, since that won't go away by itself, and so is the failure of the cycle that
+    /// makes too many in a row.
+    async fn run_logged_cycle(&self, manually_triggered: bool) -> Result<(), Box<dyn Error>> {
+        let cycle_span = cycle_span();
+        if manually_triggered {
+            cycle_span.in_scope(|| info!("Measurement cycle manually triggered"));
+        }
+        let e = match self.run_cycle().instrument(cycle_span.clone()).await {
+            Ok(()) => {
+                self.failed_cycles.set(0);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        if ConfigError::is(e.as_ref()) {
+            return Err(e);
+        }
+
+        let failed_cycles = self.failed_cycles.get() + 1;
+        self.failed_cycles.set(failed_cycles);
+        cycle_span.in_scope(|| {
+            error!(
+                failed_cycles = failed_cycles,
+                "Measurement cycle failed: {}", e
+            )
+        });
+        match self.max_failed_cycles {
+            Some(max_failed_cycles) if failed_cycles >= max_failed_cycles => {
+                error!(
+                    "{} measurement cycles failed in a row, stopping the exporter",
+                    failed_cycles
+                );
+                Err(e)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Reads and validates the config again, replacing the one kept for the next cycles. A
+    /// config that fails either keeps the previous one, and only gets logged.
+    pub(crate) fn reload_config(&self) -> bool {
+        match validate_config(&self.config_client, &self.env_config) {
+            Ok(config) => {
+                info!("Reloaded the config, applying it from the next cycle");
+                self.notify_config_observers(&config);
+                *self.config.borrow_mut() = Some(config);
+                true
+            }
+            Err(e) => {
+                error!(
+                    "Failed reloading the config, keeping the previous one: {}",
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// The config read anew. When reloading on request, a config failing to read or validate
+    /// falls back to the one kept from an earlier cycle or reload, if any.
+    fn current_config(&self) -> Result<Config, Box<dyn Error>> {
+        if self.reload_signal.is_none() {
+            let config = env_config::read_config(&self.config_client, &self.env_config)
+                .map_err(ConfigError::new)?;
+            self.notify_config_observers(&config);
+            return Ok(config);
+        }
+
+        let mut config = self.config.borrow_mut();
+        match validate_config(&self.config_client, &self.env_config) {
+            Ok(validated) => {
+                self.notify_config_observers(&validated);
+                *config = Some(validated.clone());
+                Ok(validated)
+            }
+            Err(e) => match config.as_ref() {
+                Some(previous) => {
+                    error!("Failed reading the config, keeping the previous one: {}", e);
+                    Ok(previous.clone())
+                }
+                None => Err(e),
+            },
+        }
+    }
+
+    fn notify_config_observers(&self, config: &Config) {
+        for config_observer in self.config_observers.iter() {
+            config_observer.config_changed(config);
+        }
+    }
+
+    /// Runs a single cycle in a span of its own, whose `measurement_id` field the measurement
+    /// client fills in with the id of the measurement of the cycle.
+    pub async fn run_once(&self) -> Result<(), Box<dyn Error>> {
+        self.run_cycle().instrument(cycle_span()).await
+    }
+
+    async fn run_cycle(&self) -> Result<(), Box<dyn Error>> {
+        let result = self.measure_and_publish().await;
+
+        if let Some(cycle_summary) = &self.cycle_summary {
+            if let Some(mut summary) = cycle_summary.lock().unwrap().take() {
+                summary.published = result.is_ok();
+                summary.last_success_age = self
+                    .last_success
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|last_success| last_success.age(Utc::now()));
+                summary.log();
+            }
+        }
+
+        result
+    }
+
+    async fn measure_and_publish(&self) -> Result<(), Box<dyn Error>> {
+        let config = self.current_config()?;
+        let archive_config = config.archive.clone();
+        let csv_config = config.csv.clone();
+        let last_measurements = self.store.read().map_err(PublishError::new)?;
+
+        // device requests use blocking http clients, which mustn't run on an async worker as is
+        let measurements = tokio::task::block_in_place(|| {
+            self.measurement_client
+                .get_measurements(config, last_measurements)
+        })?;
+        // a skipped cycle keeps the last measurements for the next one
+        if measurements.is_empty() {
+            return Ok(());
+        }
+
+        self.publish(&measurements).await?;
+
+        for secondary_publisher in self.secondary_publishers.iter() {
+            for measurement in measurements.iter() {
+                if let Err(e) = secondary_publisher.publish(measurement).await {
+                    warn!("Failed publishing measurement to secondary output: {}", e);
+                }
+            }
+        }
+
+        if let Some(archive_config) = &archive_config {
+            if let Err(e) = self
+                .archive
+                .archive(archive_config, &measurements, Utc::now())
+            {
+                warn!(
+                    "Failed archiving measurements to {}: {}",
+                    archive_config.directory, e
+                );
+            }
+        }
+
+        if let Some(csv_config) = &csv_config {
+            if let Err(e) = self
+                .csv_export
+                .export(csv_config, &measurements, Utc::now())
+            {
+                warn!(
+                    "Failed exporting measurements to csv {}: {}",
+                    csv_config.path, e
+                );
+            }
+        }
+
+        self.store
+            .store(&measurements)
+            .await
+            .map_err(PublishError::new)?;
+        *self.last_success.lock().unwrap() = LastSuccess::from_measurements(&measurements);
+
+        Ok(())
+    }
+
+    /// Publishes the measurements through the main publisher. With a spool its measurements go
+    /// first, and when publishing fails the measurements not published yet get spooled, so they
+    /// keep their order.
+    async fn publish(&self, measurements: &[Measurement]) -> Result<(), Box<dyn Error>> {
+        let spool = match &self.spool {
+            Some(spool) => spool,
+            None => {
+                for measurement in measurements.iter() {
+                    self.publisher
+                        .publish(measurement)
+                        .await
+                        .map_err(PublishError::new)?;
+                }
+                return Ok(());
+            }
+        };
+
+        let mut result = spool
+            .replay(self.publisher.as_ref(), Utc::now())
+            .await
+            .map(|_| ());
+        let mut published = 0;
+        while result.is_ok() && published < measurements.len() {
+            result = self.publisher.publish(&measurements[published]).await;
+            if result.is_ok() {
+                published += 1;
+            }
+        }
+
+        if let Err(e) = result {
+            if let Err(spool_error) = spool.spool(&measurements[published..], Utc::now()) {
+                warn!("Failed spooling measurements: {}", spool_error);
+            }
+            return Err(PublishError::new(e).into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Completes once a reload is requested; never completes without a reload signal.
+async fn reload_requested(reload_signal: Option<&ReloadSignal>) {
+    match reload_signal {
+        Some(reload_signal) => reload_signal.requested().await,
+        None => std::future::pending::<()>().await,
+    }
+}
+
+/// Completes once a cycle is requested; never completes without a trigger signal.
+async fn trigger_requested(trigger_signal: Option<&TriggerSignal>) {
+    match trigger_signal {
+        Some(trigger_signal) => trigger_signal.requested().await,
+        None => std::future::pending::<()>().await,
+    }
+}
+
+fn cycle_span() -> Span {
+    info_span!("cycle", measurement_id = field::Empty)
+}
+
+/// Runs discovery and sampling exactly like a regular cycle, but instead of publishing the
+/// measurements or storing state it returns them as pretty printed json.
+pub fn measure_once_as_json(
+    config_client: &ConfigClient,
+    env_config: &EnvConfig,
+    measurement_client: &dyn MeasurementClient<Config>,
+) -> Result<String, Box<dyn Error>> {
+    let config = env_config::read_config(config_client, env_config)?;
+
+    let measurements = measurement_client.get_measurements(config, None)?;
+
+    let json = measurements
+        .iter()
+        .map(serde_json::to_string_pretty)
+        .collect::<Result<Vec<String>, _>>()?
+        .join("\n");
+
+    Ok(json)
+}
+
+/// Reads the config the way a measurement cycle does and validates it.
+pub fn validate_config(
+    config_client: &ConfigClient,
+    env_config: &EnvConfig,
+) -> Result<Config, Box<dyn Error>> {
+    let config = env_config::read_config(config_client, env_config).map_err(ConfigError::new)?;
+    config.validate().map_err(ConfigError::new)?;
+
+    Ok(config)
+}
+
+/// Renders discovered devices one per line, as mdns name followed by its addresses.
+pub fn render_devices(devices: &[HomewizardDevice]) -> String {
+    let mut lines: Vec<String> = devices
+        .iter()
+        .map(|device| {
+            let mut ip_addresses: Vec<String> = device
+                .ip_addresses
+                .iter()
+                .map(|ip_address| ip_address.to_string())
+                .collect();
+            ip_addresses.sort();
+
+            format!("{} {}", device.fullname, ip_addresses.join(","))
+        })
+        .collect();
+    lines.sort();
+
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::StaticDiscoverer;
+    use crate::homewizard_client::{HomewizardClient, HomewizardClientConfig};
+    use crate::supervisor::{self, SupervisorConfig};
+    use crate::test_support::{capture_logs, FakeDeviceServer, FakeResponse};
+    use jarvis_lib::config_client::ConfigClientConfig;
+    use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+    use std::fs;
+    use std::net::Ipv4Addr;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Instant;
+
+    struct MockMeasurementClient {}
+
+    impl MeasurementClient<Config> for MockMeasurementClient {
+        fn get_measurements(
+            &self,
+            config: Config,
+            _last_measurements: Option<Vec<Measurement>>,
+        ) -> Result<Vec<Measurement>, Box<dyn Error>> {
+            Ok(vec![Measurement {
+                id: "cc6e17bb-fd60-4dde-acc3-0cda7d752ffe".into(),
+                source: String::from("jarvis-homewizard-exporter"),
+                location: config.location,
+                samples: vec![Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-SKT".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "Bonenmaler".into(),
+                    metric_type: MetricType::Gauge,
+                    value: 1450.0,
+                }],
+                measured_at_time: Utc::now(),
+            }])
+        }
+    }
+
+    #[test]
+    fn measure_once_as_json_serializes_measurement() {
+        let config_client =
+            ConfigClient::new(ConfigClientConfig::new("test-config.yaml".to_string()).unwrap());
+
+        // act
+        let json = measure_once_as_json(
+            &config_client,
+            &EnvConfig::default(),
+            &MockMeasurementClient {},
+        )
+        .unwrap();
+
+        let measurement: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(measurement["location"], "My Home");
+        assert_eq!(measurement["samples"][0]["sampleName"], "Bonenmaler");
+        assert_eq!(measurement["samples"][0]["value"], 1450.0);
+        assert!(json.contains('\n'));
+    }
+
+    struct FailingMeasurementClient {}
+
+    impl MeasurementClient<Config> for FailingMeasurementClient {
+        fn get_measurements(
+            &self,
+            _config: Config,
+            _last_measurements: Option<Vec<Measurement>>,
+        ) -> Result<Vec<Measurement>, Box<dyn Error>> {
+            Err(Box::<dyn Error>::from("Failed to browse"))
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockPublisher {
+        published: Rc<RefCell<Vec<Measurement>>>,
+        fail: bool,
+    }
+
+    #[async_trait(?Send)]
+    impl MeasurementPublisher for MockPublisher {
+        async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+            if self.fail {
+                return Err(Box::<dyn Error>::from("nats: connection refused"));
+            }
+            self.published.borrow_mut().push(measurement.clone());
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct MockStore {
+        stored: Rc<RefCell<Option<Vec<Measurement>>>>,
+    }
+
+    #[async_trait(?Send)]
+    impl MeasurementStore for MockStore {
+        fn read(&self) -> Result<Option<Vec<Measurement>>, Box<dyn Error>> {
+            Ok(self.stored.borrow().clone())
+        }
+
+        async fn store(&self, measurements: &[Measurement]) -> Result<(), Box<dyn Error>> {
+            *self.stored.borrow_mut() = Some(measurements.to_vec());
+            Ok(())
+        }
+    }
+
+    fn exporter(
+        measurement_client: Box<dyn MeasurementClient<Config>>,
+        publisher: &MockPublisher,
+        store: &MockStore,
+    ) -> Exporter {
+        Exporter::new(
+            ConfigClient::new(ConfigClientConfig::new("test-config.yaml".to_string()).unwrap()),
+            measurement_client,
+            Box::new(publisher.clone()),
+            Box::new(store.clone()),
+        )
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_publishes_and_stores_measurement() {
+        let publisher = MockPublisher::default();
+        let store = MockStore::default();
+        let exporter = exporter(Box::new(MockMeasurementClient {}), &publisher, &store);
+
+        // act
+        let result = exporter.run_once().await;
+
+        assert!(result.is_ok());
+        assert_eq!(publisher.published.borrow().len(), 1);
+        assert_eq!(
+            store.stored.borrow().as_ref().unwrap()[0].id,
+            publisher.published.borrow()[0].id
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_fails_without_publishing_when_measuring_fails() {
+        let publisher = MockPublisher::default();
+        let store = MockStore::default();
+        let exporter = exporter(Box::new(FailingMeasurementClient {}), &publisher, &store);
+
+        // act
+        let result = exporter.run_once().await;
+
+        assert!(result.is_err());
+        assert!(publisher.published.borrow().is_empty());
+        assert!(store.stored.borrow().is_none());
+    }
+
+    struct SkippingMeasurementClient {}
+
+    impl MeasurementClient<Config> for SkippingMeasurementClient {
+        fn get_measurements(
+            &self,
+            _config: Config,
+            _last_measurements: Option<Vec<Measurement>>,
+        ) -> Result<Vec<Measurement>, Box<dyn Error>> {
+            Ok(vec![])
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_keeps_last_measurements_when_cycle_is_skipped() {
+        let publisher = MockPublisher::default();
+        let store = MockStore::default();
+        exporter(Box::new(MockMeasurementClient {}), &publisher, &store)
+            .run_once()
+            .await
+            .unwrap();
+        let exporter = exporter(Box::new(SkippingMeasurementClient {}), &publisher, &store);
+
+        // act
+        let result = exporter.run_once().await;
+
+        assert!(result.is_ok());
+        assert_eq!(publisher.published.borrow().len(), 1);
+        assert_eq!(store.stored.borrow().as_ref().unwrap().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_fails_without_storing_when_publishing_fails() {
+        let publisher = MockPublisher {
+            fail: true,
+            ..Default::default()
+        };
+        let store = MockStore::default();
+        let exporter = exporter(Box::new(MockMeasurementClient {}), &publisher, &store);
+
+        // act
+        let result = exporter.run_once().await;
+
+        assert_eq!(
+            crate::error::exit_code(result.unwrap_err().as_ref()),
+            crate::error::EXIT_PUBLISH
+        );
+        assert!(store.stored.borrow().is_none());
+    }
+
+    #[test]
+    fn run_once_logs_measurement_id_throughout_the_cycle() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(
+                "/api",
+                FakeResponse::json(
+                    &fs::read_to_string("tests/fixtures/responses/api-socket.json").unwrap(),
+                ),
+            )
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(
+                    &fs::read_to_string("tests/fixtures/responses/data-socket-firmware-3.json")
+                        .unwrap(),
+                ),
+            );
+        let socket = HomewizardDevice {
+            fullname: "energysocket-3C39E7._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let ids = AtomicUsize::new(0);
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(Utc::now),
+                Arc::new(move || format!("measurement-{}", ids.fetch_add(1, Ordering::SeqCst) + 1)),
+            ),
+        )
+        .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let cycle_summary = homewizard_client.summary_handle();
+        let publisher = MockPublisher::default();
+        let exporter = exporter(
+            Box::new(homewizard_client),
+            &publisher,
+            &MockStore::default(),
+        )
+        .with_cycle_summary(cycle_summary)
+        .with_secondary_publisher(Box::new(MockPublisher {
+            fail: true,
+            ..Default::default()
+        }));
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // act
+        let logs = capture_logs("info", || {
+            runtime.block_on(exporter.run_once()).unwrap();
+        });
+
+        assert_eq!(publisher.published.borrow()[0].id, "measurement-1");
+        for message in [
+            "Discovering devices...",
+            "Fetching data for device",
+            "Failed publishing measurement to secondary output",
+            "Finished measurement cycle",
+        ] {
+            let line: serde_json::Value = serde_json::from_str(
+                logs.lines()
+                    .find(|line| line.contains(message))
+                    .unwrap_or_else(|| panic!("no log line {}", message)),
+            )
+            .unwrap();
+            assert_eq!(line["spans"][0]["name"], "cycle", "{}", message);
+            assert_eq!(
+                line["spans"][0]["measurement_id"], "measurement-1",
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn run_once_logs_cycle_summary_with_counts_of_the_devices_measured() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(
+                "/api",
+                FakeResponse::json(
+                    &fs::read_to_string("tests/fixtures/responses/api-socket.json").unwrap(),
+                ),
+            )
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(
+                    &fs::read_to_string("tests/fixtures/responses/data-socket-firmware-3.json")
+                        .unwrap(),
+                ),
+            );
+        let failing_server = FakeDeviceServer::start();
+        failing_server.respond("/api", FakeResponse::status(500));
+        let device = |fullname: &str, server: &FakeDeviceServer| HomewizardDevice {
+            fullname: fullname.into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![
+                device("energysocket-3C39E7._hwenergy._tcp.local.", &server),
+                device("watermeter-2D7A68._hwenergy._tcp.local.", &failing_server),
+            ])));
+        let cycle_summary = homewizard_client.summary_handle();
+        let publisher = MockPublisher::default();
+        let exporter = exporter(
+            Box::new(homewizard_client),
+            &publisher,
+            &MockStore::default(),
+        )
+        .with_cycle_summary(cycle_summary);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // act
+        let logs = capture_logs("info", || {
+            runtime.block_on(exporter.run_once()).unwrap();
+        });
+
+        let summary: serde_json::Value = serde_json::from_str(
+            logs.lines()
+                .find(|line| line.contains("Finished measurement cycle"))
+                .expect("no cycle summary logged"),
+        )
+        .unwrap();
+        let fields = &summary["fields"];
+        let published = publisher.published.borrow();
+        assert_eq!(fields["devices_discovered"], 2);
+        assert_eq!(fields["devices_succeeded"], 1);
+        assert_eq!(fields["devices_failed"], 1);
+        assert_eq!(
+            fields["failed_devices"],
+            "[\"watermeter-2D7A68._hwenergy._tcp.local.\"]"
+        );
+        assert_eq!(fields["samples_emitted"], published[0].samples.len());
+        assert_eq!(fields["published"], true);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_spools_failed_measurement_and_publishes_it_first_next_cycle() {
+        let directory = env::temp_dir().join(format!("spool-{}", uuid::Uuid::new_v4()));
+        let spool = || {
+            MeasurementSpool::new(
+                crate::spool::SpoolConfig::new(directory.clone(), 10, None).unwrap(),
+            )
+        };
+        let failing_publisher = MockPublisher {
+            fail: true,
+            ..Default::default()
+        };
+        let store = MockStore::default();
+        exporter(
+            Box::new(MockMeasurementClient {}),
+            &failing_publisher,
+            &store,
+        )
+        .with_spool(spool())
+        .run_once()
+        .await
+        .unwrap_err();
+        let spooled = fs::read_dir(&directory).unwrap().count();
+        let publisher = MockPublisher::default();
+        let exporter =
+            exporter(Box::new(MockMeasurementClient {}), &publisher, &store).with_spool(spool());
+
+        // act
+        let result = exporter.run_once().await;
+
+        assert!(result.is_ok());
+        assert_eq!(spooled, 1);
+        let published = publisher.published.borrow();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0].id, "cc6e17bb-fd60-4dde-acc3-0cda7d752ffe");
+        assert!(published[0].measured_at_time < published[1].measured_at_time);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_records_last_success_after_publishing() {
+        let publisher = MockPublisher::default();
+        let store = MockStore::default();
+        let exporter = exporter(Box::new(MockMeasurementClient {}), &publisher, &store);
+
+        // act
+        exporter.run_once().await.unwrap();
+
+        let last_success = exporter.last_success_handle().lock().unwrap().clone();
+        let published = &publisher.published.borrow()[0];
+        assert_eq!(
+            last_success,
+            Some(LastSuccess {
+                measured_at: published.measured_at_time,
+                measurement_id: published.id.clone(),
+                samples: 1,
+            })
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_keeps_last_success_when_publishing_fails() {
+        let store = MockStore::default();
+        exporter(
+            Box::new(MockMeasurementClient {}),
+            &MockPublisher::default(),
+            &store,
+        )
+        .run_once()
+        .await
+        .unwrap();
+        let stored = store.stored.borrow().clone().unwrap();
+        let publisher = MockPublisher {
+            fail: true,
+            ..Default::default()
+        };
+        let exporter = exporter(Box::new(MockMeasurementClient {}), &publisher, &store);
+        exporter.restore_last_success();
+
+        // act
+        let result = exporter.run_once().await;
+
+        assert!(result.is_err());
+        assert_eq!(
+            exporter.last_success_handle().lock().unwrap().clone(),
+            LastSuccess::from_measurements(&stored)
+        );
+        assert_eq!(store.stored.borrow().as_ref().unwrap()[0].id, stored[0].id);
+    }
+
+    #[test]
+    fn restore_last_success_reads_back_latest_stored_measurement() {
+        let store = MockStore::default();
+        let measurement = |id: &str, minutes_ago: i64| Measurement {
+            id: id.into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![],
+            measured_at_time: Utc::now() - chrono::Duration::minutes(minutes_ago),
+        };
+        *store.stored.borrow_mut() =
+            Some(vec![measurement("older", 90), measurement("latest", 30)]);
+        let exporter = exporter(
+            Box::new(MockMeasurementClient {}),
+            &MockPublisher::default(),
+            &store,
+        );
+
+        // act
+        exporter.restore_last_success();
+
+        let last_success = exporter
+            .last_success_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(last_success.measurement_id, "latest");
+        assert_eq!(last_success.age(Utc::now()).as_secs() / 60, 30);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_ignores_failing_secondary_publisher() {
+        let publisher = MockPublisher::default();
+        let secondary_publisher = MockPublisher {
+            fail: true,
+            ..Default::default()
+        };
+        let store = MockStore::default();
+        let exporter = exporter(Box::new(MockMeasurementClient {}), &publisher, &store)
+            .with_secondary_publisher(Box::new(secondary_publisher));
+
+        // act
+        let result = exporter.run_once().await;
+
+        assert!(result.is_ok());
+        assert_eq!(publisher.published.borrow().len(), 1);
+        assert!(store.stored.borrow().is_some());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn in_memory_store_hands_last_measurements_to_next_cycle() {
+        let store = InMemoryStore::default();
+        let measurements = MockMeasurementClient {}
+            .get_measurements(
+                Config {
+                    location: "My Home".into(),
+                    ..Default::default()
+                },
+                None,
+            )
+            .unwrap();
+
+        // act
+        store.store(&measurements).await.unwrap();
+
+        assert_eq!(store.read().unwrap().unwrap()[0].id, measurements[0].id);
+    }
+
+    #[test]
+    fn output_parses_known_values() {
+        assert_eq!("nats".parse::<Output>(), Ok(Output::Nats));
+        assert_eq!("stdout".parse::<Output>(), Ok(Output::Stdout));
+        assert_eq!("mqtt".parse::<Output>(), Ok(Output::Mqtt));
+        assert!("kafka".parse::<Output>().is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_with_interval_returns_config_errors() {
+        let publisher = MockPublisher::default();
+        let store = MockStore::default();
+        let exporter = Exporter::new(
+            ConfigClient::new(ConfigClientConfig::new("missing-config.yaml".to_string()).unwrap()),
+            Box::new(MockMeasurementClient {}),
+            Box::new(publisher.clone()),
+            Box::new(store),
+        );
+        let (_requested_sender, shutdown_signal) = crate::shutdown::channel();
+
+        // act
+        let result = exporter
+            .run(Some(Duration::from_secs(3600)), shutdown_signal)
+            .await;
+
+        assert!(ConfigError::is(result.unwrap_err().as_ref()));
+        assert!(publisher.published.borrow().is_empty());
+    }
+
+    fn reloading_exporter(config_path: &std::path::Path, publisher: &MockPublisher) -> Exporter {
+        Exporter::new(
+            ConfigClient::new(
+                ConfigClientConfig::new(config_path.to_string_lossy().to_string()).unwrap(),
+            ),
+            Box::new(MockMeasurementClient {}),
+            Box::new(publisher.clone()),
+            Box::new(MockStore::default()),
+        )
+        .with_reload(ReloadSignal::default())
+    }
+
+    fn published_locations(publisher: &MockPublisher) -> Vec<String> {
+        publisher
+            .published
+            .borrow()
+            .iter()
+            .map(|measurement| measurement.location.clone())
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reload_config_applies_valid_config_right_away() {
+        let path = env::temp_dir().join(format!("config-{}.yaml", uuid::Uuid::new_v4()));
+        fs::write(&path, "location: My Home\n").unwrap();
+        let publisher = MockPublisher::default();
+        let exporter = reloading_exporter(&path, &publisher);
+        exporter.run_once().await.unwrap();
+        fs::write(&path, "location: Holiday Home\n").unwrap();
+
+        // act
+        let reloaded = exporter.reload_config();
+
+        assert!(reloaded);
+        assert_eq!(
+            exporter.config.borrow().as_ref().unwrap().location,
+            "Holiday Home"
+        );
+        exporter.run_once().await.unwrap();
+        assert_eq!(
+            published_locations(&publisher),
+            vec!["My Home", "Holiday Home"]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_once_reads_config_every_cycle_without_reload_request() {
+        let path = env::temp_dir().join(format!("config-{}.yaml", uuid::Uuid::new_v4()));
+        fs::write(&path, "location: My Home\n").unwrap();
+        let publisher = MockPublisher::default();
+        let exporter = reloading_exporter(&path, &publisher);
+        exporter.run_once().await.unwrap();
+        fs::write(&path, "location: Holiday Home\n").unwrap();
+
+        // act
+        exporter.run_once().await.unwrap();
+
+        assert_eq!(
+            published_locations(&publisher),
+            vec!["My Home", "Holiday Home"]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn run_once_keeps_previous_config_when_invalid_without_reload_request() {
+        let path = env::temp_dir().join(format!("config-{}.yaml", uuid::Uuid::new_v4()));
+        fs::write(&path, "location: My Home\n").unwrap();
+        let publisher = MockPublisher::default();
+        let exporter = reloading_exporter(&path, &publisher);
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(exporter.run_once()).unwrap();
+        fs::write(&path, "location: ' '\n").unwrap();
+
+        // act
+        let logs = capture_logs("error", || {
+            runtime.block_on(exporter.run_once()).unwrap();
+        });
+
+        assert!(logs.contains("Failed reading the config, keeping the previous one"));
+        assert_eq!(published_locations(&publisher), vec!["My Home", "My Home"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn reload_config_keeps_previous_config_when_invalid() {
+        let path = env::temp_dir().join(format!("config-{}.yaml", uuid::Uuid::new_v4()));
+        fs::write(&path, "location: My Home\n").unwrap();
+        let publisher = MockPublisher::default();
+        let exporter = reloading_exporter(&path, &publisher);
+        exporter.run_once().await.unwrap();
+        fs::write(&path, "location: ' '\n").unwrap();
+
+        // act
+        let logs = capture_logs("error", || assert!(!exporter.reload_config()));
+
+        assert!(logs.contains("Failed reloading the config, keeping the previous one"));
+        exporter.run_once().await.unwrap();
+        assert_eq!(published_locations(&publisher), vec!["My Home", "My Home"]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        locations: RefCell<Vec<String>>,
+    }
+
+    impl ConfigObserver for RecordingObserver {
+        fn config_changed(&self, config: &Config) {
+            self.locations.borrow_mut().push(config.location.clone());
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn config_observer_is_told_about_first_and_reloaded_config() {
+        let path = env::temp_dir().join(format!("config-{}.yaml", uuid::Uuid::new_v4()));
+        fs::write(&path, "location: My Home\n").unwrap();
+        let publisher = MockPublisher::default();
+        let observer = Rc::new(RecordingObserver::default());
+        let exporter = reloading_exporter(&path, &publisher).with_config_observer(observer.clone());
+        exporter.run_once().await.unwrap();
+        exporter.run_once().await.unwrap();
+        fs::write(&path, "location: Holiday Home\n").unwrap();
+
+        // act
+        exporter.reload_config();
+
+        assert_eq!(
+            *observer.locations.borrow(),
+            vec!["My Home", "My Home", "Holiday Home"]
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn validate_config_accepts_test_file() {
+        let config_client =
+            ConfigClient::new(ConfigClientConfig::new("test-config.yaml".to_string()).unwrap());
+
+        // act
+        let config = validate_config(&config_client, &EnvConfig::default()).unwrap();
+
+        assert_eq!(config.location, "My Home");
+    }
+
+    #[test]
+    fn validate_config_fails_for_missing_file() {
+        let config_client =
+            ConfigClient::new(ConfigClientConfig::new("missing-config.yaml".to_string()).unwrap());
+
+        // act
+        let result = validate_config(&config_client, &EnvConfig::default());
+
+        assert!(ConfigError::is(result.unwrap_err().as_ref()));
+    }
+
+    #[test]
+    fn render_devices_prints_one_sorted_line_per_device() {
+        let devices = vec![
+            HomewizardDevice {
+                fullname: "watermeter-2D7A68._hwenergy._tcp.local.".into(),
+                ip_addresses: vec!["192.168.1.20".parse().unwrap()].into_iter().collect(),
+                port: 80,
+                hostname: None,
+                api_path: None,
+            },
+            HomewizardDevice {
+                fullname: "energysocket-1A2B3C._hwenergy._tcp.local.".into(),
+                ip_addresses: vec!["192.168.1.31".parse().unwrap()].into_iter().collect(),
+                port: 80,
+                hostname: None,
+                api_path: None,
+            },
+        ];
+
+        // act
+        let output = render_devices(&devices);
+
+        assert_eq!(
+            output,
+            "energysocket-1A2B3C._hwenergy._tcp.local. 192.168.1.31\nwatermeter-2D7A68._hwenergy._tcp.local. 192.168.1.20"
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_delays_first_cycle_by_startup_jitter() {
+        let publisher = MockPublisher::default();
+        let store = MockStore::default();
+        let max = Duration::from_millis(300);
+        let exporter = exporter(Box::new(MockMeasurementClient {}), &publisher, &store)
+            .with_jitter(&JitterConfig::new(max, Duration::ZERO).unwrap());
+        let startup_delay = exporter.startup_delay.get().unwrap();
+        let (_requested_sender, shutdown_signal) = crate::shutdown::channel();
+        let start = Instant::now();
+
+        // act
+        let result = exporter.run(None, shutdown_signal).await;
+
+        assert!(result.is_ok());
+        assert!(startup_delay <= max);
+        assert!(start.elapsed() >= startup_delay);
+        assert_eq!(publisher.published.borrow().len(), 1);
+        assert_eq!(exporter.startup_delay.get(), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_with_interval_stops_when_shutdown_is_requested() {
+        let publisher = MockPublisher::default();
+        let store = MockStore::default();
+        let exporter = exporter(Box::new(MockMeasurementClient {}), &publisher, &store);
+        let (requested_sender, shutdown_signal) = crate::shutdown::channel();
+        requested_sender.send(true).unwrap();
+
+        // act
+        let result = exporter
+            .run(Some(Duration::from_secs(3600)), shutdown_signal)
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(publisher.published.borrow().len(), 1);
+    }
+
+    /// Fails its first `failing_cycles` cycles, requesting a shutdown in the cycle after them.
+    struct RecoveringMeasurementClient {
+        cycles: Rc<Cell<usize>>,
+        failing_cycles: usize,
+        requested_sender: tokio::sync::watch::Sender<bool>,
+    }
+
+    impl MeasurementClient<Config> for RecoveringMeasurementClient {
+        fn get_measurements(
+            &self,
+            config: Config,
+            last_measurements: Option<Vec<Measurement>>,
+        ) -> Result<Vec<Measurement>, Box<dyn Error>> {
+            self.cycles.set(self.cycles.get() + 1);
+            if self.cycles.get() <= self.failing_cycles {
+                return FailingMeasurementClient {}.get_measurements(config, last_measurements);
+            }
+            self.requested_sender.send(true).unwrap();
+
+            MockMeasurementClient {}.get_measurements(config, last_measurements)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn supervise_restarts_run_after_too_many_failed_cycles_in_a_row() {
+        let publisher = MockPublisher::default();
+        let cycles = Rc::new(Cell::new(0));
+        let (requested_sender, shutdown_signal) = crate::shutdown::channel();
+        let exporter = exporter(
+            Box::new(RecoveringMeasurementClient {
+                cycles: cycles.clone(),
+                failing_cycles: 5,
+                requested_sender,
+            }),
+            &publisher,
+            &MockStore::default(),
+        )
+        .with_max_failed_cycles(2);
+        let supervisor_config =
+            SupervisorConfig::new(Duration::from_millis(1), Duration::from_millis(4)).unwrap();
+        let runs = Cell::new(0);
+
+        // act
+        let result = supervisor::supervise(&supervisor_config, shutdown_signal.clone(), || {
+            runs.set(runs.get() + 1);
+            exporter.run(Some(Duration::from_millis(1)), shutdown_signal.clone())
+        })
+        .await;
+
+        assert!(result.is_ok());
+        // two runs failing two cycles each, and a third failing one before recovering
+        assert_eq!(runs.get(), 3);
+        assert_eq!(cycles.get(), 6);
+        assert_eq!(publisher.published.borrow().len(), 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn supervise_stops_on_config_errors_of_run() {
+        let publisher = MockPublisher::default();
+        let exporter = Exporter::new(
+            ConfigClient::new(ConfigClientConfig::new("missing-config.yaml".to_string()).unwrap()),
+            Box::new(MockMeasurementClient {}),
+            Box::new(publisher.clone()),
+            Box::new(MockStore::default()),
+        )
+        .with_max_failed_cycles(2);
+        let supervisor_config =
+            SupervisorConfig::new(Duration::from_millis(1), Duration::from_millis(4)).unwrap();
+        let (_requested_sender, shutdown_signal) = crate::shutdown::channel();
+        let runs = Cell::new(0);
+
+        // act
+        let result = supervisor::supervise(&supervisor_config, shutdown_signal.clone(), || {
+            runs.set(runs.get() + 1);
+            exporter.run(Some(Duration::from_millis(1)), shutdown_signal.clone())
+        })
+        .await;
+
+        assert!(ConfigError::is(result.unwrap_err().as_ref()));
+        assert_eq!(runs.get(), 1);
+        assert!(publisher.published.borrow().is_empty());
+    }
+
+    /// Counts its cycles, requesting a manual cycle during the first one and a shutdown during
+    /// the second.
+    struct TriggeringMeasurementClient {
+        cycles: Rc<Cell<usize>>,
+        trigger_signal: TriggerSignal,
+        trigger_during_first_cycle: bool,
+        requested_sender: tokio::sync::watch::Sender<bool>,
+    }
+
+    impl MeasurementClient<Config> for TriggeringMeasurementClient {
+        fn get_measurements(
+            &self,
+            config: Config,
+            last_measurements: Option<Vec<Measurement>>,
+        ) -> Result<Vec<Measurement>, Box<dyn Error>> {
+            self.cycles.set(self.cycles.get() + 1);
+            match self.cycles.get() {
+                1 if self.trigger_during_first_cycle => self.trigger_signal.request(),
+                2 => self.requested_sender.send(true).unwrap(),
+                _ => {}
+            }
+
+            MockMeasurementClient {}.get_measurements(config, last_measurements)
+        }
+    }
+
+    fn triggered_exporter(
+        trigger_during_first_cycle: bool,
+        publisher: &MockPublisher,
+    ) -> (Exporter, TriggerSignal, Rc<Cell<usize>>, ShutdownSignal) {
+        let cycles = Rc::new(Cell::new(0));
+        let trigger_signal = TriggerSignal::default();
+        let (requested_sender, shutdown_signal) = crate::shutdown::channel();
+        let exporter = exporter(
+            Box::new(TriggeringMeasurementClient {
+                cycles: cycles.clone(),
+                trigger_signal: trigger_signal.clone(),
+                trigger_during_first_cycle,
+                requested_sender,
+            }),
+            publisher,
+            &MockStore::default(),
+        )
+        .with_manual_trigger(trigger_signal.clone());
+
+        (exporter, trigger_signal, cycles, shutdown_signal)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_queues_trigger_during_cycle_until_it_finishes() {
+        let publisher = MockPublisher::default();
+        let (exporter, _trigger_signal, cycles, shutdown_signal) =
+            triggered_exporter(true, &publisher);
+        let start = Instant::now();
+
+        // act
+        let result = exporter
+            .run(Some(Duration::from_secs(3600)), shutdown_signal)
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(cycles.get(), 2);
+        assert_eq!(publisher.published.borrow().len(), 2);
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn run_measures_right_away_on_trigger_between_cycles() {
+        let publisher = MockPublisher::default();
+        let (exporter, trigger_signal, cycles, shutdown_signal) =
+            triggered_exporter(false, &publisher);
+        let start = Instant::now();
+
+        // act
+        let (result, _) = tokio::join!(
+            exporter.run(Some(Duration::from_secs(3600)), shutdown_signal),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                trigger_signal.request();
+            }
+        );
+
+        assert!(result.is_ok());
+        assert_eq!(cycles.get(), 2);
+        assert!(start.elapsed() < Duration::from_secs(60));
+    }
+}
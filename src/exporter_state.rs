@@ -0,0 +1,121 @@
+use crate::daily_consumption::DailyConsumptionTracker;
+use crate::device_health::DeviceHealthTracker;
+use crate::fetch_failures::FetchFailureCounter;
+use crate::firmware::FirmwareTracker;
+use crate::gas_flow::GasFlowTracker;
+use crate::missing_devices::MissingDevicesTracker;
+use crate::time_of_use::TimeOfUseTracker;
+use crate::unmatched_serials::UnmatchedSerialsTracker;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// State the exporter itself keeps across cycles, next to the last measurements stored by the
+/// jarvis state client.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExporterState {
+    #[serde(default)]
+    pub device_health: DeviceHealthTracker,
+    #[serde(default)]
+    pub firmware: FirmwareTracker,
+    #[serde(default)]
+    pub unmatched_serials: UnmatchedSerialsTracker,
+    #[serde(default)]
+    pub missing_devices: MissingDevicesTracker,
+    #[serde(default)]
+    pub gas_flow: GasFlowTracker,
+    #[serde(default)]
+    pub fetch_failures: FetchFailureCounter,
+    #[serde(default)]
+    pub daily_consumption: DailyConsumptionTracker,
+    #[serde(default)]
+    pub time_of_use: TimeOfUseTracker,
+}
+
+/// Persists the exporter state as a json file; without a path the state only lives in memory.
+pub struct ExporterStateStore {
+    path: Option<PathBuf>,
+}
+
+impl ExporterStateStore {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self { path }
+    }
+
+    pub fn load(&self) -> ExporterState {
+        let path = match &self.path {
+            Some(path) if path.exists() => path,
+            _ => return ExporterState::default(),
+        };
+
+        match Self::read(path) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!(
+                    "Failed reading exporter state from {}, starting afresh: {}",
+                    path.display(),
+                    e
+                );
+                ExporterState::default()
+            }
+        }
+    }
+
+    pub(crate) fn read(path: &Path) -> Result<ExporterState, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)?;
+
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub fn save(&self, state: &ExporterState) -> Result<(), Box<dyn Error>> {
+        if let Some(path) = &self.path {
+            fs::write(path, serde_json::to_vec_pretty(state)?)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_health::SucceededDevice;
+    use chrono::Utc;
+    use std::env;
+    use uuid::Uuid;
+
+    #[test]
+    fn save_and_load_round_trips_state() {
+        let path = env::temp_dir().join(format!("exporter-state-{}.json", Uuid::new_v4()));
+        let store = ExporterStateStore::new(Some(path.clone()));
+        let mut state = ExporterState::default();
+        state.device_health.observe(
+            Utc::now(),
+            &[SucceededDevice {
+                serial: "3c39e72d7a68".into(),
+                friendly_name: "Watermeter".into(),
+            }],
+            3,
+        );
+
+        // act
+        store.save(&state).unwrap();
+        let loaded = store.load();
+
+        assert_eq!(loaded, state);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn load_without_path_returns_default_state() {
+        let store = ExporterStateStore::new(None);
+
+        // act
+        let state = store.load();
+
+        assert_eq!(state, ExporterState::default());
+    }
+}
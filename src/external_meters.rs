@@ -0,0 +1,180 @@
+use crate::homewizard_client::ExternalMeterReading;
+use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
+
+/// Shortest end of a unique id used in the name of an unnamed meter.
+const SHORT_ID_LENGTH: usize = 6;
+
+/// Counters of the gas and water meters connected to a P1 meter, named through `names` by their
+/// unique id; other kinds of meters are left out.
+pub fn samples(
+    names: &HashMap<String, String>,
+    product_type: &str,
+    externals: &[ExternalMeterReading],
+) -> Vec<Sample> {
+    let externals: Vec<(&ExternalMeterReading, SampleType)> = externals
+        .iter()
+        .filter_map(
+            |external| match (external.meter_type.as_str(), external.unit.as_str()) {
+                ("gas_meter", "m3") => Some((external, SampleType::GasConsumption)),
+                ("water_meter", "m3") | ("warm_water_meter", "m3") => {
+                    Some((external, SampleType::WaterConsumption))
+                }
+                _ => {
+                    debug!(
+                        "Skipping external {} {} reading in {}",
+                        external.meter_type, external.unique_id, external.unit
+                    );
+                    None
+                }
+            },
+        )
+        .collect();
+    let meters: Vec<&ExternalMeterReading> =
+        externals.iter().map(|(external, _)| *external).collect();
+
+    externals
+        .iter()
+        .zip(sample_names(names, &meters))
+        .map(|((external, sample_type), sample_name)| Sample {
+            entity_type: EntityType::Device,
+            entity_name: product_type.to_string(),
+            sample_type: sample_type.clone(),
+            sample_name,
+            metric_type: MetricType::Counter,
+            value: external.value,
+        })
+        .collect()
+}
+
+/// The configured name of every meter, or else its type followed by the end of its unique id,
+/// long enough to tell unnamed meters of the same type apart.
+fn sample_names(
+    names: &HashMap<String, String>,
+    externals: &[&ExternalMeterReading],
+) -> Vec<String> {
+    let unnamed: Vec<&&ExternalMeterReading> = externals
+        .iter()
+        .filter(|external| !names.contains_key(&external.unique_id))
+        .collect();
+    let longest_id = unnamed
+        .iter()
+        .map(|external| external.unique_id.len())
+        .max()
+        .unwrap_or(0);
+    let length = (SHORT_ID_LENGTH..longest_id)
+        .find(|length| {
+            let mut seen = HashSet::new();
+            unnamed
+                .iter()
+                .all(|external| seen.insert((&external.meter_type, short_id(external, *length))))
+        })
+        .unwrap_or(longest_id);
+
+    externals
+        .iter()
+        .map(|external| match names.get(&external.unique_id) {
+            Some(name) => name.clone(),
+            None => format!(
+                "{} {}",
+                external.meter_type.replace('_', " "),
+                short_id(external, length)
+            ),
+        })
+        .collect()
+}
+
+fn short_id(external: &ExternalMeterReading, length: usize) -> String {
+    let unique_id: Vec<char> = external.unique_id.to_lowercase().chars().collect();
+    unique_id[unique_id.len().saturating_sub(length)..]
+        .iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn external(unique_id: &str, meter_type: &str, value: f64) -> ExternalMeterReading {
+        ExternalMeterReading {
+            unique_id: unique_id.into(),
+            meter_type: meter_type.into(),
+            timestamp: 230125220957,
+            value,
+            unit: "m3".into(),
+        }
+    }
+
+    fn names_of(samples: &[Sample]) -> Vec<&str> {
+        samples
+            .iter()
+            .map(|sample| sample.sample_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn samples_are_named_after_configured_unique_id() {
+        let names: HashMap<String, String> = vec![(
+            "FFEEDDCCBBAA99887766554433221100".to_string(),
+            "Gas".to_string(),
+        )]
+        .into_iter()
+        .collect();
+
+        // act
+        let samples = samples(
+            &names,
+            "HWE-P1",
+            &[external(
+                "FFEEDDCCBBAA99887766554433221100",
+                "gas_meter",
+                2569.646,
+            )],
+        );
+
+        assert_eq!(names_of(&samples), vec!["Gas"]);
+        assert_eq!(samples[0].sample_type, SampleType::GasConsumption);
+        assert_eq!(samples[0].metric_type, MetricType::Counter);
+        assert_eq!(samples[0].value, 2569.646);
+    }
+
+    #[test]
+    fn samples_of_unnamed_meter_are_named_after_type_and_short_id() {
+        // act
+        let samples = samples(
+            &HashMap::new(),
+            "HWE-P1",
+            &[
+                external("FFEEDDCCBBAA99887766554433221100", "water_meter", 123.456),
+                external("FFEEDDCCBBAA99887766554433AABBCC", "heat_meter", 1.5),
+            ],
+        );
+
+        assert_eq!(names_of(&samples), vec!["water meter 221100"]);
+        assert_eq!(samples[0].sample_type, SampleType::WaterConsumption);
+    }
+
+    #[test]
+    fn samples_of_unnamed_meters_of_same_type_get_distinct_names() {
+        // act
+        let samples = samples(
+            &HashMap::new(),
+            "HWE-P1",
+            &[
+                external("AAAAAAAAAAAAAAAAAAAAAAAA11221100", "water_meter", 1.0),
+                external("BBBBBBBBBBBBBBBBBBBBBBBB22221100", "water_meter", 2.0),
+                external("CCCCCCCCCCCCCCCCCCCCCCCC33221100", "gas_meter", 3.0),
+            ],
+        );
+
+        assert_eq!(
+            names_of(&samples),
+            vec![
+                "water meter 1221100",
+                "water meter 2221100",
+                "gas meter 3221100"
+            ]
+        );
+    }
+}
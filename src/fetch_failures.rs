@@ -0,0 +1,43 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Counts the failed fetches of every device by serial since the counts were first kept, so
+/// they can be emitted as counters that only ever go up.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FetchFailureCounter {
+    failures: BTreeMap<String, u64>,
+}
+
+impl FetchFailureCounter {
+    /// Counts a failed fetch for every serial.
+    pub fn record<'a>(&mut self, serials: impl IntoIterator<Item = &'a str>) {
+        for serial in serials {
+            *self.failures.entry(serial.to_string()).or_default() += 1;
+        }
+    }
+
+    /// The failed fetches of the device so far.
+    pub fn count(&self, serial: &str) -> u64 {
+        self.failures.get(serial).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_counts_failures_per_serial() {
+        let mut counter = FetchFailureCounter::default();
+
+        // act
+        counter.record(vec!["3c39e72e33ce"]);
+        counter.record(vec!["3c39e72e33ce", "5c2faf0a8b3e"]);
+        counter.record(vec![]);
+
+        assert_eq!(counter.count("3c39e72e33ce"), 2);
+        assert_eq!(counter.count("5c2faf0a8b3e"), 1);
+        assert_eq!(counter.count("aabbccddeeff"), 0);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::homewizard_client::DeviceInfoResponse;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FirmwareChangeEvent {
+    pub serial: String,
+    pub friendly_name: String,
+    pub product_type: String,
+    pub previous_firmware_version: String,
+    pub firmware_version: String,
+    pub detected_at: DateTime<Utc>,
+}
+
+/// Outcome of comparing the firmware version of a device with the last one seen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FirmwareChange {
+    FirstSeen,
+    Unchanged,
+    Changed(FirmwareChangeEvent),
+}
+
+/// Remembers the last seen firmware version of every device by serial, so a firmware update -
+/// which HomeWizard devices install by themselves - gets noticed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct FirmwareTracker {
+    versions: BTreeMap<String, String>,
+}
+
+impl FirmwareTracker {
+    pub fn get(&self, serial: &str) -> Option<&str> {
+        self.versions.get(serial).map(String::as_str)
+    }
+
+    /// Records the firmware version of the device and compares it with the last one seen.
+    pub fn observe(
+        &mut self,
+        device_info: &DeviceInfoResponse,
+        friendly_name: &str,
+        detected_at: DateTime<Utc>,
+    ) -> FirmwareChange {
+        let previous = self.versions.insert(
+            device_info.serial.clone(),
+            device_info.firmware_version.clone(),
+        );
+
+        match previous {
+            None => FirmwareChange::FirstSeen,
+            Some(previous) if previous == device_info.firmware_version => FirmwareChange::Unchanged,
+            Some(previous) => FirmwareChange::Changed(FirmwareChangeEvent {
+                serial: device_info.serial.clone(),
+                friendly_name: friendly_name.to_string(),
+                product_type: device_info.product_type.clone(),
+                previous_firmware_version: previous,
+                firmware_version: device_info.firmware_version.clone(),
+                detected_at,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn socket(firmware_version: &str) -> DeviceInfoResponse {
+        DeviceInfoResponse {
+            product_type: "HWE-SKT".into(),
+            product_name: "Energy Socket".into(),
+            serial: "3c39e72e33ce".into(),
+            firmware_version: firmware_version.into(),
+            api_version: "v1".into(),
+        }
+    }
+
+    #[test]
+    fn observe_reports_a_change_but_not_the_first_sight() {
+        let mut tracker = FirmwareTracker::default();
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+
+        // act
+        let first_seen = tracker.observe(&socket("3.02"), "Bonenmaler", now);
+        let unchanged = tracker.observe(&socket("3.02"), "Bonenmaler", now);
+        let changed = tracker.observe(&socket("4.19"), "Bonenmaler", now);
+
+        assert_eq!(first_seen, FirmwareChange::FirstSeen);
+        assert_eq!(unchanged, FirmwareChange::Unchanged);
+        assert_eq!(
+            changed,
+            FirmwareChange::Changed(FirmwareChangeEvent {
+                serial: "3c39e72e33ce".into(),
+                friendly_name: "Bonenmaler".into(),
+                product_type: "HWE-SKT".into(),
+                previous_firmware_version: "3.02".into(),
+                firmware_version: "4.19".into(),
+                detected_at: now,
+            })
+        );
+        assert_eq!(tracker.get("3c39e72e33ce"), Some("4.19"));
+    }
+}
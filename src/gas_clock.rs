@@ -0,0 +1,87 @@
+use chrono::{DateTime, Duration, FixedOffset, Local, NaiveDate, TimeZone, Utc};
+
+/// How the time of the last gas reading of a P1 meter relates to the time of the measurement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GasClockSkew {
+    InRange,
+    /// The reading is older than the threshold: the gas meter stopped reporting, or the clock of
+    /// the meter lags behind.
+    Stale(Duration),
+    /// The reading is dated further ahead than the threshold, which only a wrong meter clock
+    /// explains.
+    FutureDated(Duration),
+}
+
+/// Decodes the `gas_timestamp` of a P1 meter, the `YYMMDDhhmmss` local time of the meter, using
+/// the given offset from UTC or else the local timezone of the exporter.
+pub fn parse(gas_timestamp: u64, utc_offset_minutes: Option<i32>) -> Option<DateTime<Utc>> {
+    let part = |divisor: u64| (gas_timestamp / divisor % 100) as u32;
+    let naive = NaiveDate::from_ymd_opt(
+        2000 + part(10_000_000_000) as i32,
+        part(100_000_000),
+        part(1_000_000),
+    )?
+    .and_hms_opt(part(10_000), part(100), part(1))?;
+
+    match utc_offset_minutes {
+        Some(minutes) => FixedOffset::east_opt(minutes * 60)?
+            .from_local_datetime(&naive)
+            .single()
+            .map(|time| time.with_timezone(&Utc)),
+        // the hour repeated when daylight saving time ends is read as its first occurrence
+        None => Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|time| time.with_timezone(&Utc)),
+    }
+}
+
+/// Compares the time of the gas reading with `now`, allowing `threshold` either way.
+pub fn skew(gas_time: DateTime<Utc>, now: DateTime<Utc>, threshold: Duration) -> GasClockSkew {
+    let age = now - gas_time;
+    if age > threshold {
+        GasClockSkew::Stale(age)
+    } else if -age > threshold {
+        GasClockSkew::FutureDated(-age)
+    } else {
+        GasClockSkew::InRange
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_decodes_meter_local_time() {
+        // act
+        let summer = parse(230601120000, Some(120));
+        let utc = parse(210606140010, Some(0));
+        let invalid = parse(231301120000, Some(60));
+
+        assert_eq!(
+            summer,
+            Some(Utc.with_ymd_and_hms(2023, 6, 1, 10, 0, 0).unwrap())
+        );
+        assert_eq!(
+            utc,
+            Some(Utc.with_ymd_and_hms(2021, 6, 6, 14, 0, 10).unwrap())
+        );
+        assert_eq!(invalid, None);
+    }
+
+    #[test]
+    fn skew_tells_stale_readings_from_future_dated_ones() {
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let threshold = Duration::hours(2);
+
+        // act
+        let in_range = skew(now - Duration::minutes(55), now, threshold);
+        let stale = skew(now - Duration::days(1), now, threshold);
+        let future_dated = skew(now + Duration::hours(3), now, threshold);
+
+        assert_eq!(in_range, GasClockSkew::InRange);
+        assert_eq!(stale, GasClockSkew::Stale(Duration::days(1)));
+        assert_eq!(future_dated, GasClockSkew::FutureDated(Duration::hours(3)));
+    }
+}
@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct GasReading {
+    total_m3: f64,
+    gas_time: DateTime<Utc>,
+}
+
+/// Remembers the last gas reading of every P1 meter by serial, so the gas flow can be derived
+/// from successive readings; the meter only reports its register, every 5 minutes on DSMR 5.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GasFlowTracker {
+    readings: HashMap<String, GasReading>,
+}
+
+impl GasFlowTracker {
+    /// Records the gas reading of the meter and returns the flow in m3/h since its previous
+    /// reading; nothing for its first reading, for a reading no newer than the previous one and
+    /// after the register went down.
+    pub fn observe(&mut self, serial: &str, total_m3: f64, gas_time: DateTime<Utc>) -> Option<f64> {
+        let previous = self
+            .readings
+            .insert(serial.to_string(), GasReading { total_m3, gas_time })?;
+
+        let hours = (gas_time - previous.gas_time).num_seconds() as f64 / 3600.0;
+        if hours <= 0.0 || total_m3 < previous.total_m3 {
+            return None;
+        }
+
+        Some((total_m3 - previous.total_m3) / hours)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn gas_time() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn observe_derives_flow_from_increment() {
+        let mut tracker = GasFlowTracker::default();
+        tracker.observe("5c2faf0a8b3e", 2569.646, gas_time());
+
+        // act
+        let flow = tracker.observe("5c2faf0a8b3e", 2569.746, gas_time() + Duration::minutes(5));
+
+        assert!((flow.unwrap() - 1.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn observe_skips_unchanged_reading() {
+        let mut tracker = GasFlowTracker::default();
+        tracker.observe("5c2faf0a8b3e", 2569.646, gas_time());
+
+        // act
+        let flow = tracker.observe("5c2faf0a8b3e", 2569.646, gas_time());
+
+        assert_eq!(flow, None);
+    }
+
+    #[test]
+    fn observe_skips_first_reading_and_reset() {
+        let mut tracker = GasFlowTracker::default();
+
+        // act
+        let first = tracker.observe("5c2faf0a8b3e", 2569.646, gas_time());
+        let other_meter = tracker.observe("aabbccddeeff", 12.0, gas_time());
+        let reset = tracker.observe("5c2faf0a8b3e", 0.5, gas_time() + Duration::minutes(5));
+
+        assert_eq!(first, None);
+        assert_eq!(other_meter, None);
+        assert_eq!(reset, None);
+    }
+}
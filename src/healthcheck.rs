@@ -0,0 +1,145 @@
+use crate::exporter::LastSuccess;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::error::Error;
+use std::time::Duration;
+
+/// Body of the `/health` endpoint of a running exporter, as far as the healthcheck needs it.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Health {
+    last_success: Option<LastSuccess>,
+}
+
+/// Asks the `/health` endpoint of the exporter running at `url` for its last successful
+/// measurement.
+pub async fn fetch_last_success(
+    url: &str,
+    timeout: Duration,
+) -> Result<Option<LastSuccess>, Box<dyn Error>> {
+    let health: Health = reqwest::Client::builder()
+        .timeout(timeout)
+        .build()?
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    Ok(health.last_success)
+}
+
+/// Healthy when the last successful measurement is at most `max_age` old; either way a one-line
+/// reason.
+pub fn check(
+    last_success: Option<&LastSuccess>,
+    max_age: Duration,
+    now: DateTime<Utc>,
+) -> Result<String, String> {
+    let last_success = match last_success {
+        Some(last_success) => last_success,
+        None => return Err("No successful measurement recorded yet".to_string()),
+    };
+
+    let age = last_success.age(now);
+    if age > max_age {
+        return Err(format!(
+            "Last successful measurement {} is {}s old, more than the maximum of {}s",
+            last_success.measurement_id,
+            age.as_secs(),
+            max_age.as_secs()
+        ));
+    }
+
+    Ok(format!(
+        "Last successful measurement {} is {}s old",
+        last_success.measurement_id,
+        age.as_secs()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeDeviceServer, FakeResponse};
+    use chrono::TimeZone;
+
+    fn last_success() -> LastSuccess {
+        LastSuccess {
+            measured_at: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+            measurement_id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            samples: 5,
+        }
+    }
+
+    #[test]
+    fn check_accepts_fresh_measurement() {
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 5, 0).unwrap();
+
+        // act
+        let result = check(Some(&last_success()), Duration::from_secs(600), now);
+
+        assert_eq!(
+            result,
+            Ok(
+                "Last successful measurement 0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10 is 300s old"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn check_rejects_stale_measurement() {
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 20, 0).unwrap();
+
+        // act
+        let result = check(Some(&last_success()), Duration::from_secs(600), now);
+
+        assert_eq!(
+            result,
+            Err("Last successful measurement 0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10 is 1200s old, more than the maximum of 600s".into())
+        );
+    }
+
+    #[test]
+    fn check_rejects_exporter_that_never_succeeded() {
+        // act
+        let result = check(None, Duration::from_secs(600), Utc::now());
+
+        assert_eq!(result, Err("No successful measurement recorded yet".into()));
+    }
+
+    #[tokio::test]
+    async fn fetch_last_success_reads_health_endpoint() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(
+                "/health",
+                FakeResponse::json(
+                    r#"{"lastSuccess":{"measuredAt":"2023-06-01T12:00:00Z","measurementId":"0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10","samples":5},"lastSuccessAgeSeconds":300}"#,
+                ),
+            )
+            .respond(
+                "/never",
+                FakeResponse::json(r#"{"lastSuccess":null,"lastSuccessAgeSeconds":null}"#),
+            );
+
+        // act
+        let fetched = fetch_last_success(
+            &format!("{}/health", server.base_url()),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        let never = fetch_last_success(
+            &format!("{}/never", server.base_url()),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(fetched, Some(last_success()));
+        assert_eq!(never, None);
+    }
+}
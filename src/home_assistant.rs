@@ -0,0 +1,425 @@
+use crate::apparent_power::is_apparent_power;
+use crate::daily_consumption::is_daily_consumption;
+use crate::derived::{
+    is_cost, is_device_reachable, is_energy_delta, is_fetch_failures, is_water_liters,
+};
+use crate::homewizard_client::{CycleSummary, MeasuredDevice};
+use crate::mqtt::state_topic;
+use crate::sample_cap::is_dropped_samples;
+use jarvis_lib::model::{EntityType, Measurement, MetricType, Sample, SampleType};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Announces every sample published over MQTT as a Home Assistant sensor, grouped in a Home
+/// Assistant device per HomeWizard device, through retained discovery config messages.
+pub struct HomeAssistantDiscovery {
+    prefix: String,
+    cycle_summary: Arc<Mutex<Option<CycleSummary>>>,
+    published: RefCell<HashMap<String, Vec<u8>>>,
+}
+
+impl HomeAssistantDiscovery {
+    /// Publishes under the discovery `prefix`, `homeassistant` unless changed in Home Assistant,
+    /// taking the devices behind the samples from the summary of the cycle being published.
+    pub fn new(prefix: String, cycle_summary: Arc<Mutex<Option<CycleSummary>>>) -> Self {
+        Self {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            cycle_summary,
+            published: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the discovery messages for the measurement that weren't published before in the
+    /// same form, so they only go out again for new devices or after a name change.
+    pub(crate) fn changed_messages(
+        &self,
+        base_topic: &str,
+        measurement: &Measurement,
+    ) -> Vec<(String, Vec<u8>)> {
+        let devices = match self.cycle_summary.lock().unwrap().as_ref() {
+            Some(summary) => summary.measured_devices_of(&measurement.id),
+            None => return vec![],
+        };
+        let published = self.published.borrow();
+
+        discovery_messages(&self.prefix, base_topic, measurement, &devices)
+            .into_iter()
+            .filter(|(topic, payload)| published.get(topic) != Some(payload))
+            .collect()
+    }
+
+    pub(crate) fn mark_published(&self, topic: String, payload: Vec<u8>) {
+        self.published.borrow_mut().insert(topic, payload);
+    }
+}
+
+#[derive(Serialize)]
+struct SensorConfig<'a> {
+    name: String,
+    unique_id: String,
+    object_id: String,
+    state_topic: String,
+    value_template: &'static str,
+    device_class: &'static str,
+    state_class: &'static str,
+    unit_of_measurement: &'static str,
+    device: DeviceConfig<'a>,
+}
+
+#[derive(Serialize)]
+struct DeviceConfig<'a> {
+    identifiers: Vec<String>,
+    name: &'a str,
+    manufacturer: &'static str,
+    model: &'a str,
+    sw_version: &'a str,
+}
+
+struct SensorKind {
+    name: &'static str,
+    device_class: &'static str,
+    state_class: &'static str,
+    unit_of_measurement: &'static str,
+    value_template: &'static str,
+}
+
+fn sensor_kind(sample: &Sample) -> Option<SensorKind> {
+    // costs are in a currency home assistant isn't told about, it computes energy per period
+    // from the counters itself and converts the water total to liters when asked
+    if is_cost(sample)
+        || is_energy_delta(sample)
+        || is_water_liters(sample)
+        || is_fetch_failures(sample)
+        || is_dropped_samples(sample)
+        || is_device_reachable(sample)
+        || is_daily_consumption(sample)
+    {
+        return None;
+    }
+    if is_apparent_power(sample) {
+        return Some(SensorKind {
+            name: "apparent power",
+            device_class: "apparent_power",
+            state_class: "measurement",
+            unit_of_measurement: "VA",
+            value_template: "{{ value_json.value }}",
+        });
+    }
+
+    let (name, device_class, state_class, unit_of_measurement, value_template) =
+        match (&sample.sample_type, &sample.metric_type) {
+            // energy counters are published in joules
+            (SampleType::ElectricityConsumption, MetricType::Counter) => (
+                "energy import",
+                "energy",
+                "total_increasing",
+                "kWh",
+                "{{ value_json.value / 3600000 }}",
+            ),
+            (SampleType::ElectricityProduction, MetricType::Counter) => (
+                "energy export",
+                "energy",
+                "total_increasing",
+                "kWh",
+                "{{ value_json.value / 3600000 }}",
+            ),
+            (SampleType::ElectricityConsumption, MetricType::Gauge) => (
+                "power",
+                "power",
+                "measurement",
+                "W",
+                "{{ value_json.value }}",
+            ),
+            (SampleType::WaterConsumption, MetricType::Counter) => (
+                "water",
+                "water",
+                "total_increasing",
+                "m³",
+                "{{ value_json.value }}",
+            ),
+            (SampleType::WaterConsumption, MetricType::Gauge) => (
+                "water flow",
+                "volume_flow_rate",
+                "measurement",
+                "m³/h",
+                "{{ value_json.value }}",
+            ),
+            _ => return None,
+        };
+
+    Some(SensorKind {
+        name,
+        device_class,
+        state_class,
+        unit_of_measurement,
+        value_template,
+    })
+}
+
+/// Builds a sensor config message for every sample of a measured device; the ids derive from
+/// the serial and what the sample measures, so they stay the same when names change.
+fn discovery_messages(
+    prefix: &str,
+    base_topic: &str,
+    measurement: &Measurement,
+    devices: &[MeasuredDevice],
+) -> Vec<(String, Vec<u8>)> {
+    let mut messages = vec![];
+
+    for device in devices {
+        let device_id = format!("homewizard_{}", device.info.serial.to_lowercase());
+
+        for sample in measurement
+            .samples
+            .get(device.samples.clone())
+            .unwrap_or_default()
+        {
+            let kind = match sensor_kind(sample) {
+                Some(kind) => kind,
+                None => continue,
+            };
+            // tariffs share the kind of sample, but are told apart by name, as are derived
+            // samples named after the device
+            let derived_name = sample
+                .sample_name
+                .strip_prefix(device.friendly_name.as_str())
+                .and_then(|name| name.strip_prefix(' '));
+            let sample_name = match (&sample.entity_type, derived_name) {
+                (EntityType::Tariff, _) => sample.sample_name.as_str(),
+                (_, Some(derived_name)) => derived_name,
+                _ => kind.name,
+            };
+            let unique_id = format!("{}_{}", device_id, sample_name.replace(' ', "_"));
+
+            let config = SensorConfig {
+                name: format!("{} {}", device.friendly_name, sample_name),
+                object_id: unique_id.clone(),
+                state_topic: state_topic(base_topic, measurement, Some(device), sample),
+                value_template: kind.value_template,
+                device_class: kind.device_class,
+                state_class: kind.state_class,
+                unit_of_measurement: kind.unit_of_measurement,
+                device: DeviceConfig {
+                    identifiers: vec![device_id.clone()],
+                    name: &device.friendly_name,
+                    manufacturer: "HomeWizard",
+                    model: &device.info.product_name,
+                    sw_version: &device.info.firmware_version,
+                },
+                unique_id,
+            };
+            let topic = format!(
+                "{}/sensor/{}/{}/config",
+                prefix, device_id, config.unique_id
+            );
+
+            if let Ok(payload) = serde_json::to_vec(&config) {
+                messages.push((topic, payload));
+            }
+        }
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard_client::DeviceInfoResponse;
+    use crate::test_support::assert_golden;
+    use chrono::{TimeZone, Utc};
+    use std::fs;
+
+    fn sample(
+        entity_type: EntityType,
+        entity_name: &str,
+        sample_type: SampleType,
+        sample_name: &str,
+        metric_type: MetricType,
+        value: f64,
+    ) -> Sample {
+        Sample {
+            entity_type,
+            entity_name: entity_name.into(),
+            sample_type,
+            sample_name: sample_name.into(),
+            metric_type,
+            value,
+        }
+    }
+
+    fn measured_device(
+        api_fixture: &str,
+        friendly_name: &str,
+        samples: &[Sample],
+    ) -> (Measurement, MeasuredDevice) {
+        let info: DeviceInfoResponse = serde_json::from_str(
+            &fs::read_to_string(format!("tests/fixtures/responses/{}", api_fixture)).unwrap(),
+        )
+        .unwrap();
+        let measurement = Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: samples.to_vec(),
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        };
+        let device = MeasuredDevice {
+            measurement_id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            info,
+            friendly_name: friendly_name.into(),
+            samples: 0..samples.len(),
+        };
+
+        (measurement, device)
+    }
+
+    fn render(messages: &[(String, Vec<u8>)]) -> String {
+        let rendered: Vec<serde_json::Value> = messages
+            .iter()
+            .map(|(topic, payload)| {
+                serde_json::json!({
+                    "topic": topic,
+                    "payload": serde_json::from_slice::<serde_json::Value>(payload).unwrap(),
+                })
+            })
+            .collect();
+
+        serde_json::to_string_pretty(&rendered).unwrap()
+    }
+
+    #[test]
+    fn discovery_messages_for_p1_meter_match_golden_file() {
+        let (measurement, device) = measured_device(
+            "api-p1.json",
+            "P1 meter",
+            &[
+                sample(
+                    EntityType::Tariff,
+                    "HWE-P1",
+                    SampleType::ElectricityConsumption,
+                    "t1 import",
+                    MetricType::Counter,
+                    38989839600.0,
+                ),
+                sample(
+                    EntityType::Tariff,
+                    "HWE-P1",
+                    SampleType::ElectricityProduction,
+                    "t1 export",
+                    MetricType::Counter,
+                    7200.0,
+                ),
+                sample(
+                    EntityType::Tariff,
+                    "HWE-P1",
+                    SampleType::ElectricityConsumption,
+                    "t2 import",
+                    MetricType::Counter,
+                    10615777200.0,
+                ),
+                sample(
+                    EntityType::Tariff,
+                    "HWE-P1",
+                    SampleType::ElectricityProduction,
+                    "t2 export",
+                    MetricType::Counter,
+                    10800.0,
+                ),
+                sample(
+                    EntityType::Device,
+                    "HWE-P1",
+                    SampleType::ElectricityConsumption,
+                    "P1 meter",
+                    MetricType::Gauge,
+                    -543.0,
+                ),
+            ],
+        );
+
+        // act
+        let messages = discovery_messages("homeassistant", "homewizard", &measurement, &[device]);
+
+        assert_golden(
+            "tests/fixtures/golden/home-assistant-p1.json",
+            &render(&messages),
+        );
+    }
+
+    #[test]
+    fn discovery_messages_for_water_meter_match_golden_file() {
+        let (measurement, device) = measured_device(
+            "api-watermeter.json",
+            "Watermeter",
+            &[
+                sample(
+                    EntityType::Device,
+                    "HWE-WTR",
+                    SampleType::WaterConsumption,
+                    "Watermeter",
+                    MetricType::Counter,
+                    123.456,
+                ),
+                sample(
+                    EntityType::Device,
+                    "HWE-WTR",
+                    SampleType::WaterConsumption,
+                    "Watermeter",
+                    MetricType::Gauge,
+                    0.45,
+                ),
+            ],
+        );
+
+        // act
+        let messages = discovery_messages("homeassistant", "homewizard", &measurement, &[device]);
+
+        assert_golden(
+            "tests/fixtures/golden/home-assistant-water-meter.json",
+            &render(&messages),
+        );
+    }
+
+    #[test]
+    fn changed_messages_only_returns_new_or_renamed_sensors() {
+        let (measurement, device) = measured_device(
+            "api-watermeter.json",
+            "Watermeter",
+            &[sample(
+                EntityType::Device,
+                "HWE-WTR",
+                SampleType::WaterConsumption,
+                "Watermeter",
+                MetricType::Counter,
+                123.456,
+            )],
+        );
+        let cycle_summary = Arc::new(Mutex::new(Some(CycleSummary {
+            measured_devices: vec![device.clone()],
+            ..Default::default()
+        })));
+        let discovery = HomeAssistantDiscovery::new("homeassistant".into(), cycle_summary.clone());
+
+        // act
+        let first = discovery.changed_messages("homewizard", &measurement);
+        for (topic, payload) in first.iter().cloned() {
+            discovery.mark_published(topic, payload);
+        }
+        let second = discovery.changed_messages("homewizard", &measurement);
+        cycle_summary
+            .lock()
+            .unwrap()
+            .as_mut()
+            .unwrap()
+            .measured_devices[0]
+            .friendly_name = "Tuin".into();
+        let renamed = discovery.changed_messages("homewizard", &measurement);
+
+        assert_eq!(first.len(), 1);
+        assert!(second.is_empty());
+        assert_eq!(renamed.len(), 1);
+        assert_eq!(renamed[0].0, first[0].0);
+    }
+}
@@ -1,392 +1,2465 @@
-use crate::model::Config;
+use crate::allowed_cidrs::{self, Cidr};
+use crate::apparent_power;
+use crate::burst;
+use crate::carry_forward::CarryForward;
+use crate::daily_consumption;
+use crate::deadband::Deadband;
+use crate::derived;
+use crate::device_health::SucceededDevice;
+use crate::device_registry::{DeviceRegistry, Fetch};
+use crate::device_tls;
+use crate::discovery::{self, DeviceDiscoverer};
+use crate::error::{ConfigError, DeviceError};
+use crate::events::{publish_events, EventPublisher};
+use crate::expected_samples;
+use crate::exporter_state::{ExporterState, ExporterStateStore};
+use crate::external_meters;
+use crate::firmware::FirmwareChange;
+use crate::gas_clock::{self, GasClockSkew};
+use crate::metric_split;
+use crate::model::{
+    Config, DailyConsumptionConfig, EntityNameSource, ExpectedSamples, GasClockSkewConfig, OnEmpty,
+    StaticDevice, TimeOfUseConfig,
+};
+use crate::name_normalization;
+use crate::name_sanitization;
+use crate::precision;
+use crate::replay::ReplayFixtures;
+use crate::sample_cap;
+use crate::smoothing::Smoother;
+use crate::time_of_use;
+use crate::units::{kwh_to_joules, liters_per_minute_to_cubic_meters_per_hour};
 use jarvis_lib::measurement_client::MeasurementClient;
 use jarvis_lib::model::{EntityType, Measurement, MetricType, Sample, SampleType};
 
-use chrono::Utc;
-use mdns_sd::{ServiceDaemon, ServiceEvent};
+use chrono::{DateTime, Utc};
+use openssl::x509::X509;
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::env;
 use std::error::Error;
-use std::net::Ipv4Addr;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::ops::Range;
+use std::panic;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Duration;
-use tracing::{debug, info};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, dispatcher, error, field, info, info_span, warn, Span};
 use uuid::Uuid;
 
+/// Maps a device to the base url its endpoints are requested from.
+pub type EndpointResolver =
+    Arc<dyn Fn(&HomewizardDevice) -> Result<String, Box<dyn Error + Send + Sync>> + Send + Sync>;
+
+/// Returns the time a measurement gets taken at.
+pub type Clock = Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>;
+
+/// Returns the id for a new measurement.
+pub type IdGenerator = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// Settings of a [`HomewizardClient`]; start from [`Default`], [`HomewizardClientConfig::new`] or
+/// [`HomewizardClientConfig::from_env`] and adjust with the `with_*` methods.
 pub struct HomewizardClientConfig {
     timeout_seconds: u64,
+    cycle_deadline: Option<Duration>,
+    state_file_path: Option<PathBuf>,
+    device: Option<DeviceTarget>,
+    replay_directory: Option<PathBuf>,
+    endpoint_resolver: EndpointResolver,
+    clock: Clock,
+    id_generator: IdGenerator,
+    ca_certificate: Option<reqwest::Certificate>,
+    ca_roots: Vec<X509>,
+    insecure_tls: bool,
+    device_proxy: bool,
+}
+
+impl Default for HomewizardClientConfig {
+    fn default() -> Self {
+        Self {
+            timeout_seconds: 10,
+            cycle_deadline: None,
+            state_file_path: None,
+            device: None,
+            replay_directory: None,
+            endpoint_resolver: Arc::new(HomewizardDevice::base_url),
+            clock: Arc::new(Utc::now),
+            id_generator: Arc::new(|| Uuid::new_v4().to_string()),
+            ca_certificate: None,
+            ca_roots: vec![],
+            insecure_tls: false,
+            device_proxy: false,
+        }
+    }
+}
+
+/// A single device to measure by address instead of discovering all devices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceTarget {
+    pub address: SocketAddrV4,
+    /// Serial the device at the address has to report, so a changed dhcp lease doesn't get another
+    /// device measured under this one's name.
+    pub expected_serial: Option<String>,
 }
 
 impl HomewizardClientConfig {
+    /// Config with the given timeout for discovery, no state file and no replay.
     pub fn new(timeout_seconds: u64) -> Result<Self, Box<dyn Error>> {
         debug!(
             "HomewizardClientConfig::new(timeout_seconds: {})",
             timeout_seconds
         );
-        Ok(Self { timeout_seconds })
+        Ok(Self {
+            timeout_seconds,
+            ..Default::default()
+        })
     }
 
+    /// Reads `TIMEOUT_SECONDS`, `CYCLE_DEADLINE_SECONDS`, `EXPORTER_STATE_FILE_PATH`,
+    /// `REPLAY_DIRECTORY`, `DEVICE_URL_TEMPLATE`, `DEVICE_CA_FILE`, `DEVICE_TLS_INSECURE` and
+    /// `DEVICE_PROXY`.
     pub fn from_env() -> Result<Self, Box<dyn Error>> {
         let timeout_seconds: u64 = env::var("TIMEOUT_SECONDS")
             .unwrap_or_else(|_| "10".to_string())
             .parse()?;
+        let state_file_path = env::var("EXPORTER_STATE_FILE_PATH").ok().map(PathBuf::from);
+        let replay_directory = env::var("REPLAY_DIRECTORY").ok().map(PathBuf::from);
+
+        let mut config = Self {
+            state_file_path,
+            replay_directory,
+            ..Self::new(timeout_seconds)?
+        };
+
+        if let Ok(cycle_deadline_seconds) = env::var("CYCLE_DEADLINE_SECONDS") {
+            config =
+                config.with_cycle_deadline(Duration::from_secs(cycle_deadline_seconds.parse()?));
+        }
+
+        if let Ok(template) = env::var("DEVICE_URL_TEMPLATE") {
+            config = config.with_url_template(template);
+        }
+        if let Ok(ca_file) = env::var("DEVICE_CA_FILE") {
+            config = config.with_ca_file(Path::new(&ca_file))?;
+        }
+        if env::var("DEVICE_TLS_INSECURE").map_or(false, |insecure| insecure == "true") {
+            config = config.with_insecure_tls();
+        }
+        if env::var("DEVICE_PROXY").map_or(false, |proxy| proxy == "true") {
+            config = config.with_device_proxy();
+        }
+
+        Ok(config)
+    }
+
+    /// How long discovery browses for devices.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_seconds)
+    }
+
+    /// Bounds a whole cycle - discovery, device requests and burst reads - to `cycle_deadline`;
+    /// devices not yet requested when it passes are cut off, the cycle goes on with the samples
+    /// it has.
+    pub fn with_cycle_deadline(self, cycle_deadline: Duration) -> Self {
+        Self {
+            cycle_deadline: Some(cycle_deadline),
+            ..self
+        }
+    }
+
+    /// Requests device endpoints from the base url `resolver` returns instead of from the
+    /// device's own address, for example to go through a reverse proxy.
+    pub fn with_endpoint_resolver(self, endpoint_resolver: EndpointResolver) -> Self {
+        Self {
+            endpoint_resolver,
+            ..self
+        }
+    }
+
+    /// Resolves base urls by filling in the `{ip}`, `{port}` and `{name}` of the device in
+    /// `template`, like `https://proxy.local/homewizard/{ip}`.
+    pub fn with_url_template(self, template: String) -> Self {
+        self.with_endpoint_resolver(Arc::new(
+            move |device: &HomewizardDevice| -> Result<String, Box<dyn Error + Send + Sync>> {
+                Ok(template
+                    .replace("{ip}", &device.ip_address()?.to_string())
+                    .replace("{port}", &device.port.to_string())
+                    .replace("{name}", &device.fullname))
+            },
+        ))
+    }
+
+    /// Trusts the CA certificate in the pem file at `path` for https device urls, next to the
+    /// system trust store, and for v2 devices next to the bundled HomeWizard CA.
+    pub fn with_ca_file(self, path: &Path) -> Result<Self, Box<dyn Error>> {
+        let pem = fs::read(path)
+            .map_err(|e| format!("Failed reading CA file {}: {}", path.display(), e))?;
+        let ca_certificate = reqwest::Certificate::from_pem(&pem)
+            .map_err(|e| format!("Failed parsing CA file {}: {}", path.display(), e))?;
+        let ca_roots = X509::stack_from_pem(&pem)
+            .map_err(|e| format!("Failed parsing CA file {}: {}", path.display(), e))?;
+
+        Ok(Self {
+            ca_certificate: Some(ca_certificate),
+            ca_roots,
+            ..self
+        })
+    }
+
+    /// Accepts any certificate for https device urls, which makes them as safe as plain http.
+    pub fn with_insecure_tls(self) -> Self {
+        Self {
+            insecure_tls: true,
+            ..self
+        }
+    }
+
+    /// Requests device endpoints through the proxy of the `HTTP_PROXY`, `HTTPS_PROXY` and
+    /// `NO_PROXY` environment variables, which device requests bypass by default since a proxy
+    /// can't reach devices on the local network.
+    pub fn with_device_proxy(self) -> Self {
+        Self {
+            device_proxy: true,
+            ..self
+        }
+    }
+
+    /// The client device endpoints get requested with, trusting the configured CA and bypassing
+    /// any proxy unless told otherwise.
+    pub fn http_client(&self) -> Result<reqwest::blocking::Client, Box<dyn Error + Send + Sync>> {
+        let mut builder = self.client_builder();
+        if let Some(ca_certificate) = &self.ca_certificate {
+            builder = builder.add_root_certificate(ca_certificate.clone());
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// The client v2 devices get requested with over https: their certificate has to be issued
+    /// by the bundled HomeWizard CA or the configured one, to the device with `expected_serial`
+    /// when it's known.
+    pub fn device_tls_client(
+        &self,
+        expected_serial: Option<&str>,
+    ) -> Result<reqwest::blocking::Client, Box<dyn Error + Send + Sync>> {
+        let builder = self.client_builder();
+        if self.insecure_tls {
+            return Ok(builder.build()?);
+        }
+
+        let mut roots = device_tls::bundled_roots()?;
+        roots.extend(self.ca_roots.iter().cloned());
+        Ok(builder
+            .use_preconfigured_tls(device_tls::client_config(roots, expected_serial))
+            .build()?)
+    }
+
+    fn client_builder(&self) -> reqwest::blocking::ClientBuilder {
+        let mut builder = reqwest::blocking::Client::builder();
+        if self.device_proxy {
+            info!("DEVICE_PROXY is set: device requests go through the HTTP proxy from the environment");
+        } else {
+            debug!("Device requests bypass any HTTP proxy from the environment");
+            builder = builder.no_proxy();
+        }
+        if self.insecure_tls {
+            warn!(
+                "DEVICE_TLS_INSECURE is set: device certificates are NOT verified, anyone on the network can impersonate a device"
+            );
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+
+        builder
+    }
+
+    /// Takes the measurement time from `clock` and its id from `id_generator` instead of the
+    /// system clock and a random uuid, so measurements become reproducible.
+    pub fn with_clock_and_id_generator(self, clock: Clock, id_generator: IdGenerator) -> Self {
+        Self {
+            clock,
+            id_generator,
+            ..self
+        }
+    }
+
+    /// Skips discovery and measures only the device at the target's address.
+    pub fn with_device(self, device: DeviceTarget) -> Self {
+        Self {
+            device: Some(device),
+            ..self
+        }
+    }
+
+    /// Measures the devices in a directory of fixture responses instead of the ones on the
+    /// network; see [`ReplayFixtures`] for its layout.
+    pub fn with_replay(self, replay_directory: PathBuf) -> Self {
+        Self {
+            replay_directory: Some(replay_directory),
+            ..self
+        }
+    }
+
+    /// Keeps exporter state in memory only, for runs that shouldn't leave any trace.
+    pub fn without_state(self) -> Self {
+        Self {
+            state_file_path: None,
+            ..self
+        }
+    }
 
-        Self::new(timeout_seconds)
+    /// Whether devices come from somewhere else than discovery: a single device to measure, or
+    /// a replay directory.
+    pub fn skips_discovery(&self) -> bool {
+        self.device.is_some() || self.replay_directory.is_some()
     }
 }
 
+/// Discovers HomeWizard devices and reads their measurements; use it through
+/// [`MeasurementClient::get_measurements`] for a whole cycle, or through
+/// [`HomewizardClient::discover_devices`], [`HomewizardClient::get_device_info`] and
+/// [`HomewizardClient::get_samples`] to handle devices one by one.
 pub struct HomewizardClient {
     config: HomewizardClientConfig,
+    last_summary: Arc<Mutex<Option<CycleSummary>>>,
+    registry: Arc<Mutex<DeviceRegistry>>,
+    /// The info of every device fetched so far, by mdns name.
+    device_info_cache: Mutex<HashMap<String, DeviceInfoResponse>>,
+    /// Shared by all requests, so connections to a device get reused within a cycle.
+    http_client: OnceLock<reqwest::blocking::Client>,
+    /// Clients for https requests straight to v2 devices, by the serial their certificate has to
+    /// be issued to, if known.
+    device_tls_clients: Mutex<HashMap<Option<String>, reqwest::blocking::Client>>,
+    /// Measurements of v2 devices out of their batch response, by mdns name, taken by the first
+    /// data request of the device instead of requesting its data endpoint.
+    batch_data: Mutex<HashMap<String, serde_json::Value>>,
+    /// V2 devices by mdns name whose batch endpoint turned out missing.
+    batch_unsupported: Mutex<HashSet<String>>,
+    state: Mutex<ExporterState>,
+    state_store: ExporterStateStore,
+    event_publisher: Option<Box<dyn EventPublisher + Send + Sync>>,
+    replay: Option<ReplayFixtures>,
+    discoverer: Box<dyn DeviceDiscoverer + Send + Sync>,
+    smoother: Smoother,
+    carry_forward: CarryForward,
+    deadband: Deadband,
+    /// When the running cycle has to be finished, with a cycle deadline configured.
+    deadline: Mutex<Option<Instant>>,
 }
 
 impl MeasurementClient<Config> for HomewizardClient {
     fn get_measurements(
         &self,
         config: Config,
-        _last_measurements: Option<Vec<Measurement>>,
+        last_measurements: Option<Vec<Measurement>>,
     ) -> Result<Vec<Measurement>, Box<dyn Error>> {
+        // the id of the first measurement is known from the start, so every log line of the
+        // cycle carries it, including those of the caller's span when it declares the field
+        let measurement_id = (self.config.id_generator)();
+        Span::current().record("measurement_id", measurement_id.as_str());
+        let cycle_span = info_span!(
+            "measurement_cycle",
+            location = %config.location,
+            measurement_id = %measurement_id
+        );
+        let _cycle_span = cycle_span.enter();
+
         info!("Reading measurements from homewizard devices...");
+        let start = Instant::now();
+        let measured_at = (self.config.clock)();
+        *self.deadline.lock().unwrap() =
+            self.config.cycle_deadline.map(|deadline| start + deadline);
 
-        let mut measurement = Measurement {
-            id: Uuid::new_v4().to_string(),
-            source: String::from("jarvis-homewizard-exporter"),
-            location: config.location.clone(),
-            samples: Vec::new(),
-            measured_at_time: Utc::now(),
+        let devices = match (&self.config.device, &self.replay) {
+            (Some(target), _) => vec![self.target_device(target).map_err(DeviceError::new)?],
+            (None, Some(replay)) => replay.start_cycle()?,
+            (None, None) => {
+                info!("Discovering devices...");
+                let devices = self.discover_devices().map_err(DeviceError::new)?;
+                info!("Found {} devices", devices.len());
+                let cidrs = config
+                    .allowed_cidrs
+                    .iter()
+                    .map(|cidr| Cidr::parse(cidr))
+                    .collect::<Result<Vec<Cidr>, Box<dyn Error>>>()
+                    .map_err(ConfigError::new)?;
+                with_location_devices(&config, allowed_cidrs::filter(&cidrs, devices))
+            }
         };
+        let devices_count = devices.len();
 
-        info!("Discovering devices...");
-        let devices = self.discover_devices()?;
-        info!("Found {} devices", devices.len());
+        let mut summary = CycleSummary::new(devices_count);
+        summary.on_empty = config.on_empty;
+        if let Some(deadband) = &config.deadband {
+            self.deadband.next_cycle(deadband);
+        }
 
-        for device in devices.iter() {
-            match self.get_samples(&config, device) {
-                Ok(samples) => {
-                    measurement.samples.append(&mut samples.clone());
-                }
-                Err(_) => continue,
+        // every location gets a measurement of its own, so a location whose devices all fail
+        // doesn't keep the others from getting published
+        let mut measurements = Vec::new();
+        let mut empty_measurements = Vec::new();
+        let mut measurement_id = Some(measurement_id);
+        for (location, devices) in self.group_by_location(&config, devices) {
+            let mut measurement = Measurement {
+                id: measurement_id
+                    .take()
+                    .unwrap_or_else(|| (self.config.id_generator)()),
+                source: String::from("jarvis-homewizard-exporter"),
+                location: location.to_string(),
+                samples: Vec::new(),
+                measured_at_time: measured_at,
+            };
+            let previous = last_measurements.as_ref().and_then(|last| {
+                metric_split::join(last.iter().filter(|m| m.location == location))
+            });
+            let mut location_summary = CycleSummary::new(devices.len());
+            self.measure_devices(
+                &config,
+                &devices,
+                previous.as_ref(),
+                &mut measurement,
+                &mut location_summary,
+            );
+            if config.name_normalization.is_some() {
+                warn_name_collisions(&measurement.location, &location_summary.measured_devices);
             }
+            summary.samples_dropped += cap_samples(
+                &config,
+                &mut measurement,
+                &mut location_summary.measured_devices,
+            );
+
+            if measurement.samples.is_empty() && location_summary.samples_unchanged > 0 {
+                debug!(
+                    location = %measurement.location,
+                    "Skipping publishing of measurement whose samples all stayed within the deadband"
+                );
+            } else if measurement.samples.is_empty() {
+                empty_measurements.push((measurement, devices.len()));
+            } else if config.split_by_metric_type {
+                let ids = ((self.config.id_generator)(), (self.config.id_generator)());
+                measurements.extend(metric_split::split(
+                    measurement,
+                    ids,
+                    &mut location_summary.measured_devices,
+                ));
+            } else {
+                measurements.push(measurement);
+            }
+            summary.absorb(location_summary);
+        }
+
+        info!("Read measurements from {} devices", devices_count);
+        if !summary.cut_off_devices.is_empty() {
+            warn!(
+                cut_off_devices = ?summary.cut_off_devices,
+                "Cycle deadline of {:?} passed, cut off {} devices that weren't requested yet",
+                self.config.cycle_deadline.unwrap_or_default(),
+                summary.cut_off_devices.len()
+            );
+        }
+
+        let strict_failures = self.strict_failures(&config, &summary);
+        summary.finish(&measurements, start.elapsed());
+        // measuring one device says nothing about the others being offline
+        if self.config.device.is_none() {
+            self.update_device_health(&config, measured_at, &summary);
+            self.check_missing_devices(&config, measured_at, &summary);
+            self.check_unmatched_serials(&config, &summary);
         }
+        let unchanged = summary.samples_unchanged;
+        *self.last_summary.lock().unwrap() = Some(summary);
 
-        info!("Read measurements from {} devices", devices.len());
+        if !strict_failures.is_empty() {
+            for (serial_or_name, error) in &strict_failures {
+                error!(
+                    device = %serial_or_name,
+                    "Device {} failed in strict mode: {}",
+                    serial_or_name,
+                    error
+                );
+            }
+            return Err(DeviceError::new(
+                format!(
+                    "Not publishing a partial measurement in strict mode, {} devices failed: {}",
+                    strict_failures.len(),
+                    strict_failures
+                        .iter()
+                        .map(|(serial_or_name, _)| serial_or_name.as_str())
+                        .collect::<Vec<&str>>()
+                        .join(", ")
+                )
+                .into(),
+            )
+            .into());
+        }
+        // a cycle in which nothing changed isn't empty
+        if measurements.is_empty() && unchanged == 0 && config.on_empty == OnEmpty::Error {
+            return Err(DeviceError::new(
+                format!("No samples read from any of the {} devices", devices_count).into(),
+            )
+            .into());
+        }
+        for (measurement, devices) in empty_measurements {
+            match config.on_empty {
+                OnEmpty::Error => warn!(
+                    location = %measurement.location,
+                    "No samples read from any of the {} devices of location {}",
+                    devices,
+                    measurement.location
+                ),
+                OnEmpty::Skip => info!(
+                    location = %measurement.location,
+                    "Skipping publishing of measurement without samples"
+                ),
+                OnEmpty::Heartbeat => {
+                    info!(
+                        location = %measurement.location,
+                        "Publishing measurement without samples as heartbeat"
+                    );
+                    measurements.push(measurement);
+                }
+            }
+        }
 
-        Ok(vec![measurement])
+        Ok(measurements)
     }
 }
 
 impl HomewizardClient {
+    /// Client that discovers devices through mdns, loading its state from the configured state file.
     pub fn new(config: HomewizardClientConfig) -> Self {
-        Self { config }
+        let state_store = ExporterStateStore::new(config.state_file_path.clone());
+        let state = Mutex::new(state_store.load());
+        let replay = config.replay_directory.clone().map(ReplayFixtures::new);
+
+        Self {
+            config,
+            last_summary: Arc::new(Mutex::new(None)),
+            registry: Arc::new(Mutex::new(DeviceRegistry::default())),
+            device_info_cache: Mutex::new(HashMap::new()),
+            http_client: OnceLock::new(),
+            device_tls_clients: Mutex::new(HashMap::new()),
+            batch_data: Mutex::new(HashMap::new()),
+            batch_unsupported: Mutex::new(HashSet::new()),
+            state,
+            state_store,
+            event_publisher: None,
+            replay,
+            discoverer: discovery::default_discoverer(),
+            smoother: Smoother::default(),
+            carry_forward: CarryForward::default(),
+            deadband: Deadband::default(),
+            deadline: Mutex::new(None),
+        }
+    }
+
+    /// Finds devices through `discoverer` instead of browsing mdns.
+    pub fn with_discoverer(mut self, discoverer: Box<dyn DeviceDiscoverer + Send + Sync>) -> Self {
+        self.discoverer = discoverer;
+        self
+    }
+
+    /// Publishes device offline and online events through `event_publisher`.
+    pub fn with_event_publisher(
+        mut self,
+        event_publisher: Box<dyn EventPublisher + Send + Sync>,
+    ) -> Self {
+        self.event_publisher = Some(event_publisher);
+        self
+    }
+
+    /// Returns a handle to the summary of the most recent measurement cycle, so the code driving
+    /// the cycle can complete and log it once it knows whether the measurement got published.
+    pub fn summary_handle(&self) -> Arc<Mutex<Option<CycleSummary>>> {
+        self.last_summary.clone()
+    }
+
+    /// Returns a handle to the registry of every device measured so far, with the outcome of
+    /// its last request and its samples in the most recent measurement.
+    pub fn registry_handle(&self) -> Arc<Mutex<DeviceRegistry>> {
+        self.registry.clone()
+    }
+
+    /// Builds the device for the target, verifying it is the expected one before measuring it.
+    fn target_device(
+        &self,
+        target: &DeviceTarget,
+    ) -> Result<HomewizardDevice, Box<dyn Error + Send + Sync>> {
+        let device = HomewizardDevice {
+            fullname: target.address.to_string(),
+            ip_addresses: vec![*target.address.ip()].into_iter().collect(),
+            port: target.address.port(),
+            hostname: None,
+            api_path: None,
+        };
+
+        if let Some(expected_serial) = &target.expected_serial {
+            let device_info = self.get_device_info(&device)?;
+            if !device_info.serial.eq_ignore_ascii_case(expected_serial) {
+                return Err(format!(
+                    "Device at {} has serial {} instead of expected serial {}",
+                    target.address, device_info.serial, expected_serial
+                )
+                .into());
+            }
+        }
+
+        Ok(device)
+    }
+
+    /// Splits the devices over the locations they belong to, the main location first; without
+    /// `locations` configured all of them belong to the main location. A device gets placed by
+    /// its address, or else by its serial, which takes requesting its `/api` endpoint the first
+    /// time the device is seen. A device whose serial can't be told stays in the main location,
+    /// where its failure gets recorded.
+    fn group_by_location<'a>(
+        &self,
+        config: &'a Config,
+        devices: Vec<HomewizardDevice>,
+    ) -> Vec<(&'a str, Vec<HomewizardDevice>)> {
+        if config.locations.is_empty() {
+            return vec![(config.location.as_str(), devices)];
+        }
+
+        let mut groups: Vec<(&str, Vec<HomewizardDevice>)> =
+            std::iter::once(config.location.as_str())
+                .chain(config.locations.iter().map(|l| l.location.as_str()))
+                .map(|location| (location, vec![]))
+                .collect();
+        for device in devices {
+            let addresses: Vec<SocketAddrV4> = device
+                .ip_addresses
+                .iter()
+                .map(|ip_address| SocketAddrV4::new(*ip_address, device.port))
+                .collect();
+            let mut location = config.location_of(None, &addresses);
+            if location == config.location {
+                if let Some(serial) = self.serial_of(&device) {
+                    location = config.location_of(Some(&serial), &addresses);
+                }
+            }
+            if let Some((_, devices)) = groups.iter_mut().find(|(l, _)| *l == location) {
+                devices.push(device);
+            }
+        }
+        // every device can belong to other locations, leaving nothing to measure for the main one
+        groups.retain(|(location, devices)| *location != config.location || !devices.is_empty());
+
+        groups
+    }
+
+    /// The serial of the device, from its info fetched earlier or else requested now.
+    fn serial_of(&self, device: &HomewizardDevice) -> Option<String> {
+        if let Some(device_info) = self.device_info_cache.lock().unwrap().get(&device.fullname) {
+            return Some(device_info.serial.clone());
+        }
+
+        match self.get_device_info(device) {
+            Ok(device_info) => {
+                let serial = device_info.serial.clone();
+                self.device_info_cache
+                    .lock()
+                    .unwrap()
+                    .insert(device.fullname.clone(), device_info);
+                Some(serial)
+            }
+            Err(e) => {
+                debug!(
+                    "Failed fetching info of device {} to tell its location: {}",
+                    device.fullname, e
+                );
+                None
+            }
+        }
     }
 
-    fn get_samples(
+    /// Warns and publishes an event when a device the config expects by serial goes missing or
+    /// returns.
+    fn check_missing_devices(
         &self,
         config: &Config,
-        device: &HomewizardDevice,
-    ) -> Result<Vec<Sample>, Box<dyn Error>> {
-        info!(
-            "Fetching info for device {} ({:?})...",
-            device.fullname, device.ip_addresses
+        measured_at: DateTime<Utc>,
+        summary: &CycleSummary,
+    ) {
+        let missing_devices_config = match &config.missing_devices {
+            Some(missing_devices_config) => missing_devices_config,
+            None => return,
+        };
+
+        let read: BTreeSet<&str> = summary
+            .succeeded_devices
+            .iter()
+            .map(|device| device.serial.as_str())
+            .collect();
+        let mut state = self.state.lock().unwrap();
+        let events = state.missing_devices.observe(
+            measured_at,
+            &read,
+            &config.expected_devices(),
+            missing_devices_config.missing_cycles,
         );
 
-        // get general device data to determine type and name
-        let device_info_response = reqwest::blocking::get(format!(
-            "http://{}/api",
-            device.ip_addresses.iter().next().unwrap()
-        ))?
-        .json::<DeviceInfoResponse>()?;
+        for event in events.iter() {
+            warn!(
+                serial = %event.serial,
+                friendly_name = %event.friendly_name,
+                missing_since = %event.missing_since,
+                missing_seconds = event.missing_seconds,
+                "Configured device {} ({}) is {:?} after {} consecutive cycles without being read",
+                event.friendly_name,
+                event.serial,
+                event.event_type,
+                event.consecutive_cycles
+            );
+        }
 
-        info!(
-            "Received info from device {} ({:?}):\n{:#?}",
-            device.fullname, device.ip_addresses, device_info_response
-        );
+        if let Some(event_publisher) = &self.event_publisher {
+            publish_events(
+                event_publisher.as_ref(),
+                &missing_devices_config.subject,
+                &events,
+            );
+        }
 
-        let friendly_name: String =
-            if let Some(name) = config.names.get(&device_info_response.serial) {
-                name.clone()
-            } else {
-                device_info_response.product_name.clone()
-            };
+        if let Err(e) = self.state_store.save(&state) {
+            warn!("Failed saving exporter state: {}", e);
+        }
+    }
 
-        info!(
-            "Fetching data for device {} with friendly name {} ({:?})...",
-            device.fullname, friendly_name, device.ip_addresses
+    /// Warns every so many cycles about configured serials none of the devices read so far has.
+    fn check_unmatched_serials(&self, config: &Config, summary: &CycleSummary) {
+        let unmatched_serials_config = match &config.unmatched_serials {
+            Some(unmatched_serials_config) => unmatched_serials_config,
+            None => return,
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let unmatched = state.unmatched_serials.observe(
+            summary
+                .succeeded_devices
+                .iter()
+                .map(|device| device.serial.as_str()),
+            &config.configured_serials(),
+            unmatched_serials_config.every_cycles,
         );
 
-        match HomewizardDeviceType::from_str(&device_info_response.product_type).unwrap() {
-            HomewizardDeviceType::EnergySocket => {
-                // get measurement data
-                let data_response = reqwest::blocking::get(format!(
-                    "http://{}/api/{}/data",
-                    device.ip_addresses.iter().next().unwrap(),
-                    device_info_response.api_version
-                ))?
-                .json::<EnergySocketDataResponse>()?;
+        if let Some(unmatched) = unmatched {
+            warn!(
+                unmatched_serials = ?unmatched.serials,
+                misspelled_serials = ?unmatched.misspelled,
+                cycles = unmatched.cycles,
+                "Configured serials never matched a device; remove serials of devices that are gone and write serials in lowercase without colons"
+            );
+        }
 
-                info!(
-                    "Received data from device {} with friendly name {} ({:?}):\n{:#?}",
-                    device.fullname, friendly_name, device.ip_addresses, data_response
-                );
+        if let Err(e) = self.state_store.save(&state) {
+            warn!("Failed saving exporter state: {}", e);
+        }
+    }
 
-                Ok(vec![
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityConsumption,
-                        sample_name: friendly_name.clone(),
-                        metric_type: MetricType::Counter,
-                        value: data_response.total_power_import_t1_kwh * 1000.0 * 3600.0,
-                    },
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityProduction,
-                        sample_name: friendly_name.clone(),
-                        metric_type: MetricType::Counter,
-                        value: data_response.total_power_export_t1_kwh * 1000.0 * 3600.0,
-                    },
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityConsumption,
-                        sample_name: friendly_name,
-                        metric_type: MetricType::Gauge,
-                        value: data_response.active_power_w,
-                    },
-                ])
-            }
-            HomewizardDeviceType::SinglePhaseKwhMeter => {
-                // get measurement data
-                let data_response = reqwest::blocking::get(format!(
-                    "http://{}/api/{}/data",
-                    device.ip_addresses.iter().next().unwrap(),
-                    device_info_response.api_version
-                ))?
-                .json::<SinglePhaseKwhMeterDataResponse>()?;
+    fn update_device_health(
+        &self,
+        config: &Config,
+        measured_at: DateTime<Utc>,
+        summary: &CycleSummary,
+    ) {
+        let device_events_config = match &config.device_events {
+            Some(device_events_config) => device_events_config,
+            None => return,
+        };
 
-                info!(
-                    "Received data from device {} with friendly name {} ({:?}):\n{:#?}",
-                    device.fullname, friendly_name, device.ip_addresses, data_response
-                );
+        let mut state = self.state.lock().unwrap();
+        let events = state.device_health.observe(
+            measured_at,
+            &summary.succeeded_devices,
+            device_events_config.offline_threshold,
+        );
 
-                Ok(vec![
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityConsumption,
-                        sample_name: friendly_name.clone(),
-                        metric_type: MetricType::Counter,
-                        value: data_response.total_power_import_t1_kwh * 1000.0 * 3600.0,
-                    },
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityProduction,
-                        sample_name: friendly_name.clone(),
-                        metric_type: MetricType::Counter,
-                        value: data_response.total_power_export_t1_kwh * 1000.0 * 3600.0,
-                    },
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityConsumption,
-                        sample_name: friendly_name,
-                        metric_type: MetricType::Gauge,
-                        value: data_response.active_power_w,
-                    },
-                ])
-            }
-            HomewizardDeviceType::TriplePhaseKwhMeter => {
-                // get measurement data
-                let data_response = reqwest::blocking::get(format!(
-                    "http://{}/api/{}/data",
-                    device.ip_addresses.iter().next().unwrap(),
-                    device_info_response.api_version
-                ))?
-                .json::<TriplePhaseKwhMeterDataResponse>()?;
+        for event in events.iter() {
+            warn!(
+                "Device {} ({}) is {:?} after {} consecutive failed cycles",
+                event.friendly_name, event.serial, event.event_type, event.consecutive_failures
+            );
+        }
 
-                info!(
-                    "Received data from device {} with friendly name {} ({:?}):\n{:#?}",
-                    device.fullname, friendly_name, device.ip_addresses, data_response
-                );
+        if let Some(event_publisher) = &self.event_publisher {
+            publish_events(
+                event_publisher.as_ref(),
+                &device_events_config.subject,
+                &events,
+            );
+        }
 
-                Ok(vec![
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityConsumption,
-                        sample_name: friendly_name.clone(),
-                        metric_type: MetricType::Counter,
-                        value: data_response.total_power_import_t1_kwh * 1000.0 * 3600.0,
-                    },
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityProduction,
-                        sample_name: friendly_name.clone(),
-                        metric_type: MetricType::Counter,
-                        value: data_response.total_power_export_t1_kwh * 1000.0 * 3600.0,
-                    },
-                    Sample {
-                        entity_type: EntityType::Device,
-                        entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::ElectricityConsumption,
-                        sample_name: friendly_name,
-                        metric_type: MetricType::Gauge,
-                        value: data_response.active_power_w,
-                    },
-                ])
-            }
-            HomewizardDeviceType::WaterMeter => {
-                // get measurement data
-                let data_response = reqwest::blocking::get(format!(
-                    "http://{}/api/{}/data",
-                    device.ip_addresses.iter().next().unwrap(),
-                    device_info_response.api_version
-                ))?
-                .json::<WaterMeterDataResponse>()?;
+        if let Err(e) = self.state_store.save(&state) {
+            warn!("Failed saving exporter state: {}", e);
+        }
+    }
 
-                info!(
-                    "Received data from device {} with friendly name {} ({:?}):\n{:#?}",
-                    device.fullname, friendly_name, device.ip_addresses, data_response
+    /// Remembers the firmware version of the device, warning and publishing an event when it
+    /// changed since the device was last seen.
+    fn track_firmware(
+        &self,
+        config: &Config,
+        device_info: &DeviceInfoResponse,
+        detected_at: DateTime<Utc>,
+    ) {
+        let mut state = self.state.lock().unwrap();
+        let friendly_name = friendly_name(config, device_info);
+        let event = match state
+            .firmware
+            .observe(device_info, &friendly_name, detected_at)
+        {
+            FirmwareChange::Unchanged => return,
+            FirmwareChange::FirstSeen => None,
+            FirmwareChange::Changed(event) => Some(event),
+        };
+
+        if let Some(event) = &event {
+            warn!(
+                serial = %event.serial,
+                friendly_name = %event.friendly_name,
+                product_type = %event.product_type,
+                previous_firmware_version = %event.previous_firmware_version,
+                firmware_version = %event.firmware_version,
+                "Device firmware changed"
+            );
+            if let (Some(firmware_events_config), Some(event_publisher)) =
+                (&config.firmware_events, &self.event_publisher)
+            {
+                publish_events(
+                    event_publisher.as_ref(),
+                    &firmware_events_config.subject,
+                    &[event],
                 );
+            }
+        }
 
-                Ok(vec![
-                    Sample {
-                        entity_type: EntityType::Device,
+        if let Err(e) = self.state_store.save(&state) {
+            warn!("Failed saving exporter state: {}", e);
+        }
+    }
+
+    fn measure_devices(
+        &self,
+        config: &Config,
+        devices: &[HomewizardDevice],
+        previous: Option<&Measurement>,
+        measurement: &mut Measurement,
+        summary: &mut CycleSummary,
+    ) {
+        // failed devices with their product type, unknown when their info couldn't be fetched
+        let mut failures: Vec<(String, Option<String>)> = Vec::new();
+        let measured_at = measurement.measured_at_time;
+
+        for device in devices.iter() {
+            if self.time_left() == Some(Duration::ZERO) {
+                summary.cut_off_devices.push(device.fullname.clone());
+                continue;
+            }
+            let device_span = info_span!(
+                "device",
+                device = %device.fullname,
+                measurement_id = %measurement.id,
+                serial = field::Empty,
+                status = field::Empty
+            );
+            let _device_span = device_span.enter();
+            let fetch_start = Instant::now();
+            let base_url = self.base_url(device).ok();
+            let fetch = || Fetch {
+                base_url: base_url.as_deref(),
+                at: measured_at,
+                latency: fetch_start.elapsed(),
+            };
+
+            let (device_info, samples) = match self.fetch_device(config, device) {
+                Ok(fetched) => fetched,
+                Err(e) => {
+                    warn!("Failed fetching info for device {}: {}", device.fullname, e);
+                    device_span.record("status", "failed");
+                    self.registry.lock().unwrap().record_failure(
+                        device,
+                        fetch(),
+                        None,
+                        &e.to_string(),
+                    );
+                    summary.record_failure(&device.fullname, &e.to_string());
+                    failures.push((device.fullname.clone(), None));
+                    let cached_serial = self
+                        .device_info_cache
+                        .lock()
+                        .unwrap()
+                        .get(&device.fullname)
+                        .map(|device_info| device_info.serial.clone());
+                    if let Some(serial) = cached_serial {
+                        self.carry_forward(config, &serial, measurement);
+                    }
+                    continue;
+                }
+            };
+            device_span.record("serial", device_info.serial.as_str());
+            self.track_firmware(config, &device_info, measurement.measured_at_time);
+
+            match samples {
+                Ok(mut samples) => {
+                    device_span.record("status", "ok");
+                    let friendly_name = friendly_name(config, &device_info);
+                    if let Some(expected) = config.expected_samples.get(&device_info.serial) {
+                        check_expected_samples(expected, &device_info, &friendly_name, &samples);
+                    }
+                    if let Some(smoothing) = &config.smoothing {
+                        let alpha = smoothing
+                            .devices
+                            .get(&device_info.serial)
+                            .copied()
+                            .unwrap_or(smoothing.alpha);
+                        self.smoother
+                            .smooth(alpha, smoothing.keep_raw, &mut samples, previous);
+                    }
+                    let energy_delta = config.derived.as_ref().map_or(false, |d| d.energy_delta);
+                    if let (true, Some(previous)) = (energy_delta, previous) {
+                        let deltas = derived::energy_deltas(&samples, &previous.samples);
+                        samples.extend(deltas);
+                    }
+                    // rounded before anything compares values, so thresholds apply to what's published
+                    if let Some(precision) = &config.precision {
+                        precision::round(precision, &mut samples);
+                    }
+                    if config.carry_forward.is_some() {
+                        self.carry_forward.remember(&device_info.serial, &samples);
+                    }
+                    if let Some(deadband) = &config.deadband {
+                        let before = samples.len();
+                        self.deadband.filter(deadband, &mut samples, previous);
+                        summary.samples_unchanged += before - samples.len();
+                    }
+                    summary.record_success(&device_info.serial, &friendly_name);
+                    let first_sample = measurement.samples.len();
+                    measurement.samples.append(&mut samples);
+                    // the registry outlives the measurement, so it needs its own copy
+                    self.registry.lock().unwrap().record_success(
+                        device,
+                        fetch(),
+                        &device_info,
+                        &friendly_name,
+                        measurement.samples[first_sample..].to_vec(),
+                    );
+                    summary.measured_devices.push(MeasuredDevice {
+                        measurement_id: measurement.id.clone(),
+                        info: device_info.clone(),
+                        friendly_name,
+                        samples: first_sample..measurement.samples.len(),
+                    });
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed fetching data for device {} ({}): {}",
+                        device.fullname, device_info.serial, e
+                    );
+                    device_span.record("status", "failed");
+                    self.registry.lock().unwrap().record_failure(
+                        device,
+                        fetch(),
+                        Some(&device_info),
+                        &e.to_string(),
+                    );
+                    summary.record_failure(&device_info.serial, &e.to_string());
+                    failures.push((
+                        device_info.serial.clone(),
+                        Some(device_info.product_type.clone()),
+                    ));
+                    self.carry_forward(config, &device_info.serial, measurement);
+                    continue;
+                }
+            }
+        }
+
+        let derived_config = config.derived.as_ref();
+        if let Some(daily_consumption) = derived_config.and_then(|d| d.daily_consumption.as_ref()) {
+            let samples = self.daily_consumption(daily_consumption, measurement);
+            measurement.samples.extend(samples);
+        }
+        if let Some(time_of_use) = derived_config.and_then(|d| d.time_of_use.as_ref()) {
+            let samples = self.time_of_use(time_of_use, measurement, &summary.measured_devices);
+            measurement.samples.extend(samples);
+        }
+        if let Some(socket_total) = derived_config.and_then(|d| d.socket_total.as_ref()) {
+            // a device of unknown type may well have been a socket
+            summary.excluded_from_socket_total = failures
+                .into_iter()
+                .filter(|(_, product_type)| match product_type {
+                    Some(product_type) => {
+                        derived::counts_towards_socket_total(socket_total, product_type)
+                    }
+                    None => true,
+                })
+                .map(|(serial_or_name, _)| serial_or_name)
+                .collect();
+            if let Some(sample) = derived::socket_total(
+                socket_total,
+                &measurement.samples,
+                &summary.measured_devices,
+            ) {
+                measurement.samples.push(sample);
+            }
+        }
+        if let Some(untracked_power) = derived_config.and_then(|d| d.untracked_power.as_ref()) {
+            match derived::untracked_power(
+                untracked_power,
+                &measurement.samples,
+                &summary.measured_devices,
+            ) {
+                Some(sample) => measurement.samples.push(sample),
+                None => debug!(
+                    "Skipping untracked power without both a P1 meter and an energy socket read this cycle"
+                ),
+            }
+        }
+        // a location only reports the named devices it's configured with
+        let location = measurement.location.clone();
+        let names = || -> HashMap<String, String> {
+            config
+                .names
+                .iter()
+                .filter(|(serial, _)| config.location_of(Some(serial), &[]) == location)
+                .map(|(serial, name)| (serial.clone(), name.clone()))
+                .collect()
+        };
+        if let Some(device_reachable) = derived_config.and_then(|d| d.device_reachable.as_ref()) {
+            // measuring one device says nothing about the others being reachable
+            let samples = derived::device_reachable(
+                device_reachable,
+                &names(),
+                summary,
+                self.config.device.is_none(),
+            );
+            measurement.samples.extend(samples);
+        }
+        if derived_config.map_or(false, |d| d.fetch_failures) {
+            let samples = self.count_fetch_failures(&names(), summary);
+            measurement.samples.extend(samples);
+        }
+        // derived samples are computed from rounded ones, but need rounding of their own
+        if let Some(precision) = &config.precision {
+            precision::round(precision, &mut measurement.samples);
+        }
+    }
+
+    /// Appends the counters of a failed device kept from its last successful cycle, unless they
+    /// have been carried forward for `carryForward.cycles` consecutive cycles already.
+    fn carry_forward(&self, config: &Config, serial: &str, measurement: &mut Measurement) {
+        let max_cycles = match &config.carry_forward {
+            Some(carry_forward) => carry_forward.cycles,
+            None => return,
+        };
+
+        if let Some((mut samples, cycles)) = self.carry_forward.carry(serial, max_cycles) {
+            info!(
+                serial = %serial,
+                carried_forward = samples.len(),
+                carried_forward_cycles = cycles,
+                "Carried forward {} counters of failed device {} for {} of at most {} cycles",
+                samples.len(),
+                serial,
+                cycles,
+                max_cycles
+            );
+            measurement.samples.append(&mut samples);
+        }
+    }
+
+    /// The failed and cut off devices that fail the cycle in strict mode, with their error: the
+    /// configured ones, by serial or by their address in `locations`, and the others as well with
+    /// `strictIncludeUnconfigured`. A device whose `/api` endpoint failed only tells its serial
+    /// when its info was fetched in an earlier cycle.
+    fn strict_failures(&self, config: &Config, summary: &CycleSummary) -> Vec<(String, String)> {
+        if !config.strict {
+            return vec![];
+        }
+
+        let configured_serials = config.configured_serials();
+        let configured_addresses: Vec<String> = config
+            .static_devices()
+            .map(|device| device.address().to_string())
+            .collect();
+        let device_info_cache = self.device_info_cache.lock().unwrap();
+        let is_configured = |serial_or_name: &str| {
+            let serial = device_info_cache
+                .get(serial_or_name)
+                .map_or(serial_or_name, |device_info| device_info.serial.as_str());
+            configured_serials.contains(serial)
+                || configured_addresses
+                    .iter()
+                    .any(|address| address == serial_or_name)
+        };
+
+        let failed = summary.failed_devices.iter().map(|serial_or_name| {
+            let error = summary
+                .failure_errors
+                .get(serial_or_name)
+                .cloned()
+                .unwrap_or_default();
+            (serial_or_name.clone(), error)
+        });
+        let cut_off = summary
+            .cut_off_devices
+            .iter()
+            .map(|name| (name.clone(), "Cut off by the cycle deadline".to_string()));
+
+        failed
+            .chain(cut_off)
+            .filter(|(serial_or_name, _)| {
+                config.strict_include_unconfigured || is_configured(serial_or_name)
+            })
+            .collect()
+    }
+
+    /// Counts the failed fetches of the named devices of the cycle in the exporter state and
+    /// returns their counters. A device whose `/api` endpoint failed is only known by its mdns
+    /// name, which tells its serial when its info was fetched in an earlier cycle.
+    fn count_fetch_failures(
+        &self,
+        names: &HashMap<String, String>,
+        summary: &CycleSummary,
+    ) -> Vec<Sample> {
+        let failed: Vec<String> = {
+            let device_info_cache = self.device_info_cache.lock().unwrap();
+            summary
+                .failed_devices
+                .iter()
+                .map(
+                    |serial_or_name| match device_info_cache.get(serial_or_name) {
+                        Some(device_info) => device_info.serial.clone(),
+                        None => serial_or_name.clone(),
+                    },
+                )
+                .filter(|serial| names.contains_key(serial))
+                .collect()
+        };
+
+        let mut state = self.state.lock().unwrap();
+        state
+            .fetch_failures
+            .record(failed.iter().map(String::as_str));
+        if let Err(e) = self.state_store.save(&state) {
+            warn!("Failed saving exporter state: {}", e);
+        }
+
+        // measuring one device says nothing about the others
+        derived::fetch_failures(
+            names,
+            &state.fetch_failures,
+            summary,
+            self.config.device.is_none(),
+        )
+    }
+
+    /// Requests the info and the samples of the device; an error means its info couldn't be
+    /// fetched. Once the info of a device is known from an earlier cycle its data gets requested
+    /// alongside the info, so both take a single round-trip. Should the fresh info tell the device
+    /// changed type, serial or api version, the speculative data is dropped and requested again.
+    fn fetch_device(
+        &self,
+        config: &Config,
+        device: &HomewizardDevice,
+    ) -> Result<
+        (
+            DeviceInfoResponse,
+            Result<Vec<Sample>, Box<dyn Error + Send + Sync>>,
+        ),
+        Box<dyn Error + Send + Sync>,
+    > {
+        let cached = self
+            .device_info_cache
+            .lock()
+            .unwrap()
+            .get(&device.fullname)
+            .cloned();
+
+        // a different device behind a pinned address mustn't get the expected device's name nor
+        // its token, so its serial gets checked before anything else is requested
+        let expected_serial = device.ip_addresses.iter().find_map(|ip_address| {
+            config.expected_serial_at(SocketAddrV4::new(*ip_address, device.port))
+        });
+
+        let (device_info, speculative) = match cached {
+            _ if expected_serial.is_some() => (self.get_device_info(device)?, None),
+            Some(cached) if self.batches(device, &cached) => {
+                match self.get_batch(config, device, &cached)? {
+                    Some(device_info) => (device_info, None),
+                    None => (self.get_device_info(device)?, None),
+                }
+            }
+            Some(cached) => {
+                // keep logging into the subscriber and span of this cycle
+                let dispatch = dispatcher::get_default(|dispatch| dispatch.clone());
+                let span = Span::current();
+                let (device_info, speculative) = thread::scope(|scope| {
+                    let speculative = scope.spawn(|| {
+                        dispatcher::with_default(&dispatch, || {
+                            span.in_scope(|| self.sample_device(config, device, &cached))
+                        })
+                    });
+                    let device_info = self.get_device_info(device);
+
+                    (device_info, speculative.join())
+                });
+                let device_info = device_info?;
+                let speculative = speculative.unwrap_or_else(|panic| panic::resume_unwind(panic));
+
+                if device_info.product_type == cached.product_type
+                    && device_info.api_version == cached.api_version
+                    && device_info.serial == cached.serial
+                {
+                    (device_info, Some(speculative))
+                } else {
+                    info!(
+                        "Device {} changed from {} {} on api {} to {} {} on api {}, requesting its data again",
+                        device.fullname,
+                        cached.product_type,
+                        cached.serial,
+                        cached.api_version,
+                        device_info.product_type,
+                        device_info.serial,
+                        device_info.api_version
+                    );
+                    (device_info, None)
+                }
+            }
+            None => (self.get_device_info(device)?, None),
+        };
+
+        if let Some(expected_serial) = expected_serial {
+            if !device_info.serial.eq_ignore_ascii_case(expected_serial) {
+                return Err(format!(
+                    "Device at {} has serial {} instead of expected serial {}, skipping it",
+                    device.fullname, device_info.serial, expected_serial
+                )
+                .into());
+            }
+        }
+
+        self.device_info_cache
+            .lock()
+            .unwrap()
+            .insert(device.fullname.clone(), device_info.clone());
+
+        // a dropped speculative read mustn't leave anything behind, so it only gets applied now
+        let samples = match speculative {
+            Some(read) => read.map(|read| self.apply_read(config, &device_info, read)),
+            None => self.get_samples(config, device, &device_info),
+        };
+
+        if let Some(gas_clock_skew) = &config.gas_clock_skew {
+            self.check_device_clock(config, gas_clock_skew, device, &device_info);
+        }
+
+        Ok((device_info, samples))
+    }
+
+    /// Whether the device gets requested through its batch endpoint: a v2 device seen before,
+    /// so the token for its serial is known, whose batch endpoint didn't turn out missing.
+    fn batches(&self, device: &HomewizardDevice, cached: &DeviceInfoResponse) -> bool {
+        self.replay.is_none()
+            && cached.is_v2()
+            && !self
+                .batch_unsupported
+                .lock()
+                .unwrap()
+                .contains(&device.fullname)
+    }
+
+    /// Requests info and measurement of a v2 device at once, keeping the measurement for the
+    /// sample building to take. Returns no info when the batch endpoint is missing - which gets
+    /// remembered - or the response lacks the info, for it to be requested from `/api` instead;
+    /// a response lacking the measurement leaves the data endpoint to be requested as usual.
+    fn get_batch(
+        &self,
+        config: &Config,
+        device: &HomewizardDevice,
+        cached: &DeviceInfoResponse,
+    ) -> Result<Option<DeviceInfoResponse>, Box<dyn Error + Send + Sync>> {
+        let _span = info_span!("fetch_device_batch").entered();
+
+        info!(
+            "Fetching info and data for device {} in one batch ({:?})...",
+            device.fullname, device.ip_addresses
+        );
+
+        let token = config.device_token(&cached.serial);
+        let batch_response = match self.get_json_with_token::<BatchResponse>(
+            device,
+            BATCH_PATH,
+            Some(&cached.serial),
+            token,
+        ) {
+            Ok(batch_response) => batch_response,
+            Err(e) if is_not_found(e.as_ref()) => {
+                info!(
+                    "Device {} has no batch endpoint, requesting its endpoints one by one",
+                    device.fullname
+                );
+                self.batch_unsupported
+                    .lock()
+                    .unwrap()
+                    .insert(device.fullname.clone());
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+
+        if let Some(measurement) = batch_response.measurement {
+            self.batch_data
+                .lock()
+                .unwrap()
+                .insert(device.fullname.clone(), measurement);
+        }
+        let device_info = batch_response
+            .device
+            .and_then(|device_info| serde_json::from_value(device_info).ok());
+        match &device_info {
+            Some(device_info) => log_device_info(device, device_info),
+            None => debug!(
+                "Batch response of device {} lacks its info, requesting it",
+                device.fullname
+            ),
+        }
+
+        Ok(device_info)
+    }
+
+    /// Requests the `/api` endpoint of the device, which tells its type, serial and api version.
+    pub fn get_device_info(
+        &self,
+        device: &HomewizardDevice,
+    ) -> Result<DeviceInfoResponse, Box<dyn Error + Send + Sync>> {
+        let _span = info_span!("fetch_device_info").entered();
+
+        info!(
+            "Fetching info for device {} ({:?})...",
+            device.fullname, device.ip_addresses
+        );
+
+        // get general device data to determine type and name
+        let device_info_response = self.get_json::<DeviceInfoResponse>(device, "/api")?;
+
+        log_device_info(device, &device_info_response);
+
+        Ok(device_info_response)
+    }
+
+    /// Requests the system endpoint of a v2 device with the token of its serial, which tells the
+    /// device's own clock and whether its cloud connection is enabled. v1 devices have no such
+    /// endpoint and replayed devices no fixture for it, so nothing is requested for either.
+    pub fn get_system(
+        &self,
+        config: &Config,
+        device: &HomewizardDevice,
+        device_info_response: &DeviceInfoResponse,
+    ) -> Result<Option<SystemResponse>, Box<dyn Error + Send + Sync>> {
+        if !device_info_response.is_v2() || self.replay.is_some() {
+            return Ok(None);
+        }
+        let _span = info_span!("fetch_device_system").entered();
+
+        let serial = &device_info_response.serial;
+        let system_response = self.get_json_with_token::<SystemResponse>(
+            device,
+            SYSTEM_PATH,
+            Some(serial),
+            config.device_token(serial),
+        )?;
+        debug!(
+            "Received system of device {}: {:?}",
+            device.fullname, system_response
+        );
+
+        Ok(Some(system_response))
+    }
+
+    /// Blinks the led of a v2 device to identify it, with the token of its serial. Returns false
+    /// without requesting anything for a v1 device, which can't be identified over the api.
+    pub fn identify(
+        &self,
+        config: &Config,
+        device: &HomewizardDevice,
+        device_info_response: &DeviceInfoResponse,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        if !device_info_response.is_v2() {
+            return Ok(false);
+        }
+        if self.replay.is_some() {
+            return Err("A replayed device can't be identified".into());
+        }
+
+        let serial = &device_info_response.serial;
+        self.send_with_token(
+            Method::PUT,
+            device,
+            IDENTIFY_PATH,
+            Some(serial),
+            config.device_token(serial),
+        )?;
+        info!("Device {} is blinking its led", device.fullname);
+
+        Ok(true)
+    }
+
+    /// Requests the data endpoint of the device with the token of its serial, without converting
+    /// the response into samples, failing on a status other than 2xx like every device request.
+    pub fn fetch_data(
+        &self,
+        config: &Config,
+        device: &HomewizardDevice,
+        device_info_response: &DeviceInfoResponse,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let serial = &device_info_response.serial;
+        self.get_json_with_token::<serde_json::Value>(
+            device,
+            &device.data_path(&device_info_response.api_version),
+            Some(serial),
+            config.device_token(serial),
+        )?;
+
+        Ok(())
+    }
+
+    /// Requests the data endpoint of the device and converts it into samples, named after the
+    /// device's name in `config` or its product name, with the entity name `config` selects.
+    pub fn get_samples(
+        &self,
+        config: &Config,
+        device: &HomewizardDevice,
+        device_info_response: &DeviceInfoResponse,
+    ) -> Result<Vec<Sample>, Box<dyn Error + Send + Sync>> {
+        let read = self.sample_device(config, device, device_info_response)?;
+
+        Ok(self.apply_read(config, device_info_response, read))
+    }
+
+    /// Reads the samples of the device, several times within the timeout for a device configured
+    /// for burst sampling, falling back to the reads so far when one fails. Only the values of a
+    /// burst get aggregated, the rest of the read is that of its last read.
+    fn sample_device(
+        &self,
+        config: &Config,
+        device: &HomewizardDevice,
+        device_info_response: &DeviceInfoResponse,
+    ) -> Result<DeviceRead, Box<dyn Error + Send + Sync>> {
+        // replayed fixtures don't change between reads
+        let burst = match (config.burst.get(&device_info_response.serial), &self.replay) {
+            (Some(burst), None) if burst.reads > 1 => burst,
+            _ => return self.read_samples(config, device, device_info_response),
+        };
+
+        let start = Instant::now();
+        let spacing = Duration::from_millis(burst.spacing_milliseconds);
+        let mut read = self.read_samples(config, device, device_info_response)?;
+        let mut reads = vec![mem::take(&mut read.samples)];
+        while reads.len() < burst.reads as usize {
+            let past_deadline = self
+                .time_left()
+                .map_or(false, |time_left| spacing >= time_left);
+            if start.elapsed() + spacing >= self.config.timeout() || past_deadline {
+                warn!(
+                    "Stopping burst of device {} after {} of {} reads to stay within the timeout and cycle deadline",
+                    device.fullname,
+                    reads.len(),
+                    burst.reads
+                );
+                break;
+            }
+            thread::sleep(spacing);
+
+            match self.read_samples(config, device, device_info_response) {
+                Ok(mut next) => {
+                    reads.push(mem::take(&mut next.samples));
+                    read = next;
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed burst read {} of device {}, using the {} reads so far: {}",
+                        reads.len() + 1,
+                        device.fullname,
+                        reads.len(),
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+
+        read.samples = burst::aggregate(
+            &reads,
+            &friendly_name(config, device_info_response),
+            burst.min_max,
+        );
+
+        Ok(read)
+    }
+
+    fn read_samples(
+        &self,
+        config: &Config,
+        device: &HomewizardDevice,
+        device_info_response: &DeviceInfoResponse,
+    ) -> Result<DeviceRead, Box<dyn Error + Send + Sync>> {
+        let _span = info_span!("fetch_device_data").entered();
+
+        let friendly_name = friendly_name(config, device_info_response);
+        let token = config.device_token(&device_info_response.serial);
+
+        info!(
+            "Fetching data for device {} with friendly name {} ({:?})...",
+            device.fullname, friendly_name, device.ip_addresses
+        );
+
+        let device_type = HomewizardDeviceType::from_str(&device_info_response.product_type)
+            .map_err(|_| {
+                DeviceError::new(
+                    format!(
+                        "Device {} is a {} on api {}, a product type that isn't supported",
+                        device.fullname,
+                        device_info_response.product_type,
+                        device_info_response.api_version
+                    )
+                    .into(),
+                )
+            })?;
+        match device_type {
+            HomewizardDeviceType::EnergySocket => {
+                // get measurement data
+                let data_response =
+                    self.get_data::<EnergySocketDataResponse>(device, device_info_response, token)?;
+
+                log_data_response(
+                    device,
+                    &friendly_name,
+                    &data_response,
+                    format!(
+                        "active power {} W, import t1 {} kWh, export t1 {} kWh",
+                        data_response.active_power_w,
+                        data_response.total_power_import_t1_kwh,
+                        data_response.total_power_export_t1_kwh
+                    ),
+                );
+
+                Ok(DeviceRead::new(vec![
+                    Sample {
+                        entity_type: EntityType::Device,
                         entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::WaterConsumption,
+                        sample_type: SampleType::ElectricityConsumption,
                         sample_name: friendly_name.clone(),
                         metric_type: MetricType::Counter,
-                        value: data_response.total_liter_m3,
+                        value: kwh_to_joules(data_response.total_power_import_t1_kwh),
                     },
                     Sample {
                         entity_type: EntityType::Device,
                         entity_name: device_info_response.product_type.clone(),
-                        sample_type: SampleType::WaterConsumption,
+                        sample_type: SampleType::ElectricityProduction,
+                        sample_name: friendly_name.clone(),
+                        metric_type: MetricType::Counter,
+                        value: kwh_to_joules(data_response.total_power_export_t1_kwh),
+                    },
+                    Sample {
+                        entity_type: EntityType::Device,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::ElectricityConsumption,
                         sample_name: friendly_name,
                         metric_type: MetricType::Gauge,
-                        value: data_response.active_liter_lpm * 60.0 / 1000.0, // m3/s
+                        value: data_response.active_power_w,
                     },
-                ])
+                ]))
             }
-            HomewizardDeviceType::P1Meter => {
+            HomewizardDeviceType::SinglePhaseKwhMeter => {
                 // get measurement data
-                let data_response = reqwest::blocking::get(format!(
-                    "http://{}/api/{}/data",
-                    device.ip_addresses.iter().next().unwrap(),
-                    device_info_response.api_version
-                ))?
-                .json::<P1MeterDataResponse>()?;
+                let data_response = self.get_data::<SinglePhaseKwhMeterDataResponse>(
+                    device,
+                    device_info_response,
+                    token,
+                )?;
 
-                info!(
-                    "Received data from device {} with friendly name {} ({:?}):\n{:#?}",
-                    device.fullname, friendly_name, device.ip_addresses, data_response
+                log_data_response(
+                    device,
+                    &friendly_name,
+                    &data_response,
+                    format!(
+                        "active power {} W, import t1 {} kWh, export t1 {} kWh",
+                        data_response.active_power_w,
+                        data_response.total_power_import_t1_kwh,
+                        data_response.total_power_export_t1_kwh
+                    ),
                 );
 
-                Ok(vec![
+                Ok(DeviceRead::new(vec![
                     Sample {
-                        entity_type: EntityType::Tariff,
+                        entity_type: EntityType::Device,
                         entity_name: device_info_response.product_type.clone(),
                         sample_type: SampleType::ElectricityConsumption,
-                        sample_name: "t1 import".into(),
+                        sample_name: friendly_name.clone(),
                         metric_type: MetricType::Counter,
-                        value: data_response.total_power_import_t1_kwh * 1000.0 * 3600.0,
+                        value: kwh_to_joules(data_response.total_power_import_t1_kwh),
                     },
                     Sample {
-                        entity_type: EntityType::Tariff,
+                        entity_type: EntityType::Device,
                         entity_name: device_info_response.product_type.clone(),
                         sample_type: SampleType::ElectricityProduction,
-                        sample_name: "t1 export".into(),
+                        sample_name: friendly_name.clone(),
                         metric_type: MetricType::Counter,
-                        value: data_response.total_power_export_t1_kwh * 1000.0 * 3600.0,
+                        value: kwh_to_joules(data_response.total_power_export_t1_kwh),
                     },
                     Sample {
-                        entity_type: EntityType::Tariff,
+                        entity_type: EntityType::Device,
                         entity_name: device_info_response.product_type.clone(),
                         sample_type: SampleType::ElectricityConsumption,
-                        sample_name: "t2 import".into(),
+                        sample_name: friendly_name,
+                        metric_type: MetricType::Gauge,
+                        value: data_response.active_power_w,
+                    },
+                ]))
+            }
+            HomewizardDeviceType::TriplePhaseKwhMeter => {
+                // get measurement data
+                let data_response = self.get_data::<TriplePhaseKwhMeterDataResponse>(
+                    device,
+                    device_info_response,
+                    token,
+                )?;
+
+                log_data_response(
+                    device,
+                    &friendly_name,
+                    &data_response,
+                    format!(
+                        "active power {} W, import t1 {} kWh, export t1 {} kWh",
+                        data_response.active_power_w,
+                        data_response.total_power_import_t1_kwh,
+                        data_response.total_power_export_t1_kwh
+                    ),
+                );
+
+                let mut samples = vec![
+                    Sample {
+                        entity_type: EntityType::Device,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::ElectricityConsumption,
+                        sample_name: friendly_name.clone(),
                         metric_type: MetricType::Counter,
-                        value: data_response.total_power_import_t2_kwh * 1000.0 * 3600.0,
+                        value: kwh_to_joules(data_response.total_power_import_t1_kwh),
                     },
                     Sample {
-                        entity_type: EntityType::Tariff,
+                        entity_type: EntityType::Device,
                         entity_name: device_info_response.product_type.clone(),
                         sample_type: SampleType::ElectricityProduction,
-                        sample_name: "t2 export".into(),
+                        sample_name: friendly_name.clone(),
                         metric_type: MetricType::Counter,
-                        value: data_response.total_power_export_t2_kwh * 1000.0 * 3600.0,
+                        value: kwh_to_joules(data_response.total_power_export_t1_kwh),
                     },
                     Sample {
                         entity_type: EntityType::Device,
                         entity_name: device_info_response.product_type.clone(),
                         sample_type: SampleType::ElectricityConsumption,
-                        sample_name: friendly_name,
+                        sample_name: friendly_name.clone(),
                         metric_type: MetricType::Gauge,
                         value: data_response.active_power_w,
                     },
-                ])
-            }
-        }
-    }
-
-    fn discover_devices(&self) -> Result<Vec<HomewizardDevice>, Box<dyn Error>> {
-        let mut devices: HashMap<String, HomewizardDevice> = HashMap::new();
-
-        // Create a daemon
-        let mdns = ServiceDaemon::new().expect("Failed to create daemon");
-
-        // Browse for a service type.
-        let service_type = "_hwenergy._tcp.local.";
-        let receiver = mdns.browse(service_type).expect("Failed to browse");
-
-        // while let Ok(event) = receiver.recv() {
-        let start = std::time::Instant::now();
-        let timeout = Duration::new(self.config.timeout_seconds, 0);
-
-        while let Ok(event) = receiver.recv() {
-            match event {
-                ServiceEvent::ServiceResolved(info) => {
-                    info!(
-                        "At {:?}: Resolved a new service: {} IP: {:?}",
-                        start.elapsed(),
-                        info.get_fullname(),
-                        info.get_addresses()
-                    );
-
-                    let fullname = info.get_fullname().to_string();
-                    let ip_addresses = info.get_addresses().clone();
+                ];
+                samples.extend(apparent_power::samples(
+                    &device_info_response.product_type,
+                    &friendly_name,
+                    &data_response,
+                ));
 
-                    devices.insert(
-                        fullname.clone(),
-                        HomewizardDevice {
-                            fullname,
-                            ip_addresses,
-                        },
-                    );
-                }
-                other_event => {
-                    info!(
-                        "At {:?} : Received other event: {:?}",
-                        start.elapsed(),
-                        &other_event
-                    );
+                if let Some(phase_imbalance) = config
+                    .derived
+                    .as_ref()
+                    .and_then(|derived| derived.phase_imbalance.as_ref())
+                {
+                    samples.extend(derived::phase_imbalance(
+                        phase_imbalance,
+                        &device_info_response.product_type,
+                        &friendly_name,
+                        [
+                            Some(data_response.active_power_l1_w),
+                            Some(data_response.active_power_l2_w),
+                            Some(data_response.active_power_l3_w),
+                        ],
+                    ));
                 }
-            }
 
-            if start.elapsed() > timeout {
-                break;
+                Ok(DeviceRead::new(samples))
             }
-        }
+            HomewizardDeviceType::WaterMeter => {
+                // get measurement data
+                let data_response =
+                    self.get_data::<WaterMeterDataResponse>(device, device_info_response, token)?;
 
-        Ok(devices.into_values().collect())
-    }
-}
+                log_data_response(
+                    device,
+                    &friendly_name,
+                    &data_response,
+                    format!(
+                        "active flow {} l/min, total {} m3",
+                        data_response.active_liter_lpm, data_response.total_liter_m3
+                    ),
+                );
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-pub enum HomewizardDeviceType {
-    P1Meter,
+                let mut samples = vec![
+                    Sample {
+                        entity_type: EntityType::Device,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::WaterConsumption,
+                        sample_name: friendly_name.clone(),
+                        metric_type: MetricType::Counter,
+                        value: data_response.total_liter_m3,
+                    },
+                    Sample {
+                        entity_type: EntityType::Device,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::WaterConsumption,
+                        sample_name: friendly_name.clone(),
+                        metric_type: MetricType::Gauge,
+                        value: liters_per_minute_to_cubic_meters_per_hour(
+                            data_response.active_liter_lpm,
+                        ),
+                    },
+                ];
+
+                if config.derived.as_ref().map_or(false, |d| d.water_liters) {
+                    samples.push(derived::water_liters(
+                        &device_info_response.product_type,
+                        &friendly_name,
+                        data_response.total_liter_m3,
+                    ));
+                }
+                if let Some(cost) = config
+                    .derived
+                    .as_ref()
+                    .and_then(|derived| derived.cost.as_ref())
+                {
+                    samples.extend(derived::water_cost(
+                        cost,
+                        &device_info_response.product_type,
+                        &friendly_name,
+                        data_response.total_liter_m3,
+                    ));
+                }
+
+                Ok(DeviceRead::new(samples))
+            }
+            HomewizardDeviceType::P1Meter => {
+                // get measurement data
+                let data_response =
+                    self.get_data::<P1MeterDataResponse>(device, device_info_response, token)?;
+
+                log_data_response(
+                    device,
+                    &friendly_name,
+                    &data_response,
+                    format!(
+                        "active power {} W, import t1 {} kWh, import t2 {} kWh, export t1 {} kWh, export t2 {} kWh",
+                        data_response.active_power_w,
+                        data_response.total_power_import_t1_kwh,
+                        data_response.total_power_import_t2_kwh,
+                        data_response.total_power_export_t1_kwh,
+                        data_response.total_power_export_t2_kwh
+                    ),
+                );
+
+                let mut samples = vec![
+                    Sample {
+                        entity_type: EntityType::Tariff,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::ElectricityConsumption,
+                        sample_name: "t1 import".into(),
+                        metric_type: MetricType::Counter,
+                        value: kwh_to_joules(data_response.total_power_import_t1_kwh),
+                    },
+                    Sample {
+                        entity_type: EntityType::Tariff,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::ElectricityProduction,
+                        sample_name: "t1 export".into(),
+                        metric_type: MetricType::Counter,
+                        value: kwh_to_joules(data_response.total_power_export_t1_kwh),
+                    },
+                    Sample {
+                        entity_type: EntityType::Tariff,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::ElectricityConsumption,
+                        sample_name: "t2 import".into(),
+                        metric_type: MetricType::Counter,
+                        value: kwh_to_joules(data_response.total_power_import_t2_kwh),
+                    },
+                    Sample {
+                        entity_type: EntityType::Tariff,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::ElectricityProduction,
+                        sample_name: "t2 export".into(),
+                        metric_type: MetricType::Counter,
+                        value: kwh_to_joules(data_response.total_power_export_t2_kwh),
+                    },
+                    Sample {
+                        entity_type: EntityType::Device,
+                        entity_name: device_info_response.product_type.clone(),
+                        sample_type: SampleType::ElectricityConsumption,
+                        sample_name: friendly_name.clone(),
+                        metric_type: MetricType::Gauge,
+                        value: data_response.active_power_w,
+                    },
+                ];
+
+                if !data_response.external.is_empty() {
+                    samples.extend(external_meters::samples(
+                        &config.names,
+                        &device_info_response.product_type,
+                        &data_response.external,
+                    ));
+                }
+                if let Some(net_power) = config
+                    .derived
+                    .as_ref()
+                    .and_then(|derived| derived.net_power.as_ref())
+                {
+                    samples.push(derived::net_power(
+                        net_power,
+                        &device_info_response.product_type,
+                        &friendly_name,
+                        data_response.active_power_w,
+                    ));
+                }
+                if let Some(phase_imbalance) = config
+                    .derived
+                    .as_ref()
+                    .and_then(|derived| derived.phase_imbalance.as_ref())
+                {
+                    samples.extend(derived::phase_imbalance(
+                        phase_imbalance,
+                        &device_info_response.product_type,
+                        &friendly_name,
+                        [
+                            Some(data_response.active_power_l1_w),
+                            data_response.active_power_l2_w,
+                            data_response.active_power_l3_w,
+                        ],
+                    ));
+                }
+                if let (Some(gas_energy), Some(total_gas_m3)) = (
+                    config
+                        .derived
+                        .as_ref()
+                        .and_then(|derived| derived.gas_energy.as_ref()),
+                    data_response.total_gas_m3,
+                ) {
+                    samples.push(derived::gas_energy(
+                        gas_energy,
+                        &device_info_response.product_type,
+                        total_gas_m3,
+                    ));
+                }
+                if let Some(cost) = config
+                    .derived
+                    .as_ref()
+                    .and_then(|derived| derived.cost.as_ref())
+                {
+                    samples.extend(derived::p1_costs(
+                        cost,
+                        &device_info_response.product_type,
+                        &data_response,
+                    ));
+                }
+
+                Ok(DeviceRead {
+                    samples,
+                    external_unique_ids: data_response
+                        .external
+                        .iter()
+                        .map(|external| external.unique_id.clone())
+                        .collect(),
+                    total_gas_m3: data_response.total_gas_m3,
+                    gas_timestamp: data_response.gas_timestamp,
+                })
+            }
+        }
+    }
+
+    /// Applies what the read of the device leaves behind besides its samples: warning about the
+    /// clock of a P1 meter's gas meter, noting the unique ids of its external meters and deriving
+    /// its gas flow from the gas reading, which gets remembered. Returns the samples of the read
+    /// with the gas flow, with the entity name `config` selects.
+    fn apply_read(
+        &self,
+        config: &Config,
+        device_info_response: &DeviceInfoResponse,
+        read: DeviceRead,
+    ) -> Vec<Sample> {
+        let mut samples = read.samples;
+        let friendly_name = friendly_name(config, device_info_response);
+
+        if let (Some(gas_clock_skew), Some(gas_timestamp)) =
+            (&config.gas_clock_skew, read.gas_timestamp)
+        {
+            self.check_gas_clock_skew(
+                gas_clock_skew,
+                device_info_response,
+                &friendly_name,
+                gas_timestamp,
+            );
+        }
+        if !read.external_unique_ids.is_empty() {
+            // external meters are named by unique id rather than serial
+            self.state
+                .lock()
+                .unwrap()
+                .unmatched_serials
+                .see(read.external_unique_ids.iter().map(String::as_str));
+        }
+        if let (Some(gas_flow), Some(total_gas_m3), Some(gas_timestamp)) = (
+            config
+                .derived
+                .as_ref()
+                .and_then(|derived| derived.gas_flow.as_ref()),
+            read.total_gas_m3,
+            read.gas_timestamp,
+        ) {
+            let utc_offset_minutes = config
+                .gas_clock_skew
+                .as_ref()
+                .and_then(|gas_clock_skew| gas_clock_skew.utc_offset_minutes);
+            if let Some(flow) =
+                gas_clock::parse(gas_timestamp, utc_offset_minutes).and_then(|gas_time| {
+                    self.observe_gas_reading(&device_info_response.serial, total_gas_m3, gas_time)
+                })
+            {
+                samples.push(derived::gas_flow(
+                    gas_flow,
+                    &device_info_response.product_type,
+                    &friendly_name,
+                    flow,
+                ));
+            }
+        }
+
+        let entity_name = match config.entity_name_source(&device_info_response.serial) {
+            EntityNameSource::ProductType => return samples,
+            EntityNameSource::Serial => device_info_response.serial.clone(),
+            EntityNameSource::FriendlyName => friendly_name,
+        };
+        for sample in samples.iter_mut() {
+            sample.entity_name = entity_name.clone();
+        }
+
+        samples
+    }
+
+    /// The consumption so far today of the counters of the measurement, with the snapshots of
+    /// the counters at the start of the day kept in the exporter state.
+    fn daily_consumption(
+        &self,
+        config: &DailyConsumptionConfig,
+        measurement: &Measurement,
+    ) -> Vec<Sample> {
+        // validated along with the config
+        let timezone = match config.timezone() {
+            Ok(timezone) => timezone,
+            Err(e) => {
+                warn!("Skipping daily consumption: {}", e);
+                return vec![];
+            }
+        };
+        let date = measurement
+            .measured_at_time
+            .with_timezone(&timezone)
+            .date_naive();
+
+        let mut state = self.state.lock().unwrap();
+        let samples =
+            daily_consumption::samples(&mut state.daily_consumption, &measurement.samples, date);
+        if let Err(e) = self.state_store.save(&state) {
+            warn!("Failed saving exporter state: {}", e);
+        }
+
+        samples
+    }
+
+    /// Counters of the consumption of the energy sockets and kWh meters of the measurement by
+    /// time of use window, attributing the consumption since the previous cycle to the window
+    /// the time of measuring falls into, with the counters kept in the exporter state.
+    fn time_of_use(
+        &self,
+        config: &TimeOfUseConfig,
+        measurement: &Measurement,
+        devices: &[MeasuredDevice],
+    ) -> Vec<Sample> {
+        // validated along with the config
+        let window = config.timezone().and_then(|timezone| {
+            let at = measurement.measured_at_time.with_timezone(&timezone);
+            time_of_use::window_at(config, at.naive_local())
+        });
+        let window = match window {
+            Ok(window) => window,
+            Err(e) => {
+                warn!("Skipping time of use counters: {}", e);
+                return vec![];
+            }
+        };
+
+        let mut state = self.state.lock().unwrap();
+        let samples = time_of_use::samples(
+            &mut state.time_of_use,
+            config,
+            window,
+            &measurement.samples,
+            devices,
+        );
+        if let Err(e) = self.state_store.save(&state) {
+            warn!("Failed saving exporter state: {}", e);
+        }
+
+        samples
+    }
+
+    /// Remembers the gas reading of a P1 meter, returning its gas flow since the previous one.
+    fn observe_gas_reading(
+        &self,
+        serial: &str,
+        total_gas_m3: f64,
+        gas_time: DateTime<Utc>,
+    ) -> Option<f64> {
+        let mut state = self.state.lock().unwrap();
+        let flow = state.gas_flow.observe(serial, total_gas_m3, gas_time);
+        if let Err(e) = self.state_store.save(&state) {
+            warn!("Failed saving exporter state: {}", e);
+        }
+
+        flow
+    }
+
+    /// Warns when the gas reading of a P1 meter is dated too far from now, telling a stale
+    /// reading from a wrong meter clock where the direction of the skew allows.
+    fn check_gas_clock_skew(
+        &self,
+        config: &GasClockSkewConfig,
+        device_info: &DeviceInfoResponse,
+        friendly_name: &str,
+        gas_timestamp: u64,
+    ) {
+        let gas_time = match gas_clock::parse(gas_timestamp, config.utc_offset_minutes) {
+            Some(gas_time) => gas_time,
+            None => {
+                warn!(
+                    serial = %device_info.serial,
+                    friendly_name = %friendly_name,
+                    gas_timestamp,
+                    "Gas timestamp isn't a valid time"
+                );
+                return;
+            }
+        };
+
+        let threshold = chrono::Duration::minutes(config.threshold_minutes.into());
+        match gas_clock::skew(gas_time, (self.config.clock)(), threshold) {
+            GasClockSkew::InRange => {}
+            GasClockSkew::Stale(age) => warn!(
+                serial = %device_info.serial,
+                friendly_name = %friendly_name,
+                gas_time = %gas_time,
+                skew_minutes = age.num_minutes(),
+                "Gas reading is stale; the gas meter stopped reporting or its clock lags behind"
+            ),
+            GasClockSkew::FutureDated(ahead) => warn!(
+                serial = %device_info.serial,
+                friendly_name = %friendly_name,
+                gas_time = %gas_time,
+                skew_minutes = ahead.num_minutes(),
+                "Gas reading is dated in the future; the meter clock is wrong"
+            ),
+        }
+    }
+
+    /// Warns when the own clock of a v2 device is further off than the gas clock skew threshold,
+    /// reporting whether its cloud connection is enabled along the way. Failing to read it only
+    /// gets logged, the samples of the device don't depend on it.
+    fn check_device_clock(
+        &self,
+        config: &Config,
+        skew_config: &GasClockSkewConfig,
+        device: &HomewizardDevice,
+        device_info: &DeviceInfoResponse,
+    ) {
+        let system = match self.get_system(config, device, device_info) {
+            Ok(Some(system)) => system,
+            Ok(None) => return,
+            Err(e) => {
+                warn!(
+                    serial = %device_info.serial,
+                    "Failed reading the system of device {}: {}", device.fullname, e
+                );
+                return;
+            }
+        };
+        info!(
+            serial = %device_info.serial,
+            cloud_enabled = system.cloud_enabled,
+            "Device {} has its cloud connection {}",
+            device.fullname,
+            if system.cloud_enabled {
+                "enabled"
+            } else {
+                "disabled"
+            }
+        );
+
+        let device_time = match system.time {
+            Some(device_time) => device_time,
+            None => return,
+        };
+        let threshold = chrono::Duration::minutes(skew_config.threshold_minutes.into());
+        match gas_clock::skew(device_time, (self.config.clock)(), threshold) {
+            GasClockSkew::InRange => {}
+            GasClockSkew::Stale(behind) => warn!(
+                serial = %device_info.serial,
+                device_time = %device_time,
+                skew_minutes = behind.num_minutes(),
+                "Device clock lags behind"
+            ),
+            GasClockSkew::FutureDated(ahead) => warn!(
+                serial = %device_info.serial,
+                device_time = %device_time,
+                skew_minutes = ahead.num_minutes(),
+                "Device clock runs ahead"
+            ),
+        }
+    }
+
+    /// Builds the http client on first use, from the blocking context requests are made in.
+    fn http_client(&self) -> Result<&reqwest::blocking::Client, Box<dyn Error + Send + Sync>> {
+        if let Some(http_client) = self.http_client.get() {
+            return Ok(http_client);
+        }
+
+        // a concurrent request may have won the race, either client does
+        let _ = self.http_client.set(self.config.http_client()?);
+        Ok(self.http_client.get().expect("http client was just set"))
+    }
+
+    /// The client to request `url` with: for https straight to a device the one verifying its
+    /// HomeWizard certificate, pinned to `serial` when known, or else the shared client.
+    fn http_client_for(
+        &self,
+        device: &HomewizardDevice,
+        url: &str,
+        serial: Option<&str>,
+    ) -> Result<reqwest::blocking::Client, Box<dyn Error + Send + Sync>> {
+        let url = reqwest::Url::parse(url)?;
+        let to_device = device
+            .ip_addresses
+            .iter()
+            .any(|ip_address| url.host_str() == Some(ip_address.to_string().as_str()));
+        if url.scheme() != "https" || !to_device {
+            return Ok(self.http_client()?.clone());
+        }
+
+        let serial = serial.map(str::to_lowercase);
+        let mut device_tls_clients = self.device_tls_clients.lock().unwrap();
+        if let Some(client) = device_tls_clients.get(&serial) {
+            return Ok(client.clone());
+        }
+        let client = self.config.device_tls_client(serial.as_deref())?;
+        device_tls_clients.insert(serial, client.clone());
+        Ok(client)
+    }
+
+    /// Base url of the device's endpoints, as resolved by the configured endpoint resolver.
+    pub fn base_url(
+        &self,
+        device: &HomewizardDevice,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        (self.config.endpoint_resolver)(device)
+    }
+
+    /// Requests the data endpoint of the device with `token`, unless its batch response brought a
+    /// complete measurement along.
+    fn get_data<T: DeserializeOwned>(
+        &self,
+        device: &HomewizardDevice,
+        device_info_response: &DeviceInfoResponse,
+        token: Option<&str>,
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        let batch_data = self.batch_data.lock().unwrap().remove(&device.fullname);
+        if let Some(batch_data) = batch_data {
+            match serde_json::from_value(batch_data) {
+                Ok(data_response) => return Ok(data_response),
+                Err(e) => debug!(
+                    "Measurement in the batch response of device {} is incomplete, requesting its data: {}",
+                    device.fullname, e
+                ),
+            }
+        }
+
+        self.get_json_with_token(
+            device,
+            &device.data_path(&device_info_response.api_version),
+            Some(&device_info_response.serial),
+            token,
+        )
+    }
+
+    /// Requests `path` from the device, or reads its fixture when replaying.
+    fn get_json<T: DeserializeOwned>(
+        &self,
+        device: &HomewizardDevice,
+        path: &str,
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        self.get_json_with_token(device, path, None, None)
+    }
+
+    /// Requests `path` from the device with `token`, over https only accepting the certificate
+    /// of the device with `serial` when known.
+    fn get_json_with_token<T: DeserializeOwned>(
+        &self,
+        device: &HomewizardDevice,
+        path: &str,
+        serial: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<T, Box<dyn Error + Send + Sync>> {
+        match &self.replay {
+            Some(replay) => Ok(serde_json::from_str(&replay.response(device, path)?)?),
+            None => Ok(self
+                .send_with_token(Method::GET, device, path, serial, token)?
+                .json::<T>()?),
+        }
+    }
+
+    /// Sends a `method` request for `path` to the device with `token`, failing with a device
+    /// error when the device refuses the token and on any other status than 2xx.
+    fn send_with_token(
+        &self,
+        method: Method,
+        device: &HomewizardDevice,
+        path: &str,
+        serial: Option<&str>,
+        token: Option<&str>,
+    ) -> Result<reqwest::blocking::Response, Box<dyn Error + Send + Sync>> {
+        let url = format!("{}{}", self.base_url(device)?, path);
+        let mut request = self
+            .http_client_for(device, &url, serial)?
+            .request(method, url);
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        if let Some(time_left) = self.time_left() {
+            if time_left.is_zero() {
+                return Err("Cycle deadline passed".into());
+            }
+            request = request.timeout(time_left);
+        }
+        let response = request.send()?;
+        let status = response.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            let reason = match token {
+                Some(_) => "rejected the token",
+                None => "requires a token, configure one on its entry in devices",
+            };
+            return Err(DeviceError::new(
+                format!("Device {} {} ({})", device.fullname, reason, status).into(),
+            )
+            .into());
+        }
+
+        Ok(response.error_for_status()?)
+    }
+
+    /// Time left until the deadline of the running cycle, zero once it passed; nothing without
+    /// a cycle deadline.
+    fn time_left(&self) -> Option<Duration> {
+        self.deadline
+            .lock()
+            .unwrap()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Finds the devices to measure with the configured discoverer.
+    pub fn discover_devices(&self) -> Result<Vec<HomewizardDevice>, Box<dyn Error + Send + Sync>> {
+        let _span = info_span!("discover_devices").entered();
+
+        let timeout = match self.time_left() {
+            Some(time_left) => self.config.timeout().min(time_left),
+            None => self.config.timeout(),
+        };
+
+        self.discoverer.discover(timeout)
+    }
+}
+
+/// Drops the samples over `maxSamplesPerMeasurement` from the measurement, warning which ones,
+/// and moves the sample ranges of the measured devices along; returns how many.
+fn cap_samples(
+    config: &Config,
+    measurement: &mut Measurement,
+    measured_devices: &mut [MeasuredDevice],
+) -> usize {
+    let max_samples = config.max_samples_per_measurement();
+    let dropped = sample_cap::truncate(measurement, max_samples);
+    if !dropped.is_empty() {
+        let shift = |index: usize| {
+            index
+                - dropped
+                    .iter()
+                    .filter(|(dropped, _)| *dropped < index)
+                    .count()
+        };
+        for device in measured_devices.iter_mut() {
+            device.samples = shift(device.samples.start)..shift(device.samples.end);
+        }
+
+        let dropped_samples: Vec<String> = dropped
+            .iter()
+            .map(|(_, sample)| {
+                format!(
+                    "{}/{}",
+                    sample.entity_name,
+                    expected_samples::sample_key(sample)
+                )
+            })
+            .collect();
+        warn!(
+            location = %measurement.location,
+            dropped_samples = ?dropped_samples,
+            "Measurement exceeds maxSamplesPerMeasurement of {}, dropped {} samples",
+            max_samples,
+            dropped.len()
+        );
+    }
+
+    dropped.len()
+}
+
+/// The configured name of the device - sanitized when the config got read - or else its
+/// sanitized product name, normalized when configured.
+fn friendly_name(config: &Config, device_info: &DeviceInfoResponse) -> String {
+    let name = match config.names.get(&device_info.serial) {
+        Some(name) => name.clone(),
+        None => name_sanitization::sanitize(&device_info.product_name, config.name_max_length()),
+    };
+
+    match &config.name_normalization {
+        Some(name_normalization) => name_normalization::normalize(name_normalization, &name),
+        None => name,
+    }
+}
+
+/// Warns about devices at a location that got the same friendly name, since their samples end
+/// up in the same series; normalizing names can make distinct names collide.
+fn warn_name_collisions(location: &str, devices: &[MeasuredDevice]) {
+    let mut serials_by_name: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for device in devices {
+        serials_by_name
+            .entry(device.friendly_name.as_str())
+            .or_default()
+            .push(device.info.serial.as_str());
+    }
+
+    for (friendly_name, serials) in serials_by_name {
+        if serials.len() > 1 {
+            warn!(
+                location = %location,
+                friendly_name = %friendly_name,
+                serials = ?serials,
+                "Devices {} share the friendly name {}, their samples end up in the same series",
+                serials.join(", "),
+                friendly_name
+            );
+        }
+    }
+}
+
+/// Adds the devices configured by address for any of the locations, in place of a discovered
+/// device at the same address.
+fn with_location_devices(
+    config: &Config,
+    mut devices: Vec<HomewizardDevice>,
+) -> Vec<HomewizardDevice> {
+    for address in config.static_devices().map(StaticDevice::address) {
+        devices.retain(|device| {
+            device.port != address.port() || !device.ip_addresses.contains(address.ip())
+        });
+        devices.push(HomewizardDevice {
+            fullname: address.to_string(),
+            ip_addresses: vec![*address.ip()].into_iter().collect(),
+            port: address.port(),
+            hostname: None,
+            api_path: None,
+        });
+    }
+
+    devices
+}
+
+/// Warns when a device produced fewer samples than expected, such as after a firmware update
+/// dropped a field of its data response.
+fn check_expected_samples(
+    expected: &ExpectedSamples,
+    device_info: &DeviceInfoResponse,
+    friendly_name: &str,
+    samples: &[Sample],
+) {
+    if let Some(shortfall) = expected_samples::shortfall(expected, samples) {
+        warn!(
+            serial = %device_info.serial,
+            friendly_name = %friendly_name,
+            firmware_version = %device_info.firmware_version,
+            expected_samples = shortfall.expected,
+            actual_samples = shortfall.actual,
+            missing_samples = ?shortfall.missing,
+            "Device produced fewer samples than expected"
+        );
+    }
+}
+
+fn log_device_info(device: &HomewizardDevice, device_info: &DeviceInfoResponse) {
+    info!(
+        "Received info from device {}: {} {} with serial {}, firmware {} and api {}",
+        device.fullname,
+        device_info.product_type,
+        device_info.product_name,
+        device_info.serial,
+        device_info.firmware_version,
+        device_info.api_version
+    );
+    debug!(
+        "Received info from device {} ({:?}):\n{:#?}",
+        device.fullname, device.ip_addresses, device_info
+    );
+}
+
+fn log_data_response<T: fmt::Debug>(
+    device: &HomewizardDevice,
+    friendly_name: &str,
+    data_response: &T,
+    key_values: String,
+) {
+    info!(
+        "Received data from device {} with friendly name {}: {}",
+        device.fullname, friendly_name, key_values
+    );
+    debug!(
+        "Received data from device {} with friendly name {} ({:?}):\n{:#?}",
+        device.fullname, friendly_name, device.ip_addresses, data_response
+    );
+}
+
+/// The kinds of HomeWizard devices, parsed from the `product_type` of their `/api` response.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub enum HomewizardDeviceType {
+    P1Meter,
     SinglePhaseKwhMeter,
     TriplePhaseKwhMeter,
     EnergySocket,
@@ -408,87 +2481,359 @@ impl FromStr for HomewizardDeviceType {
     }
 }
 
+/// Port of a device that doesn't advertise one.
+pub const DEFAULT_PORT: u16 = 80;
+/// Port v2 devices serve their api on, over https with a certificate of the HomeWizard CA.
+pub const V2_PORT: u16 = 443;
+
+/// A device found by discovery, named after its mdns service.
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct HomewizardDevice {
     pub fullname: String,
     pub ip_addresses: HashSet<Ipv4Addr>,
+    /// Port the device advertises, [`DEFAULT_PORT`] when it advertises none.
+    pub port: u16,
+    /// Host name the device advertises, like `energysocket-3c39e7.local.`; none for a device
+    /// that isn't discovered.
+    pub hostname: Option<String>,
+    /// Base path of the api, from the `path` TXT record the device advertises.
+    pub api_path: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
-struct DeviceInfoResponse {
-    product_type: String,
-    product_name: String,
-    serial: String,
-    firmware_version: String,
-    api_version: String,
-}
+impl HomewizardDevice {
+    /// Any of the device's ip addresses.
+    pub fn ip_address(&self) -> Result<Ipv4Addr, Box<dyn Error + Send + Sync>> {
+        self.ip_addresses
+            .iter()
+            .next()
+            .copied()
+            .ok_or_else(|| format!("Device {} has no ip address", self.fullname).into())
+    }
 
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
-struct P1MeterDataResponse {
-    pub smr_version: usize,
-    pub meter_model: String,
-    pub wifi_ssid: String,
-    pub wifi_strength: usize,
-    pub total_power_import_t1_kwh: f64,
-    pub total_power_export_t1_kwh: f64,
-    pub total_power_import_t2_kwh: f64,
-    pub total_power_export_t2_kwh: f64,
-    pub active_power_w: f64,
-    pub active_power_l1_w: f64,
-    pub active_power_l2_w: f64,
-    pub active_power_l3_w: f64,
-    pub total_gas_m3: f64,
-    pub gas_timestamp: u64,
+    /// Path of the data endpoint, below the advertised api path or else below the api version
+    /// the `/api` endpoint reports.
+    pub fn data_path(&self, api_version: &str) -> String {
+        match &self.api_path {
+            Some(api_path) => format!("{}/data", api_path),
+            None => format!("/api/{}/data", api_version),
+        }
+    }
+
+    /// The device's own base url, used unless an endpoint resolver is configured; https for a
+    /// v2 device on [`V2_PORT`].
+    pub fn base_url(&self) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let scheme = if self.port == V2_PORT {
+            "https"
+        } else {
+            "http"
+        };
+        Ok(format!("{}://{}:{}", scheme, self.ip_address()?, self.port))
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
-struct EnergySocketDataResponse {
-    pub wifi_ssid: String,
-    pub wifi_strength: usize,
-    pub total_power_import_t1_kwh: f64,
-    pub total_power_export_t1_kwh: f64,
-    pub active_power_w: f64,
-    pub active_power_l1_w: f64,
+/// Outcome of a single measurement cycle, logged as one structured line when the cycle is done.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CycleSummary {
+    pub devices_discovered: usize,
+    pub succeeded_devices: Vec<SucceededDevice>,
+    pub failed_devices: Vec<String>,
+    /// Error of every failed device, by serial or mdns name.
+    pub failure_errors: BTreeMap<String, String>,
+    /// The devices behind the samples of the measurement, for outputs that group samples by
+    /// device.
+    pub measured_devices: Vec<MeasuredDevice>,
+    /// Failed devices missing from the socket total, by serial or mdns name.
+    pub excluded_from_socket_total: Vec<String>,
+    /// Devices by mdns name that weren't requested because the cycle deadline passed.
+    pub cut_off_devices: Vec<String>,
+    pub samples_emitted: usize,
+    /// Samples dropped for exceeding `maxSamplesPerMeasurement`.
+    pub samples_dropped: usize,
+    /// Samples left out for staying within the deadband.
+    pub samples_unchanged: usize,
+    pub duration: Duration,
+    pub published: bool,
+    /// What the cycle does when it yields no samples.
+    pub on_empty: OnEmpty,
+    /// Time since the last measurement that got published, as of the end of the cycle.
+    pub last_success_age: Option<Duration>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
-struct SinglePhaseKwhMeterDataResponse {
-    pub wifi_ssid: String,
-    pub wifi_strength: usize,
-    pub total_power_import_t1_kwh: f64,
-    pub total_power_export_t1_kwh: f64,
-    pub active_power_w: f64,
-    pub active_power_l1_w: f64,
+/// A device read successfully during a cycle, with the range of the measurement's samples it
+/// produced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeasuredDevice {
+    /// Id of the measurement holding the samples.
+    pub measurement_id: String,
+    pub info: DeviceInfoResponse,
+    pub friendly_name: String,
+    pub samples: Range<usize>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
-struct TriplePhaseKwhMeterDataResponse {
-    pub wifi_ssid: String,
-    pub wifi_strength: usize,
-    pub total_power_import_t1_kwh: f64,
-    pub total_power_export_t1_kwh: f64,
+impl CycleSummary {
+    pub(crate) fn new(devices_discovered: usize) -> Self {
+        Self {
+            devices_discovered,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn record_success(&mut self, serial: &str, friendly_name: &str) {
+        self.succeeded_devices.push(SucceededDevice {
+            serial: serial.to_string(),
+            friendly_name: friendly_name.to_string(),
+        });
+    }
+
+    /// Records a failed device by its serial, or by its mdns name if the serial isn't known yet.
+    pub(crate) fn record_failure(&mut self, serial_or_name: &str, error: &str) {
+        self.failed_devices.push(serial_or_name.to_string());
+        self.failure_errors
+            .insert(serial_or_name.to_string(), error.to_string());
+    }
+
+    /// Adds the devices of a location measured during the cycle.
+    pub(crate) fn absorb(&mut self, location_summary: CycleSummary) {
+        self.succeeded_devices
+            .extend(location_summary.succeeded_devices);
+        self.failed_devices.extend(location_summary.failed_devices);
+        self.failure_errors.extend(location_summary.failure_errors);
+        self.measured_devices
+            .extend(location_summary.measured_devices);
+        self.excluded_from_socket_total
+            .extend(location_summary.excluded_from_socket_total);
+        self.cut_off_devices
+            .extend(location_summary.cut_off_devices);
+        self.samples_unchanged += location_summary.samples_unchanged;
+    }
+
+    pub(crate) fn finish(&mut self, measurements: &[Measurement], duration: Duration) {
+        self.samples_emitted = measurements.iter().map(|m| m.samples.len()).sum();
+        self.duration = duration;
+    }
+
+    /// The devices behind the samples of the measurement with `measurement_id`.
+    pub fn measured_devices_of(&self, measurement_id: &str) -> Vec<MeasuredDevice> {
+        self.measured_devices
+            .iter()
+            .filter(|device| device.measurement_id == measurement_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Logs the summary as one structured line.
+    pub fn log(&self) {
+        info!(
+            devices_discovered = self.devices_discovered,
+            devices_succeeded = self.succeeded_devices.len(),
+            devices_failed = self.failed_devices.len(),
+            failed_devices = ?self.failed_devices,
+            excluded_from_socket_total = ?self.excluded_from_socket_total,
+            cut_off_devices = ?self.cut_off_devices,
+            samples_emitted = self.samples_emitted,
+            samples_dropped = self.samples_dropped,
+            samples_unchanged = self.samples_unchanged,
+            cycle_duration_ms = self.duration.as_millis() as u64,
+            published = self.published,
+            on_empty = ?self.on_empty,
+            last_success_age_s = self.last_success_age.map(|age| age.as_secs()),
+            "Finished measurement cycle"
+        );
+    }
+}
+
+/// Response of the `/api` endpoint every device type serves.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfoResponse {
+    pub product_type: String,
+    pub product_name: String,
+    pub serial: String,
+    pub firmware_version: String,
+    pub api_version: String,
+}
+
+impl DeviceInfoResponse {
+    /// Whether the device serves the v2 api, reported as `v2` or a version like `2.0.0`.
+    pub fn is_v2(&self) -> bool {
+        self.api_version == "v2" || self.api_version.starts_with("2.")
+    }
+}
+
+/// The samples of a single read of a device, along with what else the read tells that the
+/// exporter only acts on for the read it ends up using.
+#[derive(Debug, Default)]
+struct DeviceRead {
+    samples: Vec<Sample>,
+    /// Unique ids of the external meters of a P1 meter.
+    external_unique_ids: Vec<String>,
+    total_gas_m3: Option<f64>,
+    gas_timestamp: Option<u64>,
+}
+
+impl DeviceRead {
+    fn new(samples: Vec<Sample>) -> Self {
+        Self {
+            samples,
+            ..Default::default()
+        }
+    }
+}
+
+/// Path of the endpoint of v2 devices answering their info and measurement in one response.
+const BATCH_PATH: &str = "/api/batch";
+
+/// Response of the batch endpoint, with the responses of `/api` and the data endpoint; either
+/// is left out when the device couldn't produce it, and kept as json until it's known to be
+/// complete.
+#[derive(Deserialize, Debug)]
+struct BatchResponse {
+    #[serde(default)]
+    device: Option<serde_json::Value>,
+    #[serde(default)]
+    measurement: Option<serde_json::Value>,
+}
+
+/// Path of the endpoint of v2 devices with their system settings and clock.
+const SYSTEM_PATH: &str = "/api/system";
+
+/// Path of the endpoint of v2 devices blinking their led.
+const IDENTIFY_PATH: &str = "/api/system/identify";
+
+/// Response of the system endpoint of v2 devices.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SystemResponse {
+    pub cloud_enabled: bool,
+    /// The device's own clock, left out by firmware that doesn't report it.
+    #[serde(default)]
+    pub time: Option<DateTime<Utc>>,
+}
+
+/// Whether a request failed on a 404 of the device.
+fn is_not_found(error: &(dyn Error + 'static)) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        == Some(StatusCode::NOT_FOUND)
+}
+
+/// Response of the data endpoint of a P1 meter.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct P1MeterDataResponse {
+    pub smr_version: usize,
+    pub meter_model: String,
+    pub wifi_ssid: String,
+    pub wifi_strength: usize,
+    pub total_power_import_t1_kwh: f64,
+    pub total_power_export_t1_kwh: f64,
+    pub total_power_import_t2_kwh: f64,
+    pub total_power_export_t2_kwh: f64,
+    pub active_power_w: f64,
+    pub active_power_l1_w: f64,
+    /// Absent on a single phase connection.
+    #[serde(default)]
+    pub active_power_l2_w: Option<f64>,
+    #[serde(default)]
+    pub active_power_l3_w: Option<f64>,
+    /// Absent without a gas meter connected to the smart meter.
+    #[serde(default)]
+    pub total_gas_m3: Option<f64>,
+    #[serde(default)]
+    pub gas_timestamp: Option<u64>,
+    /// Meters connected to the smart meter, like gas and water meters; absent on older firmware.
+    #[serde(default)]
+    pub external: Vec<ExternalMeterReading>,
+}
+
+/// Reading of a meter connected to the smart meter a P1 meter reads.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ExternalMeterReading {
+    pub unique_id: String,
+    /// Kind of meter, like `gas_meter` or `water_meter`.
+    #[serde(rename = "type")]
+    pub meter_type: String,
+    /// Time of the reading as `YYMMDDhhmmss`, in the local time of the meter.
+    pub timestamp: u64,
+    pub value: f64,
+    pub unit: String,
+}
+
+/// Response of the data endpoint of a energy socket.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct EnergySocketDataResponse {
+    pub wifi_ssid: String,
+    pub wifi_strength: usize,
+    pub total_power_import_t1_kwh: f64,
+    pub total_power_export_t1_kwh: f64,
+    pub active_power_w: f64,
+    pub active_power_l1_w: f64,
+}
+
+/// Response of the data endpoint of a SDM230 kWh meter.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct SinglePhaseKwhMeterDataResponse {
+    pub wifi_ssid: String,
+    pub wifi_strength: usize,
+    pub total_power_import_t1_kwh: f64,
+    pub total_power_export_t1_kwh: f64,
+    pub active_power_w: f64,
+    pub active_power_l1_w: f64,
+}
+
+/// Response of the data endpoint of a SDM630 kWh meter.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct TriplePhaseKwhMeterDataResponse {
+    pub wifi_ssid: String,
+    pub wifi_strength: usize,
+    pub total_power_import_t1_kwh: f64,
+    pub total_power_export_t1_kwh: f64,
     pub active_power_w: f64,
     pub active_power_l1_w: f64,
     pub active_power_l2_w: f64,
     pub active_power_l3_w: f64,
+    /// Absent on older firmware.
+    #[serde(default)]
+    pub apparent_power_va: Option<f64>,
+    #[serde(default)]
+    pub apparent_power_l1_va: Option<f64>,
+    #[serde(default)]
+    pub apparent_power_l2_va: Option<f64>,
+    #[serde(default)]
+    pub apparent_power_l3_va: Option<f64>,
 }
 
+/// Response of the data endpoint of a water meter.
 #[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
-struct WaterMeterDataResponse {
+pub struct WaterMeterDataResponse {
     pub wifi_ssid: String,
     pub wifi_strength: usize,
-    total_liter_m3: f64,
-    active_liter_lpm: f64,
+    pub total_liter_m3: f64,
+    pub active_liter_lpm: f64,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::discovery::StaticDiscoverer;
+    use crate::model::{
+        BurstConfig, CarryForwardConfig, CostConfig, DailyConsumptionConfig, DeadbandConfig,
+        DeadbandThreshold, DerivedConfig, DeviceReachableConfig, DeviceTokenConfig,
+        FirmwareEventsConfig, GasEnergyConfig, GasFlowConfig, LocationConfig, MissingDevicesConfig,
+        NameNormalizationConfig, NetPowerConfig, PhaseImbalanceConfig, PrecisionConfig,
+        SmoothingConfig, SocketTotalConfig, TimeOfUseConfig, TimeOfUseWindow,
+        UnmatchedSerialsConfig, UntrackedPowerConfig,
+    };
+    use crate::test_support::{
+        assert_golden, capture_logs, record_spans, FakeDeviceServer, FakeResponse,
+    };
+    use std::fs;
+    use std::net::SocketAddr;
 
     #[test]
     #[ignore]
     fn discover_devices() {
         let homewizard_client = HomewizardClient::new(HomewizardClientConfig {
             timeout_seconds: 10,
+            ..Default::default()
         });
 
         // act
@@ -506,23 +2851,27 @@ mod tests {
     #[test]
     #[ignore]
     fn get_samples() {
-        let homewizard_client =
-            HomewizardClient::new(HomewizardClientConfig { timeout_seconds: 5 });
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig {
+            timeout_seconds: 5,
+            ..Default::default()
+        });
         let devices = homewizard_client
             .discover_devices()
             .expect("Failed retrieving devices");
         let mut samples: Vec<Sample> = vec![];
         let config = Config {
             location: "My Home".into(),
-            names: HashMap::new(),
+            ..Default::default()
         };
 
         // act
         for device in devices.iter() {
-            match homewizard_client.get_samples(&config, &device) {
-                Ok(s) => {
-                    samples.append(&mut s.clone());
-                }
+            let device_info = match homewizard_client.get_device_info(&device) {
+                Ok(device_info) => device_info,
+                Err(_) => continue,
+            };
+            match homewizard_client.get_samples(&config, &device, &device_info) {
+                Ok(s) => samples.extend(s),
                 Err(_) => continue,
             }
         }
@@ -541,4 +2890,4402 @@ mod tests {
         assert_eq!(samples[1].metric_type, MetricType::Gauge);
         // assert_eq!(samples[1].value, 0.0);
     }
+
+    #[test]
+    fn cycle_summary_counts_match_measurement() {
+        let sample = Sample {
+            entity_type: EntityType::Device,
+            entity_name: "HWE-SKT".into(),
+            sample_type: SampleType::ElectricityConsumption,
+            sample_name: "Bonenmaler".into(),
+            metric_type: MetricType::Gauge,
+            value: 12.0,
+        };
+        let measurement = Measurement {
+            id: Uuid::new_v4().to_string(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![sample.clone(), sample.clone(), sample],
+            measured_at_time: Utc::now(),
+        };
+        let mut summary = CycleSummary::new(3);
+
+        // act
+        summary.record_success("3c39e72e33ce", "Bonenmaler");
+        summary.record_failure("5c2faf0a8b3e", "Connection refused");
+        summary.record_failure("watermeter-2D7A68._hwenergy._tcp.local.", "Timed out");
+        summary.finish(&[measurement], Duration::from_millis(1500));
+
+        assert_eq!(summary.devices_discovered, 3);
+        assert_eq!(summary.succeeded_devices.len(), 1);
+        assert_eq!(
+            summary.failed_devices,
+            vec![
+                "5c2faf0a8b3e".to_string(),
+                "watermeter-2D7A68._hwenergy._tcp.local.".to_string()
+            ]
+        );
+        assert_eq!(summary.samples_emitted, measurement.samples.len());
+        assert_eq!(summary.duration, Duration::from_millis(1500));
+        assert!(!summary.published);
+    }
+
+    fn water_meter_device() -> HomewizardDevice {
+        HomewizardDevice {
+            fullname: "watermeter-2D7A68._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::new(192, 168, 1, 20)].into_iter().collect(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        }
+    }
+
+    #[test]
+    fn info_logs_do_not_contain_payload_dumps() {
+        let device = water_meter_device();
+        let device_info = DeviceInfoResponse {
+            product_type: "HWE-WTR".into(),
+            product_name: "Watermeter".into(),
+            serial: "3c39e72d7a68".into(),
+            firmware_version: "2.03".into(),
+            api_version: "v1".into(),
+        };
+        let data_response = WaterMeterDataResponse {
+            wifi_ssid: "My Wi-Fi".into(),
+            wifi_strength: 84,
+            total_liter_m3: 123.456,
+            active_liter_lpm: 7.5,
+        };
+
+        // act
+        let logs = capture_logs("info", || {
+            log_device_info(&device, &device_info);
+            log_data_response(
+                &device,
+                "Watermeter",
+                &data_response,
+                "active flow 7.5 l/min, total 123.456 m3".into(),
+            );
+        });
+
+        assert!(logs.contains("serial 3c39e72d7a68"));
+        assert!(logs.contains("active flow 7.5 l/min, total 123.456 m3"));
+        assert!(!logs.contains("DeviceInfoResponse {"));
+        assert!(!logs.contains("WaterMeterDataResponse {"));
+        assert!(!logs.contains("My Wi-Fi"));
+    }
+
+    #[test]
+    fn debug_logs_contain_payload_dumps() {
+        let device = water_meter_device();
+        let device_info = DeviceInfoResponse {
+            product_type: "HWE-WTR".into(),
+            product_name: "Watermeter".into(),
+            serial: "3c39e72d7a68".into(),
+            firmware_version: "2.03".into(),
+            api_version: "v1".into(),
+        };
+
+        // act
+        let logs = capture_logs("debug", || log_device_info(&device, &device_info));
+
+        assert!(logs.contains("DeviceInfoResponse {"));
+    }
+
+    #[test]
+    fn measure_devices_nests_device_spans_under_cycle_span() {
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig {
+            timeout_seconds: 1,
+            ..Default::default()
+        });
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        let devices = vec![HomewizardDevice {
+            fullname: "watermeter-2D7A68._hwenergy._tcp.local.".into(),
+            ip_addresses: HashSet::new(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        }];
+        let mut measurement = Measurement {
+            id: Uuid::new_v4().to_string(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: config.location.clone(),
+            samples: Vec::new(),
+            measured_at_time: Utc::now(),
+        };
+        let mut summary = CycleSummary::new(devices.len());
+
+        // act
+        let spans = record_spans(|| {
+            let _cycle_span = info_span!("measurement_cycle").entered();
+            homewizard_client.measure_devices(
+                &config,
+                &devices,
+                None,
+                &mut measurement,
+                &mut summary,
+            );
+        });
+
+        assert_eq!(
+            spans
+                .iter()
+                .map(|s| (s.name.as_str(), s.parent.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("measurement_cycle", None),
+                ("device", Some("measurement_cycle")),
+                ("fetch_device_info", Some("device")),
+            ]
+        );
+        assert_eq!(spans[1].attributes["device"], devices[0].fullname);
+        assert_eq!(spans[1].attributes["status"], "failed");
+        assert_eq!(summary.failed_devices.len(), 1);
+    }
+
+    #[test]
+    fn measure_devices_keeps_parentage_of_data_requested_on_another_thread() {
+        let server = energy_socket_server();
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        let devices = vec![HomewizardDevice {
+            fullname: "energysocket-3C39E7._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        }];
+        let measure = || {
+            let mut measurement = Measurement {
+                id: Uuid::new_v4().to_string(),
+                source: String::from("jarvis-homewizard-exporter"),
+                location: config.location.clone(),
+                samples: Vec::new(),
+                measured_at_time: Utc::now(),
+            };
+            let _cycle_span = info_span!("measurement_cycle").entered();
+            homewizard_client.measure_devices(
+                &config,
+                &devices,
+                None,
+                &mut measurement,
+                &mut CycleSummary::new(devices.len()),
+            );
+        };
+        // the first cycle learns the device's info, so the next requests its data alongside
+        measure();
+
+        // act
+        let spans = record_spans(measure);
+
+        let mut tree: Vec<(&str, Option<&str>)> = spans
+            .iter()
+            .map(|s| (s.name.as_str(), s.parent.as_deref()))
+            .collect();
+        tree.sort();
+        assert_eq!(
+            tree,
+            vec![
+                ("device", Some("measurement_cycle")),
+                ("fetch_device_data", Some("device")),
+                ("fetch_device_info", Some("device")),
+                ("measurement_cycle", None),
+            ]
+        );
+        let device_span = spans.iter().find(|s| s.name == "device").unwrap();
+        assert_eq!(device_span.attributes["serial"], "3c39e72e33ce");
+        assert_eq!(device_span.attributes["status"], "ok");
+    }
+
+    fn energy_socket_server() -> FakeDeviceServer {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(
+                "/api",
+                FakeResponse::json(
+                    r#"{"product_type":"HWE-SKT","product_name":"Energy Socket","serial":"3c39e72e33ce","firmware_version":"3.02","api_version":"v1"}"#,
+                ),
+            )
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(
+                    r#"{"wifi_ssid":"My Wi-Fi","wifi_strength":100,"total_power_import_t1_kwh":30.511,"total_power_export_t1_kwh":0.0,"active_power_w":543.0,"active_power_l1_w":543.0}"#,
+                ),
+            );
+        server
+    }
+
+    fn target(server: &FakeDeviceServer, expected_serial: Option<&str>) -> DeviceTarget {
+        match server.address() {
+            SocketAddr::V4(address) => DeviceTarget {
+                address,
+                expected_serial: expected_serial.map(String::from),
+            },
+            SocketAddr::V6(_) => panic!("Fake device server should listen on ipv4"),
+        }
+    }
+
+    #[test]
+    fn get_measurements_measures_target_device_without_discovery() {
+        let server = energy_socket_server();
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_device(target(&server, Some("3C39E72E33CE"))),
+        );
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        // act
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].samples.len(), 3);
+        assert_eq!(measurements[0].samples[0].sample_name, "Bonenmaler");
+        assert_eq!(measurements[0].samples[2].value, 543.0);
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(summary.devices_discovered, 1);
+        assert_eq!(summary.succeeded_devices.len(), 1);
+    }
+
+    #[test]
+    fn get_measurements_aborts_when_target_serial_does_not_match() {
+        let server = energy_socket_server();
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_device(target(&server, Some("5c2faf0a8b3e"))),
+        );
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+
+        // act
+        let result = homewizard_client.get_measurements(config, None);
+
+        let error = result.unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("instead of expected serial 5c2faf0a8b3e"));
+        assert!(error.is::<DeviceError>());
+        assert_eq!(server.request_count("/api/v1/data"), 0);
+    }
+
+    fn fixture(name: &str) -> String {
+        fs::read_to_string(format!("tests/fixtures/responses/{}", name)).unwrap()
+    }
+
+    fn fake_device(info: &str, data: &str) -> (FakeDeviceServer, HomewizardDevice) {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api", FakeResponse::json(info))
+            .respond("/api/v1/data", FakeResponse::json(data));
+        let device = HomewizardDevice {
+            fullname: "fake-device._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+
+        (server, device)
+    }
+
+    fn measure_fake_device(info: &str, data: &str) -> Vec<Sample> {
+        let (_server, device) = fake_device(info, data);
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+        homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap()
+    }
+
+    fn sample(
+        entity_type: EntityType,
+        entity_name: &str,
+        sample_type: SampleType,
+        sample_name: &str,
+        metric_type: MetricType,
+        value: f64,
+    ) -> Sample {
+        Sample {
+            entity_type,
+            entity_name: entity_name.into(),
+            sample_type,
+            sample_name: sample_name.into(),
+            metric_type,
+            value,
+        }
+    }
+
+    fn assert_samples(actual: &[Sample], expected: &[Sample]) {
+        assert_eq!(actual.len(), expected.len());
+        for (actual, expected) in actual.iter().zip(expected.iter()) {
+            assert_eq!(actual.entity_type, expected.entity_type);
+            assert_eq!(actual.entity_name, expected.entity_name);
+            assert_eq!(actual.sample_type, expected.sample_type);
+            assert_eq!(actual.sample_name, expected.sample_name);
+            assert_eq!(actual.metric_type, expected.metric_type);
+            assert_eq!(actual.value, expected.value);
+        }
+    }
+
+    /// Measures the P1 fixture with the active power replaced and net power configured.
+    fn measure_p1_with_net_power(active_power_w: f64) -> Vec<Sample> {
+        let mut data: serde_json::Value =
+            serde_json::from_str(&fixture("data-p1-with-gas.json")).unwrap();
+        data["active_power_w"] = active_power_w.into();
+        let (_server, device) = fake_device(&fixture("api-p1.json"), &data.to_string());
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                net_power: Some(NetPowerConfig { name: "net".into() }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+        homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap()
+    }
+
+    #[test]
+    fn get_samples_for_p1_meter_adds_positive_net_power_while_importing() {
+        // act
+        let samples = measure_p1_with_net_power(2500.0);
+
+        assert_eq!(samples.len(), 6);
+        assert_samples(
+            &samples[5..],
+            &[sample(
+                EntityType::Device,
+                "HWE-P1",
+                SampleType::ElectricityConsumption,
+                "P1 meter net",
+                MetricType::Gauge,
+                2500.0,
+            )],
+        );
+    }
+
+    #[test]
+    fn get_samples_for_p1_meter_adds_negative_net_power_while_exporting() {
+        // act
+        let samples = measure_p1_with_net_power(-3200.0);
+
+        assert_eq!(samples[5].sample_name, "P1 meter net");
+        assert_eq!(samples[5].value, -3200.0);
+        assert_eq!(samples[5].value, samples[4].value);
+    }
+
+    #[test]
+    fn get_samples_for_p1_meter_adds_zero_net_power_while_balanced() {
+        // act
+        let samples = measure_p1_with_net_power(0.0);
+
+        assert_eq!(samples[5].sample_name, "P1 meter net");
+        assert_eq!(samples[5].value, 0.0);
+    }
+
+    #[test]
+    fn get_samples_for_triple_phase_kwh_meter_with_apparent_power() {
+        // act
+        let samples = measure_fake_device(
+            &fixture("api-sdm630.json"),
+            &fixture("data-sdm630-apparent-power.json"),
+        );
+
+        assert_eq!(samples.len(), 7);
+        assert_samples(
+            &samples[3..],
+            &[
+                sample(
+                    EntityType::Device,
+                    "SDM630-wifi",
+                    SampleType::ElectricityConsumption,
+                    "kWh meter 3-phase apparent power",
+                    MetricType::Gauge,
+                    1227.398,
+                ),
+                sample(
+                    EntityType::Device,
+                    "SDM630-wifi",
+                    SampleType::ElectricityConsumption,
+                    "kWh meter 3-phase l1 apparent power",
+                    MetricType::Gauge,
+                    1065.183,
+                ),
+                sample(
+                    EntityType::Device,
+                    "SDM630-wifi",
+                    SampleType::ElectricityConsumption,
+                    "kWh meter 3-phase l2 apparent power",
+                    MetricType::Gauge,
+                    162.215,
+                ),
+                sample(
+                    EntityType::Device,
+                    "SDM630-wifi",
+                    SampleType::ElectricityConsumption,
+                    "kWh meter 3-phase l3 apparent power",
+                    MetricType::Gauge,
+                    0.0,
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn base_url_defaults_to_device_address_and_port() {
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let device = HomewizardDevice {
+            port: 8080,
+            hostname: None,
+            ..water_meter_device()
+        };
+
+        // act
+        let base_url = homewizard_client.base_url(&device).unwrap();
+
+        assert_eq!(base_url, "http://192.168.1.20:8080");
+    }
+
+    #[test]
+    fn base_url_fills_in_url_template() {
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default()
+                .with_url_template("https://proxy.local/homewizard/{ip}/{port}".into()),
+        );
+
+        // act
+        let base_url = homewizard_client.base_url(&water_meter_device()).unwrap();
+
+        assert_eq!(base_url, "https://proxy.local/homewizard/192.168.1.20/80");
+    }
+
+    #[test]
+    fn http_client_trusts_configured_ca_file() {
+        let config = HomewizardClientConfig::default()
+            .with_ca_file(Path::new("tests/fixtures/tls/test-ca.pem"))
+            .unwrap();
+
+        // act
+        let http_client = config.http_client();
+
+        assert!(http_client.is_ok());
+    }
+
+    #[test]
+    fn with_ca_file_fails_for_missing_file() {
+        // act
+        let result = HomewizardClientConfig::default()
+            .with_ca_file(Path::new("tests/fixtures/tls/missing.pem"));
+
+        assert!(result
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("Failed reading CA file tests/fixtures/tls/missing.pem"));
+    }
+
+    #[test]
+    fn http_client_warns_loudly_when_tls_is_insecure() {
+        let secure = HomewizardClientConfig::default();
+        let insecure = HomewizardClientConfig::default().with_insecure_tls();
+
+        // act
+        let secure_logs = capture_logs("warn", || {
+            secure.http_client().unwrap();
+        });
+        let insecure_logs = capture_logs("warn", || {
+            insecure.http_client().unwrap();
+        });
+
+        assert!(secure_logs.is_empty());
+        assert!(insecure_logs.contains("device certificates are NOT verified"));
+    }
+
+    #[test]
+    fn http_client_bypasses_proxy_unless_device_proxy_is_set() {
+        let direct = HomewizardClientConfig::default();
+        let proxied = HomewizardClientConfig::default().with_device_proxy();
+
+        // act
+        let direct_logs = capture_logs("debug", || {
+            direct.http_client().unwrap();
+        });
+        let proxied_logs = capture_logs("debug", || {
+            proxied.http_client().unwrap();
+        });
+
+        assert!(direct_logs.contains("Device requests bypass any HTTP proxy"));
+        assert!(!direct_logs.contains("DEVICE_PROXY is set"));
+        assert!(proxied_logs.contains("device requests go through the HTTP proxy"));
+        assert!(!proxied_logs.contains("bypass any HTTP proxy"));
+    }
+
+    fn v2_device(info: &str, data: &str) -> (FakeDeviceServer, HomewizardDevice) {
+        let server =
+            FakeDeviceServer::start_tls("device-5c2faf0a8b3e.pem", "device-5c2faf0a8b3e.key");
+        server
+            .respond("/api", FakeResponse::json(info))
+            .respond("/api/v1/data", FakeResponse::json(data));
+        let device = HomewizardDevice {
+            fullname: "p1meter-0A8B3E._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+
+        (server, device)
+    }
+
+    fn v2_client(config: HomewizardClientConfig) -> HomewizardClient {
+        HomewizardClient::new(config.with_url_template("https://{ip}:{port}".into()))
+    }
+
+    #[test]
+    fn get_samples_verifies_v2_device_certificate_issued_by_configured_ca() {
+        let (server, device) =
+            v2_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let homewizard_client = v2_client(
+            HomewizardClientConfig::default()
+                .with_ca_file(Path::new("tests/fixtures/tls/test-ca.pem"))
+                .unwrap(),
+        );
+
+        // act
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+        let samples = homewizard_client.get_samples(&Config::default(), &device, &device_info);
+
+        assert_eq!(device_info.serial, "5c2faf0a8b3e");
+        assert!(!samples.unwrap().is_empty());
+        assert_eq!(server.request_count("/api/v1/data"), 1);
+    }
+
+    #[test]
+    fn get_device_info_rejects_v2_device_certificate_of_untrusted_ca() {
+        let (server, device) =
+            v2_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let homewizard_client = v2_client(HomewizardClientConfig::default());
+
+        // act
+        let result = homewizard_client.get_device_info(&device);
+
+        assert!(result.is_err());
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn get_samples_rejects_v2_device_certificate_of_another_serial_before_sending_token() {
+        // the device reports the serial of the socket, its certificate is the one of the p1 meter
+        let (server, device) = v2_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let homewizard_client = v2_client(
+            HomewizardClientConfig::default()
+                .with_ca_file(Path::new("tests/fixtures/tls/test-ca.pem"))
+                .unwrap(),
+        );
+        let config = config_with_token(&server, "2A4F0C9B");
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+
+        // act
+        let result = homewizard_client.get_samples(&config, &device, &device_info);
+
+        assert!(result.is_err());
+        assert_eq!(server.request_count("/api/v1/data"), 0);
+    }
+
+    #[test]
+    fn get_device_info_accepts_any_v2_device_certificate_when_tls_is_insecure() {
+        let (_server, device) =
+            v2_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let homewizard_client = v2_client(HomewizardClientConfig::default().with_insecure_tls());
+
+        // act
+        let result = homewizard_client.get_device_info(&device);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn http_client_requests_device_directly_by_default() {
+        let server = energy_socket_server();
+        let http_client = HomewizardClientConfig::default().http_client().unwrap();
+
+        // act
+        let response = http_client
+            .get(format!("{}/api", server.base_url()))
+            .send()
+            .unwrap();
+
+        assert!(response.status().is_success());
+    }
+
+    #[test]
+    fn get_device_info_requests_overridden_base_url() {
+        let server = energy_socket_server();
+        let base_url = server.base_url();
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_endpoint_resolver(Arc::new(
+                move |_: &HomewizardDevice| -> Result<String, Box<dyn Error + Send + Sync>> {
+                    Ok(base_url.clone())
+                },
+            )),
+        );
+
+        // act
+        let device_info = homewizard_client
+            .get_device_info(&water_meter_device())
+            .unwrap();
+
+        assert_eq!(device_info.serial, "3c39e72e33ce");
+        assert_eq!(server.request_count("/api"), 1);
+    }
+
+    #[test]
+    fn device_info_fixtures_parse() {
+        let fixtures = [
+            ("api-p1.json", "HWE-P1", HomewizardDeviceType::P1Meter),
+            (
+                "api-socket.json",
+                "HWE-SKT",
+                HomewizardDeviceType::EnergySocket,
+            ),
+            (
+                "api-sdm230.json",
+                "SDM230-wifi",
+                HomewizardDeviceType::SinglePhaseKwhMeter,
+            ),
+            (
+                "api-sdm630.json",
+                "SDM630-wifi",
+                HomewizardDeviceType::TriplePhaseKwhMeter,
+            ),
+            (
+                "api-watermeter.json",
+                "HWE-WTR",
+                HomewizardDeviceType::WaterMeter,
+            ),
+        ];
+
+        for (name, product_type, device_type) in fixtures {
+            // act
+            let device_info: DeviceInfoResponse = serde_json::from_str(&fixture(name)).unwrap();
+
+            assert_eq!(device_info.product_type, product_type);
+            assert_eq!(device_info.api_version, "v1");
+            assert_eq!(
+                HomewizardDeviceType::from_str(&device_info.product_type),
+                Ok(device_type)
+            );
+        }
+    }
+
+    #[test]
+    fn p1_meter_fixture_with_gas_parses() {
+        // act
+        let data: P1MeterDataResponse =
+            serde_json::from_str(&fixture("data-p1-with-gas.json")).unwrap();
+
+        assert_eq!(
+            data,
+            P1MeterDataResponse {
+                smr_version: 50,
+                meter_model: "ISKRA 2M550T-101".into(),
+                wifi_ssid: "My Wi-Fi".into(),
+                wifi_strength: 92,
+                total_power_import_t1_kwh: 10830.511,
+                total_power_export_t1_kwh: 0.002,
+                total_power_import_t2_kwh: 2948.827,
+                total_power_export_t2_kwh: 0.003,
+                active_power_w: -543.0,
+                active_power_l1_w: -676.0,
+                active_power_l2_w: Some(133.0),
+                active_power_l3_w: Some(0.0),
+                total_gas_m3: Some(2569.646),
+                gas_timestamp: Some(210606140010),
+                external: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn p1_meter_fixture_without_gas_parses() {
+        // act
+        let data: P1MeterDataResponse =
+            serde_json::from_str(&fixture("data-p1-without-gas.json")).unwrap();
+
+        assert_eq!(data.meter_model, "Landis + Gyr");
+        assert_eq!(data.total_power_export_t2_kwh, 2841.9);
+        assert_eq!(data.total_gas_m3, None);
+        assert_eq!(data.gas_timestamp, None);
+    }
+
+    #[test]
+    fn p1_meter_fixture_from_belgian_meter_parses() {
+        // act
+        let data: P1MeterDataResponse =
+            serde_json::from_str(&fixture("data-p1-belgian.json")).unwrap();
+
+        assert_eq!(data.meter_model, "Fluvius 253770234_A");
+        assert_eq!(data.total_power_import_t1_kwh, 4124.301);
+        assert_eq!(data.total_power_export_t1_kwh, 1501.5);
+        assert_eq!(data.active_power_w, 212.0);
+        assert_eq!(data.total_gas_m3, Some(1122.333));
+        assert_eq!(data.gas_timestamp, Some(230601120000));
+    }
+
+    #[test]
+    fn energy_socket_fixtures_parse() {
+        // act
+        let old_firmware: EnergySocketDataResponse =
+            serde_json::from_str(&fixture("data-socket-firmware-3.json")).unwrap();
+        let new_firmware: EnergySocketDataResponse =
+            serde_json::from_str(&fixture("data-socket-firmware-4.json")).unwrap();
+
+        assert_eq!(
+            old_firmware,
+            EnergySocketDataResponse {
+                wifi_ssid: "My Wi-Fi".into(),
+                wifi_strength: 100,
+                total_power_import_t1_kwh: 30.511,
+                total_power_export_t1_kwh: 0.005,
+                active_power_w: 98.1,
+                active_power_l1_w: 98.1,
+            }
+        );
+        assert_eq!(
+            new_firmware,
+            EnergySocketDataResponse {
+                wifi_ssid: "My Wi-Fi".into(),
+                wifi_strength: 94,
+                total_power_import_t1_kwh: 412.772,
+                total_power_export_t1_kwh: 0.0,
+                active_power_w: 1450.3,
+                active_power_l1_w: 1450.3,
+            }
+        );
+    }
+
+    #[test]
+    fn kwh_meter_fixtures_parse() {
+        // act
+        let single_phase: SinglePhaseKwhMeterDataResponse =
+            serde_json::from_str(&fixture("data-sdm230.json")).unwrap();
+        let triple_phase: TriplePhaseKwhMeterDataResponse =
+            serde_json::from_str(&fixture("data-sdm630.json")).unwrap();
+
+        assert_eq!(
+            single_phase,
+            SinglePhaseKwhMeterDataResponse {
+                wifi_ssid: "My Wi-Fi".into(),
+                wifi_strength: 100,
+                total_power_import_t1_kwh: 2.705,
+                total_power_export_t1_kwh: 255.551,
+                active_power_w: -1058.296,
+                active_power_l1_w: -1058.296,
+            }
+        );
+        assert_eq!(
+            triple_phase,
+            TriplePhaseKwhMeterDataResponse {
+                wifi_ssid: "My Wi-Fi".into(),
+                wifi_strength: 92,
+                total_power_import_t1_kwh: 0.101,
+                total_power_export_t1_kwh: 1002.123,
+                active_power_w: -900.194,
+                active_power_l1_w: -1058.296,
+                active_power_l2_w: 158.102,
+                active_power_l3_w: 0.0,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn kwh_meter_fixture_with_apparent_power_parses() {
+        // act
+        let triple_phase: TriplePhaseKwhMeterDataResponse =
+            serde_json::from_str(&fixture("data-sdm630-apparent-power.json")).unwrap();
+
+        assert_eq!(triple_phase.active_power_w, -900.194);
+        assert_eq!(triple_phase.apparent_power_va, Some(1227.398));
+        assert_eq!(triple_phase.apparent_power_l1_va, Some(1065.183));
+        assert_eq!(triple_phase.apparent_power_l2_va, Some(162.215));
+        assert_eq!(triple_phase.apparent_power_l3_va, Some(0.0));
+    }
+
+    #[test]
+    fn water_meter_fixtures_parse() {
+        // act
+        let usb_powered: WaterMeterDataResponse =
+            serde_json::from_str(&fixture("data-watermeter-usb.json")).unwrap();
+        let battery_powered: WaterMeterDataResponse =
+            serde_json::from_str(&fixture("data-watermeter-battery.json")).unwrap();
+
+        assert_eq!(
+            usb_powered,
+            WaterMeterDataResponse {
+                wifi_ssid: "My Wi-Fi".into(),
+                wifi_strength: 84,
+                total_liter_m3: 123.456,
+                active_liter_lpm: 7.5,
+            }
+        );
+        assert_eq!(
+            battery_powered,
+            WaterMeterDataResponse {
+                wifi_ssid: "My Wi-Fi".into(),
+                wifi_strength: 62,
+                total_liter_m3: 847.012,
+                active_liter_lpm: 0.0,
+            }
+        );
+    }
+
+    #[test]
+    fn get_measurements_measures_discovered_devices() {
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_water_meter_server, water_meter) = fake_device(
+            &fixture("api-watermeter.json"),
+            &fixture("data-watermeter-usb.json"),
+        );
+        let unreachable = HomewizardDevice {
+            fullname: "energysocket-1A2B3C._hwenergy._tcp.local.".into(),
+            ip_addresses: HashSet::new(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client =
+            HomewizardClient::new(HomewizardClientConfig::default()).with_discoverer(Box::new(
+                StaticDiscoverer::new(vec![socket, water_meter, unreachable]),
+            ));
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+
+        // act
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].samples.len(), 5);
+        assert_eq!(measurements[0].samples[0].entity_name, "HWE-SKT");
+        assert_eq!(measurements[0].samples[3].entity_name, "HWE-WTR");
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(summary.devices_discovered, 3);
+        assert_eq!(summary.succeeded_devices.len(), 2);
+        assert_eq!(
+            summary.failed_devices,
+            vec!["energysocket-1A2B3C._hwenergy._tcp.local.".to_string()]
+        );
+    }
+
+    fn measure_with_phase_imbalance(info: &str, data: &str) -> Vec<Sample> {
+        let (_server, device) = fake_device(info, data);
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                phase_imbalance: Some(PhaseImbalanceConfig {
+                    name: "phase imbalance".into(),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+        homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap()
+    }
+
+    #[test]
+    fn get_samples_for_balanced_p1_meter_adds_small_phase_imbalance() {
+        // act
+        let samples = measure_with_phase_imbalance(
+            &fixture("api-p1.json"),
+            &fixture("data-p1-without-gas.json"),
+        );
+
+        assert_samples(
+            &samples[samples.len() - 1..],
+            &[sample(
+                EntityType::Device,
+                "HWE-P1",
+                SampleType::ElectricityConsumption,
+                "P1 meter phase imbalance",
+                MetricType::Gauge,
+                1.0,
+            )],
+        );
+    }
+
+    #[test]
+    fn get_samples_for_skewed_p1_meter_adds_large_phase_imbalance() {
+        // act
+        let samples = measure_with_phase_imbalance(
+            &fixture("api-p1.json"),
+            &fixture("data-p1-with-gas.json"),
+        );
+
+        // -676, 133 and 0 W average -181 W, from which L1 deviates most
+        let imbalance = samples.last().unwrap();
+        assert_eq!(imbalance.sample_name, "P1 meter phase imbalance");
+        assert_eq!(imbalance.value, 495.0);
+    }
+
+    #[test]
+    fn get_samples_for_sdm630_adds_phase_imbalance() {
+        // act
+        let samples =
+            measure_with_phase_imbalance(&fixture("api-sdm630.json"), &fixture("data-sdm630.json"));
+
+        assert_eq!(samples.len(), 4);
+        assert!(samples[3].sample_name.ends_with(" phase imbalance"));
+        assert!((samples[3].value - 758.2313).abs() < 0.001);
+    }
+
+    #[test]
+    fn get_samples_for_single_phase_devices_adds_no_phase_imbalance() {
+        let mut single_phase_p1: serde_json::Value =
+            serde_json::from_str(&fixture("data-p1-without-gas.json")).unwrap();
+        let data = single_phase_p1.as_object_mut().unwrap();
+        data.remove("active_power_l2_w");
+        data.remove("active_power_l3_w");
+
+        // act
+        let p1_samples =
+            measure_with_phase_imbalance(&fixture("api-p1.json"), &single_phase_p1.to_string());
+        let sdm230_samples =
+            measure_with_phase_imbalance(&fixture("api-sdm230.json"), &fixture("data-sdm230.json"));
+
+        assert_eq!(p1_samples.len(), 5);
+        assert!(p1_samples
+            .iter()
+            .all(|sample| !sample.sample_name.ends_with("phase imbalance")));
+        assert_eq!(sdm230_samples.len(), 3);
+    }
+
+    fn measure_with_cost(info: &str, data: &str, cost: Option<CostConfig>) -> Vec<Sample> {
+        let (_server, device) = fake_device(info, data);
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                cost,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+        homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap()
+    }
+
+    fn all_prices() -> CostConfig {
+        CostConfig {
+            electricity_t1: Some(0.25),
+            electricity_t2: Some(0.2),
+            gas: Some(1.5),
+            water: Some(2.0),
+        }
+    }
+
+    #[test]
+    fn get_samples_for_p1_meter_adds_cost_of_every_priced_register() {
+        // act
+        let samples = measure_with_cost(
+            &fixture("api-p1.json"),
+            &fixture("data-p1-with-gas.json"),
+            Some(all_prices()),
+        );
+
+        assert_eq!(samples.len(), 8);
+        assert_samples(
+            &samples[5..],
+            &[
+                sample(
+                    EntityType::Tariff,
+                    "HWE-P1",
+                    SampleType::ElectricityConsumption,
+                    "t1 import cost",
+                    MetricType::Counter,
+                    10830.511 * 0.25,
+                ),
+                sample(
+                    EntityType::Tariff,
+                    "HWE-P1",
+                    SampleType::ElectricityConsumption,
+                    "t2 import cost",
+                    MetricType::Counter,
+                    2948.827 * 0.2,
+                ),
+                sample(
+                    EntityType::Tariff,
+                    "HWE-P1",
+                    SampleType::GasConsumption,
+                    "gas cost",
+                    MetricType::Counter,
+                    2569.646 * 1.5,
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn get_samples_for_p1_meter_adds_cost_of_priced_registers_only() {
+        // act
+        let samples = measure_with_cost(
+            &fixture("api-p1.json"),
+            &fixture("data-p1-without-gas.json"),
+            Some(CostConfig {
+                electricity_t2: Some(0.2),
+                gas: Some(1.5),
+                ..Default::default()
+            }),
+        );
+
+        // without a gas meter there's no gas to put a price on
+        assert_eq!(samples.len(), 6);
+        assert_eq!(samples[5].sample_name, "t2 import cost");
+        assert_eq!(samples[5].value, 3880.02 * 0.2);
+    }
+
+    #[test]
+    fn get_samples_for_water_meter_adds_water_cost() {
+        // act
+        let samples = measure_with_cost(
+            &fixture("api-watermeter.json"),
+            &fixture("data-watermeter-usb.json"),
+            Some(all_prices()),
+        );
+
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[2].entity_type, EntityType::Tariff);
+        assert_eq!(
+            samples[2].sample_name,
+            format!("{} cost", samples[0].sample_name)
+        );
+        assert_eq!(samples[2].metric_type, MetricType::Counter);
+        assert_eq!(samples[2].value, samples[0].value * 2.0);
+    }
+
+    #[test]
+    fn get_samples_without_prices_adds_no_cost() {
+        for (info, data) in [
+            ("api-p1.json", "data-p1-with-gas.json"),
+            ("api-watermeter.json", "data-watermeter-usb.json"),
+        ]
+        .iter()
+        {
+            // act
+            let samples = measure_with_cost(&fixture(info), &fixture(data), None);
+
+            assert!(samples.iter().all(|sample| !derived::is_cost(sample)));
+        }
+    }
+
+    /// Measures an energy socket with energy deltas configured, against the given previous
+    /// measurements.
+    fn measure_socket_with_energy_delta(
+        last_measurements: Option<Vec<Measurement>>,
+    ) -> Vec<Sample> {
+        let (_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            derived: Some(DerivedConfig {
+                energy_delta: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        homewizard_client
+            .get_measurements(config, last_measurements)
+            .unwrap()[0]
+            .samples
+            .clone()
+    }
+
+    /// A previous measurement of the socket, with its import and export counters at these values.
+    fn previous_socket_measurement(location: &str, import: f64, export: f64) -> Vec<Measurement> {
+        let counter = |sample_type, value| {
+            sample(
+                EntityType::Device,
+                "HWE-SKT",
+                sample_type,
+                "Bonenmaler",
+                MetricType::Counter,
+                value,
+            )
+        };
+
+        vec![Measurement {
+            id: Uuid::new_v4().to_string(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: location.into(),
+            samples: vec![
+                counter(SampleType::ElectricityConsumption, import),
+                counter(SampleType::ElectricityProduction, export),
+            ],
+            measured_at_time: Utc::now(),
+        }]
+    }
+
+    #[test]
+    fn get_measurements_adds_energy_delta_since_previous_measurement() {
+        let import = kwh_to_joules(30.511);
+        let export = kwh_to_joules(0.005);
+
+        // act
+        let samples = measure_socket_with_energy_delta(Some(previous_socket_measurement(
+            "My Home",
+            import - 36000.0,
+            export,
+        )));
+
+        assert_samples(
+            &samples[3..],
+            &[
+                sample(
+                    EntityType::Device,
+                    "HWE-SKT",
+                    SampleType::ElectricityConsumption,
+                    "Bonenmaler delta",
+                    MetricType::Gauge,
+                    import - (import - 36000.0),
+                ),
+                sample(
+                    EntityType::Device,
+                    "HWE-SKT",
+                    SampleType::ElectricityProduction,
+                    "Bonenmaler delta",
+                    MetricType::Gauge,
+                    0.0,
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn get_measurements_adds_no_energy_delta_in_first_cycle() {
+        // act
+        let first_cycle = measure_socket_with_energy_delta(None);
+        let other_location = measure_socket_with_energy_delta(Some(previous_socket_measurement(
+            "Holiday Home",
+            0.0,
+            0.0,
+        )));
+
+        assert_eq!(first_cycle.len(), 3);
+        assert_eq!(other_location.len(), 3);
+    }
+
+    #[test]
+    fn get_measurements_adds_no_energy_delta_after_counter_reset() {
+        let export = kwh_to_joules(0.005);
+
+        // act
+        let samples = measure_socket_with_energy_delta(Some(previous_socket_measurement(
+            "My Home",
+            kwh_to_joules(1200.0),
+            export - 3600.0,
+        )));
+
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[3].sample_type, SampleType::ElectricityProduction);
+        assert_eq!(samples[3].value, export - (export - 3600.0));
+    }
+
+    fn measure_p1_with_gas_energy(data: &str) -> Vec<Sample> {
+        let (_server, device) = fake_device(&fixture("api-p1.json"), &fixture(data));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                gas_energy: Some(GasEnergyConfig {
+                    calorific_value: 9.77,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+        homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap()
+    }
+
+    #[test]
+    fn get_samples_for_p1_meter_with_gas_adds_gas_energy_equivalent() {
+        // act
+        let samples = measure_p1_with_gas_energy("data-p1-with-gas.json");
+
+        assert_samples(
+            &samples[5..],
+            &[sample(
+                EntityType::Tariff,
+                "HWE-P1",
+                SampleType::GasConsumption,
+                "gas energy equivalent",
+                MetricType::Counter,
+                kwh_to_joules(2569.646 * 9.77),
+            )],
+        );
+    }
+
+    #[test]
+    fn get_samples_for_p1_meter_without_gas_adds_no_gas_energy_equivalent() {
+        // act
+        let samples = measure_p1_with_gas_energy("data-p1-without-gas.json");
+
+        assert_eq!(samples.len(), 5);
+    }
+
+    fn measure_water_meter_with_liters(data: &str, water_liters: bool) -> Vec<Sample> {
+        let (_server, device) = fake_device(&fixture("api-watermeter.json"), &fixture(data));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                water_liters,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+        homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap()
+    }
+
+    #[test]
+    fn get_samples_for_water_meter_adds_liters_counter() {
+        for (data, liters) in [
+            ("data-watermeter-usb.json", 123_456.0),
+            ("data-watermeter-battery.json", 847_012.0),
+        ]
+        .iter()
+        {
+            // act
+            let samples = measure_water_meter_with_liters(data, true);
+
+            assert_eq!(samples.len(), 3);
+            assert_samples(
+                &samples[2..],
+                &[sample(
+                    EntityType::Device,
+                    "HWE-WTR",
+                    SampleType::WaterConsumption,
+                    &format!("{} liters", samples[0].sample_name),
+                    MetricType::Counter,
+                    *liters,
+                )],
+            );
+            assert_eq!(samples[2].value, samples[0].value * 1000.0);
+        }
+    }
+
+    #[test]
+    fn get_samples_for_water_meter_adds_no_liters_counter_by_default() {
+        // act
+        let samples = measure_water_meter_with_liters("data-watermeter-usb.json", false);
+
+        assert_eq!(samples.len(), 2);
+    }
+
+    fn socket_data(import_kwh: f64, active_power_w: f64) -> FakeResponse {
+        FakeResponse::json(&format!(
+            r#"{{"wifi_ssid":"My Wi-Fi","wifi_strength":100,"total_power_import_t1_kwh":{},"total_power_export_t1_kwh":0.0,"active_power_w":{},"active_power_l1_w":{}}}"#,
+            import_kwh, active_power_w, active_power_w
+        ))
+    }
+
+    /// Measures a socket serving the given data responses in turn, with burst sampling configured.
+    fn measure_socket_in_burst(
+        server: &FakeDeviceServer,
+        timeout_seconds: u64,
+        reads: u32,
+    ) -> Vec<Sample> {
+        server.respond("/api", FakeResponse::json(&fixture("api-socket.json")));
+        let device = HomewizardDevice {
+            fullname: "energysocket-3c39e72e33ce._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig {
+            timeout_seconds,
+            ..Default::default()
+        });
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Wasmachine".to_string())]
+                .into_iter()
+                .collect(),
+            burst: vec![(
+                "3c39e72e33ce".to_string(),
+                BurstConfig {
+                    reads,
+                    spacing_milliseconds: 0,
+                    min_max: true,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+        homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap()
+    }
+
+    #[test]
+    fn get_samples_in_burst_averages_gauges_and_keeps_last_counters() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api/v1/data", socket_data(1.0, 100.0))
+            .respond("/api/v1/data", socket_data(1.1, 200.0))
+            .respond("/api/v1/data", socket_data(1.2, 600.0));
+
+        // act
+        let samples = measure_socket_in_burst(&server, 10, 3);
+
+        assert_eq!(server.request_count("/api/v1/data"), 3);
+        assert_samples(
+            &samples,
+            &[
+                sample(
+                    EntityType::Device,
+                    "HWE-SKT",
+                    SampleType::ElectricityConsumption,
+                    "Wasmachine",
+                    MetricType::Counter,
+                    kwh_to_joules(1.2),
+                ),
+                sample(
+                    EntityType::Device,
+                    "HWE-SKT",
+                    SampleType::ElectricityProduction,
+                    "Wasmachine",
+                    MetricType::Counter,
+                    0.0,
+                ),
+                sample(
+                    EntityType::Device,
+                    "HWE-SKT",
+                    SampleType::ElectricityConsumption,
+                    "Wasmachine",
+                    MetricType::Gauge,
+                    300.0,
+                ),
+                sample(
+                    EntityType::Device,
+                    "HWE-SKT",
+                    SampleType::ElectricityConsumption,
+                    "Wasmachine min",
+                    MetricType::Gauge,
+                    100.0,
+                ),
+                sample(
+                    EntityType::Device,
+                    "HWE-SKT",
+                    SampleType::ElectricityConsumption,
+                    "Wasmachine max",
+                    MetricType::Gauge,
+                    600.0,
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn get_samples_in_burst_uses_reads_so_far_when_a_read_fails() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api/v1/data", socket_data(1.0, 100.0))
+            .respond("/api/v1/data", socket_data(1.1, 300.0))
+            .respond("/api/v1/data", FakeResponse::status(500));
+
+        // act
+        let samples = measure_socket_in_burst(&server, 10, 5);
+
+        assert_eq!(server.request_count("/api/v1/data"), 3);
+        assert_eq!(samples[0].value, kwh_to_joules(1.1));
+        assert_eq!(samples[2].value, 200.0);
+        assert_eq!(samples[3].value, 100.0);
+        assert_eq!(samples[4].value, 300.0);
+    }
+
+    #[test]
+    fn get_samples_in_burst_stays_within_timeout() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api/v1/data", socket_data(1.0, 100.0))
+            .respond("/api/v1/data", socket_data(1.1, 300.0));
+
+        // act
+        let samples = measure_socket_in_burst(&server, 0, 5);
+
+        assert_eq!(server.request_count("/api/v1/data"), 1);
+        assert_eq!(samples[2].value, 100.0);
+    }
+
+    #[test]
+    fn get_samples_in_burst_takes_gas_reading_of_last_read_once() {
+        let data = fixture("data-p1-with-gas.json");
+        let (server, p1_meter) = fake_device(&fixture("api-p1.json"), &data);
+        let mut next_reading: serde_json::Value = serde_json::from_str(&data).unwrap();
+        next_reading["total_gas_m3"] = 2569.746.into();
+        next_reading["gas_timestamp"] = 210606140510u64.into();
+        server
+            .respond("/api/v1/data", FakeResponse::json(&data))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&next_reading.to_string()),
+            );
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(|| "2021-06-07T14:00:10Z".parse().unwrap()),
+                Arc::new(|| "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".to_string()),
+            ),
+        );
+        let config = Config {
+            location: "My Home".into(),
+            burst: vec![(
+                "5c2faf0a8b3e".to_string(),
+                BurstConfig {
+                    reads: 2,
+                    spacing_milliseconds: 0,
+                    min_max: false,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            derived: Some(DerivedConfig {
+                gas_flow: Some(GasFlowConfig {
+                    name: "gas flow".into(),
+                }),
+                ..Default::default()
+            }),
+            gas_clock_skew: Some(GasClockSkewConfig {
+                threshold_minutes: 120,
+                utc_offset_minutes: Some(0),
+            }),
+            ..Default::default()
+        };
+        let device_info = homewizard_client.get_device_info(&p1_meter).unwrap();
+
+        // act
+        let mut bursts = vec![];
+        let logs = capture_logs("warn", || {
+            for _ in 0..2 {
+                bursts.push(
+                    homewizard_client
+                        .get_samples(&config, &p1_meter, &device_info)
+                        .unwrap(),
+                );
+            }
+        });
+
+        let gas_flows: Vec<Vec<f64>> = bursts
+            .iter()
+            .map(|samples| {
+                samples
+                    .iter()
+                    .filter(|sample| sample.sample_name == "P1 meter gas flow")
+                    .map(|sample| sample.value)
+                    .collect()
+            })
+            .collect();
+        assert_eq!(server.request_count("/api/v1/data"), 4);
+        assert!(gas_flows[0].is_empty());
+        assert_eq!(gas_flows[1].len(), 1);
+        assert!((gas_flows[1][0] - 1.2).abs() < 1e-6);
+        assert_eq!(logs.matches("Gas reading is stale").count(), 2);
+    }
+
+    fn smoothing_config(keep_raw: bool) -> Config {
+        Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Wasmachine".to_string())]
+                .into_iter()
+                .collect(),
+            smoothing: Some(SmoothingConfig {
+                alpha: 0.1,
+                devices: vec![("3c39e72e33ce".to_string(), 0.5)]
+                    .into_iter()
+                    .collect(),
+                keep_raw,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn socket_serving(responses: Vec<FakeResponse>) -> (FakeDeviceServer, HomewizardDevice) {
+        let server = FakeDeviceServer::start();
+        server.respond("/api", FakeResponse::json(&fixture("api-socket.json")));
+        for response in responses {
+            server.respond("/api/v1/data", response);
+        }
+        let device = HomewizardDevice {
+            fullname: "energysocket-3c39e72e33ce._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+
+        (server, device)
+    }
+
+    #[test]
+    fn get_measurements_smooths_gauges_across_cycles() {
+        let (_server, socket) = socket_serving(vec![
+            socket_data(1.0, 100.0),
+            socket_data(1.1, 200.0),
+            socket_data(1.2, 400.0),
+        ]);
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+
+        // act
+        let cycles: Vec<Vec<Sample>> = (0..3)
+            .map(|_| {
+                homewizard_client
+                    .get_measurements(smoothing_config(true), None)
+                    .unwrap()[0]
+                    .samples
+                    .clone()
+            })
+            .collect();
+
+        // the device's alpha of 0.5 applies: 100, then 0.5 * 200 + 0.5 * 100, then
+        // 0.5 * 400 + 0.5 * 150
+        let gauges: Vec<f64> = cycles.iter().map(|samples| samples[2].value).collect();
+        assert_eq!(gauges, vec![100.0, 150.0, 275.0]);
+        let raw: Vec<f64> = cycles.iter().map(|samples| samples[3].value).collect();
+        assert_eq!(raw, vec![100.0, 200.0, 400.0]);
+        assert_eq!(cycles[2][3].sample_name, "Wasmachine raw");
+        // counters are never smoothed
+        assert_eq!(cycles[2][0].value, kwh_to_joules(1.2));
+    }
+
+    #[test]
+    fn get_measurements_seeds_smoothing_from_last_measurement() {
+        let (_server, socket) = socket_serving(vec![socket_data(1.0, 100.0)]);
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let last_measurement = Measurement {
+            id: Uuid::new_v4().to_string(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![sample(
+                EntityType::Device,
+                "HWE-SKT",
+                SampleType::ElectricityConsumption,
+                "Wasmachine",
+                MetricType::Gauge,
+                1000.0,
+            )],
+            measured_at_time: Utc::now(),
+        };
+
+        // act
+        let samples = homewizard_client
+            .get_measurements(smoothing_config(false), Some(vec![last_measurement]))
+            .unwrap()[0]
+            .samples
+            .clone();
+
+        assert_eq!(samples.len(), 3);
+        assert_eq!(samples[2].value, 550.0);
+    }
+
+    /// Client for a fleet of two devices without any address to read them on.
+    fn failing_fleet_client() -> HomewizardClient {
+        let unreachable = |fullname: &str| HomewizardDevice {
+            fullname: fullname.into(),
+            ip_addresses: HashSet::new(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        };
+
+        HomewizardClient::new(HomewizardClientConfig::default()).with_discoverer(Box::new(
+            StaticDiscoverer::new(vec![
+                unreachable("p1meter-0A8B3E._hwenergy._tcp.local."),
+                unreachable("energysocket-1A2B3C._hwenergy._tcp.local."),
+            ]),
+        ))
+    }
+
+    fn on_empty_config(on_empty: OnEmpty) -> Config {
+        Config {
+            location: "My Home".into(),
+            on_empty,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn get_measurements_fails_without_samples_by_default() {
+        let homewizard_client = failing_fleet_client();
+
+        // act
+        let result = homewizard_client.get_measurements(
+            Config {
+                location: "My Home".into(),
+                ..Default::default()
+            },
+            None,
+        );
+
+        let error = result.unwrap_err();
+        assert!(error.is::<DeviceError>());
+        assert!(error
+            .to_string()
+            .contains("No samples read from any of the 2 devices"));
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(summary.on_empty, OnEmpty::Error);
+        assert_eq!(summary.failed_devices.len(), 2);
+    }
+
+    #[test]
+    fn get_measurements_skips_measurement_without_samples() {
+        let homewizard_client = failing_fleet_client();
+
+        // act
+        let measurements = homewizard_client
+            .get_measurements(on_empty_config(OnEmpty::Skip), None)
+            .unwrap();
+
+        assert!(measurements.is_empty());
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(summary.on_empty, OnEmpty::Skip);
+        assert_eq!(summary.samples_emitted, 0);
+    }
+
+    #[test]
+    fn get_measurements_publishes_heartbeat_without_samples() {
+        let homewizard_client = failing_fleet_client();
+
+        // act
+        let measurements = homewizard_client
+            .get_measurements(on_empty_config(OnEmpty::Heartbeat), None)
+            .unwrap();
+
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].location, "My Home");
+        assert_eq!(measurements[0].source, "jarvis-homewizard-exporter");
+        assert!(measurements[0].samples.is_empty());
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(summary.on_empty, OnEmpty::Heartbeat);
+    }
+
+    fn measure_with_device_reachable(
+        devices: Vec<HomewizardDevice>,
+        include_unconfigured: bool,
+    ) -> Vec<(String, String, f64)> {
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(devices)));
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![
+                ("3c39e72e33ce".to_string(), "Bonenmaler".to_string()),
+                ("5c2faf0a8b3e".to_string(), "P1".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            derived: Some(DerivedConfig {
+                device_reachable: Some(DeviceReachableConfig {
+                    include_unconfigured,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        homewizard_client.get_measurements(config, None).unwrap()[0]
+            .samples
+            .iter()
+            .filter(|sample| derived::is_device_reachable(sample))
+            .map(|sample| {
+                (
+                    sample.entity_name.clone(),
+                    sample.sample_name.clone(),
+                    sample.value,
+                )
+            })
+            .collect()
+    }
+
+    fn unreachable_socket() -> HomewizardDevice {
+        HomewizardDevice {
+            fullname: "energysocket-1A2B3C._hwenergy._tcp.local.".into(),
+            ip_addresses: HashSet::new(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        }
+    }
+
+    #[test]
+    fn get_measurements_reports_configured_devices_reachable_or_not() {
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+
+        // act
+        let reachable = measure_with_device_reachable(vec![socket, unreachable_socket()], false);
+
+        // the configured P1 meter wasn't even discovered, the unconfigured socket is left out
+        assert_eq!(
+            reachable,
+            vec![
+                (
+                    "3c39e72e33ce".to_string(),
+                    "Bonenmaler reachable".to_string(),
+                    1.0
+                ),
+                ("5c2faf0a8b3e".to_string(), "P1 reachable".to_string(), 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_measurements_reports_configured_devices_when_all_fail() {
+        let p1_server = FakeDeviceServer::start();
+        p1_server
+            .respond("/api", FakeResponse::json(&fixture("api-p1.json")))
+            .respond("/api/v1/data", FakeResponse::status(500));
+        let p1_meter = HomewizardDevice {
+            fullname: "p1meter-0A8B3E._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: p1_server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+
+        // act
+        let reachable = measure_with_device_reachable(vec![p1_meter], false);
+
+        assert_eq!(
+            reachable,
+            vec![
+                (
+                    "3c39e72e33ce".to_string(),
+                    "Bonenmaler reachable".to_string(),
+                    0.0
+                ),
+                ("5c2faf0a8b3e".to_string(), "P1 reachable".to_string(), 0.0),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_measurements_reports_unconfigured_devices_when_enabled() {
+        let (_water_server, water_meter) = fake_device(
+            &fixture("api-watermeter.json"),
+            &fixture("data-watermeter-usb.json"),
+        );
+
+        // act
+        let reachable =
+            measure_with_device_reachable(vec![water_meter, unreachable_socket()], true);
+
+        assert_eq!(reachable.len(), 4);
+        assert_eq!(reachable[2].1, "Watermeter reachable");
+        assert_eq!(reachable[2].2, 1.0);
+        assert_eq!(
+            reachable[3],
+            (
+                "energysocket-1A2B3C._hwenergy._tcp.local.".to_string(),
+                "energysocket-1A2B3C._hwenergy._tcp.local. reachable".to_string(),
+                0.0
+            )
+        );
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingEventPublisher {
+        published: Arc<Mutex<Vec<(String, serde_json::Value)>>>,
+    }
+
+    impl EventPublisher for RecordingEventPublisher {
+        fn publish(&self, subject: &str, payload: &[u8]) -> Result<(), Box<dyn Error>> {
+            self.published
+                .lock()
+                .unwrap()
+                .push((subject.to_string(), serde_json::from_slice(payload)?));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn get_measurements_publishes_one_event_when_firmware_changes() {
+        let mut upgraded: serde_json::Value =
+            serde_json::from_str(&fixture("api-socket.json")).unwrap();
+        upgraded["firmware_version"] = "4.19".into();
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api", FakeResponse::json(&fixture("api-socket.json")))
+            .respond("/api", FakeResponse::json(&upgraded.to_string()))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")),
+            );
+        let socket = HomewizardDevice {
+            fullname: "energysocket-3c39e72e33ce._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let event_publisher = RecordingEventPublisher::default();
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])))
+            .with_event_publisher(Box::new(event_publisher.clone()));
+        let config = || Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            firmware_events: Some(FirmwareEventsConfig {
+                subject: "jarvis-homewizard-firmware-events".into(),
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let logs = capture_logs("warn", || {
+            for _ in 0..3 {
+                homewizard_client.get_measurements(config(), None).unwrap();
+            }
+        });
+
+        let published = event_publisher.published.lock().unwrap();
+        assert_eq!(published.len(), 1);
+        let (subject, event) = &published[0];
+        assert_eq!(subject, "jarvis-homewizard-firmware-events");
+        assert_eq!(event["serial"], "3c39e72e33ce");
+        assert_eq!(event["friendlyName"], "Bonenmaler");
+        assert_eq!(event["productType"], "HWE-SKT");
+        assert_eq!(event["previousFirmwareVersion"], "3.02");
+        assert_eq!(event["firmwareVersion"], "4.19");
+        assert_eq!(logs.matches("Device firmware changed").count(), 1);
+    }
+
+    #[test]
+    fn get_measurements_publishes_one_event_when_configured_device_goes_missing_and_returns() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api", FakeResponse::status(500))
+            .respond("/api", FakeResponse::status(500))
+            .respond("/api", FakeResponse::status(500))
+            .respond("/api", FakeResponse::json(&fixture("api-socket.json")))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")),
+            );
+        let socket = HomewizardDevice {
+            fullname: "energysocket-3c39e72e33ce._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let (_water_meter_server, water_meter) = fake_device(
+            &fixture("api-watermeter.json"),
+            &fixture("data-watermeter-usb.json"),
+        );
+        let now: Arc<Mutex<DateTime<Utc>>> =
+            Arc::new(Mutex::new("2023-06-01T12:00:00Z".parse().unwrap()));
+        let clock_now = now.clone();
+        let event_publisher = RecordingEventPublisher::default();
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(move || *clock_now.lock().unwrap()),
+                Arc::new(|| "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".to_string()),
+            ),
+        )
+        .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket, water_meter])))
+        .with_event_publisher(Box::new(event_publisher.clone()));
+        let config = || Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            missing_devices: Some(MissingDevicesConfig {
+                subject: "jarvis-homewizard-missing-devices".into(),
+                missing_cycles: 2,
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let events_per_cycle: Vec<usize> = (0..5)
+            .map(|_| {
+                homewizard_client.get_measurements(config(), None).unwrap();
+                *now.lock().unwrap() += chrono::Duration::minutes(5);
+                event_publisher.published.lock().unwrap().len()
+            })
+            .collect();
+
+        assert_eq!(events_per_cycle, vec![0, 1, 1, 2, 2]);
+        let published = event_publisher.published.lock().unwrap();
+        let (subject, missing) = &published[0];
+        assert_eq!(subject, "jarvis-homewizard-missing-devices");
+        assert_eq!(missing["eventType"], "missing");
+        assert_eq!(missing["serial"], "3c39e72e33ce");
+        assert_eq!(missing["friendlyName"], "Bonenmaler");
+        assert_eq!(missing["missingSince"], "2023-06-01T12:00:00Z");
+        assert_eq!(missing["missingSeconds"], 300);
+        let (_, returned) = &published[1];
+        assert_eq!(returned["eventType"], "returned");
+        assert_eq!(returned["missingSeconds"], 900);
+        assert_eq!(returned["consecutiveCycles"], 3);
+    }
+
+    #[test]
+    fn get_measurements_warns_about_names_that_normalize_identically() {
+        let mut other_socket: serde_json::Value =
+            serde_json::from_str(&fixture("api-socket.json")).unwrap();
+        other_socket["serial"] = "aabbccddeeff".into();
+        let (_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_other_server, mut other) = fake_device(
+            &other_socket.to_string(),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        other.fullname = "energysocket-aabbcc._hwenergy._tcp.local.".into();
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket, other])));
+        let config = |name_normalization| Config {
+            location: "My Home".into(),
+            names: vec![
+                (
+                    "3c39e72e33ce".to_string(),
+                    "Wasmachine ☔ / kelder".to_string(),
+                ),
+                (
+                    "aabbccddeeff".to_string(),
+                    "Wasmachine (Kelder)".to_string(),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            name_normalization,
+            ..Default::default()
+        };
+        let without_normalization = capture_logs("warn", || {
+            homewizard_client
+                .get_measurements(config(None), None)
+                .unwrap();
+        });
+
+        // act
+        let mut measurements = None;
+        let logs = capture_logs("warn", || {
+            measurements = Some(
+                homewizard_client
+                    .get_measurements(
+                        config(Some(NameNormalizationConfig {
+                            separator: "_".into(),
+                            max_length: None,
+                        })),
+                        None,
+                    )
+                    .unwrap(),
+            );
+        });
+
+        let measurements = measurements.unwrap();
+        assert!(measurements[0]
+            .samples
+            .iter()
+            .all(|sample| sample.sample_name == "wasmachine_kelder"));
+        assert!(logs.contains(
+            "Devices 3c39e72e33ce, aabbccddeeff share the friendly name wasmachine_kelder"
+        ));
+        assert!(!without_normalization.contains("share the friendly name"));
+    }
+
+    #[test]
+    fn get_samples_sends_bearer_token_of_device_to_data_endpoint_only() {
+        let (server, device) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = config_with_token(&server, "2A4F0C9B");
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+
+        // act
+        homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap();
+
+        let requests = server.requests();
+        let authorization = |path: &str| {
+            requests
+                .iter()
+                .find(|request| request.path == path)
+                .and_then(|request| request.header("authorization"))
+                .map(String::from)
+        };
+        assert_eq!(
+            authorization("/api/v1/data").as_deref(),
+            Some("Bearer 2A4F0C9B")
+        );
+        assert_eq!(authorization("/api"), None);
+    }
+
+    fn config_with_token(server: &FakeDeviceServer, token: &str) -> Config {
+        Config {
+            location: "My Home".into(),
+            devices: vec![StaticDevice::Pinned {
+                address: SocketAddrV4::new(Ipv4Addr::LOCALHOST, server.address().port()),
+                serial: "3c39e72e33ce".into(),
+                auth: DeviceTokenConfig {
+                    token: Some(token.into()),
+                    ..Default::default()
+                },
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fetch_device_checks_pinned_serial_before_requesting_data_with_token() {
+        // the pinned socket got replaced by a p1 meter after the first cycle
+        let (server, device) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        server.respond("/api", FakeResponse::json(&fixture("api-p1.json")));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = config_with_token(&server, "2A4F0C9B");
+        homewizard_client
+            .fetch_device(&config, &device)
+            .unwrap()
+            .1
+            .unwrap();
+        let first_cycle_requests = server.requests().len();
+
+        // act
+        let result = homewizard_client.fetch_device(&config, &device);
+
+        assert!(result
+            .err()
+            .unwrap()
+            .to_string()
+            .ends_with("instead of expected serial 3c39e72e33ce, skipping it"));
+        let requests = server.requests();
+        let second_cycle_requests = &requests[first_cycle_requests..];
+        assert_eq!(second_cycle_requests.len(), 1);
+        assert_eq!(second_cycle_requests[0].path, "/api");
+        assert_eq!(second_cycle_requests[0].header("authorization"), None);
+    }
+
+    #[test]
+    fn get_samples_fails_with_device_error_when_device_rejects_token() {
+        let (server, device) = fake_device(&fixture("api-socket.json"), "{}");
+        server.respond("/api/v1/data", FakeResponse::status(401));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = config_with_token(&server, "2A4F0C9B");
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+
+        // act
+        let error = homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap_err();
+
+        assert!(error.is::<DeviceError>());
+        assert_eq!(
+            error.to_string(),
+            "Device failure: Device fake-device._hwenergy._tcp.local. rejected the token (401 Unauthorized)"
+        );
+    }
+
+    #[test]
+    fn get_samples_fails_with_device_error_when_device_requires_a_token() {
+        let (server, device) = fake_device(&fixture("api-socket.json"), "{}");
+        server.respond("/api/v1/data", FakeResponse::status(403));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+
+        // act
+        let error = homewizard_client
+            .get_samples(&Config::default(), &device, &device_info)
+            .unwrap_err();
+
+        assert!(error.is::<DeviceError>());
+        assert!(error
+            .to_string()
+            .ends_with("requires a token, configure one on its entry in devices (403 Forbidden)"));
+    }
+
+    #[test]
+    fn get_device_info_fails_on_error_status_with_json_body() {
+        let (server, device) = fake_device("{}", "{}");
+        server.respond(
+            "/api",
+            FakeResponse {
+                status: 503,
+                ..FakeResponse::json("{}")
+            },
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+
+        // act
+        let error = homewizard_client.get_device_info(&device).unwrap_err();
+
+        assert!(error.to_string().contains("503"));
+    }
+
+    #[test]
+    fn base_url_uses_port_of_device() {
+        let device = |port| HomewizardDevice {
+            fullname: "energysocket-3C39E7._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::new(192, 168, 1, 31)].into_iter().collect(),
+            port,
+            hostname: Some("energysocket-3c39e7.local.".into()),
+            api_path: None,
+        };
+
+        // act
+        let default_port = device(DEFAULT_PORT).base_url().unwrap();
+        let advertised_port = device(443).base_url().unwrap();
+
+        assert_eq!(default_port, "http://192.168.1.31:80");
+        assert_eq!(advertised_port, "https://192.168.1.31:443");
+    }
+
+    #[test]
+    fn friendly_name_sanitizes_product_name() {
+        let device_info = DeviceInfoResponse {
+            product_type: "HWE-SKT".into(),
+            product_name: " Energy Socket\n".into(),
+            serial: "3c39e72e33ce".into(),
+            firmware_version: "3.02".into(),
+            api_version: "v1".into(),
+        };
+        let config = Config {
+            location: "My Home".into(),
+            name_max_length: Some(6),
+            ..Default::default()
+        };
+
+        // act
+        let name = friendly_name(&config, &device_info);
+
+        assert_eq!(name, "Energy");
+    }
+
+    /// Warnings of reading the P1 meter with gas, whose gas reading is from 2021-06-06 14:00:10
+    /// UTC, at `now`.
+    fn gas_clock_skew_logs(now: &'static str) -> String {
+        let (_server, device) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(move || now.parse().unwrap()),
+                Arc::new(|| "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".to_string()),
+            ),
+        );
+        let config = Config {
+            location: "My Home".into(),
+            gas_clock_skew: Some(GasClockSkewConfig {
+                threshold_minutes: 120,
+                utc_offset_minutes: Some(0),
+            }),
+            ..Default::default()
+        };
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+
+        capture_logs("warn", || {
+            homewizard_client
+                .get_samples(&config, &device, &device_info)
+                .unwrap();
+        })
+    }
+
+    #[test]
+    fn get_samples_accepts_gas_reading_within_threshold() {
+        // act
+        let logs = gas_clock_skew_logs("2021-06-06T15:00:00Z");
+
+        assert!(logs.is_empty(), "unexpected warnings: {}", logs);
+    }
+
+    #[test]
+    fn get_samples_warns_about_stale_gas_reading() {
+        // act
+        let logs = gas_clock_skew_logs("2021-06-07T14:00:10Z");
+
+        assert!(logs.contains("Gas reading is stale"));
+        assert!(logs.contains(r#""skew_minutes":1440"#));
+        assert!(logs.contains("5c2faf0a8b3e"));
+    }
+
+    #[test]
+    fn get_samples_warns_about_future_dated_gas_reading() {
+        // act
+        let logs = gas_clock_skew_logs("2021-06-06T11:00:10Z");
+
+        assert!(logs.contains("Gas reading is dated in the future; the meter clock is wrong"));
+        assert!(logs.contains(r#""skew_minutes":180"#));
+    }
+
+    #[test]
+    fn get_measurements_moves_every_device_sample_into_the_measurement_once() {
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_watermeter_server, mut watermeter) = fake_device(
+            &fixture("api-watermeter.json"),
+            &fixture("data-watermeter-usb.json"),
+        );
+        watermeter.fullname = "watermeter-3C39E7._hwenergy._tcp.local.".into();
+        let homewizard_client =
+            HomewizardClient::new(HomewizardClientConfig::default()).with_discoverer(Box::new(
+                StaticDiscoverer::new(vec![socket.clone(), watermeter.clone()]),
+            ));
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let expected: Vec<Vec<Sample>> = [&socket, &watermeter]
+            .iter()
+            .map(|device| {
+                let device_info = homewizard_client.get_device_info(device).unwrap();
+                homewizard_client
+                    .get_samples(&config, device, &device_info)
+                    .unwrap()
+            })
+            .collect();
+
+        // act
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        // samples don't implement PartialEq
+        assert_eq!(
+            serde_json::to_value(&measurements[0].samples).unwrap(),
+            serde_json::to_value(expected.concat()).unwrap()
+        );
+        let summary = homewizard_client.summary_handle().lock().unwrap().clone();
+        let ranges: Vec<Range<usize>> = summary
+            .unwrap()
+            .measured_devices
+            .into_iter()
+            .map(|measured_device| measured_device.samples)
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                0..expected[0].len(),
+                expected[0].len()..expected[0].len() + expected[1].len()
+            ]
+        );
+    }
+
+    #[test]
+    fn get_measurements_overlaps_info_and_data_requests_once_the_device_is_known() {
+        let latency = Duration::from_millis(300);
+        let server = FakeDeviceServer::start();
+        server
+            .respond(
+                "/api",
+                FakeResponse::json(&fixture("api-socket.json")).delayed(latency),
+            )
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")).delayed(latency),
+            );
+        let socket = HomewizardDevice {
+            fullname: "energysocket-3C39E7._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let config = || Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        let latency_ms = || {
+            homewizard_client
+                .registry_handle()
+                .lock()
+                .unwrap()
+                .get("energysocket-3C39E7._hwenergy._tcp.local.")
+                .unwrap()
+                .last_fetch
+                .latency_ms
+        };
+
+        // act
+        homewizard_client.get_measurements(config(), None).unwrap();
+        let first_cycle_ms = latency_ms();
+        let second_cycle = homewizard_client.get_measurements(config(), None).unwrap();
+        let second_cycle_ms = latency_ms();
+
+        // two sequential round-trips for an unknown device, one for a known one
+        assert!(
+            first_cycle_ms >= 600,
+            "first cycle took {} ms",
+            first_cycle_ms
+        );
+        assert!(
+            (300..550).contains(&second_cycle_ms),
+            "second cycle took {} ms instead of about one round-trip, down from {} ms",
+            second_cycle_ms,
+            first_cycle_ms
+        );
+        assert_eq!(second_cycle[0].samples.len(), 3);
+        assert_eq!(server.request_count("/api"), 2);
+        assert_eq!(server.request_count("/api/v1/data"), 2);
+    }
+
+    #[test]
+    fn get_measurements_requests_data_again_when_the_device_changed_type() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api", FakeResponse::json(&fixture("api-socket.json")))
+            .respond("/api", FakeResponse::json(&fixture("api-p1.json")))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")),
+            )
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-p1-with-gas.json")),
+            );
+        let device = HomewizardDevice {
+            fullname: "fake-device._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![device])));
+        let config = || Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        homewizard_client.get_measurements(config(), None).unwrap();
+
+        // act
+        let measurements = homewizard_client.get_measurements(config(), None).unwrap();
+
+        // the speculative read as a socket parses, but gets dropped for the p1 meter's read
+        assert_eq!(measurements[0].samples.len(), 5);
+        assert!(measurements[0]
+            .samples
+            .iter()
+            .all(|sample| sample.entity_name == "HWE-P1"));
+        assert_eq!(server.request_count("/api/v1/data"), 3);
+    }
+
+    #[test]
+    fn fetch_device_keeps_device_error_of_speculative_read() {
+        let (server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        server.respond("/api/v1/data", FakeResponse::status(401));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        homewizard_client
+            .fetch_device(&config, &socket)
+            .unwrap()
+            .1
+            .unwrap();
+
+        // act
+        let (_, samples) = homewizard_client.fetch_device(&config, &socket).unwrap();
+
+        let error = samples.unwrap_err();
+        assert!(error.is::<DeviceError>(), "unexpected error: {}", error);
+        assert!(error.to_string().contains("requires a token"));
+    }
+
+    #[test]
+    fn fetch_device_leaves_nothing_behind_for_speculative_read_of_replaced_device() {
+        let (server, p1_meter) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        server.respond(
+            "/api",
+            FakeResponse::json(&fixture("api-p1.json").replace("5c2faf0a8b3e", "5c2faf0a8b3f")),
+        );
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(|| "2021-06-07T14:00:10Z".parse().unwrap()),
+                Arc::new(|| "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".to_string()),
+            ),
+        );
+        let config = Config {
+            location: "My Home".into(),
+            gas_clock_skew: Some(GasClockSkewConfig {
+                threshold_minutes: 120,
+                utc_offset_minutes: Some(0),
+            }),
+            ..Default::default()
+        };
+        homewizard_client
+            .fetch_device(&config, &p1_meter)
+            .unwrap()
+            .1
+            .unwrap();
+
+        // act
+        let logs = capture_logs("warn", || {
+            homewizard_client
+                .fetch_device(&config, &p1_meter)
+                .unwrap()
+                .1
+                .unwrap();
+        });
+
+        // the speculative read for the replaced meter's serial gets dropped unnoticed
+        assert_eq!(logs.matches("Gas reading is stale").count(), 1);
+        assert!(logs.contains("5c2faf0a8b3f"));
+        assert!(!logs.contains("5c2faf0a8b3e"));
+        assert_eq!(server.request_count("/api/v1/data"), 3);
+    }
+
+    #[test]
+    fn get_measurements_warns_periodically_about_configured_serials_never_matched() {
+        let (_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let config = || Config {
+            location: "My Home".into(),
+            names: vec![
+                ("3c39e72e33ce".to_string(), "Bonenmaler".to_string()),
+                ("aabbccddeeff".to_string(), "Sold".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            unmatched_serials: Some(UnmatchedSerialsConfig { every_cycles: 3 }),
+            ..Default::default()
+        };
+
+        // act
+        let logs = capture_logs("warn", || {
+            for _ in 0..5 {
+                homewizard_client.get_measurements(config(), None).unwrap();
+            }
+        });
+
+        let warnings: Vec<&str> = logs
+            .lines()
+            .filter(|line| line.contains("Configured serials never matched a device"))
+            .collect();
+        assert_eq!(warnings.len(), 1);
+        let warning: serde_json::Value = serde_json::from_str(warnings[0]).unwrap();
+        assert_eq!(warning["fields"]["unmatched_serials"], "[\"aabbccddeeff\"]");
+        assert_eq!(warning["fields"]["cycles"], 3);
+    }
+
+    #[test]
+    fn get_measurements_warns_about_samples_missing_from_device() {
+        let (_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let expected = ExpectedSamples::Names(vec![
+            "bonenmaler/electricity-consumption/gauge".into(),
+            "bonenmaler/electricity-production/gauge".into(),
+        ]);
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            expected_samples: vec![("3c39e72e33ce".to_string(), expected)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        // act
+        let logs = capture_logs("warn", || {
+            homewizard_client.get_measurements(config, None).unwrap();
+        });
+
+        let warning: serde_json::Value = serde_json::from_str(
+            logs.lines()
+                .find(|line| line.contains("Device produced fewer samples than expected"))
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(warning["fields"]["serial"], "3c39e72e33ce");
+        assert_eq!(warning["fields"]["expected_samples"], 2);
+        assert_eq!(warning["fields"]["actual_samples"], 1);
+        assert_eq!(
+            warning["fields"]["missing_samples"],
+            "[\"bonenmaler/electricity-production/gauge\"]"
+        );
+    }
+
+    /// Measures an energy socket advertising `api_path` once, returning the paths requested.
+    fn paths_requested_from_socket(api_path: Option<&str>) -> Vec<String> {
+        let data = fixture("data-socket-firmware-3.json");
+        let (server, mut socket) = fake_device(&fixture("api-socket.json"), &data);
+        server.respond("/api/v2/data", FakeResponse::json(&data));
+        socket.api_path = api_path.map(String::from);
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+        assert_eq!(measurements[0].samples.len(), 3);
+
+        server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect()
+    }
+
+    #[test]
+    fn get_measurements_requests_data_below_advertised_v1_path() {
+        // act
+        let paths = paths_requested_from_socket(Some("/api/v1"));
+
+        assert_eq!(paths, vec!["/api", "/api/v1/data"]);
+    }
+
+    #[test]
+    fn get_measurements_requests_data_below_advertised_v2_style_path() {
+        // act
+        let paths = paths_requested_from_socket(Some("/api/v2"));
+
+        assert_eq!(paths, vec!["/api", "/api/v2/data"]);
+    }
+
+    #[test]
+    fn get_measurements_requests_data_below_api_version_without_advertised_path() {
+        // act
+        let paths = paths_requested_from_socket(None);
+
+        assert_eq!(paths, vec!["/api", "/api/v1/data"]);
+    }
+
+    fn parents_location(serials: Vec<String>, devices: Vec<SocketAddrV4>) -> Config {
+        Config {
+            location: "My Home".into(),
+            locations: vec![LocationConfig {
+                location: "Parents".into(),
+                serials,
+                devices: devices.into_iter().map(Into::into).collect(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn get_measurements_publishes_a_measurement_per_location() {
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_p1_server, mut p1_meter) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        p1_meter.fullname = "p1-meter._hwenergy._tcp.local.".into();
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket, p1_meter])));
+        let config = parents_location(vec!["5c2faf0a8b3e".into()], vec![]);
+
+        // act
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        let locations: Vec<(&str, usize)> = measurements
+            .iter()
+            .map(|m| (m.location.as_str(), m.samples.len()))
+            .collect();
+        assert_eq!(locations, vec![("My Home", 3), ("Parents", 5)]);
+        let summary = homewizard_client.summary_handle().lock().unwrap().clone();
+        let parents_devices = summary.unwrap().measured_devices_of(&measurements[1].id);
+        assert_eq!(parents_devices.len(), 1);
+        assert_eq!(parents_devices[0].info.serial, "5c2faf0a8b3e");
+        assert_eq!(parents_devices[0].samples, 0..5);
+    }
+
+    /// A client discovering a P1 meter for the main location, and a config with the socket at
+    /// `socket_server` pinned to `expected_serial` for the parents.
+    fn pinned_socket(
+        socket_server: &FakeDeviceServer,
+        p1_meter: HomewizardDevice,
+        expected_serial: &str,
+        strict: bool,
+    ) -> (HomewizardClient, Config) {
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![p1_meter])));
+        let config = Config {
+            location: "My Home".into(),
+            locations: vec![LocationConfig {
+                location: "Parents".into(),
+                serials: vec![],
+                devices: vec![StaticDevice::Pinned {
+                    address: SocketAddrV4::new(Ipv4Addr::LOCALHOST, socket_server.address().port()),
+                    serial: expected_serial.into(),
+                }],
+            }],
+            strict,
+            ..Default::default()
+        };
+
+        (homewizard_client, config)
+    }
+
+    #[test]
+    fn get_measurements_reads_pinned_device_with_expected_serial() {
+        let (socket_server, _) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_p1_server, p1_meter) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let (homewizard_client, config) =
+            pinned_socket(&socket_server, p1_meter, "3c39e72e33ce", true);
+
+        // act
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        let parents = measurements
+            .iter()
+            .find(|m| m.location == "Parents")
+            .unwrap();
+        assert_eq!(parents.samples.len(), 3);
+    }
+
+    #[test]
+    fn get_measurements_skips_pinned_device_with_other_serial_with_warning() {
+        let (socket_server, _) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_p1_server, p1_meter) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let (homewizard_client, config) =
+            pinned_socket(&socket_server, p1_meter, "aabbccddeeff", false);
+
+        // act
+        let mut measurements = None;
+        let logs = capture_logs("warn", || {
+            measurements = Some(homewizard_client.get_measurements(config, None).unwrap())
+        });
+
+        assert!(!measurements
+            .unwrap()
+            .iter()
+            .any(|m| m.location == "Parents" && !m.samples.is_empty()));
+        assert!(logs.contains("has serial 3c39e72e33ce instead of expected serial aabbccddeeff"));
+        assert_eq!(socket_server.request_count("/api/v1/data"), 0);
+    }
+
+    #[test]
+    fn get_measurements_fails_on_pinned_device_with_other_serial_in_strict_mode() {
+        let (socket_server, _) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_p1_server, p1_meter) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let (homewizard_client, config) =
+            pinned_socket(&socket_server, p1_meter, "aabbccddeeff", true);
+
+        // act
+        let mut result = None;
+        let logs = capture_logs("error", || {
+            result = Some(homewizard_client.get_measurements(config, None))
+        });
+
+        assert!(result.unwrap().unwrap_err().is::<DeviceError>());
+        assert!(logs.contains("has serial 3c39e72e33ce instead of expected serial aabbccddeeff"));
+    }
+
+    #[test]
+    fn get_measurements_keeps_publishing_locations_next_to_a_failing_one() {
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let failing_server = FakeDeviceServer::start();
+        failing_server.respond("/api", FakeResponse::status(500));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let config = parents_location(
+            vec![],
+            vec![SocketAddrV4::new(
+                Ipv4Addr::LOCALHOST,
+                failing_server.address().port(),
+            )],
+        );
+
+        // act
+        let mut measurements = vec![];
+        let logs = capture_logs("warn", || {
+            measurements = homewizard_client.get_measurements(config, None).unwrap();
+        });
+
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].location, "My Home");
+        assert_eq!(measurements[0].samples.len(), 3);
+        assert!(logs.contains("No samples read from any of the 1 devices of location Parents"));
+        assert_eq!(failing_server.request_count("/api"), 1);
+    }
+
+    #[test]
+    fn get_measurements_derives_gas_flow_from_successive_gas_readings() {
+        let data = fixture("data-p1-with-gas.json");
+        let (server, p1_meter) = fake_device(&fixture("api-p1.json"), &data);
+        let mut next_reading: serde_json::Value = serde_json::from_str(&data).unwrap();
+        next_reading["total_gas_m3"] = 2569.746.into();
+        next_reading["gas_timestamp"] = 210606140510u64.into();
+        server.respond(
+            "/api/v1/data",
+            FakeResponse::json(&next_reading.to_string()),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![p1_meter])));
+        let config = || Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                gas_flow: Some(GasFlowConfig {
+                    name: "gas flow".into(),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let gas_flows: Vec<Vec<Sample>> = (0..3)
+            .map(|_| {
+                homewizard_client.get_measurements(config(), None).unwrap()[0]
+                    .samples
+                    .iter()
+                    .filter(|sample| sample.sample_type == SampleType::GasConsumption)
+                    .cloned()
+                    .collect()
+            })
+            .collect();
+
+        // no flow in the first cycle, nor once the reading stays the same
+        assert!(gas_flows[0].is_empty());
+        assert_eq!(gas_flows[1].len(), 1);
+        assert_eq!(gas_flows[1][0].sample_name, "P1 meter gas flow");
+        assert_eq!(gas_flows[1][0].metric_type, MetricType::Gauge);
+        assert!((gas_flows[1][0].value - 1.2).abs() < 1e-6);
+        assert!(gas_flows[2].is_empty());
+    }
+
+    #[test]
+    fn get_samples_for_p1_meter_names_external_meters_by_unique_id() {
+        let (_server, device) = fake_device(
+            &fixture("api-p1.json"),
+            &fixture("data-p1-with-external.json"),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![(
+                "FFEEDDCCBBAA99887766554433221100".to_string(),
+                "Gas".to_string(),
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+
+        // act
+        let samples = homewizard_client
+            .get_samples(&config, &device, &device_info)
+            .unwrap();
+
+        let externals: Vec<(&str, SampleType, f64)> = samples
+            .iter()
+            .filter(|sample| {
+                matches!(sample.entity_type, EntityType::Device)
+                    && sample.metric_type == MetricType::Counter
+            })
+            .map(|sample| {
+                (
+                    sample.sample_name.as_str(),
+                    sample.sample_type.clone(),
+                    sample.value,
+                )
+            })
+            .collect();
+        assert_eq!(
+            externals,
+            vec![
+                ("Gas", SampleType::GasConsumption, 2569.646),
+                ("water meter 110011", SampleType::WaterConsumption, 123.456),
+            ]
+        );
+    }
+
+    /// Entity names of the samples of two energy sockets, the first one named in `config`, by
+    /// sample name.
+    fn entity_names_of_sockets(config: Config) -> Vec<(String, String)> {
+        let (_first_server, first) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_second_server, mut second) = fake_device(
+            &fixture("api-socket.json").replace("3c39e72e33ce", "aabbccddeeff"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        second.fullname = "energysocket-AABBCC._hwenergy._tcp.local.".into();
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![first, second])));
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            ..config
+        };
+
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        let mut entity_names: Vec<(String, String)> = measurements[0]
+            .samples
+            .iter()
+            .map(|sample| (sample.sample_name.clone(), sample.entity_name.clone()))
+            .collect();
+        assert_eq!(entity_names.len(), 6);
+        entity_names.sort();
+        entity_names.dedup();
+        entity_names
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(sample_name, entity_name)| (sample_name.to_string(), entity_name.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn get_measurements_uses_product_type_as_entity_name_by_default() {
+        // act
+        let entity_names = entity_names_of_sockets(Config::default());
+
+        assert_eq!(
+            entity_names,
+            pairs(&[("Bonenmaler", "HWE-SKT"), ("Energy Socket", "HWE-SKT")])
+        );
+    }
+
+    #[test]
+    fn get_measurements_uses_serial_as_entity_name() {
+        // act
+        let entity_names = entity_names_of_sockets(Config {
+            entity_name: EntityNameSource::Serial,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            entity_names,
+            pairs(&[
+                ("Bonenmaler", "3c39e72e33ce"),
+                ("Energy Socket", "aabbccddeeff")
+            ])
+        );
+    }
+
+    #[test]
+    fn get_measurements_uses_friendly_name_as_entity_name() {
+        // act
+        let entity_names = entity_names_of_sockets(Config {
+            entity_name: EntityNameSource::FriendlyName,
+            ..Default::default()
+        });
+
+        assert_eq!(
+            entity_names,
+            pairs(&[
+                ("Bonenmaler", "Bonenmaler"),
+                ("Energy Socket", "Energy Socket")
+            ])
+        );
+    }
+
+    #[test]
+    fn get_measurements_uses_entity_name_of_device_over_global_option() {
+        // act
+        let entity_names = entity_names_of_sockets(Config {
+            entity_name: EntityNameSource::Serial,
+            entity_names: vec![("3c39e72e33ce".to_string(), EntityNameSource::ProductType)]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            entity_names,
+            pairs(&[("Bonenmaler", "HWE-SKT"), ("Energy Socket", "aabbccddeeff")])
+        );
+    }
+
+    #[test]
+    fn get_measurements_cuts_off_devices_at_cycle_deadline() {
+        let (_fast_server, fast) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let slow_server = FakeDeviceServer::start();
+        slow_server
+            .respond("/api", FakeResponse::json(&fixture("api-watermeter.json")))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-watermeter-usb.json"))
+                    .delayed(Duration::from_secs(3)),
+            );
+        let slow = HomewizardDevice {
+            fullname: "watermeter-2D7A68._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: slow_server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let (cut_off_server, mut cut_off) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        cut_off.fullname = "p1meter-3C4D5E._hwenergy._tcp.local.".into();
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_cycle_deadline(Duration::from_millis(500)),
+        )
+        .with_discoverer(Box::new(StaticDiscoverer::new(vec![fast, slow, cut_off])));
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        let start = Instant::now();
+
+        // act
+        let mut measurements = None;
+        let logs = capture_logs("warn", || {
+            measurements = Some(homewizard_client.get_measurements(config, None).unwrap());
+        });
+
+        assert!(start.elapsed() < Duration::from_secs(2));
+        let measurements = measurements.unwrap();
+        assert_eq!(measurements[0].samples.len(), 3);
+        assert_eq!(measurements[0].samples[0].entity_name, "HWE-SKT");
+        assert_eq!(cut_off_server.request_count("/api"), 0);
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(summary.failed_devices, vec!["3c39e72d7a68".to_string()]);
+        assert_eq!(
+            summary.cut_off_devices,
+            vec!["p1meter-3C4D5E._hwenergy._tcp.local.".to_string()]
+        );
+        assert!(logs.contains("Cycle deadline of 500ms passed, cut off 1 devices"));
+        assert!(logs.contains("p1meter-3C4D5E._hwenergy._tcp.local."));
+    }
+
+    #[test]
+    fn get_measurements_counts_fetch_failures_across_cycles() {
+        let socket_server = FakeDeviceServer::start();
+        socket_server
+            .respond("/api", FakeResponse::json(&fixture("api-socket.json")))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")),
+            )
+            .respond("/api/v1/data", FakeResponse::status(500))
+            .respond("/api/v1/data", FakeResponse::status(500))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")),
+            );
+        let socket = HomewizardDevice {
+            fullname: "energysocket-1A2B3C._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: socket_server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket])));
+        let config = || Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            derived: Some(DerivedConfig {
+                fetch_failures: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let counts: Vec<Vec<(String, String, f64)>> = (0..5)
+            .map(|_| {
+                homewizard_client.get_measurements(config(), None).unwrap()[0]
+                    .samples
+                    .iter()
+                    .filter(|sample| derived::is_fetch_failures(sample))
+                    .map(|sample| {
+                        (
+                            sample.entity_name.clone(),
+                            sample.sample_name.clone(),
+                            sample.value,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let count = |value| {
+            vec![(
+                "3c39e72e33ce".to_string(),
+                "Bonenmaler fetch failures".to_string(),
+                value,
+            )]
+        };
+        assert_eq!(
+            counts,
+            vec![count(0.0), count(1.0), count(2.0), count(2.0), count(2.0)]
+        );
+    }
+
+    #[test]
+    fn get_measurements_drops_samples_over_max_samples_per_measurement() {
+        let (_first_server, first) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_second_server, mut second) = fake_device(
+            &fixture("api-socket.json").replace("3c39e72e33ce", "aabbccddeeff"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        second.fullname = "energysocket-AABBCC._hwenergy._tcp.local.".into();
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![second, first])));
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            max_samples_per_measurement: Some(4),
+            ..Default::default()
+        };
+
+        // act
+        let mut measurements = None;
+        let logs = capture_logs("warn", || {
+            measurements = Some(homewizard_client.get_measurements(config, None).unwrap());
+        });
+
+        let samples = &measurements.unwrap()[0].samples;
+        let names: Vec<&str> = samples
+            .iter()
+            .map(|sample| sample.sample_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            vec!["Bonenmaler", "Bonenmaler", "Bonenmaler", "dropped samples"]
+        );
+        assert_eq!(samples[3].value, 3.0);
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(summary.samples_dropped, 3);
+        let ranges: Vec<Range<usize>> = summary
+            .measured_devices
+            .iter()
+            .map(|device| device.samples.clone())
+            .collect();
+        assert_eq!(ranges, vec![0..0, 0..3]);
+        assert!(
+            logs.contains("Measurement exceeds maxSamplesPerMeasurement of 4, dropped 3 samples")
+        );
+        assert!(logs.contains("HWE-SKT/energy-socket/electricity-consumption/counter"));
+    }
+
+    #[test]
+    fn get_measurements_splits_counters_and_gauges_when_configured() {
+        let (_server, device) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![device])));
+        let config = Config {
+            location: "My Home".into(),
+            split_by_metric_type: true,
+            ..Default::default()
+        };
+
+        // act
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        let sources: Vec<&str> = measurements.iter().map(|m| m.source.as_str()).collect();
+        assert_eq!(
+            sources,
+            vec![
+                "jarvis-homewizard-exporter-counters",
+                "jarvis-homewizard-exporter-gauges"
+            ]
+        );
+        assert_ne!(measurements[0].id, measurements[1].id);
+        assert_eq!(measurements[0].location, measurements[1].location);
+        assert_eq!(
+            measurements[0].measured_at_time,
+            measurements[1].measured_at_time
+        );
+        assert!(measurements[0]
+            .samples
+            .iter()
+            .all(|sample| sample.metric_type == MetricType::Counter));
+        assert!(measurements[1]
+            .samples
+            .iter()
+            .all(|sample| sample.metric_type == MetricType::Gauge));
+        assert_eq!(
+            measurements[0].samples.len() + measurements[1].samples.len(),
+            5
+        );
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        for measurement in &measurements {
+            let devices = summary.measured_devices_of(&measurement.id);
+            assert_eq!(devices.len(), 1);
+            assert_eq!(devices[0].samples, 0..measurement.samples.len());
+        }
+    }
+
+    /// A working energy socket next to a P1 meter whose data endpoint fails.
+    fn fleet_with_failing_p1_meter() -> (Vec<FakeDeviceServer>, HomewizardClient) {
+        let (socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let p1_server = FakeDeviceServer::start();
+        p1_server
+            .respond("/api", FakeResponse::json(&fixture("api-p1.json")))
+            .respond("/api/v1/data", FakeResponse::status(500));
+        let p1_meter = HomewizardDevice {
+            fullname: "p1meter-0A8B3E._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: p1_server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket, p1_meter])));
+
+        (vec![socket_server, p1_server], homewizard_client)
+    }
+
+    fn strict_config(names: Vec<(&str, &str)>, strict: bool) -> Config {
+        Config {
+            location: "My Home".into(),
+            names: names
+                .into_iter()
+                .map(|(serial, name)| (serial.to_string(), name.to_string()))
+                .collect(),
+            strict,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn get_measurements_fails_on_configured_device_failure_in_strict_mode() {
+        let (_servers, homewizard_client) = fleet_with_failing_p1_meter();
+        let config = strict_config(vec![("5c2faf0a8b3e", "P1 meter")], true);
+
+        // act
+        let mut result = None;
+        let logs = capture_logs("error", || {
+            result = Some(homewizard_client.get_measurements(config, None))
+        });
+
+        let error = result.unwrap().unwrap_err();
+        assert!(error.is::<DeviceError>());
+        assert_eq!(
+            error.to_string(),
+            "Not publishing a partial measurement in strict mode, 1 devices failed: 5c2faf0a8b3e"
+        );
+        assert!(logs.contains("Device 5c2faf0a8b3e failed in strict mode"));
+    }
+
+    #[test]
+    fn get_measurements_exempts_unconfigured_device_failure_in_strict_mode() {
+        let (_servers, homewizard_client) = fleet_with_failing_p1_meter();
+        let including_unconfigured = Config {
+            strict_include_unconfigured: true,
+            ..strict_config(vec![("3c39e72e33ce", "Bonenmaler")], true)
+        };
+
+        // act
+        let exempted = homewizard_client.get_measurements(
+            strict_config(vec![("3c39e72e33ce", "Bonenmaler")], true),
+            None,
+        );
+        let included = homewizard_client.get_measurements(including_unconfigured, None);
+
+        assert_eq!(exempted.unwrap()[0].samples.len(), 3);
+        assert!(included.is_err());
+    }
+
+    #[test]
+    fn get_measurements_publishes_when_all_devices_succeed_in_strict_mode() {
+        let (_server, device) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![device])));
+        let config = strict_config(vec![("3c39e72e33ce", "Bonenmaler")], true);
+
+        // act
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].samples.len(), 3);
+    }
+
+    #[test]
+    fn get_measurements_publishes_partial_measurement_without_strict_mode() {
+        let (_servers, homewizard_client) = fleet_with_failing_p1_meter();
+        let config = strict_config(vec![("5c2faf0a8b3e", "P1 meter")], false);
+
+        // act
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].samples.len(), 3);
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+        assert_eq!(summary.failed_devices, vec!["5c2faf0a8b3e".to_string()]);
+    }
+
+    #[test]
+    fn get_measurements_carries_forward_counters_of_failing_device() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api", FakeResponse::json(&fixture("api-watermeter.json")))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-watermeter-usb.json")),
+            )
+            .respond("/api/v1/data", FakeResponse::status(500));
+        let water_meter = HomewizardDevice {
+            fullname: "watermeter-2D7A68._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![water_meter])));
+        let config = || Config {
+            location: "My Home".into(),
+            on_empty: OnEmpty::Heartbeat,
+            carry_forward: Some(CarryForwardConfig { cycles: 2 }),
+            ..Default::default()
+        };
+
+        // act
+        let cycles: Vec<Vec<(MetricType, f64)>> = (0..4)
+            .map(|_| {
+                let measurements = homewizard_client.get_measurements(config(), None).unwrap();
+                measurements[0]
+                    .samples
+                    .iter()
+                    .map(|sample| (sample.metric_type.clone(), sample.value))
+                    .collect()
+            })
+            .collect();
+
+        assert_eq!(
+            cycles,
+            vec![
+                vec![
+                    (MetricType::Counter, 123.456),
+                    (MetricType::Gauge, 7.5 * 60.0 / 1000.0)
+                ],
+                vec![(MetricType::Counter, 123.456)],
+                vec![(MetricType::Counter, 123.456)],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn get_measurements_leaves_out_unchanged_samples_within_deadband() {
+        let (_server, device) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![device])));
+        let config = || Config {
+            location: "My Home".into(),
+            deadband: Some(DeadbandConfig {
+                threshold: DeadbandThreshold {
+                    absolute: Some(1.0),
+                    percentage: None,
+                },
+                sample_types: HashMap::new(),
+                full_publish_every_cycles: Some(3),
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let cycles: Vec<usize> = (0..3)
+            .map(|_| {
+                homewizard_client
+                    .get_measurements(config(), None)
+                    .unwrap()
+                    .iter()
+                    .map(|measurement| measurement.samples.len())
+                    .sum()
+            })
+            .collect();
+
+        assert_eq!(cycles, vec![3, 0, 3]);
+    }
+
+    #[test]
+    fn get_measurements_compares_rounded_values_within_deadband() {
+        let (server, device) = fake_device(
+            &fixture("api-watermeter.json"),
+            r#"{"wifi_ssid": "My Wi-Fi", "wifi_strength": 84, "total_liter_m3": 431.0004, "active_liter_lpm": 0.0, "total_liter_offset_m3": 0.0}"#,
+        );
+        for total_m3 in ["431.0006", "431.0007"] {
+            server.respond(
+                "/api/v1/data",
+                FakeResponse::json(&format!(
+                    r#"{{"wifi_ssid": "My Wi-Fi", "wifi_strength": 84, "total_liter_m3": {}, "active_liter_lpm": 0.0, "total_liter_offset_m3": 0.0}}"#,
+                    total_m3
+                )),
+            );
+        }
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![device])));
+        let config = || Config {
+            location: "My Home".into(),
+            deadband: Some(DeadbandConfig {
+                threshold: DeadbandThreshold {
+                    absolute: Some(0.0005),
+                    percentage: None,
+                },
+                sample_types: HashMap::new(),
+                full_publish_every_cycles: None,
+            }),
+            precision: Some(PrecisionConfig {
+                decimals: None,
+                sample_types: vec![("water-consumption".to_string(), 3)]
+                    .into_iter()
+                    .collect(),
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let totals: Vec<Vec<f64>> = (0..3)
+            .map(|_| {
+                homewizard_client
+                    .get_measurements(config(), None)
+                    .unwrap()
+                    .iter()
+                    .flat_map(|measurement| measurement.samples.iter())
+                    .filter(|sample| matches!(sample.metric_type, MetricType::Counter))
+                    .map(|sample| sample.value)
+                    .collect()
+            })
+            .collect();
+
+        // unrounded, the second total changed by less than the deadband
+        assert_eq!(totals, vec![vec![431.0], vec![431.001], vec![]]);
+    }
+
+    /// Measures a water meter whose total reads `total_m3` at `now`, with the exporter state in
+    /// `state_file`, returning the daily consumption of its total.
+    fn measure_daily_water_consumption(state_file: &Path, now: &'static str, total_m3: f64) -> f64 {
+        let (_server, device) = fake_device(
+            &fixture("api-watermeter.json"),
+            &format!(
+                r#"{{"wifi_ssid": "My Wi-Fi", "wifi_strength": 84, "total_liter_m3": {}, "active_liter_lpm": 0.0, "total_liter_offset_m3": 0.0}}"#,
+                total_m3
+            ),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig {
+            state_file_path: Some(state_file.to_path_buf()),
+            ..HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(move || now.parse().unwrap()),
+                Arc::new(|| "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".to_string()),
+            )
+        })
+        .with_discoverer(Box::new(StaticDiscoverer::new(vec![device])));
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                daily_consumption: Some(DailyConsumptionConfig {
+                    timezone: "Europe/Amsterdam".into(),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+        let daily: Vec<&Sample> = measurements[0]
+            .samples
+            .iter()
+            .filter(|sample| daily_consumption::is_daily_consumption(sample))
+            .collect();
+        assert_eq!(daily.len(), 1);
+        assert_eq!(daily[0].sample_name, "Watermeter today");
+
+        daily[0].value
+    }
+
+    #[test]
+    fn get_measurements_derives_daily_consumption_across_restarts_and_midnight() {
+        let state_file = env::temp_dir().join(format!("exporter-state-{}.json", Uuid::new_v4()));
+
+        // act
+        let first_reading =
+            measure_daily_water_consumption(&state_file, "2023-06-01T08:00:00Z", 100.0);
+        // every cycle starts from a new client, as after a restart
+        let mid_day = measure_daily_water_consumption(&state_file, "2023-06-01T12:00:00Z", 100.25);
+        let before_midnight =
+            measure_daily_water_consumption(&state_file, "2023-06-01T21:30:00Z", 100.5);
+        // 00:30 in Amsterdam
+        let after_midnight =
+            measure_daily_water_consumption(&state_file, "2023-06-01T22:30:00Z", 100.75);
+        let next_morning =
+            measure_daily_water_consumption(&state_file, "2023-06-02T06:00:00Z", 101.0);
+        let device_reset =
+            measure_daily_water_consumption(&state_file, "2023-06-02T07:00:00Z", 0.5);
+        let after_reset = measure_daily_water_consumption(&state_file, "2023-06-02T08:00:00Z", 1.0);
+
+        fs::remove_file(&state_file).unwrap();
+        assert_eq!(first_reading, 0.0);
+        assert_eq!(mid_day, 0.25);
+        assert_eq!(before_midnight, 0.5);
+        assert_eq!(after_midnight, 0.0);
+        assert_eq!(next_morning, 0.25);
+        assert_eq!(device_reset, 0.25);
+        assert_eq!(after_reset, 0.75);
+    }
+
+    /// Measures an energy socket whose import reads `import_kwh` at `now`, with the exporter
+    /// state in `state_file`, returning its import counters by time of use window.
+    fn measure_time_of_use(
+        state_file: &Path,
+        now: &'static str,
+        import_kwh: f64,
+    ) -> Vec<(String, f64)> {
+        let (_server, device) = fake_device(
+            &fixture("api-socket.json"),
+            &format!(
+                r#"{{"wifi_ssid": "My Wi-Fi", "wifi_strength": 100, "total_power_import_t1_kwh": {}, "total_power_export_t1_kwh": 0.0, "active_power_w": 98.1, "active_power_l1_w": 98.1}}"#,
+                import_kwh
+            ),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig {
+            state_file_path: Some(state_file.to_path_buf()),
+            ..HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(move || now.parse().unwrap()),
+                Arc::new(|| "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".to_string()),
+            )
+        })
+        .with_discoverer(Box::new(StaticDiscoverer::new(vec![device])));
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            derived: Some(DerivedConfig {
+                time_of_use: Some(TimeOfUseConfig {
+                    timezone: "Europe/Amsterdam".into(),
+                    windows: vec![
+                        TimeOfUseWindow {
+                            name: "off-peak".into(),
+                            days: vec!["saturday".into(), "sunday".into()],
+                            from: None,
+                            to: None,
+                        },
+                        TimeOfUseWindow {
+                            name: "off-peak".into(),
+                            days: vec![],
+                            from: Some("23:00".into()),
+                            to: Some("07:00".into()),
+                        },
+                    ],
+                    default_window: "peak".into(),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+        measurements[0]
+            .samples
+            .iter()
+            .filter(|sample| {
+                sample.sample_type == SampleType::ElectricityConsumption
+                    && sample.sample_name.ends_with("peak")
+            })
+            .map(|sample| (sample.sample_name.clone(), sample.value))
+            .collect()
+    }
+
+    #[test]
+    fn get_measurements_splits_socket_consumption_by_time_of_use_window() {
+        let state_file = env::temp_dir().join(format!("exporter-state-{}.json", Uuid::new_v4()));
+        let windows = |off_peak_kwh: f64, peak_kwh: f64| {
+            vec![
+                (
+                    "Bonenmaler off-peak".to_string(),
+                    kwh_to_joules(off_peak_kwh),
+                ),
+                ("Bonenmaler peak".to_string(), kwh_to_joules(peak_kwh)),
+            ]
+        };
+
+        // act, every cycle from a new client as after a restart; june 1st 2023 is a thursday
+        let thursday_noon = measure_time_of_use(&state_file, "2023-06-01T10:00:00Z", 10.0);
+        let thursday_evening = measure_time_of_use(&state_file, "2023-06-01T20:00:00Z", 11.0);
+        let thursday_night = measure_time_of_use(&state_file, "2023-06-01T21:30:00Z", 13.0);
+        let friday_morning = measure_time_of_use(&state_file, "2023-06-02T05:30:00Z", 13.5);
+        let saturday_noon = measure_time_of_use(&state_file, "2023-06-03T10:00:00Z", 14.5);
+
+        fs::remove_file(&state_file).unwrap();
+        assert_eq!(thursday_noon, windows(0.0, 0.0));
+        assert_eq!(thursday_evening, windows(0.0, 1.0));
+        assert_eq!(thursday_night, windows(2.0, 1.0));
+        assert_eq!(friday_morning, windows(2.0, 1.5));
+        assert_eq!(saturday_noon, windows(3.0, 1.5));
+    }
+
+    /// A second energy socket, on firmware 4, with a serial of its own.
+    fn second_socket_info() -> String {
+        let mut info: serde_json::Value =
+            serde_json::from_str(&fixture("api-socket.json")).unwrap();
+        info["serial"] = "3c39e72e44df".into();
+        info.to_string()
+    }
+
+    /// Measures the devices with the socket total configured, returning the samples and the
+    /// summary of the cycle.
+    fn measure_with_socket_total(
+        devices: Vec<HomewizardDevice>,
+        include_kwh_meters: bool,
+    ) -> (Vec<Sample>, CycleSummary) {
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(devices)));
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                socket_total: Some(SocketTotalConfig {
+                    entity_name: "sockets".into(),
+                    name: "appliances".into(),
+                    include_kwh_meters,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+        let summary = homewizard_client
+            .summary_handle()
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap();
+
+        (measurements[0].samples.clone(), summary)
+    }
+
+    #[test]
+    fn get_measurements_adds_socket_total_of_all_sockets() {
+        let (_first_server, first_socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_second_server, second_socket) = fake_device(
+            &second_socket_info(),
+            &fixture("data-socket-firmware-4.json"),
+        );
+        let (_water_meter_server, water_meter) = fake_device(
+            &fixture("api-watermeter.json"),
+            &fixture("data-watermeter-usb.json"),
+        );
+
+        // act
+        let (samples, summary) =
+            measure_with_socket_total(vec![first_socket, water_meter, second_socket], false);
+
+        assert_eq!(samples.len(), 9);
+        assert_samples(
+            &samples[8..],
+            &[sample(
+                EntityType::Device,
+                "sockets",
+                SampleType::ElectricityConsumption,
+                "appliances",
+                MetricType::Gauge,
+                98.1 + 1450.3,
+            )],
+        );
+        // the total belongs to none of the devices
+        assert_eq!(summary.measured_devices[2].samples, 5..8);
+        assert!(summary.excluded_from_socket_total.is_empty());
+    }
+
+    #[test]
+    fn get_measurements_excludes_failed_sockets_from_socket_total() {
+        let (_first_server, first_socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let failing_server = FakeDeviceServer::start();
+        failing_server
+            .respond("/api", FakeResponse::json(&second_socket_info()))
+            .respond("/api/v1/data", FakeResponse::status(500));
+        let failing_socket = HomewizardDevice {
+            fullname: "energysocket-3c39e72e44df._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: failing_server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let unreachable = HomewizardDevice {
+            fullname: "energysocket-1A2B3C._hwenergy._tcp.local.".into(),
+            ip_addresses: HashSet::new(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        };
+        let water_meter_server = FakeDeviceServer::start();
+        water_meter_server
+            .respond("/api", FakeResponse::json(&fixture("api-watermeter.json")))
+            .respond("/api/v1/data", FakeResponse::status(500));
+        let failing_water_meter = HomewizardDevice {
+            fullname: "watermeter-2D7A68._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: water_meter_server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+
+        // act
+        let (samples, summary) = measure_with_socket_total(
+            vec![
+                first_socket,
+                failing_socket,
+                unreachable,
+                failing_water_meter,
+            ],
+            false,
+        );
+
+        assert_eq!(samples.len(), 4);
+        assert_eq!(samples[3].sample_name, "appliances");
+        assert_eq!(samples[3].value, 98.1);
+        assert_eq!(summary.failed_devices.len(), 3);
+        assert_eq!(
+            summary.excluded_from_socket_total,
+            vec![
+                "3c39e72e44df".to_string(),
+                "energysocket-1A2B3C._hwenergy._tcp.local.".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn get_measurements_without_sockets_adds_no_socket_total() {
+        let (_water_meter_server, water_meter) = fake_device(
+            &fixture("api-watermeter.json"),
+            &fixture("data-watermeter-usb.json"),
+        );
+        let (_kwh_meter_server, kwh_meter) =
+            fake_device(&fixture("api-sdm230.json"), &fixture("data-sdm230.json"));
+
+        // act
+        let (samples, summary) = measure_with_socket_total(vec![water_meter, kwh_meter], false);
+
+        assert_eq!(samples.len(), 5);
+        assert!(samples
+            .iter()
+            .all(|sample| sample.sample_name != "appliances"));
+        assert!(summary.excluded_from_socket_total.is_empty());
+    }
+
+    #[test]
+    fn get_measurements_adds_kwh_meters_to_socket_total_when_configured() {
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let (_kwh_meter_server, kwh_meter) =
+            fake_device(&fixture("api-sdm230.json"), &fixture("data-sdm230.json"));
+
+        // act
+        let (samples, _) = measure_with_socket_total(vec![socket, kwh_meter], true);
+
+        assert_eq!(samples.len(), 7);
+        assert_eq!(samples[6].value, 98.1 + -1058.296);
+    }
+
+    /// A P1 meter reporting the given active power.
+    fn p1_meter_with_active_power(active_power_w: f64) -> (FakeDeviceServer, HomewizardDevice) {
+        let mut data: serde_json::Value =
+            serde_json::from_str(&fixture("data-p1-with-gas.json")).unwrap();
+        data["active_power_w"] = active_power_w.into();
+
+        fake_device(&fixture("api-p1.json"), &data.to_string())
+    }
+
+    fn measure_with_untracked_power(
+        devices: Vec<HomewizardDevice>,
+        clamp_at_zero: bool,
+    ) -> Vec<Sample> {
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(devices)));
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                untracked_power: Some(UntrackedPowerConfig {
+                    entity_name: "house".into(),
+                    name: "untracked".into(),
+                    clamp_at_zero,
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        homewizard_client.get_measurements(config, None).unwrap()[0]
+            .samples
+            .clone()
+    }
+
+    #[test]
+    fn get_measurements_adds_untracked_power_of_p1_meter_minus_sockets() {
+        let (_p1_server, p1_meter) = p1_meter_with_active_power(2500.0);
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+
+        // act
+        let samples = measure_with_untracked_power(vec![p1_meter, socket], true);
+
+        assert_samples(
+            &samples[samples.len() - 1..],
+            &[sample(
+                EntityType::Device,
+                "house",
+                SampleType::ElectricityConsumption,
+                "untracked",
+                MetricType::Gauge,
+                2500.0 - 98.1,
+            )],
+        );
+    }
+
+    #[test]
+    fn get_measurements_clamps_negative_untracked_power_at_zero() {
+        let (_p1_server, p1_meter) = p1_meter_with_active_power(50.0);
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+
+        // act
+        let samples = measure_with_untracked_power(vec![p1_meter, socket], true);
+
+        assert_eq!(samples.last().unwrap().sample_name, "untracked");
+        assert_eq!(samples.last().unwrap().value, 0.0);
+    }
+
+    #[test]
+    fn get_measurements_keeps_negative_untracked_power_without_clamping() {
+        let (_p1_server, p1_meter) = p1_meter_with_active_power(50.0);
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+
+        // act
+        let samples = measure_with_untracked_power(vec![p1_meter, socket], false);
+
+        assert_eq!(samples.last().unwrap().value, 50.0 - 98.1);
+    }
+
+    #[test]
+    fn get_measurements_skips_untracked_power_when_p1_meter_failed() {
+        let p1_server = FakeDeviceServer::start();
+        p1_server
+            .respond("/api", FakeResponse::json(&fixture("api-p1.json")))
+            .respond("/api/v1/data", FakeResponse::status(500));
+        let p1_meter = HomewizardDevice {
+            fullname: "p1meter-0A8B3E._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: p1_server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let (_socket_server, socket) = fake_device(
+            &fixture("api-socket.json"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+
+        // act
+        let samples = measure_with_untracked_power(vec![p1_meter, socket], true);
+
+        assert_eq!(samples.len(), 3);
+        assert!(samples.iter().all(|sample| sample.entity_name == "HWE-SKT"));
+    }
+
+    fn golden_measurement(devices: Vec<HomewizardDevice>) -> String {
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(|| "2023-06-01T12:00:00Z".parse().unwrap()),
+                Arc::new(|| "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".to_string()),
+            ),
+        )
+        .with_discoverer(Box::new(StaticDiscoverer::new(devices)));
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        let measurements = homewizard_client.get_measurements(config, None).unwrap();
+
+        serde_json::to_string_pretty(&measurements).unwrap()
+    }
+
+    #[test]
+    fn get_measurements_for_single_water_meter_matches_golden_file() {
+        let (_server, water_meter) = fake_device(
+            &fixture("api-watermeter.json"),
+            &fixture("data-watermeter-usb.json"),
+        );
+
+        // act
+        let output = golden_measurement(vec![water_meter]);
+
+        assert_golden("tests/fixtures/golden/single-water-meter.json", &output);
+    }
+
+    #[test]
+    fn get_measurements_for_mixed_fleet_matches_golden_file() {
+        let fleet = [
+            ("api-p1.json", "data-p1-with-gas.json"),
+            ("api-socket.json", "data-socket-firmware-3.json"),
+            ("api-sdm230.json", "data-sdm230.json"),
+            ("api-sdm630.json", "data-sdm630.json"),
+            ("api-watermeter.json", "data-watermeter-usb.json"),
+        ];
+        let (_servers, devices): (Vec<FakeDeviceServer>, Vec<HomewizardDevice>) = fleet
+            .iter()
+            .map(|(info, data)| fake_device(&fixture(info), &fixture(data)))
+            .unzip();
+
+        // act
+        let output = golden_measurement(devices);
+
+        assert_golden("tests/fixtures/golden/mixed-fleet.json", &output);
+    }
+
+    #[test]
+    fn get_measurements_measures_p1_and_socket_end_to_end() {
+        let (p1_server, p1_meter) =
+            fake_device(&fixture("api-p1.json"), &fixture("data-p1-with-gas.json"));
+        let socket_server = FakeDeviceServer::start();
+        socket_server
+            .respond("/api", FakeResponse::json(&fixture("api-socket.json")))
+            .respond("/api/v1/data", FakeResponse::status(500))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")),
+            );
+        let socket = HomewizardDevice {
+            fullname: "energysocket-3c39e72e33ce._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: socket_server.address().port(),
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![p1_meter, socket])));
+        let config = || Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let summary = || {
+            homewizard_client
+                .summary_handle()
+                .lock()
+                .unwrap()
+                .clone()
+                .unwrap()
+        };
+
+        // act
+        let mut first_cycle = vec![];
+        let logs = capture_logs("info", || {
+            first_cycle = homewizard_client.get_measurements(config(), None).unwrap();
+        });
+
+        assert_eq!(first_cycle[0].location, "My Home");
+        assert_eq!(first_cycle[0].samples.len(), 5);
+        assert!(first_cycle[0]
+            .samples
+            .iter()
+            .all(|sample| sample.entity_name == "HWE-P1"));
+        assert!(logs.contains("Failed fetching data for device"));
+        let first_summary = summary();
+        assert_eq!(first_summary.devices_discovered, 2);
+        assert_eq!(first_summary.succeeded_devices.len(), 1);
+        assert_eq!(
+            first_summary.failed_devices,
+            vec!["3c39e72e33ce".to_string()]
+        );
+        assert_eq!(first_summary.samples_emitted, 5);
+
+        // act
+        let second_cycle = homewizard_client.get_measurements(config(), None).unwrap();
+
+        assert_eq!(second_cycle[0].samples.len(), 8);
+        assert_eq!(second_cycle[0].samples[5].sample_name, "Bonenmaler");
+        assert_eq!(second_cycle[0].samples[7].value, 98.1);
+        let second_summary = summary();
+        assert_eq!(second_summary.succeeded_devices.len(), 2);
+        assert_eq!(second_summary.measured_devices[0].samples, 0..5);
+        assert_eq!(second_summary.measured_devices[1].samples, 5..8);
+        assert_eq!(
+            second_summary.measured_devices[1].friendly_name,
+            "Bonenmaler"
+        );
+        assert!(second_summary.failed_devices.is_empty());
+        assert_eq!(second_summary.samples_emitted, 8);
+        // a failing device is retried in the next cycle, not within the cycle
+        assert_eq!(socket_server.request_count("/api/v1/data"), 2);
+        assert_eq!(p1_server.request_count("/api/v1/data"), 2);
+    }
+
+    fn v2_socket_info() -> String {
+        fixture("api-socket.json").replace(r#""v1""#, r#""v2""#)
+    }
+
+    /// A v2 socket answering its batch endpoint with `batch`, or a 404 without it.
+    fn v2_socket(batch: Option<String>) -> (FakeDeviceServer, HomewizardDevice) {
+        let data = fixture("data-socket-firmware-3.json");
+        let (server, mut socket) = fake_device(&v2_socket_info(), &data);
+        server.respond("/api/v2/data", FakeResponse::json(&data));
+        if let Some(batch) = batch {
+            server.respond("/api/batch", FakeResponse::json(&batch));
+        }
+        socket.api_path = Some("/api/v2".into());
+
+        (server, socket)
+    }
+
+    /// Fetches the device `cycles` times, returning the samples of the last cycle.
+    fn fetch_cycles(
+        homewizard_client: &HomewizardClient,
+        device: &HomewizardDevice,
+        cycles: usize,
+    ) -> Vec<Sample> {
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        let mut samples = vec![];
+        for _ in 0..cycles {
+            samples = homewizard_client
+                .fetch_device(&config, device)
+                .unwrap()
+                .1
+                .unwrap();
+        }
+
+        samples
+    }
+
+    #[test]
+    fn fetch_device_requests_v2_device_seen_before_in_one_batch() {
+        let batch = format!(
+            r#"{{"device":{},"measurement":{}}}"#,
+            v2_socket_info(),
+            fixture("data-socket-firmware-3.json")
+        );
+        let (server, socket) = v2_socket(Some(batch));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let first_cycle = fetch_cycles(&homewizard_client, &socket, 1);
+
+        // act
+        let samples = fetch_cycles(&homewizard_client, &socket, 2);
+
+        let values = |samples: &[Sample]| -> Vec<f64> { samples.iter().map(|s| s.value).collect() };
+        assert_eq!(values(&samples), values(&first_cycle));
+        assert_eq!(server.request_count("/api"), 1);
+        assert_eq!(server.request_count("/api/v2/data"), 1);
+        assert_eq!(server.request_count("/api/batch"), 2);
+    }
+
+    #[test]
+    fn fetch_device_falls_back_to_endpoints_one_by_one_without_batch_endpoint() {
+        let (server, socket) = v2_socket(None);
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+
+        // act
+        let samples = fetch_cycles(&homewizard_client, &socket, 3);
+
+        assert_eq!(samples.len(), 3);
+        assert_eq!(server.request_count("/api/batch"), 1);
+        assert_eq!(server.request_count("/api"), 3);
+        assert_eq!(server.request_count("/api/v2/data"), 3);
+    }
+
+    #[test]
+    fn fetch_device_requests_what_a_batch_response_lacks() {
+        let (info_only_server, info_only) =
+            v2_socket(Some(format!(r#"{{"device":{}}}"#, v2_socket_info())));
+        let (data_only_server, data_only) = v2_socket(Some(format!(
+            r#"{{"measurement":{}}}"#,
+            fixture("data-socket-firmware-3.json")
+        )));
+        let (incomplete_server, incomplete) = v2_socket(Some(
+            r#"{"device":{"serial":"3c39e72e33ce"},"measurement":{"active_power_w":98.1}}"#.into(),
+        ));
+
+        // act
+        let samples: Vec<Vec<Sample>> = [&info_only, &data_only, &incomplete]
+            .into_iter()
+            .map(|device| {
+                let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+                fetch_cycles(&homewizard_client, device, 2)
+            })
+            .collect();
+
+        assert!(samples.iter().all(|samples| samples.len() == 3));
+        assert_eq!(info_only_server.request_count("/api"), 1);
+        assert_eq!(info_only_server.request_count("/api/v2/data"), 2);
+        assert_eq!(data_only_server.request_count("/api"), 2);
+        assert_eq!(data_only_server.request_count("/api/v2/data"), 1);
+        assert_eq!(incomplete_server.request_count("/api"), 2);
+        assert_eq!(incomplete_server.request_count("/api/v2/data"), 2);
+    }
+
+    #[test]
+    fn get_samples_fails_with_device_error_on_unsupported_product_type() {
+        let (_server, socket) = fake_device(
+            &v2_socket_info().replace("HWE-SKT", "HWE-BAT"),
+            &fixture("data-socket-firmware-3.json"),
+        );
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        let device_info = homewizard_client.get_device_info(&socket).unwrap();
+
+        // act
+        let error = homewizard_client
+            .get_samples(&config, &socket, &device_info)
+            .unwrap_err();
+
+        assert!(error.is::<DeviceError>());
+        assert!(error
+            .to_string()
+            .ends_with("is a HWE-BAT on api v2, a product type that isn't supported"));
+    }
+
+    const SYSTEM: &str = r#"{"wifi_ssid":"My Wi-Fi","cloud_enabled":false,"uptime_s":356,"time":"2026-10-15T10:00:00Z"}"#;
+
+    #[test]
+    fn get_system_reads_v2_device_with_token() {
+        let (server, socket) = v2_socket(None);
+        server.respond("/api/system", FakeResponse::json(SYSTEM));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = config_with_token(&server, "2A4F0C9B");
+        let device_info = homewizard_client.get_device_info(&socket).unwrap();
+
+        // act
+        let system = homewizard_client
+            .get_system(&config, &socket, &device_info)
+            .unwrap();
+
+        assert_eq!(
+            system,
+            Some(SystemResponse {
+                cloud_enabled: false,
+                time: Some("2026-10-15T10:00:00Z".parse().unwrap()),
+            })
+        );
+        let requests = server.requests();
+        let request = requests
+            .iter()
+            .find(|request| request.path == "/api/system")
+            .unwrap();
+        assert_eq!(request.header("authorization"), Some("Bearer 2A4F0C9B"));
+    }
+
+    #[test]
+    fn get_system_fails_with_device_error_when_device_rejects_token() {
+        let (server, socket) = v2_socket(None);
+        server.respond("/api/system", FakeResponse::status(401));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = config_with_token(&server, "2A4F0C9B");
+        let device_info = homewizard_client.get_device_info(&socket).unwrap();
+
+        // act
+        let error = homewizard_client
+            .get_system(&config, &socket, &device_info)
+            .unwrap_err();
+
+        assert!(error.is::<DeviceError>());
+        assert!(error
+            .to_string()
+            .ends_with("rejected the token (401 Unauthorized)"));
+    }
+
+    #[test]
+    fn identify_blinks_led_of_v2_device_with_token() {
+        let (server, socket) = v2_socket(None);
+        server.respond("/api/system/identify", FakeResponse::status(204));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = config_with_token(&server, "2A4F0C9B");
+        let device_info = homewizard_client.get_device_info(&socket).unwrap();
+
+        // act
+        let identified = homewizard_client
+            .identify(&config, &socket, &device_info)
+            .unwrap();
+
+        assert!(identified);
+        let requests = server.requests();
+        let request = requests
+            .iter()
+            .find(|request| request.path == "/api/system/identify")
+            .unwrap();
+        assert_eq!(request.method, "PUT");
+        assert_eq!(request.header("authorization"), Some("Bearer 2A4F0C9B"));
+    }
+
+    #[test]
+    fn identify_fails_with_device_error_when_device_rejects_token() {
+        let (server, socket) = v2_socket(None);
+        server.respond("/api/system/identify", FakeResponse::status(401));
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = config_with_token(&server, "2A4F0C9B");
+        let device_info = homewizard_client.get_device_info(&socket).unwrap();
+
+        // act
+        let error = homewizard_client
+            .identify(&config, &socket, &device_info)
+            .unwrap_err();
+
+        assert!(error.is::<DeviceError>());
+        assert!(error
+            .to_string()
+            .ends_with("rejected the token (401 Unauthorized)"));
+    }
+
+    #[test]
+    fn get_system_and_identify_skip_v1_device_silently() {
+        let (server, device) = fake_device(&fixture("api-socket.json"), "{}");
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+        let config = config_with_token(&server, "2A4F0C9B");
+        let device_info = homewizard_client.get_device_info(&device).unwrap();
+
+        // act
+        let logs = capture_logs("warn", || {
+            let system = homewizard_client.get_system(&config, &device, &device_info);
+            let identified = homewizard_client.identify(&config, &device, &device_info);
+
+            assert_eq!(system.unwrap(), None);
+            assert!(!identified.unwrap());
+        });
+
+        assert!(logs.is_empty(), "unexpected warnings: {}", logs);
+        assert!(server
+            .requests()
+            .iter()
+            .all(|request| !request.path.starts_with("/api/system")));
+    }
+
+    /// Warnings of fetching a v2 socket whose clock reads 2026-10-15 10:00:00 UTC, at `now`.
+    fn device_clock_logs(now: &'static str) -> String {
+        let (server, socket) = v2_socket(None);
+        server.respond("/api/system", FakeResponse::json(SYSTEM));
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_clock_and_id_generator(
+                Arc::new(move || now.parse().unwrap()),
+                Arc::new(|| "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".to_string()),
+            ),
+        );
+        let config = Config {
+            gas_clock_skew: Some(GasClockSkewConfig {
+                threshold_minutes: 120,
+                utc_offset_minutes: Some(0),
+            }),
+            ..config_with_token(&server, "2A4F0C9B")
+        };
+
+        capture_logs("warn", || {
+            homewizard_client
+                .fetch_device(&config, &socket)
+                .unwrap()
+                .1
+                .unwrap();
+        })
+    }
+
+    #[test]
+    fn fetch_device_accepts_v2_device_clock_within_threshold() {
+        // act
+        let logs = device_clock_logs("2026-10-15T11:00:00Z");
+
+        assert!(logs.is_empty(), "unexpected warnings: {}", logs);
+    }
+
+    #[test]
+    fn fetch_device_warns_about_v2_device_clock_lagging_behind() {
+        // act
+        let logs = device_clock_logs("2026-10-15T13:00:00Z");
+
+        assert!(logs.contains("Device clock lags behind"));
+        assert!(logs.contains(r#""skew_minutes":180"#));
+        assert!(logs.contains("3c39e72e33ce"));
+    }
+
+    #[test]
+    fn fetch_device_warns_about_v2_device_clock_running_ahead() {
+        // act
+        let logs = device_clock_logs("2026-10-15T07:00:00Z");
+
+        assert!(logs.contains("Device clock runs ahead"));
+        assert!(logs.contains(r#""skew_minutes":180"#));
+    }
 }
@@ -0,0 +1,307 @@
+use crate::exporter::MeasurementPublisher;
+use async_trait::async_trait;
+use jarvis_lib::model::{Measurement, Sample};
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+const MEASUREMENT_NAME: &str = "homewizard";
+
+pub struct InfluxDbPublisherConfig {
+    url: String,
+    org: String,
+    bucket: String,
+    token: String,
+    max_attempts: u32,
+    retry_backoff: Duration,
+}
+
+impl InfluxDbPublisherConfig {
+    pub fn new(
+        url: String,
+        org: String,
+        bucket: String,
+        token: String,
+        max_attempts: u32,
+        retry_backoff: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "InfluxDbPublisherConfig::new(url: {}, org: {}, bucket: {}, max_attempts: {}, retry_backoff: {:?})",
+            url, org, bucket, max_attempts, retry_backoff
+        );
+        if max_attempts == 0 {
+            return Err("INFLUXDB_MAX_ATTEMPTS has to be at least 1".into());
+        }
+
+        Ok(Self {
+            url: url.trim_end_matches('/').to_string(),
+            org,
+            bucket,
+            token,
+            max_attempts,
+            retry_backoff,
+        })
+    }
+
+    /// Returns no config when `INFLUXDB_URL` isn't set, since writing to InfluxDB is optional.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let url = match env::var("INFLUXDB_URL") {
+            Ok(url) => url,
+            Err(_) => return Ok(None),
+        };
+        let org =
+            env::var("INFLUXDB_ORG").map_err(|_| "INFLUXDB_ORG is required with INFLUXDB_URL")?;
+        let bucket = env::var("INFLUXDB_BUCKET")
+            .map_err(|_| "INFLUXDB_BUCKET is required with INFLUXDB_URL")?;
+        let token = env::var("INFLUXDB_TOKEN")
+            .map_err(|_| "INFLUXDB_TOKEN is required with INFLUXDB_URL")?;
+        let max_attempts: u32 = env::var("INFLUXDB_MAX_ATTEMPTS")
+            .unwrap_or_else(|_| "3".to_string())
+            .parse()?;
+
+        Ok(Some(Self::new(
+            url,
+            org,
+            bucket,
+            token,
+            max_attempts,
+            Duration::from_secs(1),
+        )?))
+    }
+}
+
+/// Writes all samples of a measurement to InfluxDB v2 in a single line protocol request,
+/// retrying server errors with a doubling backoff.
+pub struct InfluxDbPublisher {
+    config: InfluxDbPublisherConfig,
+    client: reqwest::Client,
+}
+
+impl InfluxDbPublisher {
+    pub fn new(config: InfluxDbPublisherConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn write(&self, body: String) -> Result<(), Box<dyn Error>> {
+        let url = format!("{}/api/v2/write", self.config.url);
+        let mut backoff = self.config.retry_backoff;
+
+        for attempt in 1..=self.config.max_attempts {
+            let response = self
+                .client
+                .post(&url)
+                .query(&[
+                    ("org", self.config.org.as_str()),
+                    ("bucket", self.config.bucket.as_str()),
+                    ("precision", "ns"),
+                ])
+                .header("Authorization", format!("Token {}", self.config.token))
+                .header("Content-Type", "text/plain; charset=utf-8")
+                .body(body.clone())
+                .send()
+                .await?;
+
+            let status = response.status();
+            if status.is_success() {
+                return Ok(());
+            }
+            if !status.is_server_error() || attempt == self.config.max_attempts {
+                return Err(format!(
+                    "InfluxDB responded {} after {} attempts: {}",
+                    status,
+                    attempt,
+                    response.text().await.unwrap_or_default()
+                )
+                .into());
+            }
+
+            warn!(
+                "InfluxDB responded {}, retrying in {:?} (attempt {} of {})",
+                status, backoff, attempt, self.config.max_attempts
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl MeasurementPublisher for InfluxDbPublisher {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        if measurement.samples.is_empty() {
+            return Ok(());
+        }
+
+        self.write(line_protocol(measurement)).await
+    }
+}
+
+/// Formats every sample as a line, tagged with the location, the device (its sample name), the
+/// sample type and the metric type, stamped with the measurement time in nanoseconds.
+fn line_protocol(measurement: &Measurement) -> String {
+    let timestamp = measurement.measured_at_time.timestamp_nanos();
+
+    measurement
+        .samples
+        .iter()
+        .map(|sample| line(measurement, sample, timestamp))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+fn line(measurement: &Measurement, sample: &Sample, timestamp: i64) -> String {
+    let tags = [
+        ("device", sample.sample_name.clone()),
+        ("location", measurement.location.clone()),
+        ("metric_type", format!("{:?}", sample.metric_type)),
+        ("product", sample.entity_name.clone()),
+        ("sample_type", format!("{:?}", sample.sample_type)),
+    ];
+
+    let mut line = MEASUREMENT_NAME.to_string();
+    for (key, value) in tags.iter() {
+        // empty tag values aren't allowed in line protocol
+        if !value.is_empty() {
+            line.push_str(&format!(",{}={}", key, escape_tag(value)));
+        }
+    }
+    line.push_str(&format!(" value={:?} {}", sample.value, timestamp));
+
+    line
+}
+
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeDeviceServer, FakeResponse};
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, MetricType, SampleType};
+
+    const WRITE_PATH: &str = "/api/v2/write?org=home&bucket=energy&precision=ns";
+
+    fn measurement() -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![
+                Sample {
+                    entity_type: EntityType::Tariff,
+                    entity_name: "HWE-P1".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "t1 import".into(),
+                    metric_type: MetricType::Counter,
+                    value: 38989839600.0,
+                },
+                Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-SKT".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "Bonenmaler, keuken".into(),
+                    metric_type: MetricType::Gauge,
+                    value: 98.1,
+                },
+                Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-WTR".into(),
+                    sample_type: SampleType::WaterConsumption,
+                    sample_name: "Watermeter".into(),
+                    metric_type: MetricType::Counter,
+                    value: 123.456,
+                },
+            ],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn publisher(server: &FakeDeviceServer) -> InfluxDbPublisher {
+        InfluxDbPublisher::new(
+            InfluxDbPublisherConfig::new(
+                server.base_url(),
+                "home".into(),
+                "energy".into(),
+                "secret".into(),
+                3,
+                Duration::from_millis(1),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn line_protocol_tags_and_timestamps_every_sample() {
+        // act
+        let lines = line_protocol(&measurement());
+
+        assert_eq!(
+            lines,
+            "homewizard,device=t1\\ import,location=My\\ Home,metric_type=Counter,product=HWE-P1,sample_type=ElectricityConsumption value=38989839600.0 1685620800000000000\n\
+             homewizard,device=Bonenmaler\\,\\ keuken,location=My\\ Home,metric_type=Gauge,product=HWE-SKT,sample_type=ElectricityConsumption value=98.1 1685620800000000000\n\
+             homewizard,device=Watermeter,location=My\\ Home,metric_type=Counter,product=HWE-WTR,sample_type=WaterConsumption value=123.456 1685620800000000000"
+        );
+    }
+
+    #[tokio::test]
+    async fn publish_writes_all_samples_in_one_request() {
+        let server = FakeDeviceServer::start();
+        server.respond(WRITE_PATH, FakeResponse::status(204));
+
+        // act
+        publisher(&server).publish(&measurement()).await.unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].header("Authorization"), Some("Token secret"));
+        assert_eq!(requests[0].body, line_protocol(&measurement()));
+    }
+
+    #[tokio::test]
+    async fn publish_retries_server_errors() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(WRITE_PATH, FakeResponse::status(503))
+            .respond(WRITE_PATH, FakeResponse::status(204));
+
+        // act
+        let result = publisher(&server).publish(&measurement()).await;
+
+        assert!(result.is_ok());
+        assert_eq!(server.request_count(WRITE_PATH), 2);
+    }
+
+    #[tokio::test]
+    async fn publish_gives_up_on_client_errors_and_after_max_attempts() {
+        let client_error_server = FakeDeviceServer::start();
+        client_error_server.respond(WRITE_PATH, FakeResponse::status(401));
+        let server_error_server = FakeDeviceServer::start();
+        server_error_server.respond(WRITE_PATH, FakeResponse::status(500));
+
+        // act
+        let client_error = publisher(&client_error_server)
+            .publish(&measurement())
+            .await;
+        let server_error = publisher(&server_error_server)
+            .publish(&measurement())
+            .await;
+
+        assert!(client_error.is_err());
+        assert_eq!(client_error_server.request_count(WRITE_PATH), 1);
+        assert!(server_error.is_err());
+        assert_eq!(server_error_server.request_count(WRITE_PATH), 3);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::exporter::{LastSuccess, MeasurementStore};
+use crate::exporter_state::ExporterStateStore;
+use serde_json::{json, Value};
+use std::error::Error;
+use std::path::Path;
+
+/// Parts of keys holding secrets, whose values never get printed.
+const SECRET_KEYS: [&str; 5] = ["token", "password", "secret", "credential", "apikey"];
+
+const REDACTED: &str = "[redacted]";
+
+/// Everything the exporter keeps between cycles: the last measurements in `store`, the last
+/// successful measurement they tell, and the exporter state in the file at
+/// `exporter_state_path`, each `null` when there is none. Values of keys that look like they
+/// hold a secret are redacted.
+pub fn inspect(
+    store: &dyn MeasurementStore,
+    exporter_state_path: Option<&Path>,
+) -> Result<Value, Box<dyn Error>> {
+    let last_measurements = store.read()?;
+    let last_success = last_measurements
+        .as_deref()
+        .and_then(LastSuccess::from_measurements);
+    let exporter_state = match exporter_state_path {
+        Some(path) if path.exists() => Some(ExporterStateStore::read(path).map_err(|e| {
+            format!(
+                "Failed reading exporter state from {}: {}",
+                path.display(),
+                e
+            )
+        })?),
+        _ => None,
+    };
+
+    let mut state = json!({
+        "lastMeasurements": last_measurements,
+        "lastSuccess": last_success,
+        "exporterState": exporter_state,
+    });
+    redact(&mut state);
+
+    Ok(state)
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            for (key, value) in object.iter_mut() {
+                let key = key
+                    .to_lowercase()
+                    .replace(|c: char| c == '_' || c == '-', "");
+                if SECRET_KEYS.iter().any(|secret| key.contains(secret)) && !value.is_null() {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::InMemoryStore;
+    use crate::exporter_state::ExporterState;
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::Measurement;
+    use std::env;
+    use std::fs;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn inspect_prints_last_measurements_success_and_exporter_state() {
+        let store = InMemoryStore::default();
+        store
+            .store(&[Measurement {
+                id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+                source: "jarvis-homewizard-exporter".into(),
+                location: "My Home".into(),
+                samples: vec![],
+                measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+            }])
+            .await
+            .unwrap();
+        let path = env::temp_dir().join(format!("exporter-state-{}.json", Uuid::new_v4()));
+        ExporterStateStore::new(Some(path.clone()))
+            .save(&ExporterState::default())
+            .unwrap();
+
+        // act
+        let state = inspect(&store, Some(&path)).unwrap();
+
+        assert_eq!(
+            state["lastMeasurements"][0]["id"],
+            "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10"
+        );
+        assert_eq!(state["lastSuccess"]["measuredAt"], "2023-06-01T12:00:00Z");
+        assert!(state["exporterState"]["deviceHealth"].is_object());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn inspect_prints_nulls_for_empty_state() {
+        let missing = env::temp_dir().join(format!("exporter-state-{}.json", Uuid::new_v4()));
+
+        // act
+        let state = inspect(&InMemoryStore::default(), Some(&missing)).unwrap();
+
+        assert_eq!(
+            state,
+            json!({"lastMeasurements": null, "lastSuccess": null, "exporterState": null})
+        );
+    }
+
+    #[test]
+    fn redact_hides_values_of_secret_keys_at_any_depth() {
+        let mut state = json!({
+            "devices": [
+                {"serial": "3c39e72e33ce", "token": "2A4F0C9B", "bearer_token": "5D1E"},
+                {"serial": "5c2faf0a8b3e", "token": null}
+            ],
+            "influxdb": {"apiKey": "secret-key", "Password": "hunter2", "url": "http://influxdb"}
+        });
+
+        // act
+        redact(&mut state);
+
+        assert_eq!(
+            state,
+            json!({
+                "devices": [
+                    {"serial": "3c39e72e33ce", "token": "[redacted]", "bearer_token": "[redacted]"},
+                    {"serial": "5c2faf0a8b3e", "token": null}
+                ],
+                "influxdb": {"apiKey": "[redacted]", "Password": "[redacted]", "url": "http://influxdb"}
+            })
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use rand::Rng;
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+use tracing::debug;
+
+/// Random delays that keep exporters started at the same moment, like after a power cut, from
+/// polling the same devices at the same moment every cycle.
+pub struct JitterConfig {
+    pub startup_max: Duration,
+    pub cycle_max: Duration,
+}
+
+impl JitterConfig {
+    pub fn new(startup_max: Duration, cycle_max: Duration) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "JitterConfig::new(startup_max: {:?}, cycle_max: {:?})",
+            startup_max, cycle_max
+        );
+        Ok(Self {
+            startup_max,
+            cycle_max,
+        })
+    }
+
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let startup_max_seconds: u64 = env::var("STARTUP_JITTER_SECONDS")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()?;
+        let cycle_max_seconds: u64 = env::var("CYCLE_JITTER_SECONDS")
+            .unwrap_or_else(|_| "0".to_string())
+            .parse()?;
+
+        Self::new(
+            Duration::from_secs(startup_max_seconds),
+            Duration::from_secs(cycle_max_seconds),
+        )
+    }
+}
+
+/// A random delay from zero up to and including `max`, in whole milliseconds.
+pub fn draw(max: Duration) -> Duration {
+    let max_millis = max.as_millis() as u64;
+    if max_millis == 0 {
+        return Duration::ZERO;
+    }
+
+    Duration::from_millis(rand::thread_rng().gen_range(0..=max_millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_stays_within_bounds() {
+        let max = Duration::from_secs(30);
+
+        // act
+        let delays: Vec<Duration> = (0..1000).map(|_| draw(max)).collect();
+
+        assert!(delays.iter().all(|delay| *delay <= max));
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+        assert_eq!(draw(Duration::ZERO), Duration::ZERO);
+    }
+}
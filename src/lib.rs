@@ -0,0 +1,121 @@
+//! Discovers HomeWizard energy devices on the local network through mdns and reads their
+//! measurements over their local api.
+//!
+//! [`HomewizardClient`] is the entry point: it discovers devices through a [`DeviceDiscoverer`],
+//! requests their `/api` and data endpoints and converts the responses into jarvis samples.
+
+pub mod discovery;
+pub mod error;
+pub mod events;
+pub mod homewizard_client;
+pub mod model;
+
+mod allowed_cidrs;
+mod apparent_power;
+mod burst;
+mod carry_forward;
+mod config_merge;
+mod daily_consumption;
+mod deadband;
+mod derived;
+mod device_health;
+mod device_tls;
+mod device_tokens;
+mod expected_samples;
+mod exporter_state;
+mod external_meters;
+mod fetch_failures;
+mod firmware;
+mod gas_clock;
+mod gas_flow;
+mod metric_split;
+mod missing_devices;
+mod name_normalization;
+mod name_sanitization;
+mod precision;
+mod replay;
+mod sample_cap;
+mod smoothing;
+mod time_of_use;
+mod units;
+mod unmatched_serials;
+
+// the runtime of the exporter binary, not meant to be used by other crates
+#[cfg(feature = "mdns")]
+#[doc(hidden)]
+pub mod announce;
+#[doc(hidden)]
+pub mod archive;
+#[doc(hidden)]
+pub mod bench;
+#[doc(hidden)]
+pub mod bigquery;
+#[doc(hidden)]
+pub mod bootstrap;
+#[doc(hidden)]
+pub mod csv_export;
+#[doc(hidden)]
+pub mod device_registry;
+#[doc(hidden)]
+pub mod diagnose;
+#[doc(hidden)]
+pub mod env_config;
+#[doc(hidden)]
+pub mod exporter;
+#[doc(hidden)]
+pub mod healthcheck;
+#[doc(hidden)]
+pub mod home_assistant;
+#[doc(hidden)]
+pub mod influxdb;
+#[doc(hidden)]
+pub mod inspect_state;
+#[doc(hidden)]
+pub mod jitter;
+#[doc(hidden)]
+pub mod live_view;
+#[doc(hidden)]
+pub mod manual_trigger;
+#[doc(hidden)]
+pub mod metrics;
+#[doc(hidden)]
+pub mod mqtt;
+#[doc(hidden)]
+pub mod nats_subject;
+#[doc(hidden)]
+pub mod ndjson;
+#[doc(hidden)]
+pub mod otel_metrics;
+#[doc(hidden)]
+pub mod reload;
+#[doc(hidden)]
+pub mod remote_write;
+#[doc(hidden)]
+pub mod self_test;
+#[doc(hidden)]
+pub mod shutdown;
+#[doc(hidden)]
+pub mod spool;
+#[doc(hidden)]
+pub mod supervisor;
+#[doc(hidden)]
+pub mod telemetry;
+#[doc(hidden)]
+pub mod textfile;
+#[cfg(feature = "tui")]
+#[doc(hidden)]
+pub mod tui;
+#[doc(hidden)]
+pub mod webhook;
+
+#[cfg(test)]
+mod test_support;
+
+#[cfg(feature = "mdns")]
+pub use discovery::MdnsDiscoverer;
+pub use discovery::{DeviceDiscoverer, StaticDiscoverer};
+pub use homewizard_client::{
+    DeviceInfoResponse, EnergySocketDataResponse, HomewizardClient, HomewizardClientConfig,
+    HomewizardDevice, HomewizardDeviceType, P1MeterDataResponse, SinglePhaseKwhMeterDataResponse,
+    TriplePhaseKwhMeterDataResponse, WaterMeterDataResponse,
+};
@@ -0,0 +1,174 @@
+use crate::device_registry::{DeviceRegistry, FetchStatus, RegisteredDevice};
+use crate::mqtt::topic_level;
+use jarvis_lib::model::{MetricType, Sample};
+
+/// A line of the live view: a device with the outcome of its last request and its latest values.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceRow {
+    pub name: String,
+    pub product_type: String,
+    pub address: String,
+    pub latency: String,
+    pub status: String,
+    pub gauges: String,
+    pub counters: String,
+}
+
+/// One row per device in the registry, ordered by name. A device that failed its last request
+/// shows the error as its status and has no values.
+pub fn rows(registry: &DeviceRegistry) -> Vec<DeviceRow> {
+    registry.devices().map(row).collect()
+}
+
+fn row(device: &RegisteredDevice) -> DeviceRow {
+    let status = match (&device.last_fetch.status, &device.last_fetch.error) {
+        (FetchStatus::Ok, _) => "ok".to_string(),
+        (FetchStatus::Failed, Some(error)) => format!("failed: {}", error),
+        (FetchStatus::Failed, None) => "failed".to_string(),
+    };
+
+    DeviceRow {
+        name: device
+            .friendly_name
+            .clone()
+            .unwrap_or_else(|| device.name.clone()),
+        product_type: device
+            .product_type
+            .clone()
+            .unwrap_or_else(|| "unknown".to_string()),
+        address: device.addresses.first().cloned().unwrap_or_default(),
+        latency: format!("{} ms", device.last_fetch.latency_ms),
+        status,
+        gauges: values(&device.samples, MetricType::Gauge),
+        counters: values(&device.samples, MetricType::Counter),
+    }
+}
+
+/// The samples of `metric_type` as `sample-type value` pairs, separated by commas.
+fn values(samples: &[Sample], metric_type: MetricType) -> String {
+    samples
+        .iter()
+        .filter(|sample| sample.metric_type == metric_type)
+        .map(|sample| {
+            format!(
+                "{} {}",
+                topic_level(&format!("{:?}", sample.sample_type)),
+                sample.value
+            )
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device_registry::Fetch;
+    use crate::homewizard_client::{DeviceInfoResponse, HomewizardDevice};
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, SampleType};
+    use std::collections::HashSet;
+    use std::time::Duration;
+
+    fn device(fullname: &str, address: &str) -> HomewizardDevice {
+        HomewizardDevice {
+            fullname: fullname.into(),
+            ip_addresses: vec![address.parse().unwrap()].into_iter().collect(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        }
+    }
+
+    fn fetch(latency_ms: u64) -> Fetch<'static> {
+        Fetch {
+            base_url: None,
+            at: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+            latency: Duration::from_millis(latency_ms),
+        }
+    }
+
+    fn sample(sample_type: SampleType, metric_type: MetricType, value: f64) -> Sample {
+        Sample {
+            entity_type: EntityType::Device,
+            entity_name: "HWE-SKT".into(),
+            sample_type,
+            sample_name: "Bonenmaler".into(),
+            metric_type,
+            value,
+        }
+    }
+
+    #[test]
+    fn rows_show_values_of_read_device() {
+        let mut registry = DeviceRegistry::default();
+        registry.record_success(
+            &device("energysocket-1._hwenergy._tcp.local.", "192.168.1.31"),
+            fetch(42),
+            &DeviceInfoResponse {
+                product_type: "HWE-SKT".into(),
+                product_name: "Energy Socket".into(),
+                serial: "3c39e72e33ce".into(),
+                firmware_version: "3.02".into(),
+                api_version: "v1".into(),
+            },
+            "Bonenmaler",
+            vec![
+                sample(
+                    SampleType::ElectricityConsumption,
+                    MetricType::Gauge,
+                    1450.0,
+                ),
+                sample(
+                    SampleType::ElectricityConsumption,
+                    MetricType::Counter,
+                    3600000.5,
+                ),
+                sample(SampleType::ElectricityProduction, MetricType::Counter, 0.0),
+            ],
+        );
+
+        // act
+        let rows = rows(&registry);
+
+        assert_eq!(
+            rows,
+            vec![DeviceRow {
+                name: "Bonenmaler".into(),
+                product_type: "HWE-SKT".into(),
+                address: "192.168.1.31:80".into(),
+                latency: "42 ms".into(),
+                status: "ok".into(),
+                gauges: "electricity-consumption 1450".into(),
+                counters: "electricity-consumption 3600000.5, electricity-production 0".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn rows_show_error_of_failed_device_without_values() {
+        let mut registry = DeviceRegistry::default();
+        registry.record_failure(
+            &HomewizardDevice {
+                fullname: "p1meter-1._hwenergy._tcp.local.".into(),
+                ip_addresses: HashSet::new(),
+                port: 80,
+                hostname: None,
+                api_path: None,
+            },
+            fetch(3000),
+            None,
+            "connection timed out",
+        );
+
+        // act
+        let rows = rows(&registry);
+
+        assert_eq!(rows[0].name, "p1meter-1._hwenergy._tcp.local.");
+        assert_eq!(rows[0].product_type, "unknown");
+        assert_eq!(rows[0].address, "");
+        assert_eq!(rows[0].latency, "3000 ms");
+        assert_eq!(rows[0].status, "failed: connection timed out");
+        assert_eq!(rows[0].gauges, "");
+    }
+}
@@ -1,40 +1,562 @@
-mod homewizard_client;
-mod model;
+mod cli;
 
-use homewizard_client::{HomewizardClient, HomewizardClientConfig};
+use chrono::Utc;
+use clap::Parser;
+use cli::{Cli, Command};
+#[cfg(feature = "mdns")]
+use jarvis_homewizard_exporter::announce::{AnnounceConfig, Announcer};
+use jarvis_homewizard_exporter::bench::{self, BenchConfig};
+use jarvis_homewizard_exporter::bigquery::{BigQueryPublisher, BigQueryPublisherConfig};
+use jarvis_homewizard_exporter::bootstrap;
+use jarvis_homewizard_exporter::diagnose::{self, DiagnoseTarget};
+use jarvis_homewizard_exporter::discovery;
+use jarvis_homewizard_exporter::env_config::EnvConfig;
+use jarvis_homewizard_exporter::error::{self, ConfigError, DeviceError, PublishError};
+use jarvis_homewizard_exporter::events::{NatsEventPublisher, NatsEventPublisherConfig};
+use jarvis_homewizard_exporter::exporter::{
+    self, Exporter, ExporterConfig, InMemoryStore, LastSuccess, MeasurementPublisher,
+    MeasurementStore, Output,
+};
+use jarvis_homewizard_exporter::healthcheck;
+use jarvis_homewizard_exporter::homewizard_client::{
+    DeviceTarget, HomewizardClient, HomewizardClientConfig, HomewizardDevice,
+};
+use jarvis_homewizard_exporter::influxdb::{InfluxDbPublisher, InfluxDbPublisherConfig};
+use jarvis_homewizard_exporter::inspect_state;
+use jarvis_homewizard_exporter::jitter::JitterConfig;
+use jarvis_homewizard_exporter::manual_trigger;
+use jarvis_homewizard_exporter::metrics::{MetricsServerConfig, SampleMetrics};
+use jarvis_homewizard_exporter::model::Config;
+use jarvis_homewizard_exporter::mqtt::{MqttPublisher, MqttPublisherConfig};
+use jarvis_homewizard_exporter::nats_subject::{NatsSubjectPublisher, NatsSubjectPublisherConfig};
+use jarvis_homewizard_exporter::ndjson::NdjsonPublisher;
+use jarvis_homewizard_exporter::otel_metrics::OtelMetricsPublisher;
+use jarvis_homewizard_exporter::reload;
+use jarvis_homewizard_exporter::remote_write::{RemoteWritePublisher, RemoteWritePublisherConfig};
+use jarvis_homewizard_exporter::self_test::{self, ConnectionProbe, NatsProbe, SelfTestConfig};
+use jarvis_homewizard_exporter::shutdown::{self, ShutdownConfig, ShutdownSignal};
+use jarvis_homewizard_exporter::spool::{MeasurementSpool, SpoolConfig};
+use jarvis_homewizard_exporter::supervisor::{self, SupervisorConfig};
+use jarvis_homewizard_exporter::telemetry;
+use jarvis_homewizard_exporter::textfile::{TextfileConfig, TextfilePublisher};
+use jarvis_homewizard_exporter::webhook::{WebhookPublisher, WebhookPublisherConfig};
 use jarvis_lib::config_client::{ConfigClient, ConfigClientConfig};
-use jarvis_lib::exporter_service::{ExporterService, ExporterServiceConfig};
 use jarvis_lib::nats_client::{NatsClient, NatsClientConfig};
 use jarvis_lib::state_client::{StateClient, StateClientConfig};
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+#[cfg(feature = "mdns")]
+use std::rc::Rc;
+use std::time::Duration;
+use tracing::info;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+
+/// Refresh interval of the tui without INTERVAL_SECONDS.
+const TUI_INTERVAL: Duration = Duration::from_secs(10);
 
 #[tokio::main]
-pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tracing_subscriber::fmt()
-        .json()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+pub async fn main() {
+    error::install_panic_hook();
+
+    if let Err(e) = start().await {
+        eprintln!("Error: {}", e);
+        std::process::exit(error::exit_code(e.as_ref()));
+    }
+}
+
+async fn start() -> Result<(), Box<dyn Error>> {
+    let command = Cli::parse().subcommand();
+    let exporter_config = ExporterConfig::from_env().map_err(ConfigError::new)?;
+    let dry_run =
+        exporter_config.dry_run || matches!(command, Command::Measure { dry_run: true, .. });
+
+    // keep stdout clean for commands printing their result
+    let log_writer = if dry_run
+        || exporter_config.output == Output::Stdout
+        || matches!(
+            command,
+            Command::Discover
+                | Command::Bootstrap { .. }
+                | Command::ValidateConfig
+                | Command::Schema
+                | Command::Diagnose { .. }
+                | Command::Identify { .. }
+                | Command::Bench { .. }
+                | Command::InspectState
+                | Command::Healthcheck { .. }
+        ) {
+        BoxMakeWriter::new(std::io::stderr)
+    } else if command == Command::Tui {
+        // logs would garble the screen
+        BoxMakeWriter::new(std::io::sink)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+
+    tracing_subscriber::registry()
+        .with(telemetry::otlp_layer().map_err(ConfigError::new)?)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_writer(log_writer),
+        )
+        .with(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    let homewizard_client_config = HomewizardClientConfig::from_env()?;
-    let homewizard_client = HomewizardClient::new(homewizard_client_config);
+    let shutdown_config = ShutdownConfig::from_env().map_err(ConfigError::new)?;
+
+    let result = run(command, exporter_config, shutdown_config, dry_run).await;
+
+    telemetry::shutdown();
+
+    result
+}
+
+async fn run(
+    command: Command,
+    exporter_config: ExporterConfig,
+    shutdown_config: ShutdownConfig,
+    dry_run: bool,
+) -> Result<(), Box<dyn Error>> {
+    if command == Command::Schema {
+        println!("{}", Config::json_schema());
+        return Ok(());
+    }
 
-    let state_client_config = StateClientConfig::from_env().await?;
-    let state_client = StateClient::new(state_client_config);
+    if let Command::Healthcheck { max_age_seconds } = command {
+        return check_health(Duration::from_secs(max_age_seconds)).await;
+    }
 
-    let nats_client_config = NatsClientConfig::from_env().await?;
-    let nats_client = NatsClient::new(nats_client_config);
+    if command == Command::InspectState {
+        let exporter_state_path = env::var("EXPORTER_STATE_FILE_PATH").ok().map(PathBuf::from);
+        let state = inspect_state::inspect(
+            local_store().await?.as_ref(),
+            exporter_state_path.as_deref(),
+        )
+        .map_err(PublishError::new)?;
+        println!("{}", serde_json::to_string_pretty(&state)?);
+        return Ok(());
+    }
 
-    let config_client_config = ConfigClientConfig::from_env()?;
+    let shutdown_signal = shutdown::install(&shutdown_config)?;
+
+    let mut homewizard_client_config =
+        HomewizardClientConfig::from_env().map_err(ConfigError::new)?;
+    if let Command::Measure {
+        ip: Some(address),
+        serial,
+        ..
+    } = &command
+    {
+        homewizard_client_config = homewizard_client_config.with_device(DeviceTarget {
+            address: *address,
+            expected_serial: serial.clone(),
+        });
+    }
+
+    let config_client_config = ConfigClientConfig::from_env().map_err(ConfigError::new)?;
     let config_client = ConfigClient::new(config_client_config);
+    let env_config = EnvConfig::from_env().map_err(ConfigError::new)?;
+
+    if command == Command::ValidateConfig {
+        let config = exporter::validate_config(&config_client, &env_config)?;
+        info!("Config for location {} is valid", config.location);
+        println!("{:#?}", config);
+        return Ok(());
+    }
+
+    if command == Command::Discover {
+        let homewizard_client = HomewizardClient::new(homewizard_client_config.without_state());
+        let devices = tokio::task::block_in_place(|| homewizard_client.discover_devices())
+            .map_err(DeviceError::new)?;
+        println!("{}", exporter::render_devices(&devices));
+        return Ok(());
+    }
+
+    if let Command::Bootstrap { config } = &command {
+        let existing = match config {
+            Some(path) => Some(std::fs::read_to_string(path).map_err(|e| {
+                ConfigError::new(
+                    format!("Failed reading config file {}: {}", path.display(), e).into(),
+                )
+            })?),
+            None => None,
+        };
+        let homewizard_client = HomewizardClient::new(homewizard_client_config.without_state());
+        let rendered = tokio::task::block_in_place(|| {
+            bootstrap::bootstrap(&homewizard_client, existing.as_deref())
+        })?;
+        print!("{}", rendered);
+        return Ok(());
+    }
+
+    if let Command::Bench {
+        iterations,
+        delay_ms,
+        ip,
+        json,
+    } = &command
+    {
+        let config = exporter::validate_config(&config_client, &env_config)?;
+        let bench_config = BenchConfig::new(*iterations, Duration::from_millis(*delay_ms))
+            .map_err(ConfigError::new)?;
+        let mut homewizard_client = HomewizardClient::new(homewizard_client_config.without_state());
+        if let Some(address) = ip {
+            homewizard_client = homewizard_client.with_discoverer(Box::new(
+                discovery::StaticDiscoverer::new(vec![HomewizardDevice {
+                    fullname: address.to_string(),
+                    ip_addresses: vec![*address.ip()].into_iter().collect(),
+                    port: address.port(),
+                    hostname: None,
+                    api_path: None,
+                }]),
+            ));
+        }
+        let benches = tokio::task::block_in_place(|| {
+            bench::bench(&homewizard_client, &config, &bench_config)
+        })?;
+        if *json {
+            println!("{}", serde_json::to_string_pretty(&benches)?);
+        } else {
+            println!("{}", bench::render_table(&benches));
+        }
+        return Ok(());
+    }
+
+    if let Command::Diagnose { target } = &command {
+        let timeout = homewizard_client_config.timeout();
+        let homewizard_client = HomewizardClient::new(homewizard_client_config.without_state());
+        let diagnosis = tokio::task::block_in_place(|| {
+            let base_url =
+                diagnose::resolve_base_url(&DiagnoseTarget::parse(target), &homewizard_client)?;
+            diagnose::diagnose(&base_url, timeout)
+        })?;
+        println!("{}", diagnosis);
+        return Ok(());
+    }
+
+    if let Command::Identify { address } = &command {
+        let config = exporter::validate_config(&config_client, &env_config)?;
+        let homewizard_client = HomewizardClient::new(homewizard_client_config.without_state());
+        let device = HomewizardDevice {
+            fullname: address.to_string(),
+            ip_addresses: vec![*address.ip()].into_iter().collect(),
+            port: address.port(),
+            hostname: None,
+            api_path: None,
+        };
+        let identified = tokio::task::block_in_place(|| {
+            let device_info = homewizard_client.get_device_info(&device)?;
+            homewizard_client.identify(&config, &device, &device_info)
+        })
+        .map_err(DeviceError::new)?;
+        if !identified {
+            println!(
+                "Device at {} only serves the v1 api, which can't identify it",
+                address
+            );
+        }
+        return Ok(());
+    }
+
+    if !cfg!(feature = "mdns") && !homewizard_client_config.skips_discovery() {
+        let config = exporter::validate_config(&config_client, &env_config)?;
+        discovery::check_source(&config).map_err(ConfigError::new)?;
+    }
+
+    if command == Command::Tui {
+        let homewizard_client = HomewizardClient::new(homewizard_client_config.without_state());
+        let interval = exporter_config.interval.unwrap_or(TUI_INTERVAL);
+        return tokio::task::block_in_place(|| {
+            run_tui(
+                &config_client,
+                &env_config,
+                &homewizard_client,
+                interval,
+                shutdown_signal,
+            )
+        });
+    }
 
-    let exporter_service_config = ExporterServiceConfig::new(
+    if dry_run {
+        let homewizard_client = HomewizardClient::new(homewizard_client_config.without_state());
+        let cycle_summary = homewizard_client.summary_handle();
+
+        let result = tokio::task::block_in_place(|| {
+            exporter::measure_once_as_json(&config_client, &env_config, &homewizard_client)
+        });
+
+        if let Some(summary) = cycle_summary.lock().unwrap().take() {
+            summary.log();
+        }
+
+        println!("{}", result?);
+        return Ok(());
+    }
+
+    let mut homewizard_client = HomewizardClient::new(homewizard_client_config);
+    if exporter_config.output == Output::Nats {
+        let event_publisher = NatsEventPublisher::connect(
+            NatsEventPublisherConfig::from_env().map_err(ConfigError::new)?,
+        )
+        .map_err(PublishError::new)?;
+        homewizard_client = homewizard_client.with_event_publisher(Box::new(event_publisher));
+    }
+    let cycle_summary = homewizard_client.summary_handle();
+    let device_registry = homewizard_client.registry_handle();
+    let mqtt_publisher_config = MqttPublisherConfig::from_env().map_err(ConfigError::new)?;
+
+    let (publisher, store): (Box<dyn MeasurementPublisher>, Box<dyn MeasurementStore>) =
+        match exporter_config.output {
+            Output::Nats => {
+                let state_client_config = StateClientConfig::from_env()
+                    .await
+                    .map_err(ConfigError::new)?;
+                let publisher: Box<dyn MeasurementPublisher> =
+                    match NatsSubjectPublisherConfig::from_env().map_err(ConfigError::new)? {
+                        Some(nats_subject_publisher_config) => Box::new(
+                            NatsSubjectPublisher::connect(
+                                nats_subject_publisher_config,
+                                cycle_summary.clone(),
+                            )
+                            .map_err(PublishError::new)?,
+                        ),
+                        None => Box::new(NatsClient::new(
+                            NatsClientConfig::from_env()
+                                .await
+                                .map_err(ConfigError::new)?,
+                        )),
+                    };
+                (publisher, Box::new(StateClient::new(state_client_config)))
+            }
+            Output::Stdout => (
+                Box::new(NdjsonPublisher::new(std::io::stdout())),
+                local_store().await?,
+            ),
+            Output::Mqtt => {
+                let mqtt_publisher_config = mqtt_publisher_config.as_ref().ok_or_else(|| {
+                    ConfigError::new("MQTT_BROKER_URL is required for OUTPUT=mqtt".into())
+                })?;
+                (
+                    Box::new(MqttPublisher::connect(
+                        mqtt_publisher_config,
+                        cycle_summary.clone(),
+                    )),
+                    local_store().await?,
+                )
+            }
+        };
+
+    if command == Command::Run
+        && SelfTestConfig::from_env()
+            .map_err(ConfigError::new)?
+            .enabled
+    {
+        let strict = exporter::validate_config(&config_client, &env_config)?.strict;
+        let nats_probe = match exporter_config.output {
+            Output::Nats => Some(NatsProbe::from_env()),
+            _ => None,
+        };
+        let report = tokio::task::block_in_place(|| {
+            self_test::run(
+                &homewizard_client,
+                nats_probe
+                    .as_ref()
+                    .map(|probe| probe as &dyn ConnectionProbe),
+                store.as_ref(),
+            )
+        });
+        report.conclude(strict)?;
+    }
+
+    let jitter_config = JitterConfig::from_env().map_err(ConfigError::new)?;
+    let mut exporter = Exporter::new(config_client, Box::new(homewizard_client), publisher, store)
+        .with_env_config(env_config)
+        .with_cycle_summary(cycle_summary.clone())
+        .with_jitter(&jitter_config)
+        .with_max_failed_cycles(exporter_config.max_failed_cycles);
+    if command == Command::Run && exporter_config.interval.is_some() {
+        exporter = exporter
+            .with_reload(reload::install()?)
+            .with_manual_trigger(manual_trigger::install()?);
+    }
+    if let Some(metrics_server_config) =
+        MetricsServerConfig::from_env().map_err(ConfigError::new)?
+    {
+        let mut sample_metrics = SampleMetrics::default()
+            .with_last_success(exporter.last_success_handle())
+            .with_device_registry(device_registry);
+        if metrics_server_config.self_metrics {
+            sample_metrics = sample_metrics.with_inventory();
+        }
+        sample_metrics
+            .serve(&metrics_server_config)
+            .map_err(ConfigError::new)?;
+        exporter = exporter.with_secondary_publisher(Box::new(sample_metrics));
+    }
+    if exporter_config.output != Output::Mqtt {
+        if let Some(mqtt_publisher_config) = &mqtt_publisher_config {
+            exporter = exporter.with_secondary_publisher(Box::new(MqttPublisher::connect(
+                mqtt_publisher_config,
+                cycle_summary.clone(),
+            )));
+        }
+    }
+    if let Some(influxdb_publisher_config) =
+        InfluxDbPublisherConfig::from_env().map_err(ConfigError::new)?
+    {
+        exporter = exporter
+            .with_secondary_publisher(Box::new(InfluxDbPublisher::new(influxdb_publisher_config)));
+    }
+    if let Some(remote_write_publisher_config) =
+        RemoteWritePublisherConfig::from_env().map_err(ConfigError::new)?
+    {
+        exporter = exporter.with_secondary_publisher(Box::new(RemoteWritePublisher::new(
+            remote_write_publisher_config,
+        )));
+    }
+    if let Some(textfile_config) = TextfileConfig::from_env().map_err(ConfigError::new)? {
+        exporter = exporter.with_secondary_publisher(Box::new(TextfilePublisher::new(
+            &textfile_config,
+            shutdown_signal.clone(),
+        )));
+    }
+    if let Some(webhook_publisher_config) =
+        WebhookPublisherConfig::from_env().map_err(ConfigError::new)?
+    {
+        exporter = exporter.with_secondary_publisher(Box::new(
+            WebhookPublisher::new(webhook_publisher_config).map_err(ConfigError::new)?,
+        ));
+    }
+    if let Some(otel_metrics_publisher) = OtelMetricsPublisher::from_env(cycle_summary.clone()) {
+        exporter = exporter.with_secondary_publisher(Box::new(otel_metrics_publisher));
+    }
+    if let Some(bigquery_publisher_config) =
+        BigQueryPublisherConfig::from_env().map_err(ConfigError::new)?
+    {
+        let bigquery_publisher = BigQueryPublisher::from_service_account(bigquery_publisher_config)
+            .await
+            .map_err(ConfigError::new)?;
+        exporter = exporter.with_secondary_publisher(Box::new(bigquery_publisher));
+    }
+
+    if let Some(spool_config) = SpoolConfig::from_env().map_err(ConfigError::new)? {
+        exporter = exporter.with_spool(MeasurementSpool::new(spool_config));
+    }
+
+    // only a long running exporter announces itself, with the location of its first cycle
+    #[cfg(feature = "mdns")]
+    let mut announcer = None;
+    #[cfg(feature = "mdns")]
+    if command == Command::Run && exporter_config.interval.is_some() {
+        let metrics_port = MetricsServerConfig::from_env()
+            .map_err(ConfigError::new)?
+            .map(|metrics_server_config| metrics_server_config.port());
+        if let Some(announce_config) =
+            AnnounceConfig::from_env(metrics_port).map_err(ConfigError::new)?
+        {
+            let started = Rc::new(Announcer::start(announce_config).map_err(ConfigError::new)?);
+            exporter = exporter.with_config_observer(started.clone());
+            announcer = Some(started);
+        }
+    }
+
+    exporter.restore_last_success();
+    exporter.replay_spool().await;
+    let result = match (command, exporter_config.interval) {
+        (Command::Measure { .. }, _) => exporter.run_once().await,
+        (_, None) => exporter.run(None, shutdown_signal).await,
+        (_, Some(interval)) => {
+            let supervisor_config = SupervisorConfig::from_env().map_err(ConfigError::new)?;
+            let exporter = &exporter;
+            supervisor::supervise(&supervisor_config, shutdown_signal.clone(), move || {
+                exporter.run(Some(interval), shutdown_signal.clone())
+            })
+            .await
+        }
+    };
+
+    #[cfg(feature = "mdns")]
+    if let Some(announcer) = announcer {
+        announcer.unregister();
+    }
+
+    result
+}
+
+#[cfg(feature = "tui")]
+fn run_tui(
+    config_client: &ConfigClient,
+    env_config: &EnvConfig,
+    homewizard_client: &HomewizardClient,
+    interval: Duration,
+    shutdown_signal: ShutdownSignal,
+) -> Result<(), Box<dyn Error>> {
+    jarvis_homewizard_exporter::tui::run(
         config_client,
-        nats_client,
-        state_client,
-        Box::new(homewizard_client),
-    )?;
-    let mut exporter_service = ExporterService::new(exporter_service_config);
+        env_config,
+        homewizard_client,
+        homewizard_client.registry_handle(),
+        interval,
+        shutdown_signal,
+    )
+}
 
-    exporter_service.run().await?;
+#[cfg(not(feature = "tui"))]
+fn run_tui(
+    _: &ConfigClient,
+    _: &EnvConfig,
+    _: &HomewizardClient,
+    _: Duration,
+    _: ShutdownSignal,
+) -> Result<(), Box<dyn Error>> {
+    Err(ConfigError::new(
+        "Built without the tui feature, build with --features tui to use the tui command".into(),
+    )
+    .into())
+}
+
+/// Checks the last successful measurement of the exporter serving `/health` on `METRICS_PORT`,
+/// or else the one in the state client, without discovering or requesting any device.
+async fn check_health(max_age: Duration) -> Result<(), Box<dyn Error>> {
+    let last_success = match MetricsServerConfig::from_env().map_err(ConfigError::new)? {
+        Some(metrics_server_config) => {
+            healthcheck::fetch_last_success(
+                &metrics_server_config.local_url("/health"),
+                Duration::from_secs(5),
+            )
+            .await?
+        }
+        None if env::var("MEASUREMENT_FILE_CONFIG_MAP_NAME").is_ok() => {
+            let last_measurements = local_store().await?.read()?;
+            LastSuccess::from_measurements(&last_measurements.unwrap_or_default())
+        }
+        None => {
+            return Err(ConfigError::new(
+                "healthcheck needs METRICS_PORT or MEASUREMENT_FILE_CONFIG_MAP_NAME".into(),
+            )
+            .into())
+        }
+    };
+
+    let reason = healthcheck::check(last_success.as_ref(), max_age, Utc::now())?;
+    println!("{}", reason);
 
     Ok(())
 }
+
+/// Store for outputs without NATS; the state client only gets used when explicitly configured.
+async fn local_store() -> Result<Box<dyn MeasurementStore>, Box<dyn Error>> {
+    if env::var("MEASUREMENT_FILE_CONFIG_MAP_NAME").is_ok() {
+        Ok(Box::new(StateClient::new(
+            StateClientConfig::from_env()
+                .await
+                .map_err(ConfigError::new)?,
+        )))
+    } else {
+        Ok(Box::new(InMemoryStore::default()))
+    }
+}
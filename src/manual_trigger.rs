@@ -0,0 +1,39 @@
+use std::io;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tracing::info;
+
+/// Tells the exporter to run a measurement cycle right away. A request made during a cycle is
+/// queued until that cycle finishes, and requests queued together run a single cycle.
+#[derive(Clone, Default)]
+pub struct TriggerSignal {
+    notify: Arc<Notify>,
+}
+
+impl TriggerSignal {
+    pub fn request(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Completes once a cycle is requested.
+    pub async fn requested(&self) {
+        self.notify.notified().await
+    }
+}
+
+/// Installs a SIGUSR1 handler that requests an immediate measurement cycle.
+pub fn install() -> io::Result<TriggerSignal> {
+    let mut user_defined = signal(SignalKind::user_defined1())?;
+
+    let trigger_signal = TriggerSignal::default();
+    let requests = trigger_signal.clone();
+    tokio::spawn(async move {
+        while user_defined.recv().await.is_some() {
+            info!("Received SIGUSR1, measuring right away...");
+            requests.request();
+        }
+    });
+
+    Ok(trigger_signal)
+}
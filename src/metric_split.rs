@@ -0,0 +1,209 @@
+use crate::homewizard_client::MeasuredDevice;
+use jarvis_lib::model::{Measurement, MetricType, Sample};
+
+/// Splits `measurement` into one with its counters, marked by a `-counters` source suffix, and
+/// one with its gauges, marked by `-gauges`, taking their ids from `ids`. A part without samples
+/// is left out. The sample ranges of `devices` in `measurement` are moved over to both parts.
+pub fn split(
+    measurement: Measurement,
+    ids: (String, String),
+    devices: &mut Vec<MeasuredDevice>,
+) -> Vec<Measurement> {
+    let is_counter: Vec<bool> = measurement
+        .samples
+        .iter()
+        .map(|sample| sample.metric_type == MetricType::Counter)
+        .collect();
+    let (counters, gauges): (Vec<Sample>, Vec<Sample>) = measurement
+        .samples
+        .iter()
+        .cloned()
+        .partition(|sample| sample.metric_type == MetricType::Counter);
+
+    let part = |id: String, suffix: &str, samples: Vec<Sample>| Measurement {
+        id,
+        source: format!("{}-{}", measurement.source, suffix),
+        location: measurement.location.clone(),
+        samples,
+        measured_at_time: measurement.measured_at_time,
+    };
+    let counters = part(ids.0, "counters", counters);
+    let gauges = part(ids.1, "gauges", gauges);
+
+    let (moved, mut kept): (Vec<MeasuredDevice>, Vec<MeasuredDevice>) = devices
+        .drain(..)
+        .partition(|device| device.measurement_id == measurement.id);
+    for device in moved {
+        for (part, counter) in [(&counters, true), (&gauges, false)] {
+            // a sample lands at the number of samples of its part before it
+            let position = |index: usize| {
+                is_counter[..index]
+                    .iter()
+                    .filter(|is_counter| **is_counter == counter)
+                    .count()
+            };
+            kept.push(MeasuredDevice {
+                measurement_id: part.id.clone(),
+                samples: position(device.samples.start)..position(device.samples.end),
+                ..device.clone()
+            });
+        }
+    }
+    *devices = kept;
+
+    vec![counters, gauges]
+        .into_iter()
+        .filter(|part| !part.samples.is_empty())
+        .collect()
+}
+
+/// Joins the measurements of a location back into one, so the samples of the previous cycle
+/// are found whether or not it was split.
+pub fn join<'a>(measurements: impl IntoIterator<Item = &'a Measurement>) -> Option<Measurement> {
+    measurements.into_iter().fold(
+        None,
+        |joined: Option<Measurement>, measurement| match joined {
+            Some(mut joined) => {
+                joined.samples.extend(measurement.samples.iter().cloned());
+                Some(joined)
+            }
+            None => Some(measurement.clone()),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard_client::DeviceInfoResponse;
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, SampleType};
+    use std::fs;
+
+    fn sample(sample_name: &str, metric_type: MetricType) -> Sample {
+        Sample {
+            entity_type: EntityType::Device,
+            entity_name: "HWE-SKT".into(),
+            sample_type: SampleType::ElectricityConsumption,
+            sample_name: sample_name.into(),
+            metric_type,
+            value: 1.0,
+        }
+    }
+
+    fn measurement(samples: Vec<Sample>) -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: "jarvis-homewizard-exporter".into(),
+            location: "My Home".into(),
+            samples,
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn measured_device(serial: &str, samples: std::ops::Range<usize>) -> MeasuredDevice {
+        let mut info: DeviceInfoResponse = serde_json::from_str(
+            &fs::read_to_string("tests/fixtures/responses/api-socket.json").unwrap(),
+        )
+        .unwrap();
+        info.serial = serial.into();
+        MeasuredDevice {
+            measurement_id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            info,
+            friendly_name: serial.into(),
+            samples,
+        }
+    }
+
+    #[test]
+    fn split_partitions_samples_by_metric_type() {
+        let mut devices = vec![
+            measured_device("3c39e72e33ce", 0..3),
+            measured_device("aabbccddeeff", 3..5),
+        ];
+
+        // act
+        let parts = split(
+            measurement(vec![
+                sample("Bonenmaler import", MetricType::Counter),
+                sample("Bonenmaler", MetricType::Gauge),
+                sample("Bonenmaler export", MetricType::Counter),
+                sample("Koffie", MetricType::Gauge),
+                sample("Koffie import", MetricType::Counter),
+            ]),
+            ("counters-id".into(), "gauges-id".into()),
+            &mut devices,
+        );
+
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].id, "counters-id");
+        assert_eq!(parts[0].source, "jarvis-homewizard-exporter-counters");
+        assert!(parts[0]
+            .samples
+            .iter()
+            .all(|sample| sample.metric_type == MetricType::Counter));
+        assert_eq!(parts[0].samples.len(), 3);
+        assert_eq!(parts[1].id, "gauges-id");
+        assert_eq!(parts[1].source, "jarvis-homewizard-exporter-gauges");
+        assert!(parts[1]
+            .samples
+            .iter()
+            .all(|sample| sample.metric_type == MetricType::Gauge));
+        assert_eq!(parts[1].samples.len(), 2);
+        assert!(parts
+            .iter()
+            .all(|part| part.location == "My Home"
+                && part.measured_at_time == parts[0].measured_at_time));
+        let ranges: Vec<(&str, &str, std::ops::Range<usize>)> = devices
+            .iter()
+            .map(|device| {
+                (
+                    device.info.serial.as_str(),
+                    device.measurement_id.as_str(),
+                    device.samples.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                ("3c39e72e33ce", "counters-id", 0..2),
+                ("3c39e72e33ce", "gauges-id", 0..1),
+                ("aabbccddeeff", "counters-id", 2..3),
+                ("aabbccddeeff", "gauges-id", 1..2),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_leaves_out_part_without_samples() {
+        // act
+        let parts = split(
+            measurement(vec![sample("Bonenmaler import", MetricType::Counter)]),
+            ("counters-id".into(), "gauges-id".into()),
+            &mut vec![],
+        );
+
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].id, "counters-id");
+    }
+
+    #[test]
+    fn join_puts_samples_of_parts_back_together() {
+        let parts = split(
+            measurement(vec![
+                sample("Bonenmaler import", MetricType::Counter),
+                sample("Bonenmaler", MetricType::Gauge),
+            ]),
+            ("counters-id".into(), "gauges-id".into()),
+            &mut vec![],
+        );
+
+        // act
+        let joined = join(&parts).unwrap();
+
+        assert_eq!(joined.samples.len(), 2);
+        assert_eq!(joined.location, "My Home");
+        assert!(join(Vec::new()).is_none());
+    }
+}
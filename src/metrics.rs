@@ -0,0 +1,809 @@
+use crate::apparent_power::is_apparent_power;
+use crate::daily_consumption::is_daily_consumption;
+use crate::derived::{
+    is_cost, is_device_reachable, is_energy_delta, is_fetch_failures, is_water_liters,
+};
+use crate::device_registry::DeviceRegistry;
+use crate::exporter::{LastSuccess, MeasurementPublisher};
+use crate::sample_cap::is_dropped_samples;
+use async_trait::async_trait;
+use chrono::Utc;
+use jarvis_lib::model::{EntityType, Measurement, MetricType, Sample, SampleType};
+use std::env;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{debug, info, warn};
+
+pub struct MetricsServerConfig {
+    address: SocketAddr,
+    /// Also exposes metrics about the exporter itself, like the device inventory.
+    pub self_metrics: bool,
+}
+
+impl MetricsServerConfig {
+    pub fn new(address: SocketAddr, self_metrics: bool) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "MetricsServerConfig::new(address: {}, self_metrics: {})",
+            address, self_metrics
+        );
+        Ok(Self {
+            address,
+            self_metrics,
+        })
+    }
+
+    /// Reads `METRICS_PORT` and `SELF_METRICS`; returns no config when `METRICS_PORT` isn't set,
+    /// since serving metrics is optional.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let port: u16 = match env::var("METRICS_PORT") {
+            Ok(port) => port.parse()?,
+            Err(_) => return Ok(None),
+        };
+        let self_metrics = env::var("SELF_METRICS").map_or(false, |enabled| enabled == "true");
+
+        Ok(Some(Self::new(
+            SocketAddr::from(([0, 0, 0, 0], port)),
+            self_metrics,
+        )?))
+    }
+
+    pub fn port(&self) -> u16 {
+        self.address.port()
+    }
+
+    /// Url of `path` on the server as reached from the same host.
+    pub fn local_url(&self, path: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.address.port(), path)
+    }
+}
+
+/// A metric family samples are exposed in, by the kind of sample.
+pub(crate) struct Family {
+    pub(crate) name: &'static str,
+    pub(crate) help: &'static str,
+    pub(crate) metric_type: &'static str,
+}
+
+pub(crate) fn family(sample: &Sample) -> Option<Family> {
+    if is_cost(sample) {
+        return Some(Family {
+            name: "homewizard_cost_total",
+            help: "Running cost of the consumption at the configured prices.",
+            metric_type: "counter",
+        });
+    }
+    if is_energy_delta(sample) {
+        return Some(Family {
+            name: "homewizard_electricity_cycle_joules",
+            help: "Electricity imported or exported since the previous cycle in joules.",
+            metric_type: "gauge",
+        });
+    }
+    if is_daily_consumption(sample) {
+        return Some(Family {
+            name: "homewizard_consumption_today",
+            help:
+                "Consumption since local midnight, in joules, or in cubic meters for gas and water.",
+            metric_type: "gauge",
+        });
+    }
+    if is_device_reachable(sample) {
+        return Some(Family {
+            name: "homewizard_device_reachable",
+            help: "Whether the device was read successfully in the last cycle, 1 or 0.",
+            metric_type: "gauge",
+        });
+    }
+    if is_apparent_power(sample) {
+        return Some(Family {
+            name: "homewizard_electricity_apparent_power_volt_amperes",
+            help: "Apparent power in volt-amperes, in total or of a single phase.",
+            metric_type: "gauge",
+        });
+    }
+    if is_dropped_samples(sample) {
+        return Some(Family {
+            name: "homewizard_dropped_samples",
+            help: "Samples dropped from the measurement for exceeding maxSamplesPerMeasurement.",
+            metric_type: "gauge",
+        });
+    }
+    if is_fetch_failures(sample) {
+        return Some(Family {
+            name: "homewizard_device_fetch_failures_total",
+            help: "Failed fetches of the device so far.",
+            metric_type: "counter",
+        });
+    }
+    if is_water_liters(sample) {
+        return Some(Family {
+            name: "homewizard_water_consumption_liters_total",
+            help: "Total water consumption in liters.",
+            metric_type: "counter",
+        });
+    }
+
+    let (name, help, metric_type) = match (&sample.sample_type, &sample.metric_type) {
+        (SampleType::ElectricityConsumption, MetricType::Counter) => (
+            "homewizard_electricity_consumption_joules_total",
+            "Total imported electricity in joules.",
+            "counter",
+        ),
+        (SampleType::ElectricityProduction, MetricType::Counter) => (
+            "homewizard_electricity_production_joules_total",
+            "Total exported electricity in joules.",
+            "counter",
+        ),
+        (SampleType::ElectricityConsumption, MetricType::Gauge) => (
+            "homewizard_electricity_consumption_watts",
+            "Active power in watts, negative while exporting.",
+            "gauge",
+        ),
+        // gas meters connected to a P1 meter report m3, the derived energy equivalent is a tariff
+        (SampleType::GasConsumption, MetricType::Counter)
+            if matches!(sample.entity_type, EntityType::Device) =>
+        {
+            (
+                "homewizard_gas_consumption_cubic_meters_total",
+                "Total gas consumption in cubic meters.",
+                "counter",
+            )
+        }
+        (SampleType::GasConsumption, MetricType::Counter) => (
+            "homewizard_gas_energy_equivalent_joules_total",
+            "Energy equivalent of the consumed gas in joules.",
+            "counter",
+        ),
+        (SampleType::GasConsumption, MetricType::Gauge) => (
+            "homewizard_gas_flow_cubic_meters_per_hour",
+            "Gas flow in cubic meters per hour between the last two gas readings.",
+            "gauge",
+        ),
+        (SampleType::WaterConsumption, MetricType::Counter) => (
+            "homewizard_water_consumption_cubic_meters_total",
+            "Total water consumption in cubic meters.",
+            "counter",
+        ),
+        (SampleType::WaterConsumption, MetricType::Gauge) => (
+            "homewizard_water_consumption_cubic_meters_per_hour",
+            "Active water flow in cubic meters per hour.",
+            "gauge",
+        ),
+        _ => return None,
+    };
+
+    Some(Family {
+        name,
+        help,
+        metric_type,
+    })
+}
+
+/// Holds the samples of the last published measurements and renders them in the Prometheus text
+/// exposition format. Publishing a measurement replaces all samples of its location, so devices
+/// that weren't measured in the last cycle disappear instead of reporting stale values.
+#[derive(Clone, Default)]
+pub struct SampleMetrics {
+    measurements: Arc<Mutex<Vec<Measurement>>>,
+    last_success: Arc<Mutex<Option<LastSuccess>>>,
+    device_registry: Arc<Mutex<DeviceRegistry>>,
+    inventory: bool,
+}
+
+impl SampleMetrics {
+    /// Reports the last successful measurement on `/health`.
+    pub fn with_last_success(mut self, last_success: Arc<Mutex<Option<LastSuccess>>>) -> Self {
+        self.last_success = last_success;
+        self
+    }
+
+    /// Lists the devices in `device_registry` on `/debug/devices`.
+    pub fn with_device_registry(mut self, device_registry: Arc<Mutex<DeviceRegistry>>) -> Self {
+        self.device_registry = device_registry;
+        self
+    }
+
+    /// Exposes the number of devices of every product type requested and read in the last
+    /// cycle, from the device registry.
+    pub fn with_inventory(mut self) -> Self {
+        self.inventory = true;
+        self
+    }
+
+    /// The last successful measurement and its age in seconds as json, both null before the
+    /// first one.
+    pub fn render_health(&self) -> String {
+        let last_success = self.last_success.lock().unwrap().clone();
+        let age_seconds = last_success
+            .as_ref()
+            .map(|last_success| last_success.age(Utc::now()).as_secs());
+
+        serde_json::json!({
+            "lastSuccess": last_success,
+            "lastSuccessAgeSeconds": age_seconds,
+        })
+        .to_string()
+    }
+
+    pub fn render(&self) -> String {
+        let measurements = self.measurements.lock().unwrap();
+        let mut families: Vec<(Family, Vec<String>)> = vec![];
+
+        for measurement in measurements.iter() {
+            for sample in measurement.samples.iter() {
+                let family = match family(sample) {
+                    Some(family) => family,
+                    None => continue,
+                };
+                let series = format!(
+                    "{}{{location=\"{}\",entity_name=\"{}\",sample_name=\"{}\",sample_type=\"{}\"}} {}",
+                    family.name,
+                    escape_label_value(&measurement.location),
+                    escape_label_value(&sample.entity_name),
+                    escape_label_value(&sample.sample_name),
+                    sample_type_label(&sample.sample_type),
+                    format_value(sample.value)
+                );
+
+                match families.iter_mut().find(|(f, _)| f.name == family.name) {
+                    Some((_, lines)) => lines.push(series),
+                    None => families.push((family, vec![series])),
+                }
+            }
+        }
+
+        if self.inventory {
+            families.extend(self.inventory_families());
+        }
+
+        let mut output = String::new();
+        for (family, lines) in families {
+            let _ = writeln!(output, "# HELP {} {}", family.name, family.help);
+            let _ = writeln!(output, "# TYPE {} {}", family.name, family.metric_type);
+            for line in lines {
+                let _ = writeln!(output, "{}", line);
+            }
+        }
+
+        output
+    }
+
+    fn inventory_families(&self) -> Vec<(Family, Vec<String>)> {
+        let inventory = self.device_registry.lock().unwrap().inventory();
+        let lines = |name: &str, count: fn(&(usize, usize)) -> usize| -> Vec<String> {
+            inventory
+                .iter()
+                .map(|(product_type, counts)| {
+                    format!(
+                        "{}{{product_type=\"{}\"}} {}",
+                        name,
+                        escape_label_value(product_type),
+                        count(counts)
+                    )
+                })
+                .collect()
+        };
+
+        vec![
+            (
+                Family {
+                    name: "homewizard_devices_discovered",
+                    help: "Devices requested in the last cycle by product type.",
+                    metric_type: "gauge",
+                },
+                lines("homewizard_devices_discovered", |(discovered, _)| {
+                    *discovered
+                }),
+            ),
+            (
+                Family {
+                    name: "homewizard_devices_read",
+                    help: "Devices read successfully in the last cycle by product type.",
+                    metric_type: "gauge",
+                },
+                lines("homewizard_devices_read", |(_, read)| *read),
+            ),
+        ]
+    }
+
+    /// Serves the rendered metrics on `/metrics`, the last successful measurement on `/health`
+    /// and the device registry on `/debug/devices` from a background thread.
+    pub fn serve(&self, config: &MetricsServerConfig) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(config.address)?;
+        info!(
+            "Serving sample metrics on http://{}/metrics",
+            config.address
+        );
+
+        let metrics = self.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        if let Err(e) = metrics.respond(stream) {
+                            warn!("Failed serving metrics request: {}", e);
+                        }
+                    }
+                    Err(e) => warn!("Failed accepting metrics connection: {}", e),
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+        let mut request_line = String::new();
+        BufReader::new(stream.try_clone()?).read_line(&mut request_line)?;
+        let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+        let (status, content_type, body) = self.route(path);
+
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+
+        Ok(())
+    }
+
+    /// Status, content type and body of the response to a request of `path`.
+    fn route(&self, path: &str) -> (&'static str, &'static str, String) {
+        match path {
+            "/metrics" => ("200 OK", "text/plain; version=0.0.4", self.render()),
+            "/health" => ("200 OK", "application/json", self.render_health()),
+            "/debug/devices" => (
+                "200 OK",
+                "application/json",
+                self.device_registry.lock().unwrap().render(),
+            ),
+            _ => ("404 Not Found", "text/plain; version=0.0.4", String::new()),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl MeasurementPublisher for SampleMetrics {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        let mut measurements = self.measurements.lock().unwrap();
+        measurements.retain(|m| m.location != measurement.location);
+        measurements.push(measurement.clone());
+
+        Ok(())
+    }
+}
+
+pub(crate) fn sample_type_label(sample_type: &SampleType) -> String {
+    let mut label = String::new();
+    for (i, c) in format!("{:?}", sample_type).chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            label.push('_');
+        }
+        label.extend(c.to_lowercase());
+    }
+
+    label
+}
+
+pub(crate) fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value.is_infinite() {
+        if value > 0.0 { "+Inf" } else { "-Inf" }.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::discovery::StaticDiscoverer;
+    use crate::homewizard_client::{HomewizardClient, HomewizardClientConfig, HomewizardDevice};
+    use crate::model::Config;
+    use crate::test_support::{FakeDeviceServer, FakeResponse};
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::measurement_client::MeasurementClient;
+    use std::collections::HashSet;
+    use std::fs;
+    use std::net::Ipv4Addr;
+
+    fn fixture(name: &str) -> String {
+        fs::read_to_string(format!("tests/fixtures/responses/{}", name)).unwrap()
+    }
+
+    fn measurement(location: &str, samples: Vec<Sample>) -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: location.into(),
+            samples,
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn sample(
+        entity_type: EntityType,
+        entity_name: &str,
+        sample_type: SampleType,
+        sample_name: &str,
+        metric_type: MetricType,
+        value: f64,
+    ) -> Sample {
+        Sample {
+            entity_type,
+            entity_name: entity_name.into(),
+            sample_type,
+            sample_name: sample_name.into(),
+            metric_type,
+            value,
+        }
+    }
+
+    #[tokio::test]
+    async fn renders_published_samples_as_exposition_text() {
+        let metrics = SampleMetrics::default();
+
+        // act
+        metrics
+            .publish(&measurement(
+                "My Home",
+                vec![
+                    sample(
+                        EntityType::Tariff,
+                        "HWE-P1",
+                        SampleType::ElectricityConsumption,
+                        "t1 import",
+                        MetricType::Counter,
+                        38989839600.0,
+                    ),
+                    sample(
+                        EntityType::Tariff,
+                        "HWE-P1",
+                        SampleType::ElectricityProduction,
+                        "t1 export",
+                        MetricType::Counter,
+                        7200.0,
+                    ),
+                    sample(
+                        EntityType::Device,
+                        "HWE-P1",
+                        SampleType::ElectricityConsumption,
+                        "P1 meter",
+                        MetricType::Gauge,
+                        -543.0,
+                    ),
+                    sample(
+                        EntityType::Device,
+                        "HWE-SKT",
+                        SampleType::ElectricityConsumption,
+                        "Bonenmaler \"koffie\"",
+                        MetricType::Gauge,
+                        98.1,
+                    ),
+                    sample(
+                        EntityType::Device,
+                        "HWE-WTR",
+                        SampleType::WaterConsumption,
+                        "Watermeter",
+                        MetricType::Counter,
+                        123.456,
+                    ),
+                    sample(
+                        EntityType::Device,
+                        "HWE-WTR",
+                        SampleType::WaterConsumption,
+                        "Watermeter",
+                        MetricType::Gauge,
+                        0.45,
+                    ),
+                ],
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            metrics.render(),
+            r#"# HELP homewizard_electricity_consumption_joules_total Total imported electricity in joules.
+# TYPE homewizard_electricity_consumption_joules_total counter
+homewizard_electricity_consumption_joules_total{location="My Home",entity_name="HWE-P1",sample_name="t1 import",sample_type="electricity_consumption"} 38989839600
+# HELP homewizard_electricity_production_joules_total Total exported electricity in joules.
+# TYPE homewizard_electricity_production_joules_total counter
+homewizard_electricity_production_joules_total{location="My Home",entity_name="HWE-P1",sample_name="t1 export",sample_type="electricity_production"} 7200
+# HELP homewizard_electricity_consumption_watts Active power in watts, negative while exporting.
+# TYPE homewizard_electricity_consumption_watts gauge
+homewizard_electricity_consumption_watts{location="My Home",entity_name="HWE-P1",sample_name="P1 meter",sample_type="electricity_consumption"} -543
+homewizard_electricity_consumption_watts{location="My Home",entity_name="HWE-SKT",sample_name="Bonenmaler \"koffie\"",sample_type="electricity_consumption"} 98.1
+# HELP homewizard_water_consumption_cubic_meters_total Total water consumption in cubic meters.
+# TYPE homewizard_water_consumption_cubic_meters_total counter
+homewizard_water_consumption_cubic_meters_total{location="My Home",entity_name="HWE-WTR",sample_name="Watermeter",sample_type="water_consumption"} 123.456
+# HELP homewizard_water_consumption_cubic_meters_per_hour Active water flow in cubic meters per hour.
+# TYPE homewizard_water_consumption_cubic_meters_per_hour gauge
+homewizard_water_consumption_cubic_meters_per_hour{location="My Home",entity_name="HWE-WTR",sample_name="Watermeter",sample_type="water_consumption"} 0.45
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn render_exposes_cost_counters_in_their_own_family() {
+        let metrics = SampleMetrics::default();
+        metrics
+            .publish(&measurement(
+                "My Home",
+                vec![sample(
+                    EntityType::Tariff,
+                    "HWE-P1",
+                    SampleType::ElectricityConsumption,
+                    "t1 import cost",
+                    MetricType::Counter,
+                    2707.63,
+                )],
+            ))
+            .await
+            .unwrap();
+
+        // act
+        let rendered = metrics.render();
+
+        assert_eq!(
+            rendered,
+            r#"# HELP homewizard_cost_total Running cost of the consumption at the configured prices.
+# TYPE homewizard_cost_total counter
+homewizard_cost_total{location="My Home",entity_name="HWE-P1",sample_name="t1 import cost",sample_type="electricity_consumption"} 2707.63
+"#
+        );
+    }
+
+    #[tokio::test]
+    async fn render_exposes_device_reachability_in_its_own_family() {
+        let metrics = SampleMetrics::default();
+        metrics
+            .publish(&measurement(
+                "My Home",
+                vec![sample(
+                    EntityType::Device,
+                    "3c39e72e33ce",
+                    SampleType::ElectricityConsumption,
+                    "Bonenmaler reachable",
+                    MetricType::Gauge,
+                    0.0,
+                )],
+            ))
+            .await
+            .unwrap();
+
+        // act
+        let rendered = metrics.render();
+
+        assert_eq!(
+            rendered,
+            r#"# HELP homewizard_device_reachable Whether the device was read successfully in the last cycle, 1 or 0.
+# TYPE homewizard_device_reachable gauge
+homewizard_device_reachable{location="My Home",entity_name="3c39e72e33ce",sample_name="Bonenmaler reachable",sample_type="electricity_consumption"} 0
+"#
+        );
+    }
+
+    #[test]
+    fn render_health_reports_last_success_and_its_age() {
+        let measured_at = Utc::now() - chrono::Duration::seconds(90);
+        let metrics = SampleMetrics::default();
+        let empty = metrics.render_health();
+        let metrics = metrics.with_last_success(Arc::new(Mutex::new(Some(LastSuccess {
+            measured_at,
+            measurement_id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            samples: 5,
+        }))));
+
+        // act
+        let health: serde_json::Value = serde_json::from_str(&metrics.render_health()).unwrap();
+
+        assert_eq!(
+            empty,
+            r#"{"lastSuccess":null,"lastSuccessAgeSeconds":null}"#
+        );
+        assert_eq!(
+            health["lastSuccess"]["measurementId"],
+            "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10"
+        );
+        assert_eq!(health["lastSuccess"]["samples"], 5);
+        assert_eq!(health["lastSuccessAgeSeconds"], 90);
+    }
+
+    #[tokio::test]
+    async fn publishing_replaces_previous_samples_of_the_location() {
+        let metrics = SampleMetrics::default();
+        let socket = |value| {
+            sample(
+                EntityType::Device,
+                "HWE-SKT",
+                SampleType::ElectricityConsumption,
+                "Bonenmaler",
+                MetricType::Gauge,
+                value,
+            )
+        };
+
+        // act
+        metrics
+            .publish(&measurement("My Home", vec![socket(98.1)]))
+            .await
+            .unwrap();
+        metrics
+            .publish(&measurement("My Home", vec![socket(1450.0)]))
+            .await
+            .unwrap();
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("} 1450\n"));
+        assert!(!rendered.contains("} 98.1\n"));
+    }
+
+    #[test]
+    fn route_lists_registered_devices_after_a_cycle() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond("/api", FakeResponse::json(&fixture("api-socket.json")))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")),
+            );
+        let socket = HomewizardDevice {
+            fullname: "energysocket-3C39E7._hwenergy._tcp.local.".into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port: server.address().port(),
+            hostname: Some("energysocket-3c39e7.local.".into()),
+            api_path: None,
+        };
+        let unreachable = HomewizardDevice {
+            fullname: "p1meter-0A8B3E._hwenergy._tcp.local.".into(),
+            ip_addresses: HashSet::new(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default()
+                .with_url_template("http://jarvis:secret@{ip}:{port}".into()),
+        )
+        .with_discoverer(Box::new(StaticDiscoverer::new(vec![socket, unreachable])));
+        let metrics =
+            SampleMetrics::default().with_device_registry(homewizard_client.registry_handle());
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+        let measurement = homewizard_client.get_measurements(config, None).unwrap()[0].clone();
+
+        // act
+        let (status, content_type, body) = metrics.route("/debug/devices");
+
+        assert_eq!(status, "200 OK");
+        assert_eq!(content_type, "application/json");
+        assert!(!body.contains("secret"));
+        assert!(!body.contains("My Wi-Fi"));
+        let devices: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let socket = &devices["devices"][0];
+        assert_eq!(socket["name"], "energysocket-3C39E7._hwenergy._tcp.local.");
+        assert_eq!(socket["serial"], "3c39e72e33ce");
+        assert_eq!(socket["friendlyName"], "Bonenmaler");
+        assert_eq!(socket["productType"], "HWE-SKT");
+        assert_eq!(socket["hostname"], "energysocket-3c39e7.local.");
+        assert_eq!(socket["port"], server.address().port());
+        assert_eq!(
+            socket["addresses"],
+            serde_json::json!([format!("127.0.0.1:{}", server.address().port())])
+        );
+        assert_eq!(
+            socket["url"],
+            format!("http://redacted@127.0.0.1:{}", server.address().port())
+        );
+        assert_eq!(socket["lastSeen"], socket["lastFetch"]["at"]);
+        assert_eq!(socket["lastFetch"]["status"], "ok");
+        assert!(socket["lastFetch"]["latencyMs"].is_u64());
+        assert_eq!(
+            socket["samples"],
+            serde_json::to_value(&measurement.samples).unwrap()
+        );
+        let p1_meter = &devices["devices"][1];
+        assert_eq!(p1_meter["name"], "p1meter-0A8B3E._hwenergy._tcp.local.");
+        assert_eq!(p1_meter["serial"], serde_json::Value::Null);
+        assert_eq!(p1_meter["hostname"], serde_json::Value::Null);
+        assert_eq!(p1_meter["port"], 80);
+        assert_eq!(p1_meter["lastSeen"], serde_json::Value::Null);
+        assert_eq!(p1_meter["lastFetch"]["status"], "failed");
+        assert!(p1_meter["lastFetch"]["error"].is_string());
+        assert_eq!(p1_meter["samples"], serde_json::json!([]));
+        assert_eq!(metrics.route("/debug").0, "404 Not Found");
+    }
+
+    #[test]
+    fn render_counts_devices_discovered_and_read_by_product_type() {
+        let socket_server = FakeDeviceServer::start();
+        socket_server
+            .respond("/api", FakeResponse::json(&fixture("api-socket.json")))
+            .respond(
+                "/api/v1/data",
+                FakeResponse::json(&fixture("data-socket-firmware-3.json")),
+            );
+        let p1_server = FakeDeviceServer::start();
+        p1_server
+            .respond("/api", FakeResponse::json(&fixture("api-p1.json")))
+            .respond("/api/v1/data", FakeResponse::status(500));
+        let device = |fullname: &str, port: u16| HomewizardDevice {
+            fullname: fullname.into(),
+            ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+            port,
+            hostname: None,
+            api_path: None,
+        };
+        let unreachable = HomewizardDevice {
+            fullname: "p1meter-0A8B3E._hwenergy._tcp.local.".into(),
+            ip_addresses: HashSet::new(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        };
+        let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default())
+            .with_discoverer(Box::new(StaticDiscoverer::new(vec![
+                device(
+                    "energysocket-3C39E7._hwenergy._tcp.local.",
+                    socket_server.address().port(),
+                ),
+                device(
+                    "p1meter-3C39E7._hwenergy._tcp.local.",
+                    p1_server.address().port(),
+                ),
+                unreachable,
+            ])));
+        let metrics = SampleMetrics::default()
+            .with_device_registry(homewizard_client.registry_handle())
+            .with_inventory();
+        let config = Config {
+            location: "My Home".into(),
+            ..Default::default()
+        };
+        homewizard_client.get_measurements(config, None).unwrap();
+
+        // act
+        let output = metrics.render();
+
+        assert!(output.contains("# TYPE homewizard_devices_discovered gauge\n"));
+        assert!(output.contains("homewizard_devices_discovered{product_type=\"HWE-SKT\"} 1\n"));
+        assert!(output.contains("homewizard_devices_discovered{product_type=\"HWE-P1\"} 1\n"));
+        assert!(output.contains("homewizard_devices_discovered{product_type=\"unknown\"} 1\n"));
+        assert!(output.contains("homewizard_devices_read{product_type=\"HWE-SKT\"} 1\n"));
+        assert!(output.contains("homewizard_devices_read{product_type=\"HWE-P1\"} 0\n"));
+        assert!(output.contains("homewizard_devices_read{product_type=\"unknown\"} 0\n"));
+        assert!(!SampleMetrics::default()
+            .with_device_registry(homewizard_client.registry_handle())
+            .render()
+            .contains("homewizard_devices_discovered"));
+    }
+
+    #[test]
+    fn format_value_uses_prometheus_spelling_for_special_values() {
+        assert_eq!(format_value(f64::NAN), "NaN");
+        assert_eq!(format_value(f64::INFINITY), "+Inf");
+        assert_eq!(format_value(f64::NEG_INFINITY), "-Inf");
+        assert_eq!(format_value(0.45), "0.45");
+    }
+}
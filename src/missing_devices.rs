@@ -0,0 +1,188 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+struct MissingDevice {
+    /// Time of the first cycle the device wasn't read in.
+    missing_since: DateTime<Utc>,
+    consecutive_cycles: u32,
+    reported: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum MissingDeviceEventType {
+    Missing,
+    Returned,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingDeviceEvent {
+    pub event_type: MissingDeviceEventType,
+    pub serial: String,
+    pub friendly_name: String,
+    pub missing_since: DateTime<Utc>,
+    pub missing_seconds: i64,
+    pub consecutive_cycles: u32,
+}
+
+/// Tracks the configured devices that weren't read in the last cycles, so devices expected by the
+/// config that never show up - unlike [`crate::device_health::DeviceHealthTracker`], which only
+/// knows devices seen before - are reported once when they go missing and once when they return.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct MissingDevicesTracker {
+    devices: BTreeMap<String, MissingDevice>,
+}
+
+impl MissingDevicesTracker {
+    /// Updates the tracker with the serials read in a cycle and returns the resulting state
+    /// changes of the `configured` devices, given by serial with their friendly name.
+    pub fn observe(
+        &mut self,
+        measured_at: DateTime<Utc>,
+        read: &BTreeSet<&str>,
+        configured: &BTreeMap<&str, &str>,
+        missing_cycles: u32,
+    ) -> Vec<MissingDeviceEvent> {
+        // devices removed from the config are no longer expected
+        self.devices
+            .retain(|serial, _| configured.contains_key(serial.as_str()));
+
+        let mut events = vec![];
+        for (serial, friendly_name) in configured {
+            let event = |event_type, missing: &MissingDevice| MissingDeviceEvent {
+                event_type,
+                serial: serial.to_string(),
+                friendly_name: friendly_name.to_string(),
+                missing_since: missing.missing_since,
+                missing_seconds: (measured_at - missing.missing_since).num_seconds(),
+                consecutive_cycles: missing.consecutive_cycles,
+            };
+
+            if read.contains(serial) {
+                if let Some(missing) = self.devices.remove(*serial) {
+                    if missing.reported {
+                        events.push(event(MissingDeviceEventType::Returned, &missing));
+                    }
+                }
+                continue;
+            }
+
+            let missing = self
+                .devices
+                .entry(serial.to_string())
+                .or_insert(MissingDevice {
+                    missing_since: measured_at,
+                    consecutive_cycles: 0,
+                    reported: false,
+                });
+            missing.consecutive_cycles += 1;
+
+            if !missing.reported && missing.consecutive_cycles >= missing_cycles {
+                missing.reported = true;
+                events.push(event(MissingDeviceEventType::Missing, missing));
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn observe_reports_missing_once_after_threshold_and_return_once() {
+        let mut tracker = MissingDevicesTracker::default();
+        let start = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let cycle = |n: i64| start + Duration::minutes(5 * n);
+        let configured: BTreeMap<&str, &str> = vec![
+            ("3c39e72d7a68", "Watermeter"),
+            ("3c39e72e33ce", "Bonenmaler"),
+        ]
+        .into_iter()
+        .collect();
+        let only_socket: BTreeSet<&str> = vec!["3c39e72e33ce"].into_iter().collect();
+        let both: BTreeSet<&str> = configured.keys().copied().collect();
+
+        // act
+        let events: Vec<Vec<MissingDeviceEvent>> = vec![
+            &only_socket,
+            &only_socket,
+            &only_socket,
+            &only_socket,
+            &both,
+            &both,
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(n, read)| tracker.observe(cycle(n as i64), read, &configured, 3))
+        .collect();
+
+        assert!(events[0].is_empty());
+        assert!(events[1].is_empty());
+        assert_eq!(
+            events[2],
+            vec![MissingDeviceEvent {
+                event_type: MissingDeviceEventType::Missing,
+                serial: "3c39e72d7a68".into(),
+                friendly_name: "Watermeter".into(),
+                missing_since: cycle(0),
+                missing_seconds: 600,
+                consecutive_cycles: 3,
+            }]
+        );
+        assert!(events[3].is_empty());
+        assert_eq!(
+            events[4],
+            vec![MissingDeviceEvent {
+                event_type: MissingDeviceEventType::Returned,
+                serial: "3c39e72d7a68".into(),
+                friendly_name: "Watermeter".into(),
+                missing_since: cycle(0),
+                missing_seconds: 1200,
+                consecutive_cycles: 4,
+            }]
+        );
+        assert!(events[5].is_empty());
+    }
+
+    #[test]
+    fn observe_forgets_device_returning_below_threshold() {
+        let mut tracker = MissingDevicesTracker::default();
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let configured: BTreeMap<&str, &str> =
+            vec![("3c39e72d7a68", "Watermeter")].into_iter().collect();
+        let read: BTreeSet<&str> = vec!["3c39e72d7a68"].into_iter().collect();
+
+        // act
+        tracker.observe(now, &BTreeSet::new(), &configured, 2);
+        let returned = tracker.observe(now, &read, &configured, 2);
+        let missing_again = tracker.observe(now, &BTreeSet::new(), &configured, 2);
+
+        assert!(returned.is_empty());
+        assert!(missing_again.is_empty());
+    }
+
+    #[test]
+    fn observe_stops_tracking_devices_removed_from_config() {
+        let mut tracker = MissingDevicesTracker::default();
+        let now = Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap();
+        let configured: BTreeMap<&str, &str> =
+            vec![("3c39e72d7a68", "Watermeter")].into_iter().collect();
+        tracker.observe(now, &BTreeSet::new(), &configured, 1);
+
+        // act
+        tracker.observe(now, &BTreeSet::new(), &BTreeMap::new(), 1);
+        let events = tracker.observe(now, &BTreeSet::new(), &configured, 1);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, MissingDeviceEventType::Missing);
+    }
+}
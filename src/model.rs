@@ -1,18 +1,1184 @@
-use std::collections::HashMap;
+use crate::allowed_cidrs::Cidr;
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use chrono_tz::Tz;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::net::SocketAddrV4;
 
 use jarvis_lib::config_client::SetDefaults;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// The exporter's config file.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
+    /// Location the measurement of every device not assigned to one of `locations` gets
+    /// published for.
     pub location: String,
+    /// Further locations measured by the same exporter, each getting a measurement of its own.
+    #[serde(default)]
+    pub locations: Vec<LocationConfig>,
+    /// Addresses of devices at the main location to measure without discovery, or to pin a
+    /// serial and a token to.
+    #[serde(default)]
+    pub devices: Vec<StaticDevice>,
+    /// Ipv4 ranges like `192.168.1.0/24` that discovered devices are measured in; addresses
+    /// outside all of them are dropped. Empty measures devices at any address.
+    #[serde(default)]
+    pub allowed_cidrs: Vec<String>,
+    /// Friendly names of devices by serial, used as sample name instead of the product name.
     #[serde(default)]
     pub names: HashMap<String, String>,
+    /// Normalizes friendly names into lowercase ascii slugs, for systems that can't handle
+    /// spaces, punctuation or unicode in sample names.
+    #[serde(default)]
+    pub name_normalization: Option<NameNormalizationConfig>,
+    /// Most characters a friendly name - configured or the product name - keeps, after control
+    /// characters got replaced and whitespace trimmed; 64 when not set.
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub name_max_length: Option<usize>,
+    /// What the entity name of the samples of a device is, unless set for its serial in
+    /// `entity_names`.
+    #[serde(default)]
+    pub entity_name: EntityNameSource,
+    /// What the entity name of the samples of devices by serial is.
+    #[serde(default)]
+    pub entity_names: HashMap<String, EntityNameSource>,
+    /// Publishes an event when a previously seen device stops or resumes responding.
+    #[serde(default)]
+    pub device_events: Option<DeviceEventsConfig>,
+    /// Publishes an event when the firmware version of a device changed since it was last seen.
+    #[serde(default)]
+    pub firmware_events: Option<FirmwareEventsConfig>,
+    /// Publishes an event when a device configured by serial isn't read for a number of
+    /// consecutive cycles, and when it returns.
+    #[serde(default)]
+    pub missing_devices: Option<MissingDevicesConfig>,
+    /// Keeps a local json lines copy of every published measurement.
+    #[serde(default)]
+    pub archive: Option<ArchiveConfig>,
+    /// Appends a row per sample of every published measurement to a csv file.
+    #[serde(default)]
+    pub csv: Option<CsvConfig>,
+    /// Samples computed from the readings of the devices, each only emitted when configured.
+    #[serde(default)]
+    pub derived: Option<DerivedConfig>,
+    /// Devices by serial whose data endpoint is read several times per cycle, to smooth out
+    /// gauges of pulsing loads.
+    #[serde(default)]
+    pub burst: HashMap<String, BurstConfig>,
+    /// Smooths gauges across cycles with an exponential moving average.
+    #[serde(default)]
+    pub smoothing: Option<SmoothingConfig>,
+    /// Warns when the gas reading of a P1 meter is dated too far from the time of measuring.
+    #[serde(default)]
+    pub gas_clock_skew: Option<GasClockSkewConfig>,
+    /// What a cycle without any samples does, such as when every device failed.
+    #[serde(default)]
+    pub on_empty: OnEmpty,
+    /// Periodically warns about configured serials no device was ever read with.
+    #[serde(default)]
+    pub unmatched_serials: Option<UnmatchedSerialsConfig>,
+    /// Samples devices by serial are expected to produce, warned about when they fall short.
+    #[serde(default)]
+    pub expected_samples: HashMap<String, ExpectedSamples>,
+    /// Most samples a measurement can hold, the sample noting the dropped ones included; 1000
+    /// when not set.
+    #[serde(default)]
+    #[schemars(range(min = 2))]
+    pub max_samples_per_measurement: Option<usize>,
+    /// Publishes the counters and the gauges of a location as separate measurements, with
+    /// `-counters` and `-gauges` appended to their source.
+    #[serde(default)]
+    pub split_by_metric_type: bool,
+    /// Fails the cycle when any configured device fails, instead of publishing a measurement
+    /// without its samples.
+    #[serde(default)]
+    pub strict: bool,
+    /// Makes `strict` fail the cycle on devices missing from the config as well.
+    #[serde(default)]
+    pub strict_include_unconfigured: bool,
+    /// Re-emits the counters of a device that fails from its last successful cycle.
+    #[serde(default)]
+    pub carry_forward: Option<CarryForwardConfig>,
+    /// Leaves out samples of devices that changed less than a threshold since last published.
+    #[serde(default)]
+    pub deadband: Option<DeadbandConfig>,
+    /// Rounds sample values to a number of decimals, dropping the noise of floating point math.
+    #[serde(default)]
+    pub precision: Option<PrecisionConfig>,
+}
+
+/// Behavior of a cycle that yields no samples.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnEmpty {
+    /// Fails the cycle.
+    #[default]
+    Error,
+    /// Succeeds without publishing anything.
+    Skip,
+    /// Publishes the measurement without samples, so downstream still sees the exporter is alive.
+    Heartbeat,
+}
+
+/// What the entity name of the samples of a device is taken from.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum EntityNameSource {
+    /// The product type, like `HWE-SKT`, shared by all devices of the same type.
+    #[default]
+    ProductType,
+    /// The serial of the device.
+    Serial,
+    /// The name of the device in `names`, or else its product name.
+    FriendlyName,
+}
+
+/// A location with devices of its own, measured alongside the main location.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct LocationConfig {
+    /// Location the measurement of these devices gets published for.
+    pub location: String,
+    /// Serials of the discovered devices at this location.
+    #[serde(default)]
+    pub serials: Vec<String>,
+    /// Addresses of devices at this location to measure without discovery, such as at a site
+    /// mdns doesn't reach.
+    #[serde(default)]
+    pub devices: Vec<StaticDevice>,
 }
 
-impl SetDefaults for Config {
-    fn set_defaults(&mut self) {}
+/// A device configured by address.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum StaticDevice {
+    /// Just the address, like `10.1.0.31:80`.
+    Address(SocketAddrV4),
+    /// The address along with the serial of the device expected there; any other device
+    /// answering at the address gets skipped. Optionally with the bearer token sent along with
+    /// every request of its data endpoint.
+    Pinned {
+        address: SocketAddrV4,
+        serial: String,
+        #[serde(flatten)]
+        auth: DeviceTokenConfig,
+    },
+}
+
+impl StaticDevice {
+    pub fn address(&self) -> SocketAddrV4 {
+        match self {
+            StaticDevice::Address(address) => *address,
+            StaticDevice::Pinned { address, .. } => *address,
+        }
+    }
+
+    /// The serial expected at the address, if any.
+    pub fn serial(&self) -> Option<&str> {
+        match self {
+            StaticDevice::Address(_) => None,
+            StaticDevice::Pinned { serial, .. } => Some(serial),
+        }
+    }
+
+    /// The token configured for the device, if any.
+    pub fn auth(&self) -> Option<&DeviceTokenConfig> {
+        match self {
+            StaticDevice::Address(_) => None,
+            StaticDevice::Pinned { auth, .. } => Some(auth),
+        }
+    }
+}
+
+impl From<SocketAddrV4> for StaticDevice {
+    fn from(address: SocketAddrV4) -> Self {
+        StaticDevice::Address(address)
+    }
+}
+
+/// Samples a device is expected to produce every cycle it's read.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ExpectedSamples {
+    /// At least this many samples.
+    Count(usize),
+    /// Each of these samples, named like the part of their mqtt topic after the location, such
+    /// as `t1-import/electricity-consumption/counter`.
+    Names(Vec<String>),
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UnmatchedSerialsConfig {
+    /// Number of cycles between warnings, counted from the first cycle.
+    #[serde(default = "default_unmatched_serials_every_cycles")]
+    #[schemars(range(min = 1))]
+    pub every_cycles: u32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CarryForwardConfig {
+    /// Most consecutive cycles the counters of a failing device get carried forward.
+    #[schemars(range(min = 1))]
+    pub cycles: u32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadbandConfig {
+    /// Threshold of the sample types without one of their own.
+    #[serde(flatten)]
+    pub threshold: DeadbandThreshold,
+    /// Thresholds by sample type, named like in mqtt topics, such as `electricity-consumption`.
+    #[serde(default)]
+    pub sample_types: HashMap<String, DeadbandThreshold>,
+    /// Publishes every sample each this many cycles, so unchanging series don't look dead.
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub full_publish_every_cycles: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PrecisionConfig {
+    /// Decimals of the sample types without decimals of their own; unrounded when not set.
+    #[serde(default)]
+    #[schemars(range(max = 15))]
+    pub decimals: Option<u32>,
+    /// Decimals by sample type, named like in mqtt topics, such as `water-consumption`.
+    #[serde(default)]
+    pub sample_types: HashMap<String, u32>,
+}
+
+/// A change below any of the set thresholds leaves a sample out.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadbandThreshold {
+    /// Smallest change of the value that gets published.
+    #[serde(default)]
+    pub absolute: Option<f64>,
+    /// Smallest change that gets published, in percent of the last published value; gauges only.
+    #[serde(default)]
+    pub percentage: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceEventsConfig {
+    /// NATS subject the events are published on.
+    #[serde(default = "default_device_events_subject")]
+    pub subject: String,
+    /// Number of consecutive failed cycles after which a device is reported offline.
+    #[serde(default = "default_offline_threshold")]
+    #[schemars(range(min = 1))]
+    pub offline_threshold: u32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GasClockSkewConfig {
+    /// Largest difference between the gas reading and the time of measuring, either way.
+    #[serde(default = "default_gas_clock_skew_threshold_minutes")]
+    #[schemars(range(min = 1))]
+    pub threshold_minutes: u32,
+    /// Offset from UTC of the clock of the meters; without it the local timezone of the
+    /// exporter.
+    #[serde(default)]
+    #[schemars(range(min = -840, max = 840))]
+    pub utc_offset_minutes: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FirmwareEventsConfig {
+    /// NATS subject the events are published on.
+    #[serde(default = "default_firmware_events_subject")]
+    pub subject: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NameNormalizationConfig {
+    /// What replaces every run of spaces and punctuation; ascii punctuation or empty.
+    #[serde(default = "default_name_separator")]
+    pub separator: String,
+    /// Most characters a normalized name keeps.
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub max_length: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct MissingDevicesConfig {
+    /// NATS subject the events are published on.
+    #[serde(default = "default_missing_devices_subject")]
+    pub subject: String,
+    /// Number of consecutive cycles a configured device isn't read in before it's reported
+    /// missing.
+    #[serde(default = "default_missing_cycles")]
+    #[schemars(range(min = 1))]
+    pub missing_cycles: u32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ArchiveConfig {
+    /// Directory the daily files are written to.
+    pub directory: String,
+    /// Number of days of files to keep; without it files are never removed.
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub retention_days: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CsvConfig {
+    /// File the rows get appended to; a `{date}` in its file name starts a file per day.
+    pub path: String,
+    /// Number of days of files to keep; without it files are never removed.
+    #[serde(default)]
+    #[schemars(range(min = 1))]
+    pub retention_days: Option<u32>,
+}
+
+/// The bearer token of a device, either inline or in a file such as a mounted Kubernetes secret.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceTokenConfig {
+    /// The token itself.
+    #[serde(default)]
+    pub token: Option<String>,
+    /// File holding the token, read whenever the config is read.
+    #[serde(default)]
+    pub token_file: Option<String>,
+    /// The token read from `token_file`.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub file_token: Option<String>,
+}
+
+impl DeviceTokenConfig {
+    /// The token read from `tokenFile`, or else the inline token.
+    pub fn token(&self) -> Option<&str> {
+        self.file_token.as_deref().or(self.token.as_deref())
+    }
+}
+
+/// Leaves the tokens out, since configs get debug printed and logged.
+impl fmt::Debug for DeviceTokenConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redacted = |token: &Option<String>| token.as_ref().map(|_| "[redacted]");
+        f.debug_struct("DeviceTokenConfig")
+            .field("token", &redacted(&self.token))
+            .field("token_file", &self.token_file)
+            .field("file_token", &redacted(&self.file_token))
+            .finish()
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct BurstConfig {
+    /// Number of reads per cycle; gauges get the mean of the reads, counters the last read.
+    #[schemars(range(min = 1))]
+    pub reads: u32,
+    /// Time between the reads.
+    #[serde(default = "default_burst_spacing_milliseconds")]
+    pub spacing_milliseconds: u64,
+    /// Also emits the lowest and highest reading of the device's gauge.
+    #[serde(default)]
+    pub min_max: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SmoothingConfig {
+    /// Weight of the new reading, from just above 0 for heavy smoothing up to 1 for none.
+    pub alpha: f64,
+    /// Alphas of devices by serial, in place of `alpha`.
+    #[serde(default)]
+    pub devices: HashMap<String, f64>,
+    /// Also emits the unsmoothed gauges, named after the gauge followed by `raw`.
+    #[serde(default)]
+    pub keep_raw: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct DerivedConfig {
+    /// Emits the net grid power of every P1 meter: positive while importing, negative while
+    /// exporting.
+    #[serde(default)]
+    pub net_power: Option<NetPowerConfig>,
+    /// Emits the summed active power of all energy sockets read successfully in a cycle.
+    #[serde(default)]
+    pub socket_total: Option<SocketTotalConfig>,
+    /// Emits the active power of the P1 meters not accounted for by the energy sockets.
+    #[serde(default)]
+    pub untracked_power: Option<UntrackedPowerConfig>,
+    /// Emits how unevenly the power of every P1 meter and SDM630 kWh meter is spread over its
+    /// three phases.
+    #[serde(default)]
+    pub phase_imbalance: Option<PhaseImbalanceConfig>,
+    /// Emits running cost counters of the consumption of P1 and water meters at these prices.
+    #[serde(default)]
+    pub cost: Option<CostConfig>,
+    /// Emits the energy of every electricity counter since the previous cycle as a gauge.
+    #[serde(default)]
+    pub energy_delta: bool,
+    /// Emits the energy equivalent of the gas reading of every P1 meter.
+    #[serde(default)]
+    pub gas_energy: Option<GasEnergyConfig>,
+    /// Emits the gas flow of every P1 meter, derived from its successive gas readings.
+    #[serde(default)]
+    pub gas_flow: Option<GasFlowConfig>,
+    /// Emits the total of every water meter in liters next to the one in m3.
+    #[serde(default)]
+    pub water_liters: bool,
+    /// Emits whether every configured device was read successfully, even when it wasn't found.
+    #[serde(default)]
+    pub device_reachable: Option<DeviceReachableConfig>,
+    /// Emits the number of failed fetches of every configured device so far.
+    #[serde(default)]
+    pub fetch_failures: bool,
+    /// Emits the consumption so far today of every energy, gas and water counter.
+    #[serde(default)]
+    pub daily_consumption: Option<DailyConsumptionConfig>,
+    /// Emits counters of the consumption of every energy socket and kWh meter split by time of
+    /// use window.
+    #[serde(default)]
+    pub time_of_use: Option<TimeOfUseConfig>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct NetPowerConfig {
+    /// Appended to the name of the P1 meter to name the sample.
+    #[serde(default = "default_net_power_name")]
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SocketTotalConfig {
+    /// Entity name of the sample, in place of the product type of a device.
+    #[serde(default = "default_socket_total_entity_name")]
+    pub entity_name: String,
+    /// Name of the sample.
+    #[serde(default = "default_socket_total_name")]
+    pub name: String,
+    /// Adds the active power of the SDM230 and SDM630 kWh meters to the total.
+    #[serde(default)]
+    pub include_kwh_meters: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UntrackedPowerConfig {
+    /// Entity name of the sample, in place of the product type of a device.
+    #[serde(default = "default_untracked_power_entity_name")]
+    pub entity_name: String,
+    /// Name of the sample.
+    #[serde(default = "default_untracked_power_name")]
+    pub name: String,
+    /// Reports zero instead of the slightly negative values readings taken moments apart can
+    /// add up to.
+    #[serde(default = "default_clamp_at_zero")]
+    pub clamp_at_zero: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct PhaseImbalanceConfig {
+    /// Appended to the name of the device to name the sample.
+    #[serde(default = "default_phase_imbalance_name")]
+    pub name: String,
+}
+
+/// Prices in any currency; a cost counter is only emitted for a configured price.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CostConfig {
+    /// Price per kWh imported on tariff 1.
+    #[serde(default)]
+    pub electricity_t1: Option<f64>,
+    /// Price per kWh imported on tariff 2.
+    #[serde(default)]
+    pub electricity_t2: Option<f64>,
+    /// Price per m3 of gas.
+    #[serde(default)]
+    pub gas: Option<f64>,
+    /// Price per m3 of water.
+    #[serde(default)]
+    pub water: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceReachableConfig {
+    /// Also emits the gauge for discovered devices missing from the names.
+    #[serde(default)]
+    pub include_unconfigured: bool,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GasEnergyConfig {
+    /// Energy in kWh per m3 of gas, as stated on the energy bill for the location.
+    #[serde(default = "default_calorific_value")]
+    pub calorific_value: f64,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyConsumptionConfig {
+    /// Timezone whose midnight starts the day, such as `Europe/Amsterdam`.
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+}
+
+impl DailyConsumptionConfig {
+    pub fn timezone(&self) -> Result<Tz, Box<dyn Error>> {
+        parse_timezone(&self.timezone, "derived.dailyConsumption.timezone")
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeOfUseConfig {
+    /// Timezone of the windows, such as `Europe/Amsterdam`.
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    /// Windows to split the consumption by; the first window a cycle falls into gets the
+    /// consumption since the previous cycle.
+    pub windows: Vec<TimeOfUseWindow>,
+    /// Window of the cycles outside of every window.
+    #[serde(default = "default_time_of_use_window")]
+    pub default_window: String,
+}
+
+impl TimeOfUseConfig {
+    pub fn timezone(&self) -> Result<Tz, Box<dyn Error>> {
+        parse_timezone(&self.timezone, "derived.timeOfUse.timezone")
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeOfUseWindow {
+    /// Appended to the name of a counter to name its sample for the window.
+    pub name: String,
+    /// Days of the week the window is on, such as `saturday`; every day when empty.
+    #[serde(default)]
+    pub days: Vec<String>,
+    /// Time of day the window starts, such as `23:00`; midnight when not set.
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Time of day the window ends, before `from` for a window past midnight; the window lasts
+    /// all day when it equals `from`.
+    #[serde(default)]
+    pub to: Option<String>,
+}
+
+impl TimeOfUseWindow {
+    /// Whether the window contains local time `at`. A window past midnight is on the days of
+    /// the week both its evening and its morning fall on.
+    pub fn contains(&self, at: NaiveDateTime) -> Result<bool, Box<dyn Error>> {
+        let days = self.days()?;
+        let on_day = days.is_empty() || days.contains(&at.weekday());
+
+        let from = parse_time_of_day(self.from.as_deref())?;
+        let to = parse_time_of_day(self.to.as_deref())?;
+        let time = at.time();
+        let in_time = match from.cmp(&to) {
+            Ordering::Less => from <= time && time < to,
+            Ordering::Greater => from <= time || time < to,
+            Ordering::Equal => true,
+        };
+
+        Ok(on_day && in_time)
+    }
+
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        self.days()?;
+        parse_time_of_day(self.from.as_deref())?;
+        parse_time_of_day(self.to.as_deref())?;
+
+        Ok(())
+    }
+
+    fn days(&self) -> Result<Vec<Weekday>, Box<dyn Error>> {
+        self.days
+            .iter()
+            .map(|day| {
+                day.parse::<Weekday>()
+                    .map_err(|_| format!("{} isn't a day of the week", day).into())
+            })
+            .collect()
+    }
+}
+
+fn parse_time_of_day(time: Option<&str>) -> Result<NaiveTime, Box<dyn Error>> {
+    match time {
+        Some(time) => NaiveTime::parse_from_str(time, "%H:%M")
+            .map_err(|_| format!("{} isn't a time of day like 23:00", time).into()),
+        None => Ok(NaiveTime::from_hms_opt(0, 0, 0).expect("midnight is a valid time")),
+    }
+}
+
+fn parse_timezone(timezone: &str, option: &str) -> Result<Tz, Box<dyn Error>> {
+    timezone.parse().map_err(|_| {
+        format!(
+            "{} has to be a known timezone instead of {}",
+            option, timezone
+        )
+        .into()
+    })
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct GasFlowConfig {
+    /// Appended to the name of the P1 meter to name the sample.
+    #[serde(default = "default_gas_flow_name")]
+    pub name: String,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_time_of_use_window() -> String {
+    "peak".to_string()
+}
+
+fn default_device_events_subject() -> String {
+    "jarvis-homewizard-device-events".to_string()
+}
+
+fn default_firmware_events_subject() -> String {
+    "jarvis-homewizard-firmware-events".to_string()
+}
+
+fn default_name_separator() -> String {
+    "_".to_string()
+}
+
+fn default_missing_devices_subject() -> String {
+    "jarvis-homewizard-missing-devices".to_string()
+}
+
+fn default_missing_cycles() -> u32 {
+    3
+}
+
+fn default_unmatched_serials_every_cycles() -> u32 {
+    1440
+}
+
+fn default_offline_threshold() -> u32 {
+    3
+}
+
+const DEFAULT_MAX_SAMPLES_PER_MEASUREMENT: usize = 1000;
+
+const DEFAULT_NAME_MAX_LENGTH: usize = 64;
+
+fn default_net_power_name() -> String {
+    "net".to_string()
+}
+
+fn default_gas_flow_name() -> String {
+    "gas flow".to_string()
+}
+
+fn default_socket_total_entity_name() -> String {
+    "sockets".to_string()
+}
+
+fn default_socket_total_name() -> String {
+    "total".to_string()
+}
+
+fn default_untracked_power_entity_name() -> String {
+    "house".to_string()
+}
+
+fn default_untracked_power_name() -> String {
+    "untracked".to_string()
+}
+
+fn default_phase_imbalance_name() -> String {
+    "phase imbalance".to_string()
+}
+
+fn default_calorific_value() -> f64 {
+    9.77
+}
+
+fn default_burst_spacing_milliseconds() -> u64 {
+    250
+}
+
+fn default_clamp_at_zero() -> bool {
+    true
+}
+
+fn default_gas_clock_skew_threshold_minutes() -> u32 {
+    120
+}
+
+impl SetDefaults for Config {
+    fn set_defaults(&mut self) {}
+}
+
+impl Config {
+    /// The JSON schema of the config file, generated from the types it gets deserialized into.
+    pub fn json_schema() -> String {
+        serde_json::to_string_pretty(&schemars::schema_for!(Config))
+            .expect("a json schema always serializes")
+    }
+
+    /// The serials the config refers to: the keys of `names`, `entityNames`, `burst`,
+    /// `smoothing.devices` and `expectedSamples`, the serials of `locations`, and the ones
+    /// expected at the addresses of `devices`, of the main location as well as the others.
+    pub fn configured_serials(&self) -> BTreeSet<&str> {
+        let smoothing_serials = self
+            .smoothing
+            .iter()
+            .flat_map(|smoothing| smoothing.devices.keys());
+
+        self.names
+            .keys()
+            .chain(self.entity_names.keys())
+            .chain(self.burst.keys())
+            .chain(smoothing_serials)
+            .chain(self.expected_samples.keys())
+            .chain(
+                self.locations
+                    .iter()
+                    .flat_map(|location| location.serials.iter()),
+            )
+            .map(String::as_str)
+            .chain(self.static_devices().filter_map(StaticDevice::serial))
+            .collect()
+    }
+
+    /// The devices configured by address, of all locations.
+    pub fn static_devices(&self) -> impl Iterator<Item = &StaticDevice> {
+        self.devices.iter().chain(
+            self.locations
+                .iter()
+                .flat_map(|location| location.devices.iter()),
+        )
+    }
+
+    /// The devices configured by address, of all locations, to read their token files into.
+    pub fn static_devices_mut(&mut self) -> impl Iterator<Item = &mut StaticDevice> {
+        self.devices.iter_mut().chain(
+            self.locations
+                .iter_mut()
+                .flat_map(|location| location.devices.iter_mut()),
+        )
+    }
+
+    /// The serial the device at `address` is expected to have, when pinned in `devices`.
+    pub fn expected_serial_at(&self, address: SocketAddrV4) -> Option<&str> {
+        self.static_devices()
+            .find(|device| device.address() == address)
+            .and_then(StaticDevice::serial)
+    }
+
+    /// The devices the config expects to be read by serial, with their friendly name: the keys
+    /// of `names` and the serials of `locations`.
+    pub fn expected_devices(&self) -> BTreeMap<&str, &str> {
+        self.names
+            .keys()
+            .chain(
+                self.locations
+                    .iter()
+                    .flat_map(|location| location.serials.iter()),
+            )
+            .map(|serial| {
+                let friendly_name = self.names.get(serial).unwrap_or(serial);
+                (serial.as_str(), friendly_name.as_str())
+            })
+            .collect()
+    }
+
+    /// The bearer token of the device pinned with `serial`, when it has one.
+    pub fn device_token(&self, serial: &str) -> Option<&str> {
+        self.static_devices()
+            .find(|device| device.serial() == Some(serial))
+            .and_then(StaticDevice::auth)
+            .and_then(DeviceTokenConfig::token)
+    }
+
+    /// Most characters a friendly name keeps.
+    pub fn name_max_length(&self) -> usize {
+        self.name_max_length.unwrap_or(DEFAULT_NAME_MAX_LENGTH)
+    }
+
+    /// Most samples a measurement can hold.
+    pub fn max_samples_per_measurement(&self) -> usize {
+        self.max_samples_per_measurement
+            .unwrap_or(DEFAULT_MAX_SAMPLES_PER_MEASUREMENT)
+    }
+
+    /// What the entity name of the samples of the device with `serial` is taken from.
+    pub fn entity_name_source(&self, serial: &str) -> EntityNameSource {
+        self.entity_names
+            .get(serial)
+            .copied()
+            .unwrap_or(self.entity_name)
+    }
+
+    /// The location the device with `serial`, or found at one of `addresses`, belongs to.
+    pub fn location_of(&self, serial: Option<&str>, addresses: &[SocketAddrV4]) -> &str {
+        self.locations
+            .iter()
+            .find(|location| {
+                serial.map_or(false, |serial| location.serials.iter().any(|s| s == serial))
+                    || addresses.iter().any(|address| {
+                        location
+                            .devices
+                            .iter()
+                            .any(|device| device.address() == *address)
+                    })
+            })
+            .map_or(&self.location, |location| &location.location)
+    }
+
+    /// Checks the rules serde can't express; returns a description of the first violation.
+    pub fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.location.trim().is_empty() {
+            return Err("location can't be empty".into());
+        }
+
+        if self.name_max_length == Some(0) {
+            return Err("nameMaxLength has to be at least 1".into());
+        }
+
+        for (serial, name) in self.names.iter() {
+            if serial.trim().is_empty() {
+                return Err(format!("names has an entry with an empty serial for {}", name).into());
+            }
+            if name.trim().is_empty() {
+                return Err(format!("names has an empty name for serial {}", serial).into());
+            }
+        }
+
+        let mut locations: HashSet<&str> = HashSet::new();
+        locations.insert(self.location.as_str());
+        let mut serials: HashMap<&str, &str> = HashMap::new();
+        let mut devices: HashMap<SocketAddrV4, &str> = HashMap::new();
+        for location in self.locations.iter() {
+            if location.location.trim().is_empty() {
+                return Err("locations has an entry with an empty location".into());
+            }
+            if !locations.insert(location.location.as_str()) {
+                return Err(format!("location {} is configured twice", location.location).into());
+            }
+            for serial in location.serials.iter() {
+                if let Some(other) = serials.insert(serial.as_str(), location.location.as_str()) {
+                    return Err(format!(
+                        "serial {} is assigned to both location {} and location {}",
+                        serial, other, location.location
+                    )
+                    .into());
+                }
+            }
+        }
+        let devices_by_location = std::iter::once((self.location.as_str(), &self.devices)).chain(
+            self.locations
+                .iter()
+                .map(|location| (location.location.as_str(), &location.devices)),
+        );
+        for (location, location_devices) in devices_by_location {
+            for device in location_devices.iter() {
+                let address = device.address();
+                if let Some(other) = devices.insert(address, location) {
+                    return Err(format!(
+                        "device {} is assigned to both location {} and location {}",
+                        address, other, location
+                    )
+                    .into());
+                }
+                if let StaticDevice::Pinned { serial, auth, .. } = device {
+                    if serial.trim().is_empty() {
+                        return Err(format!("device {} has an empty serial", address).into());
+                    }
+                    match (&auth.token, &auth.token_file) {
+                        (Some(_), Some(_)) => {
+                            return Err(format!(
+                                "device {} can have either a token or a tokenFile, not both",
+                                serial
+                            )
+                            .into());
+                        }
+                        (Some(inline), None) if inline.trim().is_empty() => {
+                            return Err(format!("device {} has an empty token", serial).into());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        for cidr in self.allowed_cidrs.iter() {
+            Cidr::parse(cidr).map_err(|e| format!("allowedCidrs: {}", e))?;
+        }
+
+        if let Some(device_events) = &self.device_events {
+            if device_events.subject.trim().is_empty() {
+                return Err("deviceEvents.subject can't be empty".into());
+            }
+            if device_events.offline_threshold == 0 {
+                return Err("deviceEvents.offlineThreshold has to be at least 1".into());
+            }
+        }
+
+        if let Some(firmware_events) = &self.firmware_events {
+            if firmware_events.subject.trim().is_empty() {
+                return Err("firmwareEvents.subject can't be empty".into());
+            }
+        }
+
+        if let Some(name_normalization) = &self.name_normalization {
+            if !name_normalization
+                .separator
+                .chars()
+                .all(|c| c.is_ascii_punctuation())
+            {
+                return Err("nameNormalization.separator can only hold ascii punctuation".into());
+            }
+            if name_normalization.max_length == Some(0) {
+                return Err("nameNormalization.maxLength has to be at least 1".into());
+            }
+        }
+
+        if let Some(missing_devices) = &self.missing_devices {
+            if missing_devices.subject.trim().is_empty() {
+                return Err("missingDevices.subject can't be empty".into());
+            }
+            if missing_devices.missing_cycles == 0 {
+                return Err("missingDevices.missingCycles has to be at least 1".into());
+            }
+        }
+
+        if let Some(unmatched_serials) = &self.unmatched_serials {
+            if unmatched_serials.every_cycles == 0 {
+                return Err("unmatchedSerials.everyCycles has to be at least 1".into());
+            }
+        }
+
+        if let Some(carry_forward) = &self.carry_forward {
+            if carry_forward.cycles == 0 {
+                return Err("carryForward.cycles has to be at least 1".into());
+            }
+        }
+
+        if let Some(deadband) = &self.deadband {
+            if deadband.full_publish_every_cycles == Some(0) {
+                return Err("deadband.fullPublishEveryCycles has to be at least 1".into());
+            }
+            let thresholds =
+                std::iter::once(&deadband.threshold).chain(deadband.sample_types.values());
+            for threshold in thresholds {
+                let mut values = threshold.absolute.iter().chain(threshold.percentage.iter());
+                if values.any(|value| !value.is_finite() || *value < 0.0) {
+                    return Err("deadband thresholds can't be negative".into());
+                }
+            }
+        }
+
+        if let Some(precision) = &self.precision {
+            let mut decimals = precision
+                .decimals
+                .iter()
+                .chain(precision.sample_types.values());
+            if decimals.any(|decimals| *decimals > 15) {
+                return Err("precision decimals can't be more than 15".into());
+            }
+        }
+
+        if let Some(archive) = &self.archive {
+            if archive.directory.trim().is_empty() {
+                return Err("archive.directory can't be empty".into());
+            }
+            if archive.retention_days == Some(0) {
+                return Err("archive.retentionDays has to be at least 1".into());
+            }
+        }
+
+        if let Some(csv) = &self.csv {
+            if csv.path.trim().is_empty() {
+                return Err("csv.path can't be empty".into());
+            }
+            if csv.retention_days == Some(0) {
+                return Err("csv.retentionDays has to be at least 1".into());
+            }
+            let file_name = csv.path.rsplit('/').next().unwrap_or_default();
+            if csv.retention_days.is_some() && !file_name.contains("{date}") {
+                return Err("csv.retentionDays needs a {date} in the file name of csv.path".into());
+            }
+        }
+
+        for (serial, burst) in self.burst.iter() {
+            if serial.trim().is_empty() {
+                return Err("burst has an entry with an empty serial".into());
+            }
+            if burst.reads == 0 {
+                return Err(format!("burst.{}.reads has to be at least 1", serial).into());
+            }
+        }
+
+        for (serial, expected_samples) in self.expected_samples.iter() {
+            match expected_samples {
+                ExpectedSamples::Count(0) => {
+                    return Err(format!("expectedSamples.{} has to be at least 1", serial).into());
+                }
+                ExpectedSamples::Names(names) if names.is_empty() => {
+                    return Err(format!("expectedSamples.{} can't be empty", serial).into());
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(smoothing) = &self.smoothing {
+            let alphas = std::iter::once(("smoothing.alpha".to_string(), smoothing.alpha)).chain(
+                smoothing
+                    .devices
+                    .iter()
+                    .map(|(serial, alpha)| (format!("smoothing.devices.{}", serial), *alpha)),
+            );
+            for (name, alpha) in alphas {
+                if !(alpha > 0.0 && alpha <= 1.0) {
+                    return Err(format!(
+                        "{} has to be above 0 and at most 1 instead of {}",
+                        name, alpha
+                    )
+                    .into());
+                }
+            }
+        }
+
+        if let Some(gas_clock_skew) = &self.gas_clock_skew {
+            if gas_clock_skew.threshold_minutes == 0 {
+                return Err("gasClockSkew.thresholdMinutes has to be at least 1".into());
+            }
+            if let Some(utc_offset_minutes) = gas_clock_skew.utc_offset_minutes {
+                if utc_offset_minutes.abs() > 14 * 60 {
+                    return Err(format!(
+                        "gasClockSkew.utcOffsetMinutes has to be within 14 hours of UTC instead of {}",
+                        utc_offset_minutes
+                    )
+                    .into());
+                }
+            }
+        }
+
+        if self.max_samples_per_measurement() < 2 {
+            return Err(format!(
+                "maxSamplesPerMeasurement has to be at least 2 instead of {}",
+                self.max_samples_per_measurement()
+            )
+            .into());
+        }
+
+        if let Some(derived) = &self.derived {
+            if let Some(net_power) = &derived.net_power {
+                if net_power.name.trim().is_empty() {
+                    return Err("derived.netPower.name can't be empty".into());
+                }
+            }
+            if let Some(socket_total) = &derived.socket_total {
+                if socket_total.entity_name.trim().is_empty() {
+                    return Err("derived.socketTotal.entityName can't be empty".into());
+                }
+                if socket_total.name.trim().is_empty() {
+                    return Err("derived.socketTotal.name can't be empty".into());
+                }
+            }
+            if let Some(untracked_power) = &derived.untracked_power {
+                if untracked_power.entity_name.trim().is_empty() {
+                    return Err("derived.untrackedPower.entityName can't be empty".into());
+                }
+                if untracked_power.name.trim().is_empty() {
+                    return Err("derived.untrackedPower.name can't be empty".into());
+                }
+            }
+            if let Some(phase_imbalance) = &derived.phase_imbalance {
+                if phase_imbalance.name.trim().is_empty() {
+                    return Err("derived.phaseImbalance.name can't be empty".into());
+                }
+            }
+            if let Some(gas_flow) = &derived.gas_flow {
+                if gas_flow.name.trim().is_empty() {
+                    return Err("derived.gasFlow.name can't be empty".into());
+                }
+            }
+            if let Some(daily_consumption) = &derived.daily_consumption {
+                daily_consumption.timezone()?;
+            }
+            if let Some(time_of_use) = &derived.time_of_use {
+                time_of_use.timezone()?;
+                for window in time_of_use.windows.iter() {
+                    if window.name.trim().is_empty() {
+                        return Err("derived.timeOfUse.windows has a window without a name".into());
+                    }
+                    window.validate().map_err(|e| {
+                        format!(
+                            "derived.timeOfUse.windows has window {} where {}",
+                            window.name, e
+                        )
+                    })?;
+                }
+                if time_of_use.default_window.trim().is_empty() {
+                    return Err("derived.timeOfUse.defaultWindow can't be empty".into());
+                }
+            }
+            if let Some(gas_energy) = &derived.gas_energy {
+                if !gas_energy.calorific_value.is_finite() || gas_energy.calorific_value <= 0.0 {
+                    return Err(format!(
+                        "derived.gasEnergy.calorificValue has to be above 0 instead of {}",
+                        gas_energy.calorific_value
+                    )
+                    .into());
+                }
+            }
+            if let Some(cost) = &derived.cost {
+                let prices = [
+                    ("electricityT1", cost.electricity_t1),
+                    ("electricityT2", cost.electricity_t2),
+                    ("gas", cost.gas),
+                    ("water", cost.water),
+                ];
+                for (name, price) in prices.iter() {
+                    if let Some(price) = price {
+                        if !price.is_finite() || *price < 0.0 {
+                            return Err(format!(
+                                "derived.cost.{} has to be a price of at least 0 instead of {}",
+                                name, price
+                            )
+                            .into());
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -29,5 +1195,852 @@ mod tests {
 
         assert_eq!(config.location, "My Home".to_string());
         assert_eq!(config.names["3c39e72e33ce"], "Bonenmaler".to_string());
+        assert_eq!(
+            config.device_events,
+            Some(DeviceEventsConfig {
+                subject: "jarvis-homewizard-device-events".to_string(),
+                offline_threshold: 2,
+            })
+        );
+    }
+
+    #[test]
+    fn validate_accepts_test_file() {
+        let config_client =
+            ConfigClient::new(ConfigClientConfig::new("test-config.yaml".to_string()).unwrap());
+        let config: Config = config_client.read_config_from_file().unwrap();
+
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn json_schema_accepts_test_file() {
+        let schema: serde_json::Value = serde_json::from_str(&Config::json_schema()).unwrap();
+        let schema = jsonschema::JSONSchema::compile(&schema).unwrap();
+        let test_file: serde_json::Value =
+            serde_yaml::from_str(&std::fs::read_to_string("test-config.yaml").unwrap()).unwrap();
+        let invalid: serde_json::Value = serde_yaml::from_str(
+            "location: My Home\nonEmpty: sometimes\ndeviceEvents:\n  offlineThreshold: 0\n",
+        )
+        .unwrap();
+
+        // act
+        let accepts_test_file = schema.is_valid(&test_file);
+        let accepts_invalid = schema.is_valid(&invalid);
+
+        assert!(accepts_test_file);
+        assert!(!accepts_invalid);
+    }
+
+    #[test]
+    fn json_schema_lists_defaults_and_enum_values() {
+        // act
+        let schema: serde_json::Value = serde_json::from_str(&Config::json_schema()).unwrap();
+
+        assert_eq!(schema["required"], serde_json::json!(["location"]));
+        // documented variants each get their own subschema
+        let on_empty: Vec<&serde_json::Value> = schema["definitions"]["OnEmpty"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|variant| &variant["enum"][0])
+            .collect();
+        assert_eq!(on_empty, vec!["error", "skip", "heartbeat"]);
+        assert_eq!(schema["properties"]["onEmpty"]["default"], "error");
+        assert_eq!(
+            schema["definitions"]["DeviceEventsConfig"]["properties"]["offlineThreshold"]
+                ["default"],
+            serde_json::json!(default_offline_threshold())
+        );
+    }
+
+    #[test]
+    fn validate_rejects_empty_location() {
+        let config = Config {
+            location: " ".into(),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_empty_name() {
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_zero_offline_threshold() {
+        let config = Config {
+            location: "My Home".into(),
+            device_events: Some(DeviceEventsConfig {
+                subject: default_device_events_subject(),
+                offline_threshold: 0,
+            }),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_empty_firmware_events_subject() {
+        let config = Config {
+            location: "My Home".into(),
+            firmware_events: Some(FirmwareEventsConfig {
+                subject: " ".into(),
+            }),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_alphanumeric_name_separator() {
+        let config = Config {
+            location: "My Home".into(),
+            name_normalization: Some(NameNormalizationConfig {
+                separator: "x".into(),
+                max_length: None,
+            }),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_invalid_allowed_cidr() {
+        let config = Config {
+            location: "My Home".into(),
+            allowed_cidrs: vec!["192.168.1.0/24".into(), "192.168.50.0".into()],
+            ..Default::default()
+        };
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "allowedCidrs: 192.168.50.0 isn't an ipv4 range like 192.168.1.0/24"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_zero_missing_cycles() {
+        let config = Config {
+            location: "My Home".into(),
+            missing_devices: Some(MissingDevicesConfig {
+                subject: default_missing_devices_subject(),
+                missing_cycles: 0,
+            }),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn expected_devices_are_named_serials_and_location_serials() {
+        let config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+                .into_iter()
+                .collect(),
+            locations: vec![LocationConfig {
+                location: "Garage".into(),
+                serials: vec!["3c39e72d7a68".into(), "3c39e72e33ce".into()],
+                devices: vec![],
+            }],
+            ..Default::default()
+        };
+
+        // act
+        let expected = config.expected_devices();
+
+        assert_eq!(
+            expected.into_iter().collect::<Vec<(&str, &str)>>(),
+            vec![
+                ("3c39e72d7a68", "3c39e72d7a68"),
+                ("3c39e72e33ce", "Bonenmaler")
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_rejects_zero_carry_forward_cycles() {
+        let config = Config {
+            location: "My Home".into(),
+            carry_forward: Some(CarryForwardConfig { cycles: 0 }),
+            ..Default::default()
+        };
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "carryForward.cycles has to be at least 1"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_negative_deadband_threshold() {
+        let config = Config {
+            location: "My Home".into(),
+            deadband: Some(DeadbandConfig {
+                threshold: DeadbandThreshold::default(),
+                sample_types: vec![(
+                    "electricity-consumption".to_string(),
+                    DeadbandThreshold {
+                        absolute: Some(-1.0),
+                        percentage: None,
+                    },
+                )]
+                .into_iter()
+                .collect(),
+                full_publish_every_cycles: None,
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "deadband thresholds can't be negative"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_too_many_decimals() {
+        let config = Config {
+            location: "My Home".into(),
+            precision: Some(PrecisionConfig {
+                decimals: Some(3),
+                sample_types: vec![("water-consumption".to_string(), 16)]
+                    .into_iter()
+                    .collect(),
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "precision decimals can't be more than 15"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_unknown_daily_consumption_timezone() {
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                daily_consumption: Some(DailyConsumptionConfig {
+                    timezone: "Europe/Utrecht".into(),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "derived.dailyConsumption.timezone has to be a known timezone instead of Europe/Utrecht"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_time_of_use_window_with_unknown_day() {
+        let config = Config {
+            location: "My Home".into(),
+            derived: Some(DerivedConfig {
+                time_of_use: Some(TimeOfUseConfig {
+                    timezone: "Europe/Amsterdam".into(),
+                    windows: vec![TimeOfUseWindow {
+                        name: "off-peak".into(),
+                        days: vec!["zaterdag".into()],
+                        from: None,
+                        to: None,
+                    }],
+                    default_window: "peak".into(),
+                }),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "derived.timeOfUse.windows has window off-peak where zaterdag isn't a day of the week"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_zero_unmatched_serials_interval() {
+        let config = Config {
+            location: "My Home".into(),
+            unmatched_serials: Some(UnmatchedSerialsConfig { every_cycles: 0 }),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn parses_expected_samples_as_count_or_names() {
+        // act
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\nexpectedSamples:\n  3c39e72e33ce: 3\n  5c2faf0a8b3e:\n  - t1-import/electricity-consumption/counter\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.expected_samples["3c39e72e33ce"],
+            ExpectedSamples::Count(3)
+        );
+        assert_eq!(
+            config.expected_samples["5c2faf0a8b3e"],
+            ExpectedSamples::Names(vec!["t1-import/electricity-consumption/counter".into()])
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_zero_expected_samples() {
+        let config: Config =
+            serde_yaml::from_str("location: My Home\nexpectedSamples:\n  3c39e72e33ce: 0\n")
+                .unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_max_samples_per_measurement_below_two() {
+        let config: Config =
+            serde_yaml::from_str("location: My Home\nmaxSamplesPerMeasurement: 1\n").unwrap();
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "maxSamplesPerMeasurement has to be at least 2 instead of 1"
+        );
+        assert_eq!(Config::default().max_samples_per_measurement(), 1000);
+    }
+
+    #[test]
+    fn validate_rejects_name_max_length_of_zero() {
+        let config: Config = serde_yaml::from_str("location: My Home\nnameMaxLength: 0\n").unwrap();
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "nameMaxLength has to be at least 1"
+        );
+        assert_eq!(Config::default().name_max_length(), 64);
+    }
+
+    #[test]
+    fn entity_name_source_prefers_serial_over_global_option() {
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\nentityName: serial\nentityNames:\n  3c39e72e33ce: friendlyName\n",
+        )
+        .unwrap();
+
+        // act
+        let overridden = config.entity_name_source("3c39e72e33ce");
+        let global = config.entity_name_source("5c2faf0a8b3e");
+
+        assert_eq!(overridden, EntityNameSource::FriendlyName);
+        assert_eq!(global, EntityNameSource::Serial);
+        assert_eq!(
+            Config::default().entity_name_source("3c39e72e33ce"),
+            EntityNameSource::ProductType
+        );
+    }
+
+    #[test]
+    fn location_of_finds_location_by_serial_or_address() {
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\nlocations:\n- location: Parents\n  serials:\n  - 5c2faf0a8b3e\n  devices:\n  - 10.1.0.31:80\n",
+        )
+        .unwrap();
+
+        // act
+        let by_serial = config.location_of(Some("5c2faf0a8b3e"), &[]);
+        let by_address = config.location_of(None, &["10.1.0.31:80".parse().unwrap()]);
+        let unassigned = config.location_of(Some("3c39e72e33ce"), &[]);
+
+        assert_eq!(by_serial, "Parents");
+        assert_eq!(by_address, "Parents");
+        assert_eq!(unassigned, "My Home");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn parses_devices_by_address_with_or_without_serial() {
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\nlocations:\n- location: Parents\n  devices:\n  - 10.1.0.31:80\n  - address: 10.1.0.32:80\n    serial: 5c2faf0a8b3e\n",
+        )
+        .unwrap();
+
+        // act
+        let pinned = config.expected_serial_at("10.1.0.32:80".parse().unwrap());
+        let unpinned = config.expected_serial_at("10.1.0.31:80".parse().unwrap());
+
+        assert_eq!(pinned, Some("5c2faf0a8b3e"));
+        assert_eq!(unpinned, None);
+        assert_eq!(
+            config.location_of(None, &["10.1.0.32:80".parse().unwrap()]),
+            "Parents"
+        );
+        assert!(config.configured_serials().contains("5c2faf0a8b3e"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_serial_assigned_to_two_locations() {
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\nlocations:\n- location: Parents\n  serials: [5c2faf0a8b3e]\n- location: Cabin\n  serials: [5c2faf0a8b3e]\n",
+        )
+        .unwrap();
+
+        // act
+        let result = config.validate();
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "serial 5c2faf0a8b3e is assigned to both location Parents and location Cabin"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_location_configured_twice() {
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\nlocations:\n- location: My Home\n  serials: [5c2faf0a8b3e]\n",
+        )
+        .unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_zero_archive_retention() {
+        let config = Config {
+            location: "My Home".into(),
+            archive: Some(ArchiveConfig {
+                directory: "/archive".into(),
+                retention_days: Some(0),
+            }),
+            ..Default::default()
+        };
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_csv_retention_without_daily_files() {
+        let config = Config {
+            location: "My Home".into(),
+            csv: Some(CsvConfig {
+                path: "/exports/{date}/consumption.csv".into(),
+                retention_days: Some(30),
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "csv.retentionDays needs a {date} in the file name of csv.path"
+        );
+    }
+
+    /// Deserializes `yaml`, serializes the result and deserializes that again, asserting nothing
+    /// gets lost on the way; returns the config and its serialized form.
+    fn round_trip(yaml: &str) -> (Config, String) {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        let serialized = serde_yaml::to_string(&config).unwrap();
+        let reloaded: Config = serde_yaml::from_str(&serialized).unwrap();
+
+        assert_eq!(reloaded, config);
+        (config, serialized)
+    }
+
+    #[test]
+    fn round_trips_minimal_config() {
+        // act
+        let (config, serialized) = round_trip("location: My Home\n");
+
+        assert_eq!(
+            config,
+            Config {
+                location: "My Home".into(),
+                ..Default::default()
+            }
+        );
+        assert!(serialized.contains("deviceEvents:"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn round_trips_legacy_names_only_config() {
+        // act
+        let (config, _) = round_trip(
+            "location: My Home\nnames:\n  3c39e72e33ce: Bonenmaler\n  5c2faf0a8b3e: Meterkast\n",
+        );
+
+        assert_eq!(config.location, "My Home");
+        assert_eq!(config.names.len(), 2);
+        assert_eq!(config.names["3c39e72e33ce"], "Bonenmaler");
+        assert_eq!(config.names["5c2faf0a8b3e"], "Meterkast");
+        assert_eq!(config.device_events, None);
+        assert_eq!(config.archive, None);
+    }
+
+    #[test]
+    fn round_trips_full_config_with_camel_case_keys() {
+        let yaml = r#"
+location: My Home
+names:
+  3c39e72e33ce: Bonenmaler
+deviceEvents:
+  subject: homewizard-events
+  offlineThreshold: 5
+archive:
+  directory: /archive
+  retentionDays: 30
+"#;
+
+        // act
+        let (config, serialized) = round_trip(yaml);
+
+        assert_eq!(config.location, "My Home");
+        assert_eq!(config.names["3c39e72e33ce"], "Bonenmaler");
+        assert_eq!(
+            config.device_events,
+            Some(DeviceEventsConfig {
+                subject: "homewizard-events".into(),
+                offline_threshold: 5,
+            })
+        );
+        assert_eq!(
+            config.archive,
+            Some(ArchiveConfig {
+                directory: "/archive".into(),
+                retention_days: Some(30),
+            })
+        );
+        for key in ["deviceEvents:", "offlineThreshold:", "retentionDays:"] {
+            assert!(
+                serialized.contains(key),
+                "{} missing in {}",
+                key,
+                serialized
+            );
+        }
+        for key in ["device_events", "offline_threshold", "retention_days"] {
+            assert!(!serialized.contains(key), "{} found in {}", key, serialized);
+        }
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn fills_in_device_events_defaults() {
+        // act
+        let (config, _) = round_trip("location: My Home\ndeviceEvents: {}\n");
+
+        assert_eq!(
+            config.device_events,
+            Some(DeviceEventsConfig {
+                subject: "jarvis-homewizard-device-events".into(),
+                offline_threshold: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn on_empty_defaults_to_error() {
+        // act
+        let default: Config = serde_yaml::from_str("location: My Home\n").unwrap();
+        let heartbeat: Config =
+            serde_yaml::from_str("location: My Home\nonEmpty: heartbeat\n").unwrap();
+
+        assert_eq!(default.on_empty, OnEmpty::Error);
+        assert_eq!(heartbeat.on_empty, OnEmpty::Heartbeat);
+        assert!(serde_yaml::from_str::<Config>("location: My Home\nonEmpty: retry\n").is_err());
+    }
+
+    #[test]
+    fn rejects_duplicate_serials_in_names() {
+        // act
+        let result: Result<Config, _> = serde_yaml::from_str(
+            "location: My Home\nnames:\n  3c39e72e33ce: Bonenmaler\n  3c39e72e33ce: Koffie\n",
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn validate_rejects_empty_serial() {
+        let config: Config =
+            serde_yaml::from_str("location: My Home\nnames:\n  \"\": Bonenmaler\n").unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn fills_in_net_power_name_default() {
+        // act
+        let (config, serialized) = round_trip("location: My Home\nderived:\n  netPower: {}\n");
+
+        assert_eq!(
+            config.derived,
+            Some(DerivedConfig {
+                net_power: Some(NetPowerConfig { name: "net".into() }),
+                ..Default::default()
+            })
+        );
+        assert!(serialized.contains("netPower:"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_empty_net_power_name() {
+        let config: Config =
+            serde_yaml::from_str("location: My Home\nderived:\n  netPower:\n    name: \"\"\n")
+                .unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn fills_in_socket_total_defaults() {
+        // act
+        let (config, serialized) = round_trip("location: My Home\nderived:\n  socketTotal: {}\n");
+
+        assert_eq!(
+            config.derived.unwrap().socket_total,
+            Some(SocketTotalConfig {
+                entity_name: "sockets".into(),
+                name: "total".into(),
+                include_kwh_meters: false,
+            })
+        );
+        assert!(serialized.contains("includeKwhMeters: false"));
+    }
+
+    #[test]
+    fn fills_in_untracked_power_defaults() {
+        // act
+        let (config, _) = round_trip("location: My Home\nderived:\n  untrackedPower: {}\n");
+
+        assert_eq!(
+            config.derived.unwrap().untracked_power,
+            Some(UntrackedPowerConfig {
+                entity_name: "house".into(),
+                name: "untracked".into(),
+                clamp_at_zero: true,
+            })
+        );
+    }
+
+    #[test]
+    fn reads_only_configured_prices() {
+        // act
+        let (config, _) = round_trip(
+            "location: My Home\nderived:\n  cost:\n    electricityT1: 0.25\n    gas: 1.45\n",
+        );
+
+        assert_eq!(
+            config.derived.unwrap().cost,
+            Some(CostConfig {
+                electricity_t1: Some(0.25),
+                electricity_t2: None,
+                gas: Some(1.45),
+                water: None,
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_negative_price() {
+        let config: Config =
+            serde_yaml::from_str("location: My Home\nderived:\n  cost:\n    water: -1.1\n")
+                .unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn fills_in_calorific_value_default() {
+        // act
+        let (config, _) = round_trip("location: My Home\nderived:\n  gasEnergy: {}\n");
+
+        assert_eq!(
+            config.derived.unwrap().gas_energy,
+            Some(GasEnergyConfig {
+                calorific_value: 9.77
+            })
+        );
+    }
+
+    #[test]
+    fn validate_rejects_zero_calorific_value() {
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\nderived:\n  gasEnergy:\n    calorificValue: 0\n",
+        )
+        .unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn fills_in_burst_defaults() {
+        // act
+        let (config, _) = round_trip("location: My Home\nburst:\n  3c39e72e33ce:\n    reads: 5\n");
+
+        assert_eq!(
+            config.burst["3c39e72e33ce"],
+            BurstConfig {
+                reads: 5,
+                spacing_milliseconds: 250,
+                min_max: false,
+            }
+        );
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_token_config_naming_the_device() {
+        for (yaml, expected) in [
+            (
+                "location: My Home\ndevices:\n- address: 10.1.0.31:80\n  serial: 3c39e72e33ce\n  token: ' '\n",
+                "device 3c39e72e33ce has an empty token",
+            ),
+            (
+                "location: My Home\nlocations:\n- location: Parents\n  devices:\n  - address: 10.1.0.31:80\n    serial: 3c39e72e33ce\n    token: 2A4F0C9B\n    tokenFile: /run/secrets/token\n",
+                "device 3c39e72e33ce can have either a token or a tokenFile, not both",
+            ),
+        ] {
+            let config: Config = serde_yaml::from_str(yaml).unwrap();
+
+            // act
+            let result = config.validate();
+
+            assert_eq!(result.unwrap_err().to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn debug_output_redacts_device_tokens() {
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\ndevices:\n- address: 10.1.0.31:80\n  serial: 3c39e72e33ce\n  token: 2A4F0C9B\n",
+        )
+        .unwrap();
+
+        // act
+        let debug = format!("{:#?}", config);
+
+        assert!(!debug.contains("2A4F0C9B"));
+        assert!(debug.contains("[redacted]"));
+        assert_eq!(config.device_token("3c39e72e33ce"), Some("2A4F0C9B"));
+        assert_eq!(config.device_token("5c2faf0a8b3e"), None);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn devices_of_the_main_location_are_static_devices_of_it() {
+        let config: Config = serde_yaml::from_str(
+            "location: My Home\ndevices:\n- 10.1.0.31:80\n- address: 10.1.0.32:80\n  serial: 3c39e72e33ce\n",
+        )
+        .unwrap();
+
+        // act
+        let addresses: Vec<SocketAddrV4> =
+            config.static_devices().map(StaticDevice::address).collect();
+
+        assert_eq!(
+            addresses,
+            vec![
+                "10.1.0.31:80".parse().unwrap(),
+                "10.1.0.32:80".parse().unwrap()
+            ]
+        );
+        assert_eq!(
+            config.location_of(None, &["10.1.0.32:80".parse().unwrap()]),
+            "My Home"
+        );
+        assert_eq!(config.device_token("3c39e72e33ce"), None);
+        assert!(config.configured_serials().contains("3c39e72e33ce"));
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn validate_rejects_burst_without_reads() {
+        let config: Config =
+            serde_yaml::from_str("location: My Home\nburst:\n  3c39e72e33ce:\n    reads: 0\n")
+                .unwrap();
+
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn validate_rejects_smoothing_alpha_out_of_range() {
+        for yaml in [
+            "location: My Home\nsmoothing:\n  alpha: 0\n",
+            "location: My Home\nsmoothing:\n  alpha: 1.5\n",
+            "location: My Home\nsmoothing:\n  alpha: 0.5\n  devices:\n    3c39e72e33ce: -0.1\n",
+        ]
+        .iter()
+        {
+            let config: Config = serde_yaml::from_str(yaml).unwrap();
+
+            assert!(config.validate().is_err(), "{} should be invalid", yaml);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_invalid_gas_clock_skew() {
+        for yaml in [
+            "location: My Home\ngasClockSkew:\n  thresholdMinutes: 0\n",
+            "location: My Home\ngasClockSkew:\n  utcOffsetMinutes: 900\n",
+        ]
+        .iter()
+        {
+            let config: Config = serde_yaml::from_str(yaml).unwrap();
+
+            assert!(config.validate().is_err(), "{} should be invalid", yaml);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_empty_archive_directory() {
+        let config: Config =
+            serde_yaml::from_str("location: My Home\narchive:\n  directory: \" \"\n").unwrap();
+
+        assert!(config.validate().is_err());
     }
 }
@@ -0,0 +1,522 @@
+use crate::expected_samples::sample_key;
+use crate::exporter::MeasurementPublisher;
+use crate::home_assistant::HomeAssistantDiscovery;
+use crate::homewizard_client::{CycleSummary, MeasuredDevice};
+use async_trait::async_trait;
+use jarvis_lib::model::{Measurement, Sample};
+use rumqttc::{Client, MqttOptions, QoS};
+use serde::Serialize;
+use std::env;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(60);
+
+pub struct MqttPublisherConfig {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    base_topic: String,
+    qos: QoS,
+    home_assistant_prefix: Option<String>,
+}
+
+impl MqttPublisherConfig {
+    pub fn new(
+        broker_url: &str,
+        credentials: Option<(String, String)>,
+        base_topic: String,
+        qos: u8,
+        home_assistant_prefix: Option<String>,
+    ) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "MqttPublisherConfig::new(broker_url: {}, username: {:?}, base_topic: {}, qos: {}, home_assistant_prefix: {:?})",
+            broker_url,
+            credentials.as_ref().map(|(username, _)| username),
+            base_topic,
+            qos,
+            home_assistant_prefix
+        );
+
+        let address = match broker_url.strip_prefix("mqtt://") {
+            Some(address) => address.trim_end_matches('/'),
+            None => {
+                return Err(format!(
+                    "MQTT_BROKER_URL has to start with mqtt:// instead of {}",
+                    broker_url
+                )
+                .into())
+            }
+        };
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse()?),
+            None => (address, 1883),
+        };
+        let qos = match qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            2 => QoS::ExactlyOnce,
+            _ => return Err(format!("MQTT_QOS has to be 0, 1 or 2 instead of {}", qos).into()),
+        };
+
+        Ok(Self {
+            host: host.to_string(),
+            port,
+            credentials,
+            base_topic: base_topic.trim_end_matches('/').to_string(),
+            qos,
+            home_assistant_prefix,
+        })
+    }
+
+    /// Returns no config when `MQTT_BROKER_URL` isn't set, since publishing to MQTT is optional.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let broker_url = match env::var("MQTT_BROKER_URL") {
+            Ok(broker_url) => broker_url,
+            Err(_) => return Ok(None),
+        };
+        let credentials = match (env::var("MQTT_USERNAME"), env::var("MQTT_PASSWORD")) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+        let base_topic = env::var("MQTT_BASE_TOPIC").unwrap_or_else(|_| "homewizard".to_string());
+        let qos: u8 = env::var("MQTT_QOS")
+            .unwrap_or_else(|_| "1".to_string())
+            .parse()?;
+
+        let home_assistant_prefix = env::var("MQTT_HOME_ASSISTANT_PREFIX").ok();
+
+        Ok(Some(Self::new(
+            &broker_url,
+            credentials,
+            base_topic,
+            qos,
+            home_assistant_prefix,
+        )?))
+    }
+}
+
+/// Something retained messages can be published to, so tests don't need a broker.
+pub trait MqttSink {
+    fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), Box<dyn Error>>;
+}
+
+/// Sink publishing through a client whose connection is driven by a background thread, which
+/// keeps reconnecting with an exponential backoff after the connection is lost.
+pub struct BrokerSink {
+    client: Client,
+    qos: QoS,
+}
+
+impl BrokerSink {
+    pub fn connect(config: &MqttPublisherConfig) -> Self {
+        let mut options = MqttOptions::new(
+            format!("jarvis-homewizard-exporter-{}", uuid::Uuid::new_v4()),
+            config.host.clone(),
+            config.port,
+        );
+        options.set_keep_alive(Duration::from_secs(30));
+        if let Some((username, password)) = &config.credentials {
+            options.set_credentials(username.clone(), password.clone());
+        }
+
+        let (client, mut connection) = Client::new(options, 100);
+        let broker = format!("{}:{}", config.host, config.port);
+        thread::spawn(move || {
+            let mut backoff = Duration::from_secs(1);
+            for notification in connection.iter() {
+                match notification {
+                    Ok(_) => backoff = Duration::from_secs(1),
+                    Err(e) => {
+                        warn!(
+                            "Lost connection to MQTT broker {}, reconnecting in {:?}: {}",
+                            broker, backoff, e
+                        );
+                        thread::sleep(backoff);
+                        backoff = (backoff * 2).min(MAX_RECONNECT_BACKOFF);
+                    }
+                }
+            }
+            info!("Stopped MQTT connection to {}", broker);
+        });
+
+        Self {
+            client,
+            qos: config.qos,
+        }
+    }
+}
+
+impl MqttSink for BrokerSink {
+    fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        // never blocks the cycle; while the broker is unreachable the request queue fills up and
+        // publishing fails instead
+        self.client.try_publish(topic, self.qos, true, payload)?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize)]
+struct SamplePayload<'a> {
+    value: f64,
+    timestamp: &'a str,
+}
+
+/// Publishes every sample of a measurement as a retained json message on its own topic,
+/// `<base topic>/<location>/<device>/<sample name>/<sample type>/<metric type>`, so devices
+/// sharing a friendly name don't overwrite each other's messages.
+pub struct MqttPublisher<S: MqttSink> {
+    sink: S,
+    base_topic: String,
+    cycle_summary: Arc<Mutex<Option<CycleSummary>>>,
+    home_assistant: Option<HomeAssistantDiscovery>,
+}
+
+impl MqttPublisher<BrokerSink> {
+    pub fn connect(
+        config: &MqttPublisherConfig,
+        cycle_summary: Arc<Mutex<Option<CycleSummary>>>,
+    ) -> Self {
+        let publisher = Self::new(
+            BrokerSink::connect(config),
+            config.base_topic.clone(),
+            cycle_summary.clone(),
+        );
+
+        match &config.home_assistant_prefix {
+            Some(prefix) => publisher.with_home_assistant_discovery(HomeAssistantDiscovery::new(
+                prefix.clone(),
+                cycle_summary,
+            )),
+            None => publisher,
+        }
+    }
+}
+
+impl<S: MqttSink> MqttPublisher<S> {
+    /// The cycle summary tells which device every sample came from.
+    pub fn new(
+        sink: S,
+        base_topic: String,
+        cycle_summary: Arc<Mutex<Option<CycleSummary>>>,
+    ) -> Self {
+        Self {
+            sink,
+            base_topic,
+            cycle_summary,
+            home_assistant: None,
+        }
+    }
+
+    /// Publishes Home Assistant discovery config for the samples ahead of their values.
+    pub fn with_home_assistant_discovery(mut self, discovery: HomeAssistantDiscovery) -> Self {
+        self.home_assistant = Some(discovery);
+        self
+    }
+}
+
+#[async_trait(?Send)]
+impl<S: MqttSink> MeasurementPublisher for MqttPublisher<S> {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        if let Some(discovery) = &self.home_assistant {
+            for (topic, payload) in discovery.changed_messages(&self.base_topic, measurement) {
+                self.sink.publish(&topic, payload.clone())?;
+                discovery.mark_published(topic, payload);
+            }
+        }
+
+        let timestamp = measurement.measured_at_time.to_rfc3339();
+        let devices = self
+            .cycle_summary
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|summary| summary.measured_devices_of(&measurement.id))
+            .unwrap_or_default();
+
+        for (index, sample) in measurement.samples.iter().enumerate() {
+            let payload = serde_json::to_vec(&SamplePayload {
+                value: sample.value,
+                timestamp: &timestamp,
+            })?;
+            let device = devices
+                .iter()
+                .find(|device| device.samples.contains(&index));
+            self.sink.publish(
+                &state_topic(&self.base_topic, measurement, device, sample),
+                payload,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Topic the value of the sample gets published on, below the serial of the device it came from;
+/// a sample no device produced, like a derived one, goes below its entity name instead.
+pub(crate) fn state_topic(
+    base_topic: &str,
+    measurement: &Measurement,
+    device: Option<&MeasuredDevice>,
+    sample: &Sample,
+) -> String {
+    let device_level = match device {
+        Some(device) => topic_level(&device.info.serial.to_lowercase()),
+        None => topic_level(&sample.entity_name),
+    };
+
+    format!(
+        "{}/{}/{}/{}",
+        base_topic,
+        topic_level(&measurement.location),
+        device_level,
+        sample_key(sample)
+    )
+}
+
+/// Turns a name into a single topic level: lowercase words separated by dashes, splitting
+/// CamelCase words, with anything but letters and digits - including the `/`, `+` and `#` that
+/// have a meaning in topics - treated as a separator.
+pub(crate) fn topic_level(name: &str) -> String {
+    let mut level = String::new();
+    let mut previous_lowercase = false;
+    for c in name.chars() {
+        if !c.is_alphanumeric() {
+            if !level.is_empty() && !level.ends_with('-') {
+                level.push('-');
+            }
+            previous_lowercase = false;
+            continue;
+        }
+
+        if c.is_uppercase() && previous_lowercase {
+            level.push('-');
+        }
+        level.extend(c.to_lowercase());
+        previous_lowercase = c.is_lowercase() || c.is_numeric();
+    }
+
+    level.trim_end_matches('-').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard_client::DeviceInfoResponse;
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, MetricType, SampleType};
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct RecordingSink {
+        messages: RefCell<Vec<(String, String)>>,
+    }
+
+    impl MqttSink for &RecordingSink {
+        fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), Box<dyn Error>> {
+            self.messages
+                .borrow_mut()
+                .push((topic.to_string(), String::from_utf8(payload)?));
+            Ok(())
+        }
+    }
+
+    const MEASUREMENT_ID: &str = "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10";
+
+    fn sample(
+        entity_type: EntityType,
+        entity_name: &str,
+        sample_name: &str,
+        metric_type: MetricType,
+        value: f64,
+    ) -> Sample {
+        Sample {
+            entity_type,
+            entity_name: entity_name.into(),
+            sample_type: SampleType::ElectricityConsumption,
+            sample_name: sample_name.into(),
+            metric_type,
+            value,
+        }
+    }
+
+    fn measured_device(product_type: &str, serial: &str, sample: usize) -> MeasuredDevice {
+        MeasuredDevice {
+            measurement_id: MEASUREMENT_ID.into(),
+            info: DeviceInfoResponse {
+                product_type: product_type.into(),
+                product_name: product_type.into(),
+                serial: serial.into(),
+                firmware_version: "4.00".into(),
+                api_version: "v1".into(),
+            },
+            friendly_name: "Bonenmaler".into(),
+            samples: sample..sample + 1,
+        }
+    }
+
+    fn measurement(samples: Vec<Sample>) -> Measurement {
+        Measurement {
+            id: MEASUREMENT_ID.into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples,
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn publisher<'a>(
+        sink: &'a RecordingSink,
+        devices: Vec<MeasuredDevice>,
+    ) -> MqttPublisher<&'a RecordingSink> {
+        let cycle_summary = Arc::new(Mutex::new(Some(CycleSummary {
+            measured_devices: devices,
+            ..Default::default()
+        })));
+
+        MqttPublisher::new(sink, "homewizard".into(), cycle_summary)
+    }
+
+    #[tokio::test]
+    async fn publishes_every_sample_on_its_own_topic_below_its_device() {
+        let sink = RecordingSink::default();
+        let publisher = publisher(
+            &sink,
+            vec![
+                measured_device("HWE-P1", "5c2faf0a8b3e", 0),
+                measured_device("HWE-SKT", "3C39E72E33CE", 1),
+            ],
+        );
+        let measurement = measurement(vec![
+            sample(
+                EntityType::Tariff,
+                "HWE-P1",
+                "t1 import",
+                MetricType::Counter,
+                7200.0,
+            ),
+            sample(
+                EntityType::Device,
+                "HWE-SKT",
+                "Bonenmaler",
+                MetricType::Gauge,
+                98.1,
+            ),
+            sample(
+                EntityType::Device,
+                "house",
+                "untracked",
+                MetricType::Gauge,
+                120.0,
+            ),
+        ]);
+
+        // act
+        publisher.publish(&measurement).await.unwrap();
+
+        assert_eq!(
+            *sink.messages.borrow(),
+            vec![
+                (
+                    "homewizard/my-home/5c2faf0a8b3e/t1-import/electricity-consumption/counter"
+                        .to_string(),
+                    r#"{"value":7200.0,"timestamp":"2023-06-01T12:00:00+00:00"}"#.to_string()
+                ),
+                (
+                    "homewizard/my-home/3c39e72e33ce/bonenmaler/electricity-consumption/gauge"
+                        .to_string(),
+                    r#"{"value":98.1,"timestamp":"2023-06-01T12:00:00+00:00"}"#.to_string()
+                ),
+                (
+                    "homewizard/my-home/house/untracked/electricity-consumption/gauge".to_string(),
+                    r#"{"value":120.0,"timestamp":"2023-06-01T12:00:00+00:00"}"#.to_string()
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn devices_sharing_a_friendly_name_get_topics_of_their_own() {
+        let sink = RecordingSink::default();
+        let publisher = publisher(
+            &sink,
+            vec![
+                measured_device("HWE-SKT", "3c39e72e33ce", 0),
+                measured_device("HWE-SKT", "3c39e72e44df", 1),
+            ],
+        );
+        let socket_sample = sample(
+            EntityType::Device,
+            "HWE-SKT",
+            "Bonenmaler",
+            MetricType::Gauge,
+            98.1,
+        );
+        let measurement = measurement(vec![socket_sample.clone(), socket_sample]);
+
+        // act
+        publisher.publish(&measurement).await.unwrap();
+
+        let topics: Vec<String> = sink
+            .messages
+            .borrow()
+            .iter()
+            .map(|(topic, _)| topic.clone())
+            .collect();
+        assert_eq!(
+            topics,
+            vec![
+                "homewizard/my-home/3c39e72e33ce/bonenmaler/electricity-consumption/gauge",
+                "homewizard/my-home/3c39e72e44df/bonenmaler/electricity-consumption/gauge",
+            ]
+        );
+    }
+
+    #[test]
+    fn topic_level_escapes_topic_wildcards_and_separators() {
+        assert_eq!(topic_level("Keuken/Koffie #2"), "keuken-koffie-2");
+        assert_eq!(topic_level("+ Wasmachine +"), "wasmachine");
+        assert_eq!(
+            topic_level("ElectricityConsumption"),
+            "electricity-consumption"
+        );
+        assert_eq!(topic_level("HWE-P1"), "hwe-p1");
+    }
+
+    #[test]
+    fn config_parses_broker_url() {
+        // act
+        let config = MqttPublisherConfig::new(
+            "mqtt://broker.local:1884/",
+            None,
+            "home/energy/".into(),
+            0,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(config.host, "broker.local");
+        assert_eq!(config.port, 1884);
+        assert_eq!(config.base_topic, "home/energy");
+        assert_eq!(config.qos, QoS::AtMostOnce);
+    }
+
+    #[test]
+    fn config_rejects_invalid_broker_url_and_qos() {
+        assert!(
+            MqttPublisherConfig::new("tcp://broker.local", None, "homewizard".into(), 1, None)
+                .is_err()
+        );
+        assert!(MqttPublisherConfig::new(
+            "mqtt://broker.local",
+            None,
+            "homewizard".into(),
+            3,
+            None
+        )
+        .is_err());
+    }
+}
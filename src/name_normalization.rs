@@ -0,0 +1,83 @@
+use crate::model::NameNormalizationConfig;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+/// Turns a friendly name into a lowercase ascii slug: accents are folded off their letters, other
+/// non-ascii letters and symbols dropped, and every run of spaces and punctuation replaced by the
+/// separator, with none left at either end. The result is cut off at `maxLength` characters.
+pub fn normalize(config: &NameNormalizationConfig, name: &str) -> String {
+    let mut normalized = String::new();
+    let mut pending_separator = false;
+    for c in name.nfd().filter(|c| !is_combining_mark(*c)) {
+        if c.is_ascii_alphanumeric() {
+            if pending_separator && !normalized.is_empty() {
+                normalized.push_str(&config.separator);
+            }
+            pending_separator = false;
+            normalized.push(c.to_ascii_lowercase());
+        } else if c.is_ascii() || c.is_whitespace() {
+            pending_separator = true;
+        }
+    }
+
+    if let Some(max_length) = config.max_length {
+        if normalized.len() > max_length {
+            normalized.truncate(max_length);
+            // a cut at a separator leaves it dangling
+            let trimmed_length = normalized
+                .trim_end_matches(|c| config.separator.contains(c))
+                .len();
+            normalized.truncate(trimmed_length);
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(separator: &str, max_length: Option<usize>) -> NameNormalizationConfig {
+        NameNormalizationConfig {
+            separator: separator.into(),
+            max_length,
+        }
+    }
+
+    #[test]
+    fn normalize_replaces_spaces_and_punctuation_and_drops_symbols() {
+        // act
+        let normalized = normalize(&config("_", None), "Wasmachine ☔ / kelder");
+
+        assert_eq!(normalized, "wasmachine_kelder");
+    }
+
+    #[test]
+    fn normalize_folds_accents_to_ascii() {
+        // act
+        let normalized = normalize(&config("_", None), "Crème Brûlée-Koeling ÅÄÖ");
+
+        assert_eq!(normalized, "creme_brulee_koeling_aao");
+    }
+
+    #[test]
+    fn normalize_uses_configured_separator() {
+        // act
+        let dashed = normalize(&config("-", None), " Koffie (keuken) ");
+        let joined = normalize(&config("", None), " Koffie (keuken) ");
+
+        assert_eq!(dashed, "koffie-keuken");
+        assert_eq!(joined, "koffiekeuken");
+    }
+
+    #[test]
+    fn normalize_truncates_without_trailing_separator() {
+        // act
+        let truncated = normalize(&config("_", Some(11)), "Wasmachine kelder");
+        let cut_at_separator = normalize(&config("__", Some(11)), "Wasmachine kelder");
+
+        assert_eq!(truncated, "wasmachine");
+        assert_eq!(cut_at_separator, "wasmachine");
+    }
+}
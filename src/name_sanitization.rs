@@ -0,0 +1,125 @@
+use crate::model::Config;
+use tracing::warn;
+
+/// Makes a friendly name safe to use as sample name: every run of control characters - like a
+/// line break pasted along from an app - becomes a single space, whitespace at either end is
+/// trimmed and the result is cut off at `max_length` characters.
+pub fn sanitize(name: &str, max_length: usize) -> String {
+    let mut sanitized = String::with_capacity(name.len());
+    let mut in_control_run = false;
+    for c in name.chars() {
+        if c.is_control() {
+            if !in_control_run {
+                sanitized.push(' ');
+            }
+            in_control_run = true;
+        } else {
+            sanitized.push(c);
+            in_control_run = false;
+        }
+    }
+
+    let sanitized = sanitized.trim();
+    match sanitized.char_indices().nth(max_length) {
+        Some((cut_at, _)) => sanitized[..cut_at].trim_end().to_string(),
+        None => sanitized.to_string(),
+    }
+}
+
+/// Sanitizes the configured names in place, warning about every name that changed so it's clear
+/// the emitted name differs from the config. A name left empty is rejected by validating the
+/// config.
+pub fn sanitize_names(config: &mut Config) {
+    let max_length = config.name_max_length();
+    for (serial, name) in config.names.iter_mut() {
+        let sanitized = sanitize(name, max_length);
+        if sanitized != *name {
+            warn!(
+                "Sanitized the name of serial {} from {:?} to {:?}, samples get the sanitized name",
+                serial, name, sanitized
+            );
+            *name = sanitized;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::capture_logs;
+
+    #[test]
+    fn sanitize_trims_whitespace() {
+        // act
+        let sanitized = sanitize("  Bonenmaler \t", 64);
+
+        assert_eq!(sanitized, "Bonenmaler");
+    }
+
+    #[test]
+    fn sanitize_replaces_runs_of_control_characters_by_a_space() {
+        // act
+        let sanitized = sanitize("Koffie\r\nkeuken\u{7}\n", 64);
+
+        assert_eq!(sanitized, "Koffie keuken");
+    }
+
+    #[test]
+    fn sanitize_keeps_unicode_that_isnt_control() {
+        // act
+        let sanitized = sanitize("Crème brûlée ☕", 64);
+
+        assert_eq!(sanitized, "Crème brûlée ☕");
+    }
+
+    #[test]
+    fn sanitize_cuts_off_at_max_length_in_characters() {
+        // act
+        let sanitized = sanitize("Crème brûlée koeling", 12);
+        let cut_at_space = sanitize("Crème brûlée koeling", 13);
+
+        assert_eq!(sanitized, "Crème brûlée");
+        assert_eq!(cut_at_space, "Crème brûlée");
+    }
+
+    #[test]
+    fn sanitize_names_warns_about_changed_names_only() {
+        let mut config = Config {
+            location: "My Home".into(),
+            names: vec![
+                ("3c39e72e33ce".to_string(), "Bonenmaler\n".to_string()),
+                ("5c2faf0a8b3e".to_string(), "Meterkast".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+
+        // act
+        let logs = capture_logs("warn", || sanitize_names(&mut config));
+
+        assert_eq!(config.names["3c39e72e33ce"], "Bonenmaler");
+        assert_eq!(config.names["5c2faf0a8b3e"], "Meterkast");
+        assert!(logs.contains("Sanitized the name of serial 3c39e72e33ce"));
+        assert!(!logs.contains("5c2faf0a8b3e"));
+    }
+
+    #[test]
+    fn sanitize_names_leaves_name_of_only_control_characters_empty_for_validation() {
+        let mut config = Config {
+            location: "My Home".into(),
+            names: vec![("3c39e72e33ce".to_string(), "\u{7}\n".to_string())]
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        };
+
+        // act
+        capture_logs("warn", || sanitize_names(&mut config));
+
+        assert_eq!(
+            config.validate().unwrap_err().to_string(),
+            "names has an empty name for serial 3c39e72e33ce"
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use crate::exporter::MeasurementPublisher;
+use crate::homewizard_client::{CycleSummary, MeasuredDevice};
+use crate::mqtt::topic_level;
+use async_trait::async_trait;
+use jarvis_lib::model::Measurement;
+use std::env;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+const PLACEHOLDERS: [&str; 4] = ["location", "source", "serial", "name"];
+
+/// A NATS subject with `{location}`, `{source}`, `{serial}` and `{name}` placeholders, the last
+/// two filled in with the serial and friendly name of a device.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubjectTemplate {
+    template: String,
+}
+
+impl SubjectTemplate {
+    pub fn parse(template: &str) -> Result<Self, Box<dyn Error>> {
+        if template.trim().is_empty() {
+            return Err("NATS_SUBJECT_TEMPLATE can't be empty".into());
+        }
+
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("NATS_SUBJECT_TEMPLATE {} has an unclosed {{", template))?;
+            let placeholder = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!(
+                    "NATS_SUBJECT_TEMPLATE {} has unknown placeholder {{{}}}, use {{location}}, {{source}}, {{serial}} or {{name}}",
+                    template, placeholder
+                )
+                .into());
+            }
+            rest = &rest[start + end + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("NATS_SUBJECT_TEMPLATE {} has an unopened }}", template).into());
+        }
+
+        Ok(Self {
+            template: template.to_string(),
+        })
+    }
+
+    /// Whether the subject differs per device, in which case every device gets a measurement of
+    /// its own.
+    pub fn has_device_placeholder(&self) -> bool {
+        self.template.contains("{serial}") || self.template.contains("{name}")
+    }
+
+    fn render(&self, measurement: &Measurement, device: Option<&MeasuredDevice>) -> String {
+        // every value becomes a single token, without the dots and wildcards of subjects
+        let mut subject = self
+            .template
+            .replace("{location}", &topic_level(&measurement.location))
+            .replace("{source}", &topic_level(&measurement.source));
+        if let Some(device) = device {
+            subject = subject
+                .replace("{serial}", &topic_level(&device.info.serial))
+                .replace("{name}", &topic_level(&device.friendly_name));
+        }
+
+        subject
+    }
+
+    /// Renders the subject for the measurement, or with a device placeholder splits it into a
+    /// measurement per device, holding only the samples of that device.
+    fn subjects(
+        &self,
+        measurement: &Measurement,
+        devices: &[MeasuredDevice],
+    ) -> Vec<(String, Measurement)> {
+        if !self.has_device_placeholder() {
+            return vec![(self.render(measurement, None), measurement.clone())];
+        }
+
+        devices
+            .iter()
+            .map(|device| {
+                let device_measurement = Measurement {
+                    samples: measurement
+                        .samples
+                        .get(device.samples.clone())
+                        .unwrap_or_default()
+                        .to_vec(),
+                    ..measurement.clone()
+                };
+                (self.render(measurement, Some(device)), device_measurement)
+            })
+            .filter(|(_, device_measurement)| !device_measurement.samples.is_empty())
+            .collect()
+    }
+}
+
+pub struct NatsSubjectPublisherConfig {
+    host: String,
+    template: SubjectTemplate,
+}
+
+impl NatsSubjectPublisherConfig {
+    pub fn new(host: String, template: SubjectTemplate) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "NatsSubjectPublisherConfig::new(host: {}, template: {:?})",
+            host, template
+        );
+        Ok(Self { host, template })
+    }
+
+    /// Returns no config when `NATS_SUBJECT_TEMPLATE` isn't set, so measurements keep going to
+    /// the single `NATS_SUBJECT` of the jarvis NATS client.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let template = match env::var("NATS_SUBJECT_TEMPLATE") {
+            Ok(template) => SubjectTemplate::parse(&template)?,
+            Err(_) => return Ok(None),
+        };
+        let host = env::var("NATS_HOST").unwrap_or_else(|_| "jarvis-nats".to_string());
+
+        Ok(Some(Self::new(host, template)?))
+    }
+}
+
+/// Publishes measurements as json on the subject rendered from the template, taking the devices
+/// behind the samples from the summary of the cycle being published. The connection is made once
+/// and reconnects by itself when the server goes away.
+pub struct NatsSubjectPublisher {
+    template: SubjectTemplate,
+    connection: nats::Connection,
+    cycle_summary: Arc<Mutex<Option<CycleSummary>>>,
+}
+
+impl NatsSubjectPublisher {
+    pub fn connect(
+        config: NatsSubjectPublisherConfig,
+        cycle_summary: Arc<Mutex<Option<CycleSummary>>>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let connection = nats::connect(&config.host)
+            .map_err(|e| format!("Failed connecting to NATS at {}: {}", config.host, e))?;
+
+        Ok(Self {
+            template: config.template,
+            connection,
+            cycle_summary,
+        })
+    }
+}
+
+#[async_trait(?Send)]
+impl MeasurementPublisher for NatsSubjectPublisher {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        let devices = self
+            .cycle_summary
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|summary| summary.measured_devices_of(&measurement.id))
+            .unwrap_or_default();
+
+        let subjects = self.template.subjects(measurement, &devices);
+        // the nats client blocks, like the device requests do
+        tokio::task::block_in_place(|| -> Result<(), Box<dyn Error>> {
+            for (subject, measurement) in subjects {
+                debug!("Publishing measurement to subject {}", subject);
+                self.connection
+                    .publish(&subject, serde_json::to_vec(&measurement)?)?;
+            }
+            self.connection.flush()?;
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard_client::DeviceInfoResponse;
+    use crate::test_support::FakeNatsServer;
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+    use std::fs;
+
+    fn sample(entity_name: &str, sample_name: &str, value: f64) -> Sample {
+        Sample {
+            entity_type: EntityType::Device,
+            entity_name: entity_name.into(),
+            sample_type: SampleType::ElectricityConsumption,
+            sample_name: sample_name.into(),
+            metric_type: MetricType::Gauge,
+            value,
+        }
+    }
+
+    fn measured_device(api_fixture: &str, friendly_name: &str, sample: usize) -> MeasuredDevice {
+        let info: DeviceInfoResponse = serde_json::from_str(
+            &fs::read_to_string(format!("tests/fixtures/responses/{}", api_fixture)).unwrap(),
+        )
+        .unwrap();
+        MeasuredDevice {
+            measurement_id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            info,
+            friendly_name: friendly_name.into(),
+            samples: sample..sample + 1,
+        }
+    }
+
+    fn measurement() -> (Measurement, Vec<MeasuredDevice>) {
+        let measurement = Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "Home".into(),
+            samples: vec![
+                sample("HWE-P1", "P1", -543.0),
+                sample("HWE-SKT", "Bonenmaler", 98.1),
+            ],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        };
+        let devices = vec![
+            measured_device("api-p1.json", "P1", 0),
+            measured_device("api-socket.json", "Bonenmaler", 1),
+        ];
+
+        (measurement, devices)
+    }
+
+    fn rendered(template: &str) -> Vec<(String, usize)> {
+        let (measurement, devices) = measurement();
+
+        SubjectTemplate::parse(template)
+            .unwrap()
+            .subjects(&measurement, &devices)
+            .into_iter()
+            .map(|(subject, measurement)| (subject, measurement.samples.len()))
+            .collect()
+    }
+
+    #[test]
+    fn subjects_without_placeholders_publish_the_whole_measurement() {
+        assert_eq!(
+            rendered("jarvis.measurements"),
+            vec![("jarvis.measurements".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn subjects_by_location_publish_the_whole_measurement() {
+        assert_eq!(
+            rendered("jarvis.measurements.{location}"),
+            vec![("jarvis.measurements.home".to_string(), 2)]
+        );
+        assert_eq!(
+            rendered("{source}.{location}"),
+            vec![("jarvis-homewizard-exporter.home".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn subjects_by_device_split_the_measurement() {
+        assert_eq!(
+            rendered("jarvis.measurements.{location}.{name}"),
+            vec![
+                ("jarvis.measurements.home.p1".to_string(), 1),
+                ("jarvis.measurements.home.bonenmaler".to_string(), 1),
+            ]
+        );
+
+        let (measurement, devices) = measurement();
+        let by_serial = SubjectTemplate::parse("jarvis.{serial}")
+            .unwrap()
+            .subjects(&measurement, &devices);
+        assert_eq!(by_serial[0].0, "jarvis.5c2faf0a8b3e");
+        assert_eq!(by_serial[1].1.samples[0].sample_name, "Bonenmaler");
+    }
+
+    #[test]
+    fn parse_rejects_unknown_and_malformed_placeholders() {
+        assert!(SubjectTemplate::parse("jarvis.{device}").is_err());
+        assert!(SubjectTemplate::parse("jarvis.{location").is_err());
+        assert!(SubjectTemplate::parse("jarvis.location}").is_err());
+        assert!(SubjectTemplate::parse(" ").is_err());
+        assert!(SubjectTemplate::parse("jarvis.{location}.{source}.{serial}.{name}").is_ok());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn publish_reuses_one_connection_for_every_measurement() {
+        let server = FakeNatsServer::start();
+        let publisher = NatsSubjectPublisher::connect(
+            NatsSubjectPublisherConfig::new(
+                server.address().to_string(),
+                SubjectTemplate::parse("jarvis.measurements.{location}").unwrap(),
+            )
+            .unwrap(),
+            Arc::new(Mutex::new(None)),
+        )
+        .unwrap();
+        let (measurement, _) = measurement();
+
+        // act
+        publisher.publish(&measurement).await.unwrap();
+        publisher.publish(&measurement).await.unwrap();
+
+        assert_eq!(server.connections(), 1);
+        let published = server.published();
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0].0, "jarvis.measurements.home");
+        let posted: Measurement = serde_json::from_str(&published[1].1).unwrap();
+        assert_eq!(posted.samples.len(), 2);
+    }
+}
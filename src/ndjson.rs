@@ -0,0 +1,95 @@
+use crate::exporter::MeasurementPublisher;
+use async_trait::async_trait;
+use jarvis_lib::model::Measurement;
+use std::error::Error;
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Writes every measurement as a single line of json, flushed right away, so the output can be
+/// piped into tools like `jq` while the exporter keeps running.
+pub struct NdjsonPublisher<W: Write> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write> NdjsonPublisher<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl<W: Write> MeasurementPublisher for NdjsonPublisher<W> {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut *writer, measurement)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::{Exporter, InMemoryStore};
+    use crate::model::Config;
+    use crate::test_support::CapturedLogs;
+    use chrono::Utc;
+    use jarvis_lib::config_client::{ConfigClient, ConfigClientConfig};
+    use jarvis_lib::measurement_client::MeasurementClient;
+    use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+    use uuid::Uuid;
+
+    struct MockMeasurementClient {}
+
+    impl MeasurementClient<Config> for MockMeasurementClient {
+        fn get_measurements(
+            &self,
+            config: Config,
+            _last_measurements: Option<Vec<Measurement>>,
+        ) -> Result<Vec<Measurement>, Box<dyn Error>> {
+            Ok(vec![Measurement {
+                id: Uuid::new_v4().to_string(),
+                source: String::from("jarvis-homewizard-exporter"),
+                location: config.location,
+                samples: vec![Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-SKT".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "Bonenmaler".into(),
+                    metric_type: MetricType::Gauge,
+                    value: 1450.0,
+                }],
+                measured_at_time: Utc::now(),
+            }])
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn writes_one_json_line_per_cycle() {
+        let output = CapturedLogs::default();
+        let exporter = Exporter::new(
+            ConfigClient::new(ConfigClientConfig::new("test-config.yaml".to_string()).unwrap()),
+            Box::new(MockMeasurementClient {}),
+            Box::new(NdjsonPublisher::new(output.clone())),
+            Box::new(InMemoryStore::default()),
+        );
+
+        // act
+        exporter.run_once().await.unwrap();
+        exporter.run_once().await.unwrap();
+
+        let contents = output.contents();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 2);
+        for line in lines {
+            let measurement: Measurement = serde_json::from_str(line).unwrap();
+            assert_eq!(measurement.location, "My Home");
+            assert_eq!(measurement.samples[0].sample_name, "Bonenmaler");
+        }
+        assert!(contents.ends_with('\n'));
+    }
+}
@@ -0,0 +1,462 @@
+use crate::apparent_power::is_apparent_power;
+use crate::daily_consumption::is_daily_consumption;
+use crate::derived::{
+    is_cost, is_device_reachable, is_energy_delta, is_fetch_failures, is_water_liters,
+};
+use crate::exporter::MeasurementPublisher;
+use crate::homewizard_client::{CycleSummary, MeasuredDevice};
+use crate::sample_cap::is_dropped_samples;
+use async_trait::async_trait;
+use jarvis_lib::model::{Measurement, MetricType, Sample, SampleType};
+use opentelemetry::metrics::{Meter, MeterProvider as _, Unit};
+use opentelemetry::sdk::metrics::MeterProvider;
+use opentelemetry::sdk::Resource;
+use opentelemetry::{Context, KeyValue};
+use std::any::Any;
+use std::cell::RefCell;
+use std::env;
+use std::error::Error;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::info;
+
+type ProviderFactory = Box<dyn Fn(Resource) -> Result<MeterProvider, Box<dyn Error>>>;
+
+/// An instrument samples are recorded in, by the kind of sample.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Instrument {
+    ElectricityConsumption,
+    ElectricityProduction,
+    ElectricityPower,
+    WaterConsumption,
+    WaterFlow,
+}
+
+impl Instrument {
+    const ALL: [Instrument; 5] = [
+        Instrument::ElectricityConsumption,
+        Instrument::ElectricityProduction,
+        Instrument::ElectricityPower,
+        Instrument::WaterConsumption,
+        Instrument::WaterFlow,
+    ];
+
+    fn for_sample(sample: &Sample) -> Option<Self> {
+        if is_cost(sample)
+            || is_energy_delta(sample)
+            || is_water_liters(sample)
+            || is_fetch_failures(sample)
+            || is_dropped_samples(sample)
+            || is_apparent_power(sample)
+            || is_device_reachable(sample)
+            || is_daily_consumption(sample)
+        {
+            return None;
+        }
+
+        match (&sample.sample_type, &sample.metric_type) {
+            (SampleType::ElectricityConsumption, MetricType::Counter) => {
+                Some(Instrument::ElectricityConsumption)
+            }
+            (SampleType::ElectricityProduction, MetricType::Counter) => {
+                Some(Instrument::ElectricityProduction)
+            }
+            (SampleType::ElectricityConsumption, MetricType::Gauge) => {
+                Some(Instrument::ElectricityPower)
+            }
+            (SampleType::WaterConsumption, MetricType::Counter) => {
+                Some(Instrument::WaterConsumption)
+            }
+            (SampleType::WaterConsumption, MetricType::Gauge) => Some(Instrument::WaterFlow),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Instrument::ElectricityConsumption => "homewizard.electricity.consumption",
+            Instrument::ElectricityProduction => "homewizard.electricity.production",
+            Instrument::ElectricityPower => "homewizard.electricity.power",
+            Instrument::WaterConsumption => "homewizard.water.consumption",
+            Instrument::WaterFlow => "homewizard.water.flow",
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        match self {
+            Instrument::ElectricityConsumption | Instrument::ElectricityProduction => "J",
+            Instrument::ElectricityPower => "W",
+            Instrument::WaterConsumption => "m3",
+            Instrument::WaterFlow => "m3/h",
+        }
+    }
+
+    fn is_counter(&self) -> bool {
+        !matches!(self, Instrument::ElectricityPower | Instrument::WaterFlow)
+    }
+}
+
+struct Observation {
+    instrument: Instrument,
+    value: f64,
+    attributes: Vec<KeyValue>,
+}
+
+/// Records the samples of every cycle in OpenTelemetry instruments - counters as monotonic sums
+/// and gauges as gauges - and exports them over OTLP once the cycle is published, instead of on
+/// an interval.
+pub struct OtelMetricsPublisher {
+    build_provider: ProviderFactory,
+    provider: RefCell<Option<(String, MeterProvider)>>,
+    observations: Arc<Mutex<Vec<Observation>>>,
+    cycle_summary: Arc<Mutex<Option<CycleSummary>>>,
+}
+
+impl OtelMetricsPublisher {
+    /// Exports to the OTLP endpoint from the standard `OTEL_*` env vars; returns `None` when no
+    /// endpoint is set, like the trace export.
+    pub fn from_env(cycle_summary: Arc<Mutex<Option<CycleSummary>>>) -> Option<Self> {
+        if env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err()
+            && env::var("OTEL_EXPORTER_OTLP_METRICS_ENDPOINT").is_err()
+        {
+            return None;
+        }
+
+        Some(Self::new(
+            Box::new(|resource| {
+                Ok(opentelemetry_otlp::new_pipeline()
+                    .metrics(opentelemetry::runtime::Tokio)
+                    .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+                    .with_resource(resource)
+                    // exports are forced after every cycle; the period only covers shutdown
+                    .with_period(Duration::from_secs(24 * 60 * 60))
+                    .build()?)
+            }),
+            cycle_summary,
+        ))
+    }
+
+    /// The provider gets built on the first publish, with the location of the measurement as
+    /// resource attribute, and again whenever the location changes.
+    fn new(
+        build_provider: ProviderFactory,
+        cycle_summary: Arc<Mutex<Option<CycleSummary>>>,
+    ) -> Self {
+        Self {
+            build_provider,
+            provider: RefCell::new(None),
+            observations: Arc::new(Mutex::new(vec![])),
+            cycle_summary,
+        }
+    }
+
+    fn provider_for(&self, measurement: &Measurement) -> Result<MeterProvider, Box<dyn Error>> {
+        let mut provider = self.provider.borrow_mut();
+        if let Some((location, current)) = provider.as_ref() {
+            if *location == measurement.location {
+                return Ok(current.clone());
+            }
+            current.shutdown()?;
+        }
+
+        info!(
+            "Exporting samples as OpenTelemetry metrics for location {}",
+            measurement.location
+        );
+        let resource = Resource::new(vec![
+            KeyValue::new("location", measurement.location.clone()),
+            KeyValue::new("source", measurement.source.clone()),
+        ]);
+        let new_provider = (self.build_provider)(resource)?;
+        register_instruments(
+            &new_provider.meter("jarvis-homewizard-exporter"),
+            self.observations.clone(),
+        )?;
+        *provider = Some((measurement.location.clone(), new_provider.clone()));
+
+        Ok(new_provider)
+    }
+}
+
+#[async_trait(?Send)]
+impl MeasurementPublisher for OtelMetricsPublisher {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        let devices = self
+            .cycle_summary
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|summary| summary.measured_devices_of(&measurement.id))
+            .unwrap_or_default();
+        *self.observations.lock().unwrap() = observations(measurement, &devices);
+
+        let provider = self.provider_for(measurement)?;
+        provider.force_flush(&Context::current())?;
+
+        Ok(())
+    }
+}
+
+/// Creates an observable instrument for every kind of sample, reporting the observations of the
+/// last cycle whenever the metrics get collected.
+fn register_instruments(
+    meter: &Meter,
+    observations: Arc<Mutex<Vec<Observation>>>,
+) -> Result<(), Box<dyn Error>> {
+    let mut counters = vec![];
+    let mut gauges = vec![];
+    let mut instruments: Vec<Arc<dyn Any>> = vec![];
+
+    for instrument in Instrument::ALL.iter() {
+        if instrument.is_counter() {
+            let counter = meter
+                .f64_observable_counter(instrument.name())
+                .with_unit(Unit::new(instrument.unit()))
+                .init();
+            instruments.push(counter.as_any());
+            counters.push((*instrument, counter));
+        } else {
+            let gauge = meter
+                .f64_observable_gauge(instrument.name())
+                .with_unit(Unit::new(instrument.unit()))
+                .init();
+            instruments.push(gauge.as_any());
+            gauges.push((*instrument, gauge));
+        }
+    }
+
+    meter.register_callback(&instruments, move |observer| {
+        for observation in observations.lock().unwrap().iter() {
+            if let Some((_, counter)) = counters
+                .iter()
+                .find(|(instrument, _)| *instrument == observation.instrument)
+            {
+                observer.observe_f64(counter, observation.value, &observation.attributes);
+            }
+            if let Some((_, gauge)) = gauges
+                .iter()
+                .find(|(instrument, _)| *instrument == observation.instrument)
+            {
+                observer.observe_f64(gauge, observation.value, &observation.attributes);
+            }
+        }
+    })?;
+
+    Ok(())
+}
+
+/// Turns the samples into observations, attributed with the serial and friendly name of the
+/// device they were read from.
+fn observations(measurement: &Measurement, devices: &[MeasuredDevice]) -> Vec<Observation> {
+    measurement
+        .samples
+        .iter()
+        .enumerate()
+        .filter_map(|(i, sample)| {
+            let instrument = Instrument::for_sample(sample)?;
+            let mut attributes = vec![
+                KeyValue::new("sample_type", format!("{:?}", sample.sample_type)),
+                KeyValue::new("sample_name", sample.sample_name.clone()),
+            ];
+            if let Some(device) = devices.iter().find(|device| device.samples.contains(&i)) {
+                attributes.push(KeyValue::new("device_serial", device.info.serial.clone()));
+                attributes.push(KeyValue::new("device_name", device.friendly_name.clone()));
+            }
+
+            Some(Observation {
+                instrument,
+                value: sample.value,
+                attributes,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homewizard_client::DeviceInfoResponse;
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::EntityType;
+    use opentelemetry::sdk::metrics::data::{Gauge, ResourceMetrics, Sum};
+    use opentelemetry::sdk::metrics::reader::{
+        AggregationSelector, MetricReader, TemporalitySelector,
+    };
+    use opentelemetry::sdk::metrics::{
+        Aggregation, InstrumentKind, ManualReader, Pipeline, Temporality,
+    };
+    use opentelemetry::Key;
+    use std::fs;
+    use std::sync::Weak;
+
+    /// Keeps the collected metrics in memory for the test to read, while the meter provider owns
+    /// the reader.
+    #[derive(Debug, Clone)]
+    struct InMemoryReader(Arc<ManualReader>);
+
+    impl AggregationSelector for InMemoryReader {
+        fn aggregation(&self, kind: InstrumentKind) -> Aggregation {
+            self.0.aggregation(kind)
+        }
+    }
+
+    impl TemporalitySelector for InMemoryReader {
+        fn temporality(&self, kind: InstrumentKind) -> Temporality {
+            self.0.temporality(kind)
+        }
+    }
+
+    impl MetricReader for InMemoryReader {
+        fn register_pipeline(&self, pipeline: Weak<Pipeline>) {
+            self.0.register_pipeline(pipeline)
+        }
+
+        fn collect(&self, rm: &mut ResourceMetrics) -> opentelemetry::metrics::Result<()> {
+            self.0.collect(rm)
+        }
+
+        fn force_flush(&self, cx: &Context) -> opentelemetry::metrics::Result<()> {
+            self.0.force_flush(cx)
+        }
+
+        fn shutdown(&self) -> opentelemetry::metrics::Result<()> {
+            self.0.shutdown()
+        }
+    }
+
+    fn publisher() -> (OtelMetricsPublisher, InMemoryReader) {
+        let reader = InMemoryReader(Arc::new(ManualReader::builder().build()));
+        let provider_reader = reader.clone();
+        let info: DeviceInfoResponse = serde_json::from_str(
+            &fs::read_to_string("tests/fixtures/responses/api-watermeter.json").unwrap(),
+        )
+        .unwrap();
+        let cycle_summary = Arc::new(Mutex::new(Some(CycleSummary {
+            measured_devices: vec![MeasuredDevice {
+                measurement_id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+                info,
+                friendly_name: "Tuin".into(),
+                samples: 1..3,
+            }],
+            ..Default::default()
+        })));
+
+        let publisher = OtelMetricsPublisher::new(
+            Box::new(move |resource| {
+                Ok(MeterProvider::builder()
+                    .with_resource(resource)
+                    .with_reader(provider_reader.clone())
+                    .build())
+            }),
+            cycle_summary,
+        );
+
+        (publisher, reader)
+    }
+
+    fn measurement() -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![
+                Sample {
+                    entity_type: EntityType::Tariff,
+                    entity_name: "HWE-P1".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "t1 import".into(),
+                    metric_type: MetricType::Counter,
+                    value: 38989839600.0,
+                },
+                Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-WTR".into(),
+                    sample_type: SampleType::WaterConsumption,
+                    sample_name: "Tuin".into(),
+                    metric_type: MetricType::Counter,
+                    value: 123.456,
+                },
+                Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-WTR".into(),
+                    sample_type: SampleType::WaterConsumption,
+                    sample_name: "Tuin".into(),
+                    metric_type: MetricType::Gauge,
+                    value: 0.45,
+                },
+            ],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn collect(reader: &InMemoryReader) -> ResourceMetrics {
+        let mut metrics = ResourceMetrics {
+            resource: Resource::empty(),
+            scope_metrics: vec![],
+        };
+        reader.collect(&mut metrics).unwrap();
+        metrics
+    }
+
+    fn attribute(attributes: &opentelemetry::sdk::AttributeSet, key: &str) -> Option<String> {
+        attributes
+            .iter()
+            .find(|(k, _)| **k == Key::from(key.to_string()))
+            .map(|(_, value)| value.to_string())
+    }
+
+    #[tokio::test]
+    async fn publish_records_counters_as_monotonic_sums_and_gauges_as_gauges() {
+        let (publisher, reader) = publisher();
+
+        // act
+        publisher.publish(&measurement()).await.unwrap();
+
+        let metrics = collect(&reader);
+        assert_eq!(
+            metrics.resource.get(Key::from_static_str("location")),
+            Some("My Home".into())
+        );
+        let metrics = &metrics.scope_metrics[0].metrics;
+
+        let energy = metrics
+            .iter()
+            .find(|metric| metric.name == "homewizard.electricity.consumption")
+            .unwrap();
+        assert_eq!(energy.unit.as_str(), "J");
+        let energy = energy.data.as_any().downcast_ref::<Sum<f64>>().unwrap();
+        assert!(energy.is_monotonic);
+        assert_eq!(energy.data_points[0].value, 38989839600.0);
+        assert_eq!(
+            attribute(&energy.data_points[0].attributes, "sample_name"),
+            Some("t1 import".into())
+        );
+        assert_eq!(
+            attribute(&energy.data_points[0].attributes, "device_serial"),
+            None
+        );
+
+        let water = metrics
+            .iter()
+            .find(|metric| metric.name == "homewizard.water.consumption")
+            .unwrap();
+        let water = water.data.as_any().downcast_ref::<Sum<f64>>().unwrap();
+        assert!(water.is_monotonic);
+        assert_eq!(water.data_points[0].value, 123.456);
+
+        let flow = metrics
+            .iter()
+            .find(|metric| metric.name == "homewizard.water.flow")
+            .unwrap();
+        assert_eq!(flow.unit.as_str(), "m3/h");
+        let flow = flow.data.as_any().downcast_ref::<Gauge<f64>>().unwrap();
+        assert_eq!(flow.data_points[0].value, 0.45);
+        let attributes = &flow.data_points[0].attributes;
+        assert_eq!(
+            attribute(attributes, "sample_type"),
+            Some("WaterConsumption".into())
+        );
+        assert_eq!(attribute(attributes, "device_name"), Some("Tuin".into()));
+        assert!(attribute(attributes, "device_serial").is_some());
+    }
+}
@@ -0,0 +1,99 @@
+use crate::model::PrecisionConfig;
+use crate::mqtt::topic_level;
+use jarvis_lib::model::Sample;
+
+/// Rounds the value of every sample to the decimals of its sample type, or else the global
+/// decimals; a sample type without either keeps its value as is, and so do values that aren't
+/// finite.
+pub fn round(config: &PrecisionConfig, samples: &mut [Sample]) {
+    for sample in samples.iter_mut() {
+        let decimals = config
+            .sample_types
+            .get(&topic_level(&format!("{:?}", sample.sample_type)))
+            .or(config.decimals.as_ref());
+        if let (Some(decimals), true) = (decimals, sample.value.is_finite()) {
+            let factor = 10f64.powi(*decimals as i32);
+            sample.value = (sample.value * factor).round() / factor;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jarvis_lib::model::{EntityType, MetricType, SampleType};
+
+    fn sample(sample_type: SampleType, value: f64) -> Sample {
+        Sample {
+            entity_type: EntityType::Device,
+            entity_name: "HWE-SKT".into(),
+            sample_type,
+            sample_name: "Bonenmaler".into(),
+            metric_type: MetricType::Counter,
+            value,
+        }
+    }
+
+    fn values(samples: &[Sample]) -> Vec<f64> {
+        samples.iter().map(|sample| sample.value).collect()
+    }
+
+    #[test]
+    fn round_uses_decimals_of_sample_type_before_global_ones() {
+        let config = PrecisionConfig {
+            decimals: Some(0),
+            sample_types: vec![("water-consumption".to_string(), 3)]
+                .into_iter()
+                .collect(),
+        };
+        let mut samples = vec![
+            sample(SampleType::ElectricityConsumption, 123456789.00000001),
+            sample(SampleType::ElectricityProduction, 2.5),
+            sample(SampleType::WaterConsumption, 431.00049999),
+        ];
+
+        // act
+        round(&config, &mut samples);
+
+        assert_eq!(values(&samples), vec![123456789.0, 3.0, 431.0]);
+    }
+
+    #[test]
+    fn round_keeps_precise_values_and_values_without_decimals() {
+        let config = PrecisionConfig {
+            decimals: None,
+            sample_types: vec![("electricity-consumption".to_string(), 2)]
+                .into_iter()
+                .collect(),
+        };
+        let mut samples = vec![
+            sample(SampleType::ElectricityConsumption, 0.1 + 0.2),
+            sample(SampleType::ElectricityConsumption, f64::NAN),
+            sample(SampleType::WaterConsumption, 0.1 + 0.2),
+        ];
+
+        // act
+        round(&config, &mut samples);
+
+        assert_eq!(samples[0].value, 0.3);
+        assert!(samples[1].value.is_nan());
+        assert_eq!(samples[2].value, 0.1 + 0.2);
+    }
+
+    #[test]
+    fn round_twice_changes_nothing() {
+        let config = PrecisionConfig {
+            decimals: Some(6),
+            sample_types: Default::default(),
+        };
+        let mut samples = vec![sample(SampleType::ElectricityConsumption, 3600000.1234567)];
+        round(&config, &mut samples);
+        let once = values(&samples);
+
+        // act
+        round(&config, &mut samples);
+
+        assert_eq!(values(&samples), once);
+        assert_eq!(once, vec![3600000.123457]);
+    }
+}
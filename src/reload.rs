@@ -0,0 +1,60 @@
+use std::io;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Notify;
+use tracing::info;
+
+/// Tells the exporter to reload its config. A request made while nobody waits for one is kept
+/// until the next wait, so a reload requested during a cycle happens right after it.
+#[derive(Clone, Default)]
+pub struct ReloadSignal {
+    notify: Arc<Notify>,
+}
+
+impl ReloadSignal {
+    pub fn request(&self) {
+        self.notify.notify_one();
+    }
+
+    /// Completes once a reload is requested.
+    pub async fn requested(&self) {
+        self.notify.notified().await
+    }
+}
+
+/// Installs a SIGHUP handler that requests a config reload.
+pub fn install() -> io::Result<ReloadSignal> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    let reload_signal = ReloadSignal::default();
+    let requests = reload_signal.clone();
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading the config...");
+            requests.request();
+        }
+    });
+
+    Ok(reload_signal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn request_without_waiter_is_kept_for_next_wait() {
+        let reload_signal = ReloadSignal::default();
+        reload_signal.request();
+
+        // act
+        let result =
+            tokio::time::timeout(Duration::from_millis(50), reload_signal.requested()).await;
+
+        assert!(result.is_ok());
+        let result =
+            tokio::time::timeout(Duration::from_millis(50), reload_signal.requested()).await;
+        assert!(result.is_err());
+    }
+}
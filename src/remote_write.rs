@@ -0,0 +1,286 @@
+use crate::exporter::MeasurementPublisher;
+use crate::metrics::{family, sample_type_label};
+use async_trait::async_trait;
+use jarvis_lib::model::Measurement;
+use prost::Message;
+use std::env;
+use std::error::Error;
+use tracing::debug;
+
+/// The messages of the Prometheus remote write protocol, as defined in `prompb/remote.proto` and
+/// `prompb/types.proto`, limited to the fields the exporter sends.
+mod prompb {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub timeseries: Vec<TimeSeries>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TimeSeries {
+        #[prost(message, repeated, tag = "1")]
+        pub labels: Vec<Label>,
+        #[prost(message, repeated, tag = "2")]
+        pub samples: Vec<Sample>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Label {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sample {
+        #[prost(double, tag = "1")]
+        pub value: f64,
+        /// Milliseconds since the epoch.
+        #[prost(int64, tag = "2")]
+        pub timestamp: i64,
+    }
+}
+
+pub struct RemoteWritePublisherConfig {
+    url: String,
+    credentials: Option<(String, String)>,
+}
+
+impl RemoteWritePublisherConfig {
+    pub fn new(url: String, credentials: Option<(String, String)>) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "RemoteWritePublisherConfig::new(url: {}, username: {:?})",
+            url,
+            credentials.as_ref().map(|(username, _)| username)
+        );
+        Ok(Self { url, credentials })
+    }
+
+    /// Returns no config when `REMOTE_WRITE_URL` isn't set, since remote write is optional.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let url = match env::var("REMOTE_WRITE_URL") {
+            Ok(url) => url,
+            Err(_) => return Ok(None),
+        };
+        let credentials = match (
+            env::var("REMOTE_WRITE_USERNAME"),
+            env::var("REMOTE_WRITE_PASSWORD"),
+        ) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+
+        Ok(Some(Self::new(url, credentials)?))
+    }
+}
+
+/// Pushes the samples of every measurement to a Prometheus remote write endpoint, such as
+/// VictoriaMetrics, as the same series the `/metrics` endpoint exposes.
+pub struct RemoteWritePublisher {
+    config: RemoteWritePublisherConfig,
+    client: reqwest::Client,
+}
+
+impl RemoteWritePublisher {
+    pub fn new(config: RemoteWritePublisherConfig) -> Self {
+        Self {
+            config,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl MeasurementPublisher for RemoteWritePublisher {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        let write_request = write_request(measurement);
+        if write_request.timeseries.is_empty() {
+            return Ok(());
+        }
+
+        let body = snap::raw::Encoder::new().compress_vec(&write_request.encode_to_vec())?;
+        let mut request = self
+            .client
+            .post(&self.config.url)
+            .header("Content-Type", "application/x-protobuf")
+            .header("Content-Encoding", "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .body(body);
+        if let Some((username, password)) = &self.config.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        request.send().await?.error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Turns every sample into a series named after its metric family, so counters keep their
+/// `_total` suffix, with the labels of the exposition format sorted by name as remote write
+/// requires.
+fn write_request(measurement: &Measurement) -> prompb::WriteRequest {
+    let timestamp = measurement.measured_at_time.timestamp_millis();
+
+    let timeseries = measurement
+        .samples
+        .iter()
+        .filter_map(|sample| {
+            let family = family(sample)?;
+            let label = |name: &str, value: &str| prompb::Label {
+                name: name.to_string(),
+                value: value.to_string(),
+            };
+
+            Some(prompb::TimeSeries {
+                labels: vec![
+                    label("__name__", family.name),
+                    label("entity_name", &sample.entity_name),
+                    label("location", &measurement.location),
+                    label("sample_name", &sample.sample_name),
+                    label("sample_type", &sample_type_label(&sample.sample_type)),
+                ],
+                samples: vec![prompb::Sample {
+                    value: sample.value,
+                    timestamp,
+                }],
+            })
+        })
+        .collect();
+
+    prompb::WriteRequest { timeseries }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeDeviceServer, FakeResponse};
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+
+    const WRITE_PATH: &str = "/api/v1/write";
+
+    fn measurement() -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![
+                Sample {
+                    entity_type: EntityType::Tariff,
+                    entity_name: "HWE-P1".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "t1 import".into(),
+                    metric_type: MetricType::Counter,
+                    value: 38989839600.0,
+                },
+                Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-WTR".into(),
+                    sample_type: SampleType::WaterConsumption,
+                    sample_name: "Watermeter".into(),
+                    metric_type: MetricType::Gauge,
+                    value: 0.45,
+                },
+            ],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn series(
+        name: &str,
+        entity_name: &str,
+        sample_name: &str,
+        sample_type: &str,
+        value: f64,
+    ) -> prompb::TimeSeries {
+        let label = |name: &str, value: &str| prompb::Label {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+
+        prompb::TimeSeries {
+            labels: vec![
+                label("__name__", name),
+                label("entity_name", entity_name),
+                label("location", "My Home"),
+                label("sample_name", sample_name),
+                label("sample_type", sample_type),
+            ],
+            samples: vec![prompb::Sample {
+                value,
+                timestamp: 1685620800000,
+            }],
+        }
+    }
+
+    #[tokio::test]
+    async fn publish_pushes_snappy_compressed_series() {
+        let server = FakeDeviceServer::start();
+        server.respond(WRITE_PATH, FakeResponse::status(204));
+        let publisher = RemoteWritePublisher::new(
+            RemoteWritePublisherConfig::new(
+                format!("{}{}", server.base_url(), WRITE_PATH),
+                Some(("jarvis".into(), "secret".into())),
+            )
+            .unwrap(),
+        );
+
+        // act
+        publisher.publish(&measurement()).await.unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.header("Content-Encoding"), Some("snappy"));
+        assert_eq!(
+            request.header("Content-Type"),
+            Some("application/x-protobuf")
+        );
+        // base64 of jarvis:secret
+        assert_eq!(
+            request.header("Authorization"),
+            Some("Basic amFydmlzOnNlY3JldA==")
+        );
+        let decompressed = snap::raw::Decoder::new()
+            .decompress_vec(&request.raw_body)
+            .unwrap();
+        let write_request = prompb::WriteRequest::decode(decompressed.as_slice()).unwrap();
+        assert_eq!(
+            write_request,
+            prompb::WriteRequest {
+                timeseries: vec![
+                    series(
+                        "homewizard_electricity_consumption_joules_total",
+                        "HWE-P1",
+                        "t1 import",
+                        "electricity_consumption",
+                        38989839600.0
+                    ),
+                    series(
+                        "homewizard_water_consumption_cubic_meters_per_hour",
+                        "HWE-WTR",
+                        "Watermeter",
+                        "water_consumption",
+                        0.45
+                    ),
+                ]
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn publish_fails_when_receiver_rejects_the_write() {
+        let server = FakeDeviceServer::start();
+        server.respond(WRITE_PATH, FakeResponse::status(400));
+        let publisher = RemoteWritePublisher::new(
+            RemoteWritePublisherConfig::new(format!("{}{}", server.base_url(), WRITE_PATH), None)
+                .unwrap(),
+        );
+
+        // act
+        let result = publisher.publish(&measurement()).await;
+
+        assert!(result.is_err());
+        assert_eq!(server.requests()[0].header("Authorization"), None);
+    }
+}
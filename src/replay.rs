@@ -0,0 +1,186 @@
+use crate::homewizard_client::HomewizardDevice;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::debug;
+
+/// Serves device responses from fixture files instead of discovering devices and requesting
+/// their endpoints over http.
+///
+/// Every subdirectory of the fixture directory is a device, named after the directory. A device
+/// endpoint is served from the file named after the last segment of its path, so `/api` comes
+/// from `api.json` and `/api/v1/data` from `data.json`. To vary a response per cycle, number the
+/// files instead - `data-1.json`, `data-2.json` - the last one repeats once cycles run out.
+pub struct ReplayFixtures {
+    directory: PathBuf,
+    cycle: AtomicUsize,
+}
+
+impl ReplayFixtures {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            cycle: AtomicUsize::new(0),
+        }
+    }
+
+    /// Moves on to the next cycle's responses and returns the devices to measure in it.
+    pub fn start_cycle(&self) -> Result<Vec<HomewizardDevice>, Box<dyn Error>> {
+        let cycle = self.cycle.fetch_add(1, Ordering::SeqCst) + 1;
+        debug!(
+            "Replaying cycle {} from {}",
+            cycle,
+            self.directory.display()
+        );
+
+        let mut names = vec![];
+        for entry in fs::read_dir(&self.directory)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+        names.sort();
+
+        Ok(names
+            .into_iter()
+            .map(|fullname| HomewizardDevice {
+                fullname,
+                ip_addresses: HashSet::new(),
+                port: 80,
+                hostname: None,
+                api_path: None,
+            })
+            .collect())
+    }
+
+    /// Returns the body the device would respond with to a request for `path` this cycle.
+    pub fn response(
+        &self,
+        device: &HomewizardDevice,
+        path: &str,
+    ) -> Result<String, Box<dyn Error + Send + Sync>> {
+        let device_directory = self.directory.join(&device.fullname);
+        let endpoint = path
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        let cycle = self.cycle.load(Ordering::SeqCst).max(1);
+
+        let file = fixture_file(&device_directory, endpoint, cycle).ok_or_else(|| {
+            format!(
+                "No fixture for {} of device {} in {}",
+                path,
+                device.fullname,
+                device_directory.display()
+            )
+        })?;
+
+        Ok(fs::read_to_string(file)?)
+    }
+}
+
+fn fixture_file(device_directory: &Path, endpoint: &str, cycle: usize) -> Option<PathBuf> {
+    let file = device_directory.join(format!("{}.json", endpoint));
+    if file.exists() {
+        return Some(file);
+    }
+
+    (1..=cycle)
+        .rev()
+        .map(|n| device_directory.join(format!("{}-{}.json", endpoint, n)))
+        .find(|file| file.exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exporter::{Exporter, InMemoryStore, MeasurementPublisher};
+    use crate::homewizard_client::{HomewizardClient, HomewizardClientConfig};
+    use async_trait::async_trait;
+    use jarvis_lib::config_client::{ConfigClient, ConfigClientConfig};
+    use jarvis_lib::model::Measurement;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct RecordingPublisher {
+        published: Rc<RefCell<Vec<Measurement>>>,
+    }
+
+    #[async_trait(?Send)]
+    impl MeasurementPublisher for RecordingPublisher {
+        async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+            self.published.borrow_mut().push(measurement.clone());
+            Ok(())
+        }
+    }
+
+    fn render(measurements: &[Measurement]) -> String {
+        let mut lines = vec![];
+        for (cycle, measurement) in measurements.iter().enumerate() {
+            lines.push(format!("cycle {} at {}", cycle + 1, measurement.location));
+            for sample in measurement.samples.iter() {
+                lines.push(format!(
+                    "{:?} {} {:?} {} {:?} {}",
+                    sample.entity_type,
+                    sample.entity_name,
+                    sample.sample_type,
+                    sample.sample_name,
+                    sample.metric_type,
+                    sample.value
+                ));
+            }
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn replays_fixtures_through_the_full_pipeline() {
+        let publisher = RecordingPublisher::default();
+        let homewizard_client = HomewizardClient::new(
+            HomewizardClientConfig::default().with_replay(PathBuf::from("tests/fixtures/replay")),
+        );
+        let exporter = Exporter::new(
+            ConfigClient::new(ConfigClientConfig::new("test-config.yaml".to_string()).unwrap()),
+            Box::new(homewizard_client),
+            Box::new(publisher.clone()),
+            Box::new(InMemoryStore::default()),
+        );
+
+        // act
+        exporter.run_once().await.unwrap();
+        exporter.run_once().await.unwrap();
+
+        assert_eq!(
+            render(&publisher.published.borrow()),
+            fs::read_to_string("tests/fixtures/replay.golden").unwrap()
+        );
+    }
+
+    #[test]
+    fn response_repeats_last_numbered_fixture() {
+        let fixtures = ReplayFixtures::new(PathBuf::from("tests/fixtures/replay"));
+        let device = HomewizardDevice {
+            fullname: "energysocket-3c39e72e33ce".into(),
+            ip_addresses: HashSet::new(),
+            port: 80,
+            hostname: None,
+            api_path: None,
+        };
+
+        // act
+        fixtures.start_cycle().unwrap();
+        fixtures.start_cycle().unwrap();
+        let second = fixtures.response(&device, "/api/v1/data").unwrap();
+        fixtures.start_cycle().unwrap();
+        let third = fixtures.response(&device, "/api/v1/data").unwrap();
+
+        assert_eq!(second, third);
+        assert!(fixtures.response(&device, "/api/v1/telegram").is_err());
+    }
+}
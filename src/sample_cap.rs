@@ -0,0 +1,139 @@
+use jarvis_lib::model::{EntityType, Measurement, MetricType, Sample, SampleType};
+use std::cmp::Ordering;
+
+const DROPPED_SAMPLES_NAME: &str = "dropped samples";
+
+/// Cuts the samples of `measurement` down to `max_samples`, one of which becomes a gauge of the
+/// number of samples dropped. Which samples stay doesn't depend on the order devices were found
+/// in: the first ones sorted by entity name, sample name, sample type and metric type do, in their
+/// original order. Returns the dropped samples with their index before truncating.
+pub fn truncate(measurement: &mut Measurement, max_samples: usize) -> Vec<(usize, Sample)> {
+    if measurement.samples.len() <= max_samples {
+        return vec![];
+    }
+
+    let mut order: Vec<usize> = (0..measurement.samples.len()).collect();
+    order.sort_by(|a, b| compare(&measurement.samples[*a], &measurement.samples[*b]));
+    let mut kept = vec![false; measurement.samples.len()];
+    for index in order.into_iter().take(max_samples.saturating_sub(1)) {
+        kept[index] = true;
+    }
+
+    let (samples, dropped): (Vec<(usize, Sample)>, Vec<(usize, Sample)>) = measurement
+        .samples
+        .drain(..)
+        .enumerate()
+        .partition(|(index, _)| kept[*index]);
+    measurement.samples = samples.into_iter().map(|(_, sample)| sample).collect();
+    measurement.samples.push(dropped_samples(dropped.len()));
+
+    dropped
+}
+
+fn compare(a: &Sample, b: &Sample) -> Ordering {
+    (
+        &a.entity_name,
+        &a.sample_name,
+        format!("{:?}", a.sample_type),
+        format!("{:?}", a.metric_type),
+    )
+        .cmp(&(
+            &b.entity_name,
+            &b.sample_name,
+            format!("{:?}", b.sample_type),
+            format!("{:?}", b.metric_type),
+        ))
+}
+
+/// Jarvis samples have no type for the exporter itself, so the number of dropped samples is a
+/// gauge of the electricity consumption type, told apart by its entity and sample name.
+fn dropped_samples(count: usize) -> Sample {
+    Sample {
+        entity_type: EntityType::Device,
+        entity_name: "jarvis-homewizard-exporter".into(),
+        sample_type: SampleType::ElectricityConsumption,
+        sample_name: DROPPED_SAMPLES_NAME.into(),
+        metric_type: MetricType::Gauge,
+        value: count as f64,
+    }
+}
+
+/// Whether the sample tells how many samples got dropped from the measurement.
+pub fn is_dropped_samples(sample: &Sample) -> bool {
+    matches!(sample.entity_type, EntityType::Device)
+        && matches!(sample.metric_type, MetricType::Gauge)
+        && sample.entity_name == "jarvis-homewizard-exporter"
+        && sample.sample_name == DROPPED_SAMPLES_NAME
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn measurement(sample_names: &[&str]) -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: "jarvis-homewizard-exporter".into(),
+            location: "My Home".into(),
+            samples: sample_names
+                .iter()
+                .map(|sample_name| Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-SKT".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: sample_name.to_string(),
+                    metric_type: MetricType::Gauge,
+                    value: 1.0,
+                })
+                .collect(),
+            measured_at_time: Utc::now(),
+        }
+    }
+
+    fn names_of(samples: &[Sample]) -> Vec<&str> {
+        samples
+            .iter()
+            .map(|sample| sample.sample_name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn truncate_keeps_measurement_under_limit() {
+        let mut measurement = measurement(&["Koffie", "Bonenmaler"]);
+
+        // act
+        let dropped = truncate(&mut measurement, 3);
+
+        assert!(dropped.is_empty());
+        assert_eq!(names_of(&measurement.samples), vec!["Koffie", "Bonenmaler"]);
+    }
+
+    #[test]
+    fn truncate_keeps_measurement_at_limit() {
+        let mut measurement = measurement(&["Koffie", "Bonenmaler", "Wasmachine"]);
+
+        // act
+        let dropped = truncate(&mut measurement, 3);
+
+        assert!(dropped.is_empty());
+        assert_eq!(measurement.samples.len(), 3);
+    }
+
+    #[test]
+    fn truncate_drops_last_sorted_samples_over_limit() {
+        let mut measurement = measurement(&["Wasmachine", "Koffie", "Droger", "Bonenmaler"]);
+
+        // act
+        let dropped = truncate(&mut measurement, 3);
+
+        let dropped_indices: Vec<usize> = dropped.iter().map(|(index, _)| *index).collect();
+        assert_eq!(dropped_indices, vec![0, 1]);
+        assert_eq!(
+            names_of(&measurement.samples),
+            vec!["Droger", "Bonenmaler", "dropped samples"]
+        );
+        assert_eq!(measurement.samples[2].value, 2.0);
+        assert!(is_dropped_samples(&measurement.samples[2]));
+    }
+}
@@ -0,0 +1,376 @@
+use crate::error::{DeviceError, PublishError};
+use crate::exporter::MeasurementStore;
+use crate::homewizard_client::{DeviceInfoResponse, HomewizardClient, HomewizardDevice};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use tracing::{debug, info, warn};
+
+pub struct SelfTestConfig {
+    pub enabled: bool,
+}
+
+impl SelfTestConfig {
+    pub fn new(enabled: bool) -> Result<Self, Box<dyn Error>> {
+        debug!("SelfTestConfig::new(enabled: {})", enabled);
+        Ok(Self { enabled })
+    }
+
+    /// The self-test runs unless `SKIP_SELF_TEST` is `true`.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let skip = env::var("SKIP_SELF_TEST").map_or(false, |skip| skip == "true");
+
+        Self::new(!skip)
+    }
+}
+
+/// Discovers devices and requests their `/api` endpoint, like [`HomewizardClient`] does.
+pub trait DeviceProbe {
+    fn discover_devices(&self) -> Result<Vec<HomewizardDevice>, Box<dyn Error + Send + Sync>>;
+    fn get_device_info(
+        &self,
+        device: &HomewizardDevice,
+    ) -> Result<DeviceInfoResponse, Box<dyn Error + Send + Sync>>;
+}
+
+impl DeviceProbe for HomewizardClient {
+    fn discover_devices(&self) -> Result<Vec<HomewizardDevice>, Box<dyn Error + Send + Sync>> {
+        HomewizardClient::discover_devices(self)
+    }
+
+    fn get_device_info(
+        &self,
+        device: &HomewizardDevice,
+    ) -> Result<DeviceInfoResponse, Box<dyn Error + Send + Sync>> {
+        HomewizardClient::get_device_info(self, device)
+    }
+}
+
+/// A connection to check without sending anything over it.
+pub trait ConnectionProbe {
+    fn check(&self) -> Result<(), Box<dyn Error>>;
+}
+
+/// Connects to the NATS server measurements and events get published to.
+pub struct NatsProbe {
+    host: String,
+}
+
+impl NatsProbe {
+    /// Reads `NATS_HOST`, like the NATS publishers.
+    pub fn from_env() -> Self {
+        Self {
+            host: env::var("NATS_HOST").unwrap_or_else(|_| "jarvis-nats".to_string()),
+        }
+    }
+}
+
+impl ConnectionProbe for NatsProbe {
+    fn check(&self) -> Result<(), Box<dyn Error>> {
+        let connection = nats::connect(&self.host)?;
+        connection.flush()?;
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Check {
+    Discovery,
+    DeviceApi,
+    Nats,
+    State,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Check::Discovery => "discovery",
+            Check::DeviceApi => "device api",
+            Check::Nats => "nats",
+            Check::State => "state",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What a check found, or why it failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckOutcome {
+    pub check: Check,
+    pub result: Result<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelfTestReport {
+    pub outcomes: Vec<CheckOutcome>,
+}
+
+/// Runs every check once, in order: discovery, the `/api` endpoint of the first device that
+/// answers, the NATS connection when there is one, and reading the last measurements from the
+/// store. A failing check doesn't stop the later ones, so the report tells everything at once.
+pub fn run(
+    devices: &dyn DeviceProbe,
+    nats: Option<&dyn ConnectionProbe>,
+    store: &dyn MeasurementStore,
+) -> SelfTestReport {
+    let mut outcomes = vec![];
+
+    let discovered = devices.discover_devices();
+    outcomes.push(CheckOutcome {
+        check: Check::Discovery,
+        result: match &discovered {
+            Ok(discovered) if discovered.is_empty() => Err("no devices discovered".into()),
+            Ok(discovered) => Ok(format!("{} devices discovered", discovered.len())),
+            Err(e) => Err(e.to_string()),
+        },
+    });
+
+    outcomes.push(CheckOutcome {
+        check: Check::DeviceApi,
+        result: check_device_api(devices, discovered.as_deref().unwrap_or_default()),
+    });
+
+    if let Some(nats) = nats {
+        outcomes.push(CheckOutcome {
+            check: Check::Nats,
+            result: nats
+                .check()
+                .map(|_| "connected".to_string())
+                .map_err(|e| e.to_string()),
+        });
+    }
+
+    outcomes.push(CheckOutcome {
+        check: Check::State,
+        result: match store.read() {
+            Ok(Some(measurements)) => Ok(format!("{} last measurements read", measurements.len())),
+            Ok(None) => Ok("no last measurements stored yet".into()),
+            Err(e) => Err(e.to_string()),
+        },
+    });
+
+    SelfTestReport { outcomes }
+}
+
+/// Requests `/api` of the discovered devices until one answers.
+fn check_device_api(
+    devices: &dyn DeviceProbe,
+    discovered: &[HomewizardDevice],
+) -> Result<String, String> {
+    let mut last_error = "no device to request".to_string();
+    for device in discovered {
+        match devices.get_device_info(device) {
+            Ok(info) => return Ok(format!("{} {} answered", info.product_type, info.serial)),
+            Err(e) => last_error = format!("{}: {}", device.fullname, e),
+        }
+    }
+
+    Err(format!(
+        "none of the {} discovered devices answered, last error {}",
+        discovered.len(),
+        last_error
+    ))
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.outcomes.iter().all(|outcome| outcome.result.is_ok())
+    }
+
+    /// Logs a line per check and a PASS or FAIL summary, then in strict mode fails with the exit
+    /// code of the first failing check: a device failure for discovery and the device api, a
+    /// publish failure for NATS and the state. Outside strict mode failures only get warned
+    /// about.
+    pub fn conclude(&self, strict: bool) -> Result<(), Box<dyn Error>> {
+        for outcome in &self.outcomes {
+            match &outcome.result {
+                Ok(found) => info!("Self-test {} PASS: {}", outcome.check, found),
+                Err(reason) => warn!("Self-test {} FAIL: {}", outcome.check, reason),
+            }
+        }
+
+        let failed: Vec<&CheckOutcome> = self
+            .outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_err())
+            .collect();
+        let first_failed = match failed.first() {
+            Some(first_failed) => first_failed,
+            None => {
+                info!("Self-test PASS: {} checks passed", self.outcomes.len());
+                return Ok(());
+            }
+        };
+
+        let summary = format!(
+            "Self-test FAIL: {} of {} checks failed, first {}: {}",
+            failed.len(),
+            self.outcomes.len(),
+            first_failed.check,
+            first_failed.result.as_ref().unwrap_err()
+        );
+        if !strict {
+            warn!("{}, continuing since strict mode is off", summary);
+            return Ok(());
+        }
+
+        Err(match first_failed.check {
+            Check::Discovery | Check::DeviceApi => DeviceError::new(summary.into()).into(),
+            Check::Nats | Check::State => PublishError::new(summary.into()).into(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error;
+    use crate::exporter::InMemoryStore;
+    use crate::test_support::capture_logs;
+    use std::collections::HashSet;
+
+    struct MockDevices {
+        discovered: usize,
+        answering: bool,
+    }
+
+    impl DeviceProbe for MockDevices {
+        fn discover_devices(&self) -> Result<Vec<HomewizardDevice>, Box<dyn Error + Send + Sync>> {
+            Ok((0..self.discovered)
+                .map(|n| HomewizardDevice {
+                    fullname: format!("energysocket-{}._hwenergy._tcp.local.", n),
+                    ip_addresses: HashSet::new(),
+                    port: 80,
+                    hostname: None,
+                    api_path: None,
+                })
+                .collect())
+        }
+
+        fn get_device_info(
+            &self,
+            _: &HomewizardDevice,
+        ) -> Result<DeviceInfoResponse, Box<dyn Error + Send + Sync>> {
+            if !self.answering {
+                return Err("connection timed out".into());
+            }
+            Ok(DeviceInfoResponse {
+                product_type: "HWE-SKT".into(),
+                product_name: "Energy Socket".into(),
+                serial: "3c39e72e33ce".into(),
+                firmware_version: "3.02".into(),
+                api_version: "v1".into(),
+            })
+        }
+    }
+
+    struct MockConnection {
+        fail: bool,
+    }
+
+    impl ConnectionProbe for MockConnection {
+        fn check(&self) -> Result<(), Box<dyn Error>> {
+            if self.fail {
+                return Err("nats: connection refused".into());
+            }
+            Ok(())
+        }
+    }
+
+    fn failed_checks(report: &SelfTestReport) -> Vec<Check> {
+        report
+            .outcomes
+            .iter()
+            .filter(|outcome| outcome.result.is_err())
+            .map(|outcome| outcome.check)
+            .collect()
+    }
+
+    #[test]
+    fn run_passes_with_answering_device_and_nats() {
+        let devices = MockDevices {
+            discovered: 2,
+            answering: true,
+        };
+
+        // act
+        let report = run(
+            &devices,
+            Some(&MockConnection { fail: false }),
+            &InMemoryStore::default(),
+        );
+
+        assert!(report.passed());
+        assert_eq!(
+            report.outcomes[0].result,
+            Ok("2 devices discovered".to_string())
+        );
+        assert_eq!(
+            report.outcomes[1].result,
+            Ok("HWE-SKT 3c39e72e33ce answered".to_string())
+        );
+        let logs = capture_logs("info", || report.conclude(true).unwrap());
+        assert!(logs.contains("Self-test PASS: 4 checks passed"));
+    }
+
+    #[test]
+    fn run_fails_device_api_when_no_device_answers() {
+        let devices = MockDevices {
+            discovered: 2,
+            answering: false,
+        };
+
+        // act
+        let report = run(
+            &devices,
+            Some(&MockConnection { fail: false }),
+            &InMemoryStore::default(),
+        );
+
+        assert_eq!(failed_checks(&report), vec![Check::DeviceApi]);
+        let error = report.conclude(true).unwrap_err();
+        assert_eq!(error::exit_code(error.as_ref()), error::EXIT_DEVICE);
+        assert!(error
+            .to_string()
+            .contains("none of the 2 discovered devices answered"));
+    }
+
+    #[test]
+    fn run_fails_discovery_and_device_api_without_devices() {
+        let devices = MockDevices {
+            discovered: 0,
+            answering: true,
+        };
+
+        // act
+        let report = run(&devices, None, &InMemoryStore::default());
+
+        assert_eq!(
+            failed_checks(&report),
+            vec![Check::Discovery, Check::DeviceApi]
+        );
+    }
+
+    #[test]
+    fn run_fails_nats_with_publish_exit_code_in_strict_mode_only() {
+        let devices = MockDevices {
+            discovered: 1,
+            answering: true,
+        };
+
+        // act
+        let report = run(
+            &devices,
+            Some(&MockConnection { fail: true }),
+            &InMemoryStore::default(),
+        );
+
+        assert_eq!(failed_checks(&report), vec![Check::Nats]);
+        let error = report.conclude(true).unwrap_err();
+        assert_eq!(error::exit_code(error.as_ref()), error::EXIT_PUBLISH);
+        let logs = capture_logs("warn", || report.conclude(false).unwrap());
+        assert!(logs.contains("Self-test nats FAIL: nats: connection refused"));
+        assert!(logs.contains("Self-test FAIL: 1 of 4 checks failed"));
+    }
+}
@@ -0,0 +1,193 @@
+use std::env;
+use std::error::Error;
+use std::io;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch};
+use tracing::{debug, info, warn};
+
+pub struct ShutdownConfig {
+    grace_period: Duration,
+}
+
+impl ShutdownConfig {
+    pub fn new(grace_period: Duration) -> Result<Self, Box<dyn Error>> {
+        debug!("ShutdownConfig::new(grace_period: {:?})", grace_period);
+        Ok(Self { grace_period })
+    }
+
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let grace_period_seconds: u64 = env::var("GRACE_PERIOD_SECONDS")
+            .unwrap_or_else(|_| "30".to_string())
+            .parse()?;
+
+        Self::new(Duration::from_secs(grace_period_seconds))
+    }
+}
+
+/// Tells long running loops that a graceful stop was requested.
+#[derive(Clone)]
+pub struct ShutdownSignal {
+    receiver: watch::Receiver<bool>,
+}
+
+impl ShutdownSignal {
+    pub fn is_requested(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Completes once a stop is requested; never completes if that can no longer happen.
+    pub async fn requested(&mut self) {
+        while !*self.receiver.borrow() {
+            if self.receiver.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+pub fn channel() -> (watch::Sender<bool>, ShutdownSignal) {
+    let (sender, receiver) = watch::channel(false);
+    (sender, ShutdownSignal { receiver })
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ForcedExit {
+    SecondSignal,
+    GracePeriodExpired,
+}
+
+impl ForcedExit {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            // the in-flight cycle gets abandoned, but the stop itself was requested
+            ForcedExit::GracePeriodExpired => 0,
+            ForcedExit::SecondSignal => 130,
+        }
+    }
+}
+
+/// Turns incoming signals into a graceful stop request, and returns when the process has to be
+/// stopped regardless: on a second signal or when the grace period after the first runs out.
+pub async fn coordinate(
+    mut signals: mpsc::Receiver<()>,
+    requested: watch::Sender<bool>,
+    grace_period: Duration,
+) -> ForcedExit {
+    if signals.recv().await.is_none() {
+        std::future::pending::<()>().await;
+    }
+
+    info!(
+        "Received shutdown signal, stopping once the in-flight cycle finishes (at most {:?})...",
+        grace_period
+    );
+    let _ = requested.send(true);
+
+    tokio::select! {
+        Some(()) = signals.recv() => ForcedExit::SecondSignal,
+        _ = tokio::time::sleep(grace_period) => ForcedExit::GracePeriodExpired,
+    }
+}
+
+/// Installs SIGTERM and SIGINT handlers; a second signal or an expired grace period exits the
+/// process directly.
+pub fn install(config: &ShutdownConfig) -> io::Result<ShutdownSignal> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    let (signals_sender, signals_receiver) = mpsc::channel(2);
+    let (requested_sender, shutdown_signal) = channel();
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = terminate.recv() => {},
+                _ = interrupt.recv() => {},
+            }
+            if signals_sender.send(()).await.is_err() {
+                return;
+            }
+        }
+    });
+
+    let grace_period = config.grace_period;
+    tokio::spawn(async move {
+        let forced_exit = coordinate(signals_receiver, requested_sender, grace_period).await;
+
+        warn!("Exiting immediately: {:?}", forced_exit);
+        crate::telemetry::shutdown();
+        std::process::exit(forced_exit.exit_code());
+    });
+
+    Ok(shutdown_signal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn first_signal_requests_graceful_stop_and_second_forces_exit() {
+        let (signals_sender, signals_receiver) = mpsc::channel(2);
+        let (requested_sender, mut shutdown_signal) = channel();
+        let coordinator = tokio::spawn(coordinate(
+            signals_receiver,
+            requested_sender,
+            Duration::from_secs(60),
+        ));
+
+        assert!(!shutdown_signal.is_requested());
+
+        // act
+        signals_sender.send(()).await.unwrap();
+        shutdown_signal.requested().await;
+
+        assert!(shutdown_signal.is_requested());
+        assert!(!coordinator.is_finished());
+
+        // act
+        signals_sender.send(()).await.unwrap();
+
+        assert_eq!(coordinator.await.unwrap(), ForcedExit::SecondSignal);
+    }
+
+    #[tokio::test]
+    async fn grace_period_expiry_forces_exit() {
+        let (signals_sender, signals_receiver) = mpsc::channel(2);
+        let (requested_sender, shutdown_signal) = channel();
+
+        // act
+        signals_sender.send(()).await.unwrap();
+        let forced_exit = coordinate(
+            signals_receiver,
+            requested_sender,
+            Duration::from_millis(10),
+        )
+        .await;
+
+        assert_eq!(forced_exit, ForcedExit::GracePeriodExpired);
+        assert_eq!(forced_exit.exit_code(), 0);
+        assert!(shutdown_signal.is_requested());
+    }
+
+    #[tokio::test]
+    async fn closed_signal_source_never_forces_exit() {
+        let (signals_sender, signals_receiver) = mpsc::channel(2);
+        let (requested_sender, _shutdown_signal) = channel();
+        drop(signals_sender);
+
+        // act
+        let result = tokio::time::timeout(
+            Duration::from_millis(50),
+            coordinate(
+                signals_receiver,
+                requested_sender,
+                Duration::from_millis(10),
+            ),
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,69 @@
+use jarvis_lib::model::{Measurement, MetricType, Sample};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Keeps the smoothed value of every gauge series between cycles, for exponential moving
+/// averages across cycles. The values only live as long as the process; after a restart series
+/// are seeded from the last published measurement, or else from their first reading.
+#[derive(Default)]
+pub struct Smoother {
+    values: Mutex<HashMap<String, f64>>,
+}
+
+impl Smoother {
+    /// Replaces every gauge among the samples of a device with `alpha * new + (1 - alpha) *
+    /// previous`. With `keep_raw` the unsmoothed gauges are appended as well, named after the
+    /// gauge followed by `raw`. Counters are left as they are.
+    pub fn smooth(
+        &self,
+        alpha: f64,
+        keep_raw: bool,
+        samples: &mut Vec<Sample>,
+        previous: Option<&Measurement>,
+    ) {
+        let mut values = self.values.lock().unwrap();
+        let mut raw_samples = vec![];
+
+        for sample in samples
+            .iter_mut()
+            .filter(|sample| matches!(sample.metric_type, MetricType::Gauge))
+        {
+            if keep_raw {
+                raw_samples.push(Sample {
+                    sample_name: format!("{} raw", sample.sample_name),
+                    ..sample.clone()
+                });
+            }
+            // a missing reading shouldn't poison the average of later cycles
+            if !sample.value.is_finite() {
+                continue;
+            }
+
+            let key = series_key(sample);
+            let previous_value = values.get(&key).copied().or_else(|| {
+                previous?
+                    .samples
+                    .iter()
+                    .find(|previous| {
+                        matches!(previous.metric_type, MetricType::Gauge)
+                            && series_key(previous) == key
+                    })
+                    .map(|previous| previous.value)
+                    .filter(|value| value.is_finite())
+            });
+            if let Some(previous_value) = previous_value {
+                sample.value = alpha * sample.value + (1.0 - alpha) * previous_value;
+            }
+            values.insert(key, sample.value);
+        }
+
+        samples.extend(raw_samples);
+    }
+}
+
+fn series_key(sample: &Sample) -> String {
+    format!(
+        "{:?}/{}/{:?}/{}",
+        sample.entity_type, sample.entity_name, sample.sample_type, sample.sample_name
+    )
+}
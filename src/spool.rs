@@ -0,0 +1,346 @@
+use crate::exporter::MeasurementPublisher;
+use chrono::{DateTime, Duration, NaiveDateTime, TimeZone, Utc};
+use jarvis_lib::model::Measurement;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, warn};
+
+const FILE_EXTENSION: &str = ".json";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.9fZ";
+
+/// Where measurements that failed to publish wait for the next attempt, and how many and how old
+/// they may get before the oldest are dropped.
+pub struct SpoolConfig {
+    pub directory: PathBuf,
+    pub max_entries: usize,
+    pub max_age: Option<Duration>,
+}
+
+impl SpoolConfig {
+    pub fn new(
+        directory: PathBuf,
+        max_entries: usize,
+        max_age: Option<Duration>,
+    ) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "SpoolConfig::new(directory: {}, max_entries: {}, max_age: {:?})",
+            directory.display(),
+            max_entries,
+            max_age
+        );
+        if max_entries == 0 {
+            return Err("SPOOL_MAX_ENTRIES has to be at least 1".into());
+        }
+
+        Ok(Self {
+            directory,
+            max_entries,
+            max_age,
+        })
+    }
+
+    /// Reads `SPOOL_DIRECTORY`, `SPOOL_MAX_ENTRIES` and `SPOOL_MAX_AGE_HOURS`; returns no config
+    /// when `SPOOL_DIRECTORY` isn't set, since spooling is optional.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let directory = match env::var("SPOOL_DIRECTORY") {
+            Ok(directory) => PathBuf::from(directory),
+            Err(_) => return Ok(None),
+        };
+        let max_entries: usize = env::var("SPOOL_MAX_ENTRIES")
+            .unwrap_or_else(|_| "1000".to_string())
+            .parse()?;
+        let max_age = env::var("SPOOL_MAX_AGE_HOURS")
+            .ok()
+            .map(|hours| hours.parse::<i64>().map(Duration::hours))
+            .transpose()?;
+
+        Ok(Some(Self::new(directory, max_entries, max_age)?))
+    }
+}
+
+/// Keeps measurements that failed to publish as json files, one per measurement named after its
+/// time of measuring and id, so they can be published in order once publishing works again.
+pub struct MeasurementSpool {
+    config: SpoolConfig,
+}
+
+impl MeasurementSpool {
+    pub fn new(config: SpoolConfig) -> Self {
+        Self { config }
+    }
+
+    /// Writes the measurements to the spool, dropping the oldest entries beyond the limits.
+    pub fn spool(
+        &self,
+        measurements: &[Measurement],
+        now: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.config.directory)?;
+        for measurement in measurements {
+            let path = self.config.directory.join(format!(
+                "{}-{}{}",
+                measurement.measured_at_time.format(TIMESTAMP_FORMAT),
+                measurement.id,
+                FILE_EXTENSION
+            ));
+            fs::write(&path, serde_json::to_vec(measurement)?)?;
+            info!(
+                measurement_id = %measurement.id,
+                "Spooled measurement {} to {}",
+                measurement.id,
+                path.display()
+            );
+        }
+
+        self.prune(now)
+    }
+
+    /// Publishes the spooled measurements oldest first, removing each once it's published.
+    /// Stops at the first one failing, leaving it and the ones after it for the next attempt.
+    /// Returns the number of measurements published.
+    pub async fn replay(
+        &self,
+        publisher: &dyn MeasurementPublisher,
+        now: DateTime<Utc>,
+    ) -> Result<usize, Box<dyn Error>> {
+        if !self.config.directory.exists() {
+            return Ok(0);
+        }
+        self.prune(now)?;
+
+        let mut replayed = 0;
+        for path in self.entries()? {
+            let measurement: Measurement = match serde_json::from_slice(&fs::read(&path)?) {
+                Ok(measurement) => measurement,
+                Err(e) => {
+                    // a file that can't be read now never can, and would hold up the others
+                    warn!(
+                        "Dropping unreadable spooled measurement {}: {}",
+                        path.display(),
+                        e
+                    );
+                    fs::remove_file(&path)?;
+                    continue;
+                }
+            };
+            publisher.publish(&measurement).await?;
+            fs::remove_file(&path)?;
+            info!(
+                measurement_id = %measurement.id,
+                "Published spooled measurement {} of {}",
+                measurement.id,
+                measurement.measured_at_time
+            );
+            replayed += 1;
+        }
+
+        Ok(replayed)
+    }
+
+    /// The spooled measurement files, oldest first.
+    fn entries(&self) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.config.directory)? {
+            let path = entry?.path();
+            if measured_at(&path).is_some() {
+                entries.push(path);
+            }
+        }
+        entries.sort();
+
+        Ok(entries)
+    }
+
+    /// Drops spooled measurements older than the max age, and the oldest ones beyond the max
+    /// number of entries.
+    fn prune(&self, now: DateTime<Utc>) -> Result<(), Box<dyn Error>> {
+        let entries = self.entries()?;
+        let excess = entries.len().saturating_sub(self.config.max_entries);
+        for (index, path) in entries.iter().enumerate() {
+            let too_old = match (self.config.max_age, measured_at(path)) {
+                (Some(max_age), Some(measured_at)) => now - measured_at > max_age,
+                _ => false,
+            };
+            if index < excess || too_old {
+                warn!(
+                    "Dropping spooled measurement {}, the spool holds at most {} measurements of at most {:?}",
+                    path.display(),
+                    self.config.max_entries,
+                    self.config.max_age
+                );
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The time of measuring a spool file is named after, if it's one.
+fn measured_at(path: &Path) -> Option<DateTime<Utc>> {
+    let name = path.file_name()?.to_str()?.strip_suffix(FILE_EXTENSION)?;
+    let (timestamp, _) = name.split_once('-')?;
+
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .ok()
+        .map(|measured_at| Utc.from_utc_datetime(&measured_at))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::cell::RefCell;
+    use uuid::Uuid;
+
+    #[derive(Default)]
+    struct RecordingPublisher {
+        published: RefCell<Vec<String>>,
+        fail_after: Option<usize>,
+    }
+
+    #[async_trait(?Send)]
+    impl MeasurementPublisher for RecordingPublisher {
+        async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+            if Some(self.published.borrow().len()) == self.fail_after {
+                return Err("nats: connection refused".into());
+            }
+            self.published.borrow_mut().push(measurement.id.clone());
+            Ok(())
+        }
+    }
+
+    fn spool(max_entries: usize, max_age: Option<Duration>) -> (MeasurementSpool, PathBuf) {
+        let directory = env::temp_dir().join(format!("spool-{}", Uuid::new_v4()));
+        let spool = MeasurementSpool::new(
+            SpoolConfig::new(directory.clone(), max_entries, max_age).unwrap(),
+        );
+
+        (spool, directory)
+    }
+
+    fn measurement(id: &str, minute: u32) -> Measurement {
+        Measurement {
+            id: id.into(),
+            source: "jarvis-homewizard-exporter".into(),
+            location: "My Home".into(),
+            samples: vec![],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, minute, 0).unwrap(),
+        }
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2023, 6, 1, 13, 0, 0).unwrap()
+    }
+
+    #[tokio::test]
+    async fn replay_publishes_in_order_of_measuring_and_empties_spool() {
+        let (spool, directory) = spool(10, None);
+        spool
+            .spool(&[measurement("second", 5), measurement("third", 10)], now())
+            .unwrap();
+        spool.spool(&[measurement("first", 0)], now()).unwrap();
+        let publisher = RecordingPublisher::default();
+
+        // act
+        let replayed = spool.replay(&publisher, now()).await.unwrap();
+
+        assert_eq!(replayed, 3);
+        assert_eq!(
+            *publisher.published.borrow(),
+            vec!["first", "second", "third"]
+        );
+        assert!(spool.entries().unwrap().is_empty());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_measurements_from_first_failure_on() {
+        let (spool, directory) = spool(10, None);
+        spool
+            .spool(
+                &[
+                    measurement("first", 0),
+                    measurement("second", 5),
+                    measurement("third", 10),
+                ],
+                now(),
+            )
+            .unwrap();
+        let publisher = RecordingPublisher {
+            fail_after: Some(1),
+            ..Default::default()
+        };
+
+        // act
+        let result = spool.replay(&publisher, now()).await;
+
+        assert!(result.is_err());
+        assert_eq!(*publisher.published.borrow(), vec!["first"]);
+        assert_eq!(spool.entries().unwrap().len(), 2);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_keeps_original_id_and_time_of_measuring() {
+        let (spool, directory) = spool(10, None);
+        let spooled = measurement("0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10", 0);
+        spool.spool(&[spooled.clone()], now()).unwrap();
+        let path = spool.entries().unwrap().remove(0);
+
+        // act
+        let read: Measurement = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+
+        assert_eq!(read.id, spooled.id);
+        assert_eq!(read.measured_at_time, spooled.measured_at_time);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn spool_drops_oldest_beyond_max_entries() {
+        let (spool, directory) = spool(2, None);
+
+        // act
+        spool
+            .spool(
+                &[
+                    measurement("first", 0),
+                    measurement("second", 5),
+                    measurement("third", 10),
+                ],
+                now(),
+            )
+            .unwrap();
+
+        let entries: Vec<String> = spool
+            .entries()
+            .unwrap()
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                "20230601T120500.000000000Z-second.json",
+                "20230601T121000.000000000Z-third.json"
+            ]
+        );
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn spool_drops_measurements_older_than_max_age() {
+        let (spool, directory) = spool(10, Some(Duration::minutes(55)));
+
+        // act
+        spool
+            .spool(&[measurement("first", 0), measurement("second", 10)], now())
+            .unwrap();
+
+        assert_eq!(spool.entries().unwrap().len(), 1);
+        assert!(spool.entries().unwrap()[0].ends_with("20230601T121000.000000000Z-second.json"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use crate::error::ConfigError;
+use crate::shutdown::ShutdownSignal;
+use std::env;
+use std::error::Error;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::{debug, error, info};
+
+pub struct SupervisorConfig {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl SupervisorConfig {
+    pub fn new(initial_backoff: Duration, max_backoff: Duration) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "SupervisorConfig::new(initial_backoff: {:?}, max_backoff: {:?})",
+            initial_backoff, max_backoff
+        );
+        Ok(Self {
+            initial_backoff,
+            max_backoff,
+        })
+    }
+
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let initial_backoff_seconds: u64 = env::var("RESTART_BACKOFF_SECONDS")
+            .unwrap_or_else(|_| "1".to_string())
+            .parse()?;
+        let max_backoff_seconds: u64 = env::var("RESTART_MAX_BACKOFF_SECONDS")
+            .unwrap_or_else(|_| "300".to_string())
+            .parse()?;
+
+        Self::new(
+            Duration::from_secs(initial_backoff_seconds),
+            Duration::from_secs(max_backoff_seconds),
+        )
+    }
+
+    fn next_backoff(&self, backoff: Duration) -> Duration {
+        (backoff * 2).min(self.max_backoff)
+    }
+}
+
+/// Keeps `run` going in the same process, so in-memory state survives: after an error it waits
+/// with an exponentially growing backoff and starts it again, except for errors caused by the
+/// config, which are returned right away since retrying can't fix them. A run that lasted at
+/// least the max backoff counts as healthy, so the backoff starts over after it.
+pub async fn supervise<F, Fut>(
+    config: &SupervisorConfig,
+    mut shutdown: ShutdownSignal,
+    mut run: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<(), Box<dyn Error>>>,
+{
+    let mut backoff = config.initial_backoff.min(config.max_backoff);
+
+    loop {
+        let started = Instant::now();
+        let e = match run().await {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if started.elapsed() >= config.max_backoff {
+            backoff = config.initial_backoff.min(config.max_backoff);
+        }
+
+        if ConfigError::is(e.as_ref()) {
+            error!("Stopping for unrecoverable error: {}", e);
+            return Err(e);
+        }
+
+        if shutdown.is_requested() {
+            return Err(e);
+        }
+
+        error!("Exporter failed, restarting in {:?}: {}", backoff, e);
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {},
+            _ = shutdown.requested() => {
+                info!("Stopping after shutdown request");
+                return Ok(());
+            },
+        }
+
+        backoff = config.next_backoff(backoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    fn config() -> SupervisorConfig {
+        SupervisorConfig::new(Duration::from_millis(1), Duration::from_millis(4)).unwrap()
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_max() {
+        let config = config();
+
+        let backoffs: Vec<Duration> = (0..4)
+            .scan(Duration::from_millis(1), |backoff, _| {
+                *backoff = config.next_backoff(*backoff);
+                Some(*backoff)
+            })
+            .collect();
+
+        assert_eq!(
+            backoffs,
+            vec![
+                Duration::from_millis(2),
+                Duration::from_millis(4),
+                Duration::from_millis(4),
+                Duration::from_millis(4)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn restarts_after_transient_failures() {
+        let (_requested_sender, shutdown_signal) = crate::shutdown::channel();
+        let attempts = Cell::new(0);
+
+        // act
+        let result = supervise(&config(), shutdown_signal, || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt < 3 {
+                    Err(Box::<dyn Error>::from("nats: connection refused"))
+                } else {
+                    Ok(())
+                }
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn resets_backoff_after_healthy_run() {
+        let (_requested_sender, shutdown_signal) = crate::shutdown::channel();
+        let attempts = Cell::new(0);
+        let started = Instant::now();
+
+        // act
+        let result = supervise(&config(), shutdown_signal, || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                match attempt {
+                    // fails right away twice, growing the backoff to 4ms
+                    1 | 2 => Err(Box::<dyn Error>::from("nats: connection refused")),
+                    // runs healthy for a while before failing
+                    3 => {
+                        tokio::time::sleep(Duration::from_millis(10)).await;
+                        Err(Box::<dyn Error>::from("nats: connection refused"))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(attempts.get(), 4);
+        // 1ms + 2ms of backoff, the healthy run and 1ms instead of 4ms of backoff after it
+        assert_eq!(started.elapsed(), Duration::from_millis(14));
+    }
+
+    #[tokio::test]
+    async fn exits_on_config_errors() {
+        let (_requested_sender, shutdown_signal) = crate::shutdown::channel();
+        let attempts = Cell::new(0);
+
+        // act
+        let result = supervise(&config(), shutdown_signal, || {
+            attempts.set(attempts.get() + 1);
+            async {
+                Err(Box::<dyn Error>::from(ConfigError::new(
+                    "missing location".into(),
+                )))
+            }
+        })
+        .await;
+
+        assert!(ConfigError::is(result.unwrap_err().as_ref()));
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn stops_restarting_when_shutdown_is_requested() {
+        let (requested_sender, shutdown_signal) = crate::shutdown::channel();
+        requested_sender.send(true).unwrap();
+        let attempts = Cell::new(0);
+
+        // act
+        let result = supervise(&config(), shutdown_signal, || {
+            attempts.set(attempts.get() + 1);
+            async { Err(Box::<dyn Error>::from("nats: connection refused")) }
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+}
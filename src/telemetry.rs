@@ -0,0 +1,40 @@
+use opentelemetry::sdk::trace::Tracer;
+use std::env;
+use std::error::Error;
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Builds a layer exporting spans over OTLP, configured through the standard `OTEL_*` env vars.
+/// Returns `None` when no OTLP endpoint is set, so tracing stays local by default.
+pub fn otlp_layer<S>() -> Result<Option<OpenTelemetryLayer<S, Tracer>>, Box<dyn Error>>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if env::var("OTEL_EXPORTER_OTLP_ENDPOINT").is_err()
+        && env::var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").is_err()
+    {
+        return Ok(None);
+    }
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .install_batch(opentelemetry::runtime::Tokio)?;
+
+    Ok(Some(tracing_layer(tracer)))
+}
+
+/// Builds the layer turning tracing spans into OpenTelemetry spans of `tracer`, keeping their
+/// parentage and recording their fields as attributes.
+pub fn tracing_layer<S>(tracer: Tracer) -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
+
+/// Flushes any spans still queued for export.
+pub fn shutdown() {
+    opentelemetry::global::shutdown_tracer_provider();
+}
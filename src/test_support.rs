@@ -0,0 +1,532 @@
+#[cfg(feature = "mdns")]
+use mdns_sd::{ServiceDaemon, ServiceInfo};
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use opentelemetry::sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+use opentelemetry::sdk::trace::TracerProvider;
+use opentelemetry::trace::TracerProvider as _;
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::future::{self, Future};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::EnvFilter;
+
+/// In-memory log sink for asserting on the output of the json formatter used in main.rs.
+#[derive(Clone, Default)]
+pub struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    pub fn contents(&self) -> String {
+        String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+    }
+}
+
+impl io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CapturedLogs {
+    type Writer = CapturedLogs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Runs `f` with a subscriber using the given env filter directives and returns what it logged.
+pub fn capture_logs<F: FnOnce()>(filter: &str, f: F) -> String {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .json()
+        .with_env_filter(EnvFilter::new(filter))
+        .with_writer(logs.clone())
+        .finish();
+
+    tracing::subscriber::with_default(subscriber, f);
+
+    logs.contents()
+}
+
+/// A span as exported over OTLP, with the name of its parent looked up by its parent span id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedSpan {
+    pub name: String,
+    pub parent: Option<String>,
+    pub attributes: HashMap<String, String>,
+}
+
+/// Span exporter keeping the exported spans in memory for the test to read.
+#[derive(Debug, Clone, Default)]
+struct InMemorySpanExporter(Arc<Mutex<Vec<SpanData>>>);
+
+impl SpanExporter for InMemorySpanExporter {
+    fn export(
+        &mut self,
+        batch: Vec<SpanData>,
+    ) -> Pin<Box<dyn Future<Output = ExportResult> + Send + 'static>> {
+        self.0.lock().unwrap().extend(batch);
+        Box::pin(future::ready(Ok(())))
+    }
+}
+
+/// Runs `f` with the OpenTelemetry layer of [`crate::telemetry::tracing_layer`] exporting to
+/// memory, and returns the spans it exported in the order they started.
+pub fn record_spans<F: FnOnce()>(f: F) -> Vec<RecordedSpan> {
+    let exporter = InMemorySpanExporter::default();
+    let provider = TracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry().with(crate::telemetry::tracing_layer(
+        provider.tracer("jarvis-homewizard-exporter"),
+    ));
+
+    tracing::subscriber::with_default(subscriber, f);
+    // shutting down the provider waits for the spans ended so far to get exported
+    drop(provider);
+
+    let mut spans = exporter.0.lock().unwrap().clone();
+    spans.sort_by_key(|span| span.start_time);
+    let name_of = |span_id| {
+        spans
+            .iter()
+            .find(|span| span.span_context.span_id() == span_id)
+            .map(|span| span.name.to_string())
+    };
+    spans
+        .iter()
+        .map(|span| RecordedSpan {
+            name: span.name.to_string(),
+            parent: name_of(span.parent_span_id),
+            attributes: span
+                .attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        })
+        .collect()
+}
+
+/// Compares `actual` with the checked-in golden file at `path`. Run the tests with
+/// `UPDATE_GOLDEN=true` to (re)write the golden files instead, then review the diff.
+pub fn assert_golden(path: &str, actual: &str) {
+    if env::var("UPDATE_GOLDEN").map_or(false, |update| update == "true") {
+        fs::write(path, format!("{}\n", actual)).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("Failed reading golden file {}: {}", path, e));
+    assert_eq!(
+        actual,
+        expected.trim_end(),
+        "output differs from golden file {}, run with UPDATE_GOLDEN=true to update it",
+        path
+    );
+}
+
+type Published = Arc<Mutex<Vec<(String, String)>>>;
+
+/// Minimal in-process NATS server, speaking just enough of the protocol for a client to connect,
+/// publish and flush. It counts the connections made and records what got published.
+pub struct FakeNatsServer {
+    address: SocketAddr,
+    connections: Arc<AtomicUsize>,
+    published: Published,
+}
+
+impl FakeNatsServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let published: Published = Arc::new(Mutex::new(vec![]));
+
+        let server_connections = connections.clone();
+        let server_published = published.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                server_connections.fetch_add(1, Ordering::SeqCst);
+                let published = server_published.clone();
+                thread::spawn(move || handle_nats_connection(stream, address, published));
+            }
+        });
+
+        Self {
+            address,
+            connections,
+            published,
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// The subject and payload of every message published so far.
+    pub fn published(&self) -> Vec<(String, String)> {
+        self.published.lock().unwrap().clone()
+    }
+}
+
+fn handle_nats_connection(mut stream: TcpStream, address: SocketAddr, published: Published) {
+    let info = format!(
+        "INFO {{\"server_id\":\"fake\",\"version\":\"2.9.0\",\"go\":\"go1.20\",\"host\":\"{}\",\"port\":{},\"max_payload\":1048576,\"proto\":1}}\r\n",
+        address.ip(),
+        address.port()
+    );
+    if stream.write_all(info.as_bytes()).is_err() {
+        return;
+    }
+    let mut reader = match stream.try_clone() {
+        Ok(stream) => BufReader::new(stream),
+        Err(_) => return,
+    };
+
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("PING") => {
+                if stream.write_all(b"PONG\r\n").is_err() {
+                    return;
+                }
+            }
+            Some("PUB") => {
+                let parts: Vec<&str> = parts.collect();
+                let (subject, length) = match (parts.first(), parts.last()) {
+                    (Some(subject), Some(length)) => {
+                        (subject.to_string(), length.parse().unwrap_or(0))
+                    }
+                    _ => return,
+                };
+                // the payload is followed by a line break of its own
+                let mut payload = vec![0; length + 2];
+                if reader.read_exact(&mut payload).is_err() {
+                    return;
+                }
+                payload.truncate(length);
+                published
+                    .lock()
+                    .unwrap()
+                    .push((subject, String::from_utf8_lossy(&payload).to_string()));
+            }
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeResponse {
+    pub status: u16,
+    pub body: String,
+    pub delay: Duration,
+}
+
+impl FakeResponse {
+    pub fn json(body: &str) -> Self {
+        Self {
+            status: 200,
+            body: body.to_string(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn status(status: u16) -> Self {
+        Self {
+            status,
+            body: String::new(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    pub fn delayed(self, delay: Duration) -> Self {
+        Self { delay, ..self }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FakeRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub raw_body: Vec<u8>,
+}
+
+impl FakeRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+type Routes = Arc<Mutex<HashMap<String, VecDeque<FakeResponse>>>>;
+type Tokens = Arc<Mutex<HashMap<String, String>>>;
+
+/// Minimal in-process http server standing in for HomeWizard devices. Responses queued for a
+/// path are served in order, with the last one repeating; unknown paths return 404.
+pub struct FakeDeviceServer {
+    address: SocketAddr,
+    routes: Routes,
+    tokens: Tokens,
+    requests: Arc<Mutex<Vec<FakeRequest>>>,
+}
+
+impl FakeDeviceServer {
+    pub fn start() -> Self {
+        Self::start_with(None)
+    }
+
+    /// Serves https with the certificate chain and private key in the pem files of
+    /// `tests/fixtures/tls`, like a v2 device does.
+    pub fn start_tls(certificate_chain: &str, private_key: &str) -> Self {
+        let fixture = |name: &str| format!("tests/fixtures/tls/{}", name);
+        let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        acceptor
+            .set_certificate_chain_file(fixture(certificate_chain))
+            .unwrap();
+        acceptor
+            .set_private_key_file(fixture(private_key), SslFiletype::PEM)
+            .unwrap();
+
+        Self::start_with(Some(Arc::new(acceptor.build())))
+    }
+
+    fn start_with(acceptor: Option<Arc<SslAcceptor>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let routes: Routes = Arc::new(Mutex::new(HashMap::new()));
+        let tokens: Tokens = Arc::new(Mutex::new(HashMap::new()));
+        let requests = Arc::new(Mutex::new(vec![]));
+
+        let server_routes = routes.clone();
+        let server_tokens = tokens.clone();
+        let server_requests = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let routes = server_routes.clone();
+                let tokens = server_tokens.clone();
+                let requests = server_requests.clone();
+                let acceptor = acceptor.clone();
+                thread::spawn(move || match acceptor {
+                    // a client rejecting the certificate fails the handshake, before any request
+                    Some(acceptor) => {
+                        if let Ok(stream) = acceptor.accept(stream) {
+                            handle_connection(stream, routes, tokens, requests);
+                        }
+                    }
+                    None => handle_connection(stream, routes, tokens, requests),
+                });
+            }
+        });
+
+        Self {
+            address,
+            routes,
+            tokens,
+            requests,
+        }
+    }
+
+    pub fn respond(&self, path: &str, response: FakeResponse) -> &Self {
+        self.routes
+            .lock()
+            .unwrap()
+            .entry(path.to_string())
+            .or_default()
+            .push_back(response);
+        self
+    }
+
+    /// Answers requests for `path` lacking `token` as bearer token with a 401, like a v2 device.
+    pub fn require_token(&self, path: &str, token: &str) -> &Self {
+        self.tokens
+            .lock()
+            .unwrap()
+            .insert(path.to_string(), token.to_string());
+        self
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.address)
+    }
+
+    pub fn requests(&self) -> Vec<FakeRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn request_count(&self, path: &str) -> usize {
+        self.requests()
+            .iter()
+            .filter(|request| request.path == path)
+            .count()
+    }
+}
+
+fn handle_connection<S: Read + Write>(
+    mut stream: S,
+    routes: Routes,
+    tokens: Tokens,
+    requests: Arc<Mutex<Vec<FakeRequest>>>,
+) {
+    let request = match read_request(&mut stream) {
+        Some(request) => request,
+        None => return,
+    };
+
+    let required_token = tokens.lock().unwrap().get(&request.path).cloned();
+    let authorized = required_token.map_or(true, |token| {
+        request.header("authorization") == Some(format!("Bearer {}", token).as_str())
+    });
+    let response = if !authorized {
+        Some(FakeResponse::status(401))
+    } else {
+        let mut routes = routes.lock().unwrap();
+        match routes.get_mut(&request.path) {
+            Some(queue) if queue.len() > 1 => queue.pop_front(),
+            Some(queue) => queue.front().cloned(),
+            None => None,
+        }
+    }
+    .unwrap_or_else(|| FakeResponse::status(404));
+
+    requests.lock().unwrap().push(request);
+
+    thread::sleep(response.delay);
+
+    let _ = write!(
+        stream,
+        "HTTP/1.1 {} Fake\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    );
+    let _ = stream.flush();
+}
+
+fn read_request<S: Read>(stream: &mut S) -> Option<FakeRequest> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).ok()?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+
+    let content_length: usize = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+
+    Some(FakeRequest {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).to_string(),
+        raw_body: body,
+    })
+}
+
+/// Announces fake HomeWizard `_hwenergy._tcp.local.` services from a separate mdns daemon in the
+/// test process, so discovery can be tested without devices. Needs multicast loopback, which
+/// many CI hosts lack; tests using it are ignored by default and run with `cargo test -- --ignored`.
+#[cfg(feature = "mdns")]
+pub struct FakeMdnsResponder {
+    daemon: ServiceDaemon,
+}
+
+#[cfg(feature = "mdns")]
+impl FakeMdnsResponder {
+    pub fn start() -> Self {
+        Self {
+            daemon: ServiceDaemon::new().unwrap(),
+        }
+    }
+
+    /// Announces a device with the txt records a real device of the product type has, returning
+    /// the fullname it gets discovered under.
+    pub fn announce(
+        &self,
+        instance_name: &str,
+        product_type: &str,
+        serial: &str,
+        port: u16,
+    ) -> String {
+        let properties: HashMap<String, String> = vec![
+            ("api_enabled", "1"),
+            ("path", "/api/v1"),
+            ("product_name", product_type),
+            ("product_type", product_type),
+            ("serial", serial),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .collect();
+
+        let service = ServiceInfo::new(
+            "_hwenergy._tcp.local.",
+            instance_name,
+            &format!("{}.local.", instance_name),
+            "127.0.0.1",
+            port,
+            Some(properties),
+        )
+        .unwrap();
+        let fullname = service.get_fullname().to_string();
+        self.daemon.register(service).unwrap();
+
+        fullname
+    }
+}
+
+#[cfg(feature = "mdns")]
+impl Drop for FakeMdnsResponder {
+    fn drop(&mut self) {
+        let _ = self.daemon.shutdown();
+    }
+}
@@ -0,0 +1,224 @@
+use crate::exporter::MeasurementPublisher;
+use crate::metrics::{escape_label_value, SampleMetrics};
+use crate::shutdown::ShutdownSignal;
+use async_trait::async_trait;
+use jarvis_lib::model::Measurement;
+use std::env;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use tracing::{debug, info, warn};
+
+const FILE_NAME: &str = "homewizard.prom";
+
+pub struct TextfileConfig {
+    directory: PathBuf,
+}
+
+impl TextfileConfig {
+    pub fn new(directory: PathBuf) -> Result<Self, Box<dyn Error>> {
+        debug!("TextfileConfig::new(directory: {})", directory.display());
+        Ok(Self { directory })
+    }
+
+    /// Returns no config when `TEXTFILE_DIRECTORY` isn't set, since writing a textfile is
+    /// optional.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        match env::var("TEXTFILE_DIRECTORY") {
+            Ok(directory) => Ok(Some(Self::new(PathBuf::from(directory))?)),
+            Err(_) => Ok(None),
+        }
+    }
+}
+
+/// Writes the samples of the last cycle as a `.prom` file for the node_exporter textfile
+/// collector. The file gets replaced by a rename, so the collector never reads a half written
+/// file, and is removed when the exporter stops after a shutdown request.
+pub struct TextfilePublisher {
+    path: PathBuf,
+    metrics: SampleMetrics,
+    shutdown: ShutdownSignal,
+}
+
+impl TextfilePublisher {
+    pub fn new(config: &TextfileConfig, shutdown: ShutdownSignal) -> Self {
+        Self {
+            path: config.directory.join(FILE_NAME),
+            metrics: SampleMetrics::default(),
+            shutdown,
+        }
+    }
+
+    fn write(&self, contents: &str) -> Result<(), Box<dyn Error>> {
+        // node_exporter only reads *.prom files, and a rename within the directory is atomic
+        let temp_path = self
+            .path
+            .with_file_name(format!(".{}.{}.tmp", FILE_NAME, process::id()));
+
+        if let Err(e) = fs::write(&temp_path, contents) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e.into());
+        }
+        fs::rename(&temp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl MeasurementPublisher for TextfilePublisher {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        self.metrics.publish(measurement).await?;
+
+        let mut contents = self.metrics.render();
+        let _ = writeln!(
+            contents,
+            "# HELP homewizard_last_cycle_timestamp_seconds Time of the last measurement written to this file.\n\
+             # TYPE homewizard_last_cycle_timestamp_seconds gauge\n\
+             homewizard_last_cycle_timestamp_seconds{{location=\"{}\"}} {}",
+            escape_label_value(&measurement.location),
+            measurement.measured_at_time.timestamp()
+        );
+
+        self.write(&contents)
+    }
+}
+
+impl Drop for TextfilePublisher {
+    fn drop(&mut self) {
+        // one-off runs leave the file for the next run to replace
+        if !self.shutdown.is_requested() || !self.path.exists() {
+            return;
+        }
+
+        match fs::remove_file(&self.path) {
+            Ok(()) => info!("Removed textfile {}", self.path.display()),
+            Err(e) => warn!("Failed removing textfile {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shutdown;
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+    use uuid::Uuid;
+
+    fn config() -> TextfileConfig {
+        let directory = env::temp_dir().join(format!("textfile-{}", Uuid::new_v4()));
+        fs::create_dir_all(&directory).unwrap();
+        TextfileConfig::new(directory).unwrap()
+    }
+
+    fn measurement(value: f64) -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![
+                Sample {
+                    entity_type: EntityType::Tariff,
+                    entity_name: "HWE-P1".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "t1 import".into(),
+                    metric_type: MetricType::Counter,
+                    value: 38989839600.0,
+                },
+                Sample {
+                    entity_type: EntityType::Device,
+                    entity_name: "HWE-SKT".into(),
+                    sample_type: SampleType::ElectricityConsumption,
+                    sample_name: "Bonenmaler".into(),
+                    metric_type: MetricType::Gauge,
+                    value,
+                },
+            ],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn file_names(config: &TextfileConfig) -> Vec<String> {
+        fs::read_dir(&config.directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn publish_writes_samples_and_freshness_in_exposition_format() {
+        let config = config();
+        let (_sender, shutdown) = shutdown::channel();
+        let publisher = TextfilePublisher::new(&config, shutdown);
+
+        // act
+        publisher.publish(&measurement(98.1)).await.unwrap();
+
+        assert_eq!(
+            fs::read_to_string(config.directory.join("homewizard.prom")).unwrap(),
+            r#"# HELP homewizard_electricity_consumption_joules_total Total imported electricity in joules.
+# TYPE homewizard_electricity_consumption_joules_total counter
+homewizard_electricity_consumption_joules_total{location="My Home",entity_name="HWE-P1",sample_name="t1 import",sample_type="electricity_consumption"} 38989839600
+# HELP homewizard_electricity_consumption_watts Active power in watts, negative while exporting.
+# TYPE homewizard_electricity_consumption_watts gauge
+homewizard_electricity_consumption_watts{location="My Home",entity_name="HWE-SKT",sample_name="Bonenmaler",sample_type="electricity_consumption"} 98.1
+# HELP homewizard_last_cycle_timestamp_seconds Time of the last measurement written to this file.
+# TYPE homewizard_last_cycle_timestamp_seconds gauge
+homewizard_last_cycle_timestamp_seconds{location="My Home"} 1685620800
+"#
+        );
+        assert_eq!(file_names(&config), vec!["homewizard.prom"]);
+        fs::remove_dir_all(&config.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_write_leaves_previous_file_intact() {
+        let config = config();
+        let (_sender, shutdown) = shutdown::channel();
+        let publisher = TextfilePublisher::new(&config, shutdown);
+        publisher.publish(&measurement(98.1)).await.unwrap();
+        let previous = fs::read_to_string(&publisher.path).unwrap();
+        // a directory in the way of the temp file makes writing it fail
+        fs::create_dir(
+            config
+                .directory
+                .join(format!(".homewizard.prom.{}.tmp", process::id())),
+        )
+        .unwrap();
+
+        // act
+        let result = publisher.publish(&measurement(1450.0)).await;
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&publisher.path).unwrap(), previous);
+        fs::remove_dir_all(&config.directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_is_removed_on_shutdown_only() {
+        let config = config();
+        let path = config.directory.join("homewizard.prom");
+        let (_sender, shutdown) = shutdown::channel();
+        let publisher = TextfilePublisher::new(&config, shutdown);
+        publisher.publish(&measurement(98.1)).await.unwrap();
+
+        // act
+        drop(publisher);
+
+        assert!(path.exists());
+
+        let (sender, shutdown) = shutdown::channel();
+        let publisher = TextfilePublisher::new(&config, shutdown);
+        publisher.publish(&measurement(98.1)).await.unwrap();
+        sender.send(true).unwrap();
+
+        // act
+        drop(publisher);
+
+        assert!(!path.exists());
+        fs::remove_dir_all(&config.directory).unwrap();
+    }
+}
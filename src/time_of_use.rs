@@ -0,0 +1,196 @@
+use crate::homewizard_client::{HomewizardDeviceType, MeasuredDevice};
+use crate::model::TimeOfUseConfig;
+use chrono::NaiveDateTime;
+use jarvis_lib::model::{MetricType, Sample, SampleType};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct WindowCounters {
+    last: f64,
+    /// Increase of the counter in every window so far.
+    windows: BTreeMap<String, f64>,
+}
+
+/// Remembers the last value of every counter and how much it went up in every time of use
+/// window, so the consumption of devices without tariff registers can be split by window.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TimeOfUseTracker {
+    counters: HashMap<String, WindowCounters>,
+}
+
+impl TimeOfUseTracker {
+    /// Attributes the increase of a counter since its previous reading to `window`, returning the
+    /// increase of the counter in every window so far. The first reading and a counter that went
+    /// down, like after a device reset, add nothing.
+    pub fn observe(&mut self, series: &str, value: f64, window: &str) -> &BTreeMap<String, f64> {
+        let counters = self
+            .counters
+            .entry(series.to_string())
+            .or_insert(WindowCounters {
+                last: value,
+                windows: BTreeMap::new(),
+            });
+        let increase = (value - counters.last).max(0.0);
+        *counters.windows.entry(window.to_string()).or_insert(0.0) += increase;
+        counters.last = value;
+
+        &counters.windows
+    }
+}
+
+/// The window a cycle at local time `at` falls into: the first configured window containing it,
+/// or else the default window.
+pub fn window_at(config: &TimeOfUseConfig, at: NaiveDateTime) -> Result<&str, Box<dyn Error>> {
+    for window in config.windows.iter() {
+        if window.contains(at)? {
+            return Ok(&window.name);
+        }
+    }
+
+    Ok(&config.default_window)
+}
+
+/// A counter per window for every electricity counter of the energy sockets and kWh meters
+/// among `devices`, named after the counter followed by the window, with the increase of the
+/// counter while cycles fell into `window` attributed to it.
+pub fn samples(
+    tracker: &mut TimeOfUseTracker,
+    config: &TimeOfUseConfig,
+    window: &str,
+    samples: &[Sample],
+    devices: &[MeasuredDevice],
+) -> Vec<Sample> {
+    let mut window_names: Vec<&str> = config
+        .windows
+        .iter()
+        .map(|window| window.name.as_str())
+        .chain(std::iter::once(config.default_window.as_str()))
+        .collect();
+    window_names.sort_unstable();
+    window_names.dedup();
+
+    devices
+        .iter()
+        .filter(|device| {
+            matches!(
+                HomewizardDeviceType::from_str(&device.info.product_type),
+                Ok(HomewizardDeviceType::EnergySocket)
+                    | Ok(HomewizardDeviceType::SinglePhaseKwhMeter)
+                    | Ok(HomewizardDeviceType::TriplePhaseKwhMeter)
+            )
+        })
+        .flat_map(|device| samples[device.samples.clone()].iter())
+        .filter(|sample| {
+            matches!(sample.metric_type, MetricType::Counter)
+                && matches!(
+                    sample.sample_type,
+                    SampleType::ElectricityConsumption | SampleType::ElectricityProduction
+                )
+        })
+        .flat_map(|sample| {
+            let series = format!(
+                "{}/{:?}/{}",
+                sample.entity_name, sample.sample_type, sample.sample_name
+            );
+            let windows = tracker.observe(&series, sample.value, window).clone();
+            window_names
+                .iter()
+                .map(|name| Sample {
+                    sample_name: format!("{} {}", sample.sample_name, name),
+                    value: windows.get(*name).copied().unwrap_or(0.0),
+                    ..sample.clone()
+                })
+                .collect::<Vec<Sample>>()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::TimeOfUseWindow;
+    use chrono::NaiveDate;
+
+    fn config() -> TimeOfUseConfig {
+        TimeOfUseConfig {
+            timezone: "Europe/Amsterdam".into(),
+            windows: vec![
+                TimeOfUseWindow {
+                    name: "off-peak".into(),
+                    days: vec!["saturday".into(), "sunday".into()],
+                    from: None,
+                    to: None,
+                },
+                TimeOfUseWindow {
+                    name: "off-peak".into(),
+                    days: vec![],
+                    from: Some("23:00".into()),
+                    to: Some("07:00".into()),
+                },
+            ],
+            default_window: "peak".into(),
+        }
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // june 2023 starts on a thursday
+        NaiveDate::from_ymd_opt(2023, 6, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn window_at_switches_at_window_boundaries() {
+        let config = config();
+
+        // act
+        let windows: Vec<&str> = vec![
+            at(1, 6, 59),
+            at(1, 7, 0),
+            at(1, 22, 59),
+            at(1, 23, 0),
+            at(2, 0, 30),
+        ]
+        .into_iter()
+        .map(|at| window_at(&config, at).unwrap())
+        .collect();
+
+        assert_eq!(
+            windows,
+            vec!["off-peak", "peak", "peak", "off-peak", "off-peak"]
+        );
+    }
+
+    #[test]
+    fn window_at_applies_weekend_rule_all_day() {
+        let config = config();
+
+        // act
+        let saturday_noon = window_at(&config, at(3, 12, 0)).unwrap();
+        let monday_noon = window_at(&config, at(5, 12, 0)).unwrap();
+
+        assert_eq!(saturday_noon, "off-peak");
+        assert_eq!(monday_noon, "peak");
+    }
+
+    #[test]
+    fn observe_attributes_increase_to_window_of_reading() {
+        let mut tracker = TimeOfUseTracker::default();
+        tracker.observe("Bonenmaler", 10.0, "peak");
+        tracker.observe("Bonenmaler", 12.0, "peak");
+
+        // act
+        let windows = tracker.observe("Bonenmaler", 15.0, "off-peak").clone();
+        let after_reset = tracker.observe("Bonenmaler", 1.0, "off-peak").clone();
+
+        assert_eq!(windows["peak"], 2.0);
+        assert_eq!(windows["off-peak"], 3.0);
+        assert_eq!(after_reset, windows);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::device_registry::DeviceRegistry;
+use crate::env_config::{self, EnvConfig};
+use crate::live_view::{self, DeviceRow};
+use crate::model::Config;
+use crate::shutdown::ShutdownSignal;
+use chrono::Local;
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use jarvis_lib::config_client::ConfigClient;
+use jarvis_lib::measurement_client::MeasurementClient;
+use jarvis_lib::model::Measurement;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{Block, Borders, Cell, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::error::Error;
+use std::io::{self, Stdout};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const COLUMNS: [&str; 7] = [
+    "Name", "Type", "Address", "Latency", "Status", "Gauges", "Counters",
+];
+
+/// Restores the terminal when the live view ends, however it ends.
+struct TerminalGuard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+}
+
+impl TerminalGuard {
+    fn enter() -> Result<Self, Box<dyn Error>> {
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        execute!(stdout, EnterAlternateScreen)?;
+
+        Ok(Self {
+            terminal: Terminal::new(CrosstermBackend::new(stdout))?,
+        })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.terminal.backend_mut(), LeaveAlternateScreen);
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+enum Key {
+    Refresh,
+    Quit,
+}
+
+/// Shows the devices in `device_registry` with their latest values in the terminal, measuring
+/// them through `measurement_client` every `interval`, on `r` and until `q`. Measurements only
+/// get handed to the next cycle, never published.
+pub fn run(
+    config_client: &ConfigClient,
+    env_config: &EnvConfig,
+    measurement_client: &dyn MeasurementClient<Config>,
+    device_registry: Arc<Mutex<DeviceRegistry>>,
+    interval: Duration,
+    shutdown: ShutdownSignal,
+) -> Result<(), Box<dyn Error>> {
+    let mut guard = TerminalGuard::enter()?;
+    let mut last_measurements = None;
+
+    loop {
+        let rows = live_view::rows(&device_registry.lock().unwrap());
+        guard
+            .terminal
+            .draw(|frame| render(frame, &rows, "Measuring..."))?;
+
+        let status = match measure(
+            config_client,
+            env_config,
+            measurement_client,
+            &mut last_measurements,
+        ) {
+            Ok(()) => format!(
+                "Measured at {}, refreshing every {:?} - r: refresh, q: quit",
+                Local::now().format("%H:%M:%S"),
+                interval
+            ),
+            Err(e) => format!("Measuring failed: {} - r: retry, q: quit", e),
+        };
+        let rows = live_view::rows(&device_registry.lock().unwrap());
+        guard.terminal.draw(|frame| render(frame, &rows, &status))?;
+
+        match wait_for_key(interval, &shutdown)? {
+            Some(Key::Quit) => return Ok(()),
+            Some(Key::Refresh) | None => {}
+        }
+    }
+}
+
+fn measure(
+    config_client: &ConfigClient,
+    env_config: &EnvConfig,
+    measurement_client: &dyn MeasurementClient<Config>,
+    last_measurements: &mut Option<Vec<Measurement>>,
+) -> Result<(), Box<dyn Error>> {
+    let config = env_config::read_config(config_client, env_config)?;
+    let measurements = measurement_client.get_measurements(config, last_measurements.clone())?;
+    // a skipped cycle keeps the last measurements for the next one
+    if !measurements.is_empty() {
+        *last_measurements = Some(measurements);
+    }
+
+    Ok(())
+}
+
+/// Waits up to `interval` for a key to refresh or quit; a shutdown request quits as well.
+fn wait_for_key(interval: Duration, shutdown: &ShutdownSignal) -> io::Result<Option<Key>> {
+    let refresh_at = Instant::now() + interval;
+    while let Some(remaining) = refresh_at.checked_duration_since(Instant::now()) {
+        if shutdown.is_requested() {
+            return Ok(Some(Key::Quit));
+        }
+        if !event::poll(remaining.min(Duration::from_millis(200)))? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(Some(Key::Quit)),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(Some(Key::Quit))
+                }
+                KeyCode::Char('r') => return Ok(Some(Key::Refresh)),
+                _ => {}
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+fn render<B: Backend>(frame: &mut Frame<B>, rows: &[DeviceRow], status: &str) {
+    let areas = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(1)].as_ref())
+        .split(frame.size());
+
+    let header = Row::new(COLUMNS.iter().map(|column| Cell::from(*column)))
+        .style(Style::default().add_modifier(Modifier::BOLD));
+    let rows = rows.iter().map(|row| {
+        Row::new(vec![
+            Cell::from(row.name.clone()),
+            Cell::from(row.product_type.clone()),
+            Cell::from(row.address.clone()),
+            Cell::from(row.latency.clone()),
+            Cell::from(row.status.clone()),
+            Cell::from(row.gauges.clone()),
+            Cell::from(row.counters.clone()),
+        ])
+    });
+    let widths = [
+        Constraint::Percentage(14),
+        Constraint::Percentage(8),
+        Constraint::Percentage(14),
+        Constraint::Percentage(7),
+        Constraint::Percentage(13),
+        Constraint::Percentage(22),
+        Constraint::Percentage(22),
+    ];
+    let table = Table::new(rows)
+        .header(header)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("HomeWizard devices"),
+        )
+        .widths(&widths);
+
+    frame.render_widget(table, areas[0]);
+    frame.render_widget(Paragraph::new(status.to_string()), areas[1]);
+}
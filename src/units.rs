@@ -0,0 +1,145 @@
+/// Converts an energy counter in kWh, as reported by the devices, to joules.
+pub fn kwh_to_joules(kwh: f64) -> f64 {
+    kwh * 1000.0 * 3600.0
+}
+
+/// Converts a water flow in liters per minute, as reported by the water meter, to m3 per hour.
+pub fn liters_per_minute_to_cubic_meters_per_hour(liters_per_minute: f64) -> f64 {
+    liters_per_minute * 60.0 / 1000.0
+}
+
+/// Converts a water volume in m3, as reported by the water meter, to liters.
+pub fn cubic_meters_to_liters(cubic_meters: f64) -> f64 {
+    cubic_meters * 1000.0
+}
+
+/// Converts a gas volume in m3 to its energy equivalent in kWh, given the calorific value of the
+/// gas in kWh per m3.
+pub fn gas_cubic_meters_to_kwh(cubic_meters: f64, calorific_value: f64) -> f64 {
+    cubic_meters * calorific_value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    // readings range from nothing to well beyond what any household meter will ever report
+    const MAX_READING: f64 = 1e9;
+
+    fn joules_to_kwh(joules: f64) -> f64 {
+        joules / 3600.0 / 1000.0
+    }
+
+    fn cubic_meters_per_hour_to_liters_per_minute(cubic_meters_per_hour: f64) -> f64 {
+        cubic_meters_per_hour * 1000.0 / 60.0
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        let tolerance = 1e-12 * expected.abs().max(1.0);
+        assert!(
+            (actual - expected).abs() <= tolerance,
+            "{} is not within {} of {}",
+            actual,
+            tolerance,
+            expected
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn kwh_to_joules_round_trips(kwh in -MAX_READING..MAX_READING) {
+            assert_close(joules_to_kwh(kwh_to_joules(kwh)), kwh);
+        }
+
+        #[test]
+        fn kwh_to_joules_is_monotonic(
+            a in -MAX_READING..MAX_READING,
+            b in -MAX_READING..MAX_READING,
+        ) {
+            let (low, high) = if a <= b { (a, b) } else { (b, a) };
+            prop_assert!(kwh_to_joules(low) <= kwh_to_joules(high));
+        }
+
+        #[test]
+        fn kwh_to_joules_keeps_finite_readings_finite(kwh in -MAX_READING..MAX_READING) {
+            prop_assert!(kwh_to_joules(kwh).is_finite());
+        }
+
+        #[test]
+        fn liters_per_minute_round_trips(lpm in -MAX_READING..MAX_READING) {
+            assert_close(
+                cubic_meters_per_hour_to_liters_per_minute(
+                    liters_per_minute_to_cubic_meters_per_hour(lpm),
+                ),
+                lpm,
+            );
+        }
+
+        #[test]
+        fn liters_per_minute_is_monotonic(
+            a in -MAX_READING..MAX_READING,
+            b in -MAX_READING..MAX_READING,
+        ) {
+            let (low, high) = if a <= b { (a, b) } else { (b, a) };
+            prop_assert!(
+                liters_per_minute_to_cubic_meters_per_hour(low)
+                    <= liters_per_minute_to_cubic_meters_per_hour(high)
+            );
+        }
+
+        #[test]
+        fn liters_per_minute_keeps_finite_readings_finite(lpm in -MAX_READING..MAX_READING) {
+            prop_assert!(liters_per_minute_to_cubic_meters_per_hour(lpm).is_finite());
+        }
+
+        #[test]
+        fn gas_cubic_meters_to_kwh_is_monotonic(
+            a in 0.0..MAX_READING,
+            b in 0.0..MAX_READING,
+            calorific_value in 0.1..20.0,
+        ) {
+            let (low, high) = if a <= b { (a, b) } else { (b, a) };
+            prop_assert!(
+                gas_cubic_meters_to_kwh(low, calorific_value)
+                    <= gas_cubic_meters_to_kwh(high, calorific_value)
+            );
+        }
+    }
+
+    #[test]
+    fn zero_converts_to_zero() {
+        assert_eq!(kwh_to_joules(0.0), 0.0);
+        assert_eq!(liters_per_minute_to_cubic_meters_per_hour(0.0), 0.0);
+        assert_eq!(gas_cubic_meters_to_kwh(0.0, 9.77), 0.0);
+        assert_eq!(cubic_meters_to_liters(0.0), 0.0);
+    }
+
+    #[test]
+    fn kwh_to_joules_converts_known_readings() {
+        assert_eq!(kwh_to_joules(1.0), 3_600_000.0);
+        assert_eq!(kwh_to_joules(0.002), 7200.0);
+    }
+
+    #[test]
+    fn water_flow_is_converted_to_cubic_meters_per_hour() {
+        // 7.5 liters a minute is 450 liters an hour; the flow was once documented and nearly
+        // converted as m3/s, which would have reported 0.000125 here
+        assert_eq!(liters_per_minute_to_cubic_meters_per_hour(7.5), 0.45);
+        assert_eq!(liters_per_minute_to_cubic_meters_per_hour(1000.0), 60.0);
+    }
+
+    #[test]
+    fn water_is_converted_to_whole_liters() {
+        assert_eq!(cubic_meters_to_liters(847.012), 847_012.0);
+        assert_eq!(cubic_meters_to_liters(123.456), 123_456.0);
+        assert_eq!(cubic_meters_to_liters(0.001), 1.0);
+    }
+
+    #[test]
+    fn gas_is_converted_to_kwh_with_calorific_value() {
+        assert_eq!(gas_cubic_meters_to_kwh(1.0, 9.77), 9.77);
+        assert_eq!(gas_cubic_meters_to_kwh(100.0, 9.77), 977.0);
+        assert_eq!(gas_cubic_meters_to_kwh(2.0, 10.55), 21.1);
+    }
+}
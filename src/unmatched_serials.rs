@@ -0,0 +1,103 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Configured serials no device was read with, as reported by [`UnmatchedSerialsTracker`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnmatchedSerials {
+    pub cycles: u32,
+    pub serials: Vec<String>,
+    /// Unmatched serials that do match a device once written in lowercase without colons, with
+    /// the serial of that device.
+    pub misspelled: Vec<(String, String)>,
+}
+
+/// Remembers the serial of every device read so far and counts cycles, so configured serials
+/// that never match a device - of devices that are gone, or typos - get reported periodically.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct UnmatchedSerialsTracker {
+    cycles: u32,
+    seen: BTreeSet<String>,
+}
+
+impl UnmatchedSerialsTracker {
+    /// Records ids read other than device serials, like the unique ids of external meters.
+    pub fn see<'a>(&mut self, ids: impl IntoIterator<Item = &'a str>) {
+        self.seen.extend(ids.into_iter().map(String::from));
+    }
+
+    /// Records the serials read in a cycle; every `every_cycles` cycles returns the configured
+    /// serials never read so far, if any.
+    pub fn observe<'a>(
+        &mut self,
+        read: impl IntoIterator<Item = &'a str>,
+        configured: &BTreeSet<&str>,
+        every_cycles: u32,
+    ) -> Option<UnmatchedSerials> {
+        self.seen.extend(read.into_iter().map(String::from));
+        self.cycles = self.cycles.saturating_add(1);
+        if self.cycles % every_cycles.max(1) != 0 {
+            return None;
+        }
+
+        let serials: Vec<String> = configured
+            .iter()
+            .filter(|serial| !self.seen.contains(**serial))
+            .map(|serial| serial.to_string())
+            .collect();
+        if serials.is_empty() {
+            return None;
+        }
+
+        let misspelled = serials
+            .iter()
+            .filter_map(|serial| {
+                let normalized = serial.to_lowercase().replace(':', "");
+                self.seen
+                    .contains(&normalized)
+                    .then(|| (serial.clone(), normalized))
+            })
+            .collect();
+
+        Some(UnmatchedSerials {
+            cycles: self.cycles,
+            serials,
+            misspelled,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observe_reports_configured_serials_never_read_every_interval() {
+        let mut tracker = UnmatchedSerialsTracker::default();
+        let configured: BTreeSet<&str> = vec!["3c39e72e33ce", "5C:2F:AF:0A:8B:3E", "aabbccddeeff"]
+            .into_iter()
+            .collect();
+
+        // act
+        let reports: Vec<Option<UnmatchedSerials>> = (0..4)
+            .map(|cycle| {
+                let read = if cycle == 0 {
+                    vec!["3c39e72e33ce", "5c2faf0a8b3e"]
+                } else {
+                    vec!["3c39e72e33ce"]
+                };
+                tracker.observe(read, &configured, 2)
+            })
+            .collect();
+
+        let expected = |cycles| UnmatchedSerials {
+            cycles,
+            serials: vec!["5C:2F:AF:0A:8B:3E".into(), "aabbccddeeff".into()],
+            misspelled: vec![("5C:2F:AF:0A:8B:3E".into(), "5c2faf0a8b3e".into())],
+        };
+        assert_eq!(
+            reports,
+            vec![None, Some(expected(2)), None, Some(expected(4))]
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use crate::exporter::MeasurementPublisher;
+use async_trait::async_trait;
+use jarvis_lib::model::Measurement;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+pub struct WebhookPublisherConfig {
+    url: String,
+    headers: HeaderMap,
+    timeout: Duration,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    accept_invalid_certs: bool,
+}
+
+impl WebhookPublisherConfig {
+    pub fn new(
+        url: String,
+        bearer_token: Option<String>,
+        headers: Vec<(String, String)>,
+        timeout: Duration,
+        max_attempts: u32,
+        retry_backoff: Duration,
+        accept_invalid_certs: bool,
+    ) -> Result<Self, Box<dyn Error>> {
+        debug!(
+            "WebhookPublisherConfig::new(url: {}, headers: {:?}, timeout: {:?}, max_attempts: {}, retry_backoff: {:?}, accept_invalid_certs: {})",
+            url,
+            headers.iter().map(|(name, _)| name).collect::<Vec<&String>>(),
+            timeout,
+            max_attempts,
+            retry_backoff,
+            accept_invalid_certs
+        );
+        if max_attempts == 0 {
+            return Err("WEBHOOK_MAX_ATTEMPTS has to be at least 1".into());
+        }
+
+        let mut header_map = HeaderMap::new();
+        for (name, value) in headers {
+            header_map.insert(
+                HeaderName::from_bytes(name.trim().as_bytes())?,
+                HeaderValue::from_str(value.trim())?,
+            );
+        }
+        if let Some(bearer_token) = bearer_token {
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", bearer_token))?;
+            value.set_sensitive(true);
+            header_map.insert(AUTHORIZATION, value);
+        }
+
+        Ok(Self {
+            url,
+            headers: header_map,
+            timeout,
+            max_attempts,
+            retry_backoff,
+            accept_invalid_certs,
+        })
+    }
+
+    /// Returns no config when `WEBHOOK_URL` isn't set, since posting to a webhook is optional.
+    pub fn from_env() -> Result<Option<Self>, Box<dyn Error>> {
+        let url = match env::var("WEBHOOK_URL") {
+            Ok(url) => url,
+            Err(_) => return Ok(None),
+        };
+        let bearer_token = env::var("WEBHOOK_BEARER_TOKEN").ok();
+        let headers = parse_headers(&env::var("WEBHOOK_HEADERS").unwrap_or_default())?;
+        let timeout_seconds: u64 = env::var("WEBHOOK_TIMEOUT_SECONDS")
+            .unwrap_or_else(|_| "10".to_string())
+            .parse()?;
+        let max_attempts: u32 = env::var("WEBHOOK_MAX_ATTEMPTS")
+            .unwrap_or_else(|_| "3".to_string())
+            .parse()?;
+        let accept_invalid_certs: bool = env::var("WEBHOOK_INSECURE_SKIP_VERIFY")
+            .unwrap_or_else(|_| "false".to_string())
+            .parse()?;
+
+        Ok(Some(Self::new(
+            url,
+            bearer_token,
+            headers,
+            Duration::from_secs(timeout_seconds),
+            max_attempts,
+            Duration::from_secs(1),
+            accept_invalid_certs,
+        )?))
+    }
+}
+
+/// Parses comma separated `name=value` pairs.
+fn parse_headers(headers: &str) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    headers
+        .split(',')
+        .filter(|header| !header.trim().is_empty())
+        .map(|header| match header.split_once('=') {
+            Some((name, value)) => Ok((name.trim().to_string(), value.trim().to_string())),
+            None => Err(format!(
+                "WEBHOOK_HEADERS has to be a list of name=value pairs instead of {}",
+                header
+            )
+            .into()),
+        })
+        .collect()
+}
+
+/// Posts every measurement as json to a webhook, retrying server and connection errors with a
+/// doubling backoff.
+pub struct WebhookPublisher {
+    config: WebhookPublisherConfig,
+    client: reqwest::Client,
+}
+
+impl WebhookPublisher {
+    pub fn new(config: WebhookPublisherConfig) -> Result<Self, Box<dyn Error>> {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .default_headers(config.headers.clone())
+            .danger_accept_invalid_certs(config.accept_invalid_certs)
+            .build()?;
+
+        Ok(Self { config, client })
+    }
+}
+
+#[async_trait(?Send)]
+impl MeasurementPublisher for WebhookPublisher {
+    async fn publish(&self, measurement: &Measurement) -> Result<(), Box<dyn Error>> {
+        let mut backoff = self.config.retry_backoff;
+
+        for attempt in 1..=self.config.max_attempts {
+            let error = match self
+                .client
+                .post(&self.config.url)
+                .json(measurement)
+                .send()
+                .await
+            {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) if response.status().is_server_error() => {
+                    format!("webhook responded {}", response.status())
+                }
+                Ok(response) => {
+                    return Err(format!("Webhook responded {}", response.status()).into())
+                }
+                Err(e) if e.is_connect() || e.is_timeout() => e.to_string(),
+                Err(e) => return Err(e.into()),
+            };
+
+            if attempt == self.config.max_attempts {
+                return Err(format!(
+                    "Posting to webhook failed after {} attempts: {}",
+                    attempt, error
+                )
+                .into());
+            }
+
+            warn!(
+                "Posting to webhook failed, retrying in {:?} (attempt {} of {}): {}",
+                backoff, attempt, self.config.max_attempts, error
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{FakeDeviceServer, FakeResponse};
+    use chrono::{TimeZone, Utc};
+    use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+    use std::net::TcpListener;
+
+    const WEBHOOK_PATH: &str = "/measurements";
+
+    fn measurement() -> Measurement {
+        Measurement {
+            id: "0b5a33a4-9b6b-4e7f-8f3a-7c3d6a1e2f10".into(),
+            source: String::from("jarvis-homewizard-exporter"),
+            location: "My Home".into(),
+            samples: vec![Sample {
+                entity_type: EntityType::Device,
+                entity_name: "HWE-SKT".into(),
+                sample_type: SampleType::ElectricityConsumption,
+                sample_name: "Bonenmaler".into(),
+                metric_type: MetricType::Gauge,
+                value: 98.1,
+            }],
+            measured_at_time: Utc.with_ymd_and_hms(2023, 6, 1, 12, 0, 0).unwrap(),
+        }
+    }
+
+    fn publisher(url: String, bearer_token: Option<String>) -> WebhookPublisher {
+        WebhookPublisher::new(
+            WebhookPublisherConfig::new(
+                url,
+                bearer_token,
+                vec![("X-Source".into(), "homewizard".into())],
+                Duration::from_secs(5),
+                3,
+                Duration::from_millis(1),
+                false,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn publish_posts_measurement_as_json() {
+        let server = FakeDeviceServer::start();
+        server.respond(WEBHOOK_PATH, FakeResponse::status(202));
+
+        // act
+        publisher(format!("{}{}", server.base_url(), WEBHOOK_PATH), None)
+            .publish(&measurement())
+            .await
+            .unwrap();
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].header("Content-Type"), Some("application/json"));
+        let posted: Measurement = serde_json::from_str(&requests[0].body).unwrap();
+        assert_eq!(posted.id, measurement().id);
+        assert_eq!(posted.samples[0].value, 98.1);
+    }
+
+    #[tokio::test]
+    async fn publish_sends_bearer_token_and_custom_headers() {
+        let server = FakeDeviceServer::start();
+        server.respond(WEBHOOK_PATH, FakeResponse::status(200));
+
+        // act
+        publisher(
+            format!("{}{}", server.base_url(), WEBHOOK_PATH),
+            Some("secret".into()),
+        )
+        .publish(&measurement())
+        .await
+        .unwrap();
+
+        let request = &server.requests()[0];
+        assert_eq!(request.header("Authorization"), Some("Bearer secret"));
+        assert_eq!(request.header("X-Source"), Some("homewizard"));
+    }
+
+    #[tokio::test]
+    async fn publish_retries_server_errors() {
+        let server = FakeDeviceServer::start();
+        server
+            .respond(WEBHOOK_PATH, FakeResponse::status(502))
+            .respond(WEBHOOK_PATH, FakeResponse::status(200));
+
+        // act
+        let result = publisher(format!("{}{}", server.base_url(), WEBHOOK_PATH), None)
+            .publish(&measurement())
+            .await;
+
+        assert!(result.is_ok());
+        assert_eq!(server.request_count(WEBHOOK_PATH), 2);
+    }
+
+    #[tokio::test]
+    async fn publish_gives_up_after_max_attempts() {
+        let server = FakeDeviceServer::start();
+        server.respond(WEBHOOK_PATH, FakeResponse::status(500));
+
+        // act
+        let result = publisher(format!("{}{}", server.base_url(), WEBHOOK_PATH), None)
+            .publish(&measurement())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(server.request_count(WEBHOOK_PATH), 3);
+    }
+
+    #[tokio::test]
+    async fn publish_does_not_retry_client_errors() {
+        let server = FakeDeviceServer::start();
+        server.respond(WEBHOOK_PATH, FakeResponse::status(401));
+
+        // act
+        let result = publisher(format!("{}{}", server.base_url(), WEBHOOK_PATH), None)
+            .publish(&measurement())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(server.request_count(WEBHOOK_PATH), 1);
+    }
+
+    #[tokio::test]
+    async fn publish_retries_connection_errors_and_gives_up() {
+        // nothing listens on a port that was just released
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // act
+        let result = publisher(format!("http://{}{}", address, WEBHOOK_PATH), None)
+            .publish(&measurement())
+            .await;
+
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("failed after 3 attempts"));
+    }
+
+    #[test]
+    fn parse_headers_reads_name_value_pairs() {
+        assert_eq!(
+            parse_headers("X-Source=homewizard, X-Env = home").unwrap(),
+            vec![
+                ("X-Source".to_string(), "homewizard".to_string()),
+                ("X-Env".to_string(), "home".to_string()),
+            ]
+        );
+        assert!(parse_headers("").unwrap().is_empty());
+        assert!(parse_headers("X-Source").is_err());
+    }
+}
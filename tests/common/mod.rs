@@ -0,0 +1,84 @@
+//! Helpers shared by the integration tests, which request devices faked by httpmock servers
+//! serving the captured responses in `tests/fixtures/responses`.
+
+use httpmock::prelude::*;
+use jarvis_homewizard_exporter::homewizard_client::{
+    HomewizardClient, HomewizardClientConfig, HomewizardDevice,
+};
+use jarvis_homewizard_exporter::model::Config;
+use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+use std::error::Error;
+use std::fs;
+use std::net::Ipv4Addr;
+
+/// The captured response in `tests/fixtures/responses` named `name`.
+pub fn fixture(name: &str) -> String {
+    fs::read_to_string(format!("tests/fixtures/responses/{}", name)).unwrap()
+}
+
+/// A v1 device answering its `/api` endpoint with `info` and its data endpoint with `data`.
+pub fn mock_device(info: &str, data: &str) -> (MockServer, HomewizardDevice) {
+    let server = MockServer::start();
+    server.mock(|when, then| {
+        when.method(GET).path("/api");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(info);
+    });
+    server.mock(|when, then| {
+        when.method(GET).path("/api/v1/data");
+        then.status(200)
+            .header("content-type", "application/json")
+            .body(data);
+    });
+    let device = HomewizardDevice {
+        fullname: "mock-device._hwenergy._tcp.local.".into(),
+        ip_addresses: vec![Ipv4Addr::LOCALHOST].into_iter().collect(),
+        port: server.port(),
+        hostname: None,
+        api_path: None,
+    };
+
+    (server, device)
+}
+
+/// Requests the info and then the samples of the device, the way a measurement cycle does.
+pub fn measure(
+    device: &HomewizardDevice,
+    config: &Config,
+) -> Result<Vec<Sample>, Box<dyn Error + Send + Sync>> {
+    let homewizard_client = HomewizardClient::new(HomewizardClientConfig::default());
+    let device_info = homewizard_client.get_device_info(device)?;
+
+    homewizard_client.get_samples(config, device, &device_info)
+}
+
+pub fn sample(
+    entity_type: EntityType,
+    entity_name: &str,
+    sample_type: SampleType,
+    sample_name: &str,
+    metric_type: MetricType,
+    value: f64,
+) -> Sample {
+    Sample {
+        entity_type,
+        entity_name: entity_name.into(),
+        sample_type,
+        sample_name: sample_name.into(),
+        metric_type,
+        value,
+    }
+}
+
+pub fn assert_samples(actual: &[Sample], expected: &[Sample]) {
+    assert_eq!(actual.len(), expected.len());
+    for (actual, expected) in actual.iter().zip(expected.iter()) {
+        assert_eq!(actual.entity_type, expected.entity_type);
+        assert_eq!(actual.entity_name, expected.entity_name);
+        assert_eq!(actual.sample_type, expected.sample_type);
+        assert_eq!(actual.sample_name, expected.sample_name);
+        assert_eq!(actual.metric_type, expected.metric_type);
+        assert_eq!(actual.value, expected.value);
+    }
+}
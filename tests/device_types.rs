@@ -0,0 +1,226 @@
+//! The samples of every supported device type, read from httpmock servers serving the captured
+//! responses of the device.
+
+mod common;
+
+use common::{assert_samples, fixture, measure, mock_device, sample};
+use jarvis_homewizard_exporter::model::Config;
+use jarvis_lib::model::{EntityType, MetricType, Sample, SampleType};
+
+fn config() -> Config {
+    Config {
+        location: "My Home".into(),
+        names: vec![("3c39e72e33ce".to_string(), "Bonenmaler".to_string())]
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// Measures a device serving the fixtures named `info` and `data`.
+fn measure_mock_device(info: &str, data: &str) -> Vec<Sample> {
+    let (_server, device) = mock_device(&fixture(info), &fixture(data));
+
+    measure(&device, &config()).unwrap()
+}
+
+#[test]
+fn get_samples_for_p1_meter() {
+    // act
+    let samples = measure_mock_device("api-p1.json", "data-p1-with-gas.json");
+
+    assert_samples(
+        &samples,
+        &[
+            sample(
+                EntityType::Tariff,
+                "HWE-P1",
+                SampleType::ElectricityConsumption,
+                "t1 import",
+                MetricType::Counter,
+                10830.511 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Tariff,
+                "HWE-P1",
+                SampleType::ElectricityProduction,
+                "t1 export",
+                MetricType::Counter,
+                0.002 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Tariff,
+                "HWE-P1",
+                SampleType::ElectricityConsumption,
+                "t2 import",
+                MetricType::Counter,
+                2948.827 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Tariff,
+                "HWE-P1",
+                SampleType::ElectricityProduction,
+                "t2 export",
+                MetricType::Counter,
+                0.003 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Device,
+                "HWE-P1",
+                SampleType::ElectricityConsumption,
+                "P1 meter",
+                MetricType::Gauge,
+                -543.0,
+            ),
+        ],
+    );
+}
+
+#[test]
+fn get_samples_for_energy_socket() {
+    // act
+    let samples = measure_mock_device("api-socket.json", "data-socket-firmware-3.json");
+
+    assert_samples(
+        &samples,
+        &[
+            sample(
+                EntityType::Device,
+                "HWE-SKT",
+                SampleType::ElectricityConsumption,
+                "Bonenmaler",
+                MetricType::Counter,
+                30.511 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Device,
+                "HWE-SKT",
+                SampleType::ElectricityProduction,
+                "Bonenmaler",
+                MetricType::Counter,
+                0.005 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Device,
+                "HWE-SKT",
+                SampleType::ElectricityConsumption,
+                "Bonenmaler",
+                MetricType::Gauge,
+                98.1,
+            ),
+        ],
+    );
+}
+
+#[test]
+fn get_samples_for_single_phase_kwh_meter() {
+    // act
+    let samples = measure_mock_device("api-sdm230.json", "data-sdm230.json");
+
+    assert_samples(
+        &samples,
+        &[
+            sample(
+                EntityType::Device,
+                "SDM230-wifi",
+                SampleType::ElectricityConsumption,
+                "kWh meter",
+                MetricType::Counter,
+                2.705 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Device,
+                "SDM230-wifi",
+                SampleType::ElectricityProduction,
+                "kWh meter",
+                MetricType::Counter,
+                255.551 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Device,
+                "SDM230-wifi",
+                SampleType::ElectricityConsumption,
+                "kWh meter",
+                MetricType::Gauge,
+                -1058.296,
+            ),
+        ],
+    );
+}
+
+#[test]
+fn get_samples_for_triple_phase_kwh_meter() {
+    // act
+    let samples = measure_mock_device("api-sdm630.json", "data-sdm630.json");
+
+    assert_samples(
+        &samples,
+        &[
+            sample(
+                EntityType::Device,
+                "SDM630-wifi",
+                SampleType::ElectricityConsumption,
+                "kWh meter 3-phase",
+                MetricType::Counter,
+                0.101 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Device,
+                "SDM630-wifi",
+                SampleType::ElectricityProduction,
+                "kWh meter 3-phase",
+                MetricType::Counter,
+                1002.123 * 1000.0 * 3600.0,
+            ),
+            sample(
+                EntityType::Device,
+                "SDM630-wifi",
+                SampleType::ElectricityConsumption,
+                "kWh meter 3-phase",
+                MetricType::Gauge,
+                -900.194,
+            ),
+        ],
+    );
+}
+
+#[test]
+fn get_samples_for_water_meter() {
+    // act
+    let samples = measure_mock_device("api-watermeter.json", "data-watermeter-usb.json");
+
+    assert_samples(
+        &samples,
+        &[
+            sample(
+                EntityType::Device,
+                "HWE-WTR",
+                SampleType::WaterConsumption,
+                "Watermeter",
+                MetricType::Counter,
+                123.456,
+            ),
+            sample(
+                EntityType::Device,
+                "HWE-WTR",
+                SampleType::WaterConsumption,
+                "Watermeter",
+                MetricType::Gauge,
+                7.5 * 60.0 / 1000.0,
+            ),
+        ],
+    );
+}
+
+#[test]
+fn get_samples_fails_for_malformed_data() {
+    let (_server, device) = mock_device(
+        &fixture("api-watermeter.json"),
+        r#"{"wifi_ssid":"My Wi-Fi"}"#,
+    );
+
+    // act
+    let result = measure(&device, &config());
+
+    assert!(result.is_err());
+}